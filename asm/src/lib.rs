@@ -0,0 +1,260 @@
+//! Two-pass assembler of Hack assembly into Hack machine code.
+//!
+//! Source is [parsed](parse::parse) into statements the way the Assembler of nand2tetris takes
+//! assembly written by hand, then assembled with [`vm::asm::assemble_all`], which build, run and
+//! test assemble generated code with too: the first pass gives each label the address of the
+//! instruction after it, the second one resolves the other symbols and encodes every instruction
+//! into a word.
+
+pub mod parse;
+
+use crate::parse::Statement;
+use snafu::Snafu;
+use std::ops::Range;
+use vm::asm::{self, AsmInstr};
+use vm::diagnostic::Diagnostic;
+use vm::layout::SCREEN;
+use vm::parse::{MAX_ADDRESSABLE, Span};
+
+#[derive(Snafu, Debug, PartialEq, Clone)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("invalid address `@{address}`, expected a constant or a symbol"))]
+    InvalidAddress { address: String, span: Span },
+    #[snafu(display(
+        "constant {constant} is larger than {MAX_ADDRESSABLE}, the largest an A-instruction loads"
+    ))]
+    ConstantTooLarge { constant: String, span: Span },
+    #[snafu(display("invalid label `{label}`, expected a symbol in parentheses"))]
+    InvalidLabel { label: String, span: Span },
+    #[snafu(display("invalid destination `{dest}`, expected A, D and M each at most once"))]
+    InvalidDest { dest: String, span: Span },
+    #[snafu(display("invalid computation `{comp}`"))]
+    InvalidComp { comp: String, span: Span },
+    #[snafu(display("invalid jump `{jump}`, expected JGT, JEQ, JGE, JLT, JNE, JLE or JMP"))]
+    InvalidJump { jump: String, span: Span },
+    /// A statement that parses but does not assemble, or the whole program when there is no span.
+    #[snafu(display("{source}"))]
+    Assembling {
+        source: asm::Error,
+        span: Option<Span>,
+        /// Where a label defined more than once is defined first.
+        first: Option<Span>,
+    },
+}
+
+impl Error {
+    /// Stable code of the error, the code of the matching [`vm::asm::Error`], which
+    /// [`vm::explain::explain`] describes.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Assembling { source, .. } => source.code(),
+            _ => "VM0201",
+        }
+    }
+
+    /// Bytes of the source the error points at, none for errors of the whole program.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::InvalidAddress { span, .. }
+            | Error::ConstantTooLarge { span, .. }
+            | Error::InvalidLabel { span, .. }
+            | Error::InvalidDest { span, .. }
+            | Error::InvalidComp { span, .. }
+            | Error::InvalidJump { span, .. } => Some(span.clone()),
+            Error::Assembling { span, .. } => span.clone(),
+        }
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(error: &Error) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string()).with_code(error.code());
+        match error.span() {
+            Some(span) => diagnostic.with_span(span),
+            None => diagnostic,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AssembleOptions {
+    /// Words variables are allocated from, in order of first use. `RAM[16]` up to the screen by
+    /// default, like the Assembler of nand2tetris.
+    pub variables: Range<u16>,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        Self {
+            variables: 16..SCREEN,
+        }
+    }
+}
+
+/// Assembles `source` into machine code with the default options, see [`assemble_with`].
+pub fn assemble(source: &str) -> Result<Vec<u16>, Vec<Error>> {
+    assemble_with(source, &AssembleOptions::default())
+}
+
+/// Assembles `source` into machine code, one word per instruction. Fails with every error found,
+/// the lines that do not parse first, in source order.
+pub fn assemble_with(source: &str, options: &AssembleOptions) -> Result<Vec<u16>, Vec<Error>> {
+    let (statements, mut errors) = parse::parse(source);
+    let asm = statements
+        .iter()
+        .map(|statement| statement.instr.clone())
+        .collect::<Vec<_>>();
+    match asm::assemble_all(&asm, options.variables.clone()) {
+        Ok(binary) if errors.is_empty() => Ok(binary),
+        Ok(_) => Err(errors),
+        Err(assembling) => {
+            let located = assembling.into_iter().map(|(index, source)| {
+                let statement = index.map(|index| &statements[index]);
+                let first = match &source {
+                    asm::Error::DuplicateLabel { label } => first_definition(&statements, label),
+                    _ => None,
+                };
+                Error::Assembling {
+                    source,
+                    span: statement.map(|statement| statement.span.clone()),
+                    first,
+                }
+            });
+            errors.extend(located);
+            Err(errors)
+        }
+    }
+}
+
+/// Bytes of the first statement of `statements` defining `label`.
+fn first_definition(statements: &[Statement], label: &str) -> Option<Span> {
+    statements
+        .iter()
+        .find_map(|statement| match &statement.instr {
+            AsmInstr::Label(defined) if defined == label => Some(statement.span.clone()),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AssembleOptions, Error, assemble, assemble_with};
+    use vm::asm::{self, ROM_SIZE, parse_asm, render_hack};
+    use vm::diagnostic::Diagnostic;
+
+    const MAX_ASM: &str = "\
+// Computes R2 = max(R0, R1)
+   @R0
+   D = M              // D = first number
+   @R1
+   D=D-M
+   @OUTPUT_FIRST
+   D ; JGT            // if D>0 goto OUTPUT_FIRST
+   @R1
+   D=M
+   @OUTPUT_D
+   0;JMP
+(OUTPUT_FIRST)
+   @R0
+   D=M
+(OUTPUT_D)
+   @R2
+   M=D
+(INFINITE_LOOP)
+   @INFINITE_LOOP
+   0;JMP
+";
+
+    #[test]
+    fn assemble_max() {
+        let binary = assemble(MAX_ASM).expect("expect ok");
+        assert_eq!(
+            render_hack(&binary),
+            "0000000000000000\n1111110000010000\n0000000000000001\n1111010011010000\n\
+            0000000000001010\n1110001100000001\n0000000000000001\n1111110000010000\n\
+            0000000000001100\n1110101010000111\n0000000000000000\n1111110000010000\n\
+            0000000000000010\n1110001100001000\n0000000000001110\n1110101010000111\n"
+        );
+    }
+
+    #[test]
+    fn assemble_variables() {
+        let source = "@i\nM=1\n@sum\nM=0\n@i\n@SCREEN\n@KBD\n@LOOP\n(LOOP)";
+        let binary = assemble(source).expect("expect ok");
+        assert_eq!(binary, vec![16, 0xefc8, 17, 0xea88, 16, 16384, 24576, 8]);
+        let options = AssembleOptions { variables: 16..17 };
+        let errors = assemble_with("@i\n@j\n@i\n@k", &options).expect_err("expect err");
+        let overflow = |symbol: &str, span| Error::Assembling {
+            source: asm::Error::VariableOverflow {
+                symbol: symbol.to_owned(),
+            },
+            span: Some(span),
+            first: None,
+        };
+        assert_eq!(errors, vec![overflow("j", 3..5), overflow("k", 9..11)]);
+    }
+
+    #[test]
+    fn assemble_like_vm() {
+        const TESTING_ASM: &str =
+            "(LOOP)\n@i\nM=1\n@j\nAM=M-1;JNE\n@i\n@END\n0;JMP\n(END)\n@KBD\n@R13";
+        let asm = parse_asm(TESTING_ASM).expect("expect ok");
+        let options = AssembleOptions { variables: 16..256 };
+        assert_eq!(
+            assemble_with(TESTING_ASM, &options).expect("expect ok"),
+            asm::assemble(&asm, 16..256).expect("expect ok")
+        );
+    }
+
+    #[test]
+    fn report_every_error() {
+        let source = "(LOOP)\nD=D*M\n@LOOP\n(LOOP)\n(R5)\nX=D\nD;JMQ\n@99999\n@1x";
+        let errors = assemble(source).expect_err("expect err");
+        let rendered = errors
+            .iter()
+            .map(|error| (error.to_string(), error.code()))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("invalid computation `D*M`", "VM0201"),
+            (
+                "invalid destination `X`, expected A, D and M each at most once",
+                "VM0201",
+            ),
+            (
+                "invalid jump `JMQ`, expected JGT, JEQ, JGE, JLT, JNE, JLE or JMP",
+                "VM0201",
+            ),
+            (
+                "constant 99999 is larger than 32767, the largest an A-instruction loads",
+                "VM0201",
+            ),
+            (
+                "invalid address `@1x`, expected a constant or a symbol",
+                "VM0201",
+            ),
+            ("label `LOOP` is defined more than once", "VM0202"),
+            ("label `R5` redefines a predefined symbol", "VM0202"),
+        ];
+        let expected = expected.map(|(message, code)| (message.to_owned(), code));
+        assert_eq!(rendered, expected);
+        assert_eq!(errors[0].span(), Some(9..12));
+        let Error::Assembling { span, first, .. } = &errors[5] else {
+            panic!("expect duplicate label");
+        };
+        assert_eq!((span.clone(), first.clone()), (Some(19..25), Some(0..6)));
+        let diagnostic = Diagnostic::from(&errors[6]);
+        assert_eq!(diagnostic.code, Some("VM0202"));
+        assert_eq!(diagnostic.span, Some(26..30));
+    }
+
+    #[test]
+    fn assemble_rom_overflow() {
+        let full = "D=0\n".repeat(ROM_SIZE - 1);
+        let binary = assemble(&format!("{full}(END)\n@END")).expect("expect ok");
+        assert_eq!(binary.len(), ROM_SIZE);
+        let errors = assemble(&format!("{full}D=0\nD=0")).expect_err("expect err");
+        assert_eq!(errors[0].code(), "VM0204");
+        assert_eq!(errors[0].span(), None);
+    }
+}
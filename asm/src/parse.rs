@@ -0,0 +1,187 @@
+//! Splits Hack assembly into statements, one per line holding code.
+
+use crate::{
+    ConstantTooLargeSnafu, Error, InvalidAddressSnafu, InvalidCompSnafu, InvalidDestSnafu,
+    InvalidJumpSnafu, InvalidLabelSnafu,
+};
+use snafu::OptionExt;
+use vm::asm::{AsmInstr, Dest, at, is_symbol, label};
+use vm::parse::{MAX_ADDRESSABLE, Span};
+
+/// An instruction or a label, along with the bytes of the source it takes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Statement {
+    pub instr: AsmInstr,
+    /// The code of the line, without the spaces around it or its comment.
+    pub span: Span,
+}
+
+/// Parses each line of `source` holding code into a statement, skipping blank lines and `//`
+/// comments, which may also end a line. Lines that do not parse are left out, their errors
+/// returned in source order.
+pub fn parse(source: &str) -> (Vec<Statement>, Vec<Error>) {
+    let mut statements = vec![];
+    let mut errors = vec![];
+    for line in source.lines() {
+        let code = line.split_once("//").map_or(line, |(code, _)| code).trim();
+        if code.is_empty() {
+            continue;
+        }
+        match parse_code(source, code) {
+            Ok(instr) => statements.push(Statement {
+                instr,
+                span: span_of(source, code),
+            }),
+            Err(error) => errors.push(error),
+        }
+    }
+    (statements, errors)
+}
+
+/// Parses `code`, a line of `source` without its comment, which may have spaces around `=` and `;`.
+fn parse_code(source: &str, code: &str) -> Result<AsmInstr, Error> {
+    if let Some(address) = code.strip_prefix('@') {
+        let span = span_of(source, code);
+        return if !address.is_empty() && address.bytes().all(|byte| byte.is_ascii_digit()) {
+            let value = address
+                .parse::<u32>()
+                .ok()
+                .filter(|value| *value <= MAX_ADDRESSABLE);
+            Ok(at(value.context(ConstantTooLargeSnafu {
+                constant: address,
+                span,
+            })? as u16))
+        } else if is_symbol(address) {
+            Ok(at(address))
+        } else {
+            InvalidAddressSnafu { address, span }.fail()
+        };
+    }
+    if code.starts_with('(') {
+        let name = code
+            .strip_prefix('(')
+            .and_then(|name| name.strip_suffix(')'))
+            .map(str::trim);
+        return match name {
+            Some(name) if is_symbol(name) => Ok(label(name)),
+            _ => InvalidLabelSnafu {
+                label: code,
+                span: span_of(source, code),
+            }
+            .fail(),
+        };
+    }
+    let (dest, rest) = match code.split_once('=') {
+        Some((dest, rest)) => (Some(dest.trim_end()), rest.trim_start()),
+        None => (None, code),
+    };
+    let (comp, jump) = match rest.split_once(';') {
+        Some((comp, jump)) => (comp.trim_end(), Some(jump.trim_start())),
+        None => (rest, None),
+    };
+    let dest = match dest {
+        // `=M` sets nothing, which is spelled without the `=`.
+        Some(dest) => dest
+            .parse()
+            .ok()
+            .filter(|_| !dest.is_empty())
+            .context(InvalidDestSnafu {
+                dest,
+                span: span_of(source, dest),
+            })?,
+        None => Dest::NONE,
+    };
+    let comp = comp.parse().ok().context(InvalidCompSnafu {
+        comp,
+        span: span_of(source, comp),
+    })?;
+    let jump = match jump {
+        Some(jump) => Some(jump.parse().ok().context(InvalidJumpSnafu {
+            jump,
+            span: span_of(source, jump),
+        })?),
+        None => None,
+    };
+    Ok(AsmInstr::C { dest, comp, jump })
+}
+
+/// Bytes `part`, a slice of `source`, takes in `source`.
+fn span_of(source: &str, part: &str) -> Span {
+    let start = part.as_ptr() as usize - source.as_ptr() as usize;
+    start..start + part.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Error;
+    use crate::parse::{Statement, parse};
+    use vm::asm::{AsmInstr, Comp, Dest, Jump, at, label, set};
+
+    #[test]
+    fn parse_spaced() {
+        let (statements, errors) =
+            parse("  @ten // load\n\n( END )\nAM = M-1\nD ; JGT\nM=D+1;JMP // all of it\n");
+        assert!(errors.is_empty());
+        let instrs = statements
+            .iter()
+            .map(|statement| statement.instr.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instrs,
+            vec![
+                at("ten"),
+                label("END"),
+                set(Dest::AM, Comp::MMinusOne),
+                AsmInstr::C {
+                    dest: Dest::NONE,
+                    comp: Comp::D,
+                    jump: Some(Jump::Greater)
+                },
+                AsmInstr::C {
+                    dest: Dest::M,
+                    comp: Comp::DPlusOne,
+                    jump: Some(Jump::Always)
+                },
+            ]
+        );
+        assert_eq!(
+            statements[0],
+            Statement {
+                instr: at("ten"),
+                span: 2..6
+            }
+        );
+        assert_eq!(statements[2].span, 24..32);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let (statements, errors) =
+            parse("@\n@-1\n@32768\n(END\n(1st)\n=M\nDD=M\nD=M;\nD\nD=M // fine");
+        assert_eq!(statements.len(), 2);
+        let invalid = errors.iter().map(|error| match error {
+            Error::InvalidAddress { address, span } => ("address", address.as_str(), span.clone()),
+            Error::ConstantTooLarge { constant, span } => {
+                ("constant", constant.as_str(), span.clone())
+            }
+            Error::InvalidLabel { label, span } => ("label", label.as_str(), span.clone()),
+            Error::InvalidDest { dest, span } => ("dest", dest.as_str(), span.clone()),
+            Error::InvalidComp { comp, span } => ("comp", comp.as_str(), span.clone()),
+            Error::InvalidJump { jump, span } => ("jump", jump.as_str(), span.clone()),
+            error => panic!("unexpected error {error}"),
+        });
+        assert_eq!(
+            invalid.collect::<Vec<_>>(),
+            vec![
+                ("address", "", 0..1),
+                ("address", "-1", 2..5),
+                ("constant", "32768", 6..12),
+                ("label", "(END", 13..17),
+                ("label", "(1st)", 18..23),
+                ("dest", "", 24..24),
+                ("dest", "DD", 27..29),
+                ("jump", "", 36..36),
+            ]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::GlobalOpts;
+use crate::error::Error::{AssemblingSource, EmptySource, Whatever};
+use crate::error::{DisassemblingSnafu, Error, IOSnafu};
+use crate::output::create;
+use crate::session::Session;
+use clio::ClioPath;
+use snafu::ResultExt;
+use std::io::{Write, read_to_string};
+use vm::asm::{disassemble, parse_hack, render, render_hack};
+use vm::source::SourceFile;
+
+/// Assembles the single input into machine code, next to it by default. Every line that does not
+/// assemble is reported, the last one as the error returned.
+pub(crate) fn assemble_file(session: &Session, global: GlobalOpts) -> Result<(), Error> {
+    let [input_path] = &global.sources[..] else {
+        return Err(EmptySource {
+            message: "expected one assembly file".to_owned(),
+        });
+    };
+    if !input_path.is_file() && !input_path.is_std() {
+        return Err(EmptySource {
+            message: "input is not an assembly file".to_owned(),
+        });
+    }
+    let path = input_path.path().display().to_string();
+    let name = if input_path.is_std() {
+        format!("{}.asm", global.stdin_name)
+    } else {
+        let name = input_path.file_name().expect("expect file name");
+        name.to_string_lossy().into_owned()
+    };
+    let input = read_to_string(input_path.clone().read_all()?).context(IOSnafu)?;
+    session.keep_source(&path, &SourceFile::new(&name, &input));
+    let binary = asm::assemble(&input).map_err(|errors| {
+        let mut errors = errors.into_iter().map(|source| AssemblingSource {
+            source,
+            path: path.clone(),
+        });
+        let last = errors.next_back().expect("assembling fails with an error");
+        errors.for_each(|error| session.report_error(error));
+        last
+    })?;
+    let output = hack_output(&global).expect("a single input has an output");
+    let mut writer = create(output)?;
+    writer
+        .write_all(render_hack(&binary).as_bytes())
+        .context(IOSnafu)?;
+    writer.flush().context(IOSnafu)
+}
+
+/// Disassembles the single input, see [`disassemble`]. Input made of nothing but binary digits and
+/// whitespace is read as text, any other as two bytes per word.
+pub(crate) fn disassemble_file(global: GlobalOpts) -> Result<(), Error> {
+    let [input_path] = &global.sources[..] else {
+        return Err(EmptySource {
+            message: "expected one machine code file".to_owned(),
+        });
+    };
+    if !input_path.is_file() && !input_path.is_std() {
+        return Err(EmptySource {
+            message: "input is not a machine code file".to_owned(),
+        });
+    }
+    let cached = input_path.clone().read_all()?;
+    let input = cached.get_data();
+    let text = input
+        .iter()
+        .all(|byte| matches!(byte, b'0' | b'1') || byte.is_ascii_whitespace());
+    let binary = if text {
+        parse_hack(&String::from_utf8_lossy(input)).context(DisassemblingSnafu)?
+    } else if input.len() % 2 == 0 {
+        let words = input.chunks_exact(2);
+        words
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect()
+    } else {
+        return Err(Whatever {
+            message: "raw machine code takes two bytes per word, but the input has an odd length"
+                .to_owned(),
+        });
+    };
+    let asm = disassemble(&binary).context(DisassemblingSnafu)?;
+    let mut writer = create(global.output.unwrap_or_else(ClioPath::std))?;
+    writer.write_all(render(&asm).as_bytes()).context(IOSnafu)?;
+    writer.flush().context(IOSnafu)
+}
+
+/// File `asm` writes the machine code of its single input into, next to the input by default, or
+/// none without a single input.
+pub(crate) fn hack_output(global: &GlobalOpts) -> Option<ClioPath> {
+    match (&global.output, &global.sources[..]) {
+        (Some(output), _) => Some(output.clone()),
+        (None, [input]) if input.is_std() => Some(ClioPath::std()),
+        (None, [input]) => Some(ClioPath::local(input.with_extension("hack"))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Command;
+    use crate::session::Session;
+    use crate::tests::{configured, temp_dir};
+    use std::fs;
+
+    #[test]
+    fn assemble_next_to_input() {
+        let dir = temp_dir("asm");
+        let input = dir.join("Add.asm");
+        fs::write(&input, "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n").expect("expect ok");
+        let opts = configured(["vm-cli", "asm", "-i", input.to_str().expect("expect utf-8")]);
+        assert!(matches!(opts.command, Command::Asm));
+        let session = Session::new(&opts.global);
+        super::assemble_file(&session, opts.global).expect("expect ok");
+        let hack = fs::read_to_string(dir.join("Add.hack")).expect("expect ok");
+        assert_eq!(
+            hack,
+            "0000000000000010\n1110110000010000\n0000000000000011\n1110000010010000\n\
+                0000000000000000\n1110001100001000\n"
+        );
+        let opts = configured(["vm-cli", "asm", "-i", dir.to_str().expect("expect utf-8")]);
+        super::assemble_file(&session, opts.global).expect_err("expect err");
+
+        fs::write(&input, "@2\nD=A\n(R5)\nD;JMQ\n").expect("expect ok");
+        let opts = configured(["vm-cli", "asm", "-i", input.to_str().expect("expect utf-8")]);
+        let error = super::assemble_file(&session, opts.global).expect_err("expect err");
+        let diagnostic = error.diagnostic();
+        assert_eq!(
+            (diagnostic.code, diagnostic.span),
+            (Some("VM0202"), Some(7..11))
+        );
+        assert_eq!(error.exit_code(), 4);
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn disassemble_raw_words() {
+        let dir = temp_dir("disasm");
+        let input = dir.join("Jump.bin");
+        fs::write(&input, [0x00, 0x02, 0xec, 0x07]).expect("expect ok");
+        let output = dir.join("Jump.asm");
+        let args = [
+            "vm-cli",
+            "disasm",
+            "-i",
+            input.to_str().expect("expect utf-8"),
+            "-o",
+            output.to_str().expect("expect utf-8"),
+        ];
+        super::disassemble_file(configured(args).global).expect("expect ok");
+        let asm = fs::read_to_string(&output).expect("expect ok");
+        assert_eq!(asm, "@L2\nA;JMP\n(L2)\n");
+
+        fs::write(&input, [0x00, 0x02, 0xec]).expect("expect ok");
+        let args = [
+            "vm-cli",
+            "disasm",
+            "-i",
+            input.to_str().expect("expect utf-8"),
+        ];
+        let error = super::disassemble_file(configured(args).global).expect_err("expect err");
+        assert!(error.to_string().ends_with("the input has an odd length"));
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+}
@@ -0,0 +1,780 @@
+use crate::GlobalOpts;
+use crate::check::report_findings;
+use crate::error::Error::{EmptySource, Whatever};
+use crate::error::{
+    AssemblingSnafu, ConvertingSnafu, Error, GeneratingSnafu, IOSnafu, LinkingSnafu, WatchingSnafu,
+};
+use crate::input::{find_asm_sources, find_sources, read_asm_files, read_sources};
+use crate::manifest::Manifest;
+use crate::output::{create, write_output};
+use crate::progress;
+use crate::report::BuildReport;
+use crate::session::{Session, parse_switch};
+use clap::{Args, ValueEnum};
+use clio::ClioPath;
+use indicatif::ProgressBar;
+use notify::{Event, RecursiveMode, Watcher};
+use snafu::ResultExt;
+use std::fs;
+use std::io::{Write, read_to_string};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tracing::{Level, info, trace};
+use vm::Linker;
+use vm::asm::{assemble, render, render_hack};
+use vm::cache::CompileCache;
+use vm::generate::{
+    BootstrapOptions, Class, Clock, Context, ENTRY, Generate, GenerateOptions, OptLevel,
+};
+use vm::ir;
+use vm::optimize::{OptPipeline, Pass};
+use vm::program::Program;
+use vm::source::SourceFile;
+use vm::tokenize::tokenize;
+
+/// Directory next to the input that builds keep the output of each class in.
+const CACHE_DIR: &str = ".jack-cache";
+
+#[derive(Args)]
+pub(crate) struct BootOpts {
+    #[clap(long, action, default_value_t = false)]
+    no_boot: bool,
+    /// Function the bootstrap code starts the program in, Sys.init by default
+    #[clap(long, env = "JACK_VM_ENTRY")]
+    entry: Option<String>,
+    /// Enter the program by jumping into the entry function instead of calling it with a frame
+    #[clap(long, action, default_value_t = false)]
+    boot_jump: bool,
+    /// Start the output with the assembly of this file instead of the generated bootstrap code
+    #[clap(long, conflicts_with_all = ["no_boot", "entry", "boot_jump"])]
+    bootstrap_file: Option<PathBuf>,
+}
+
+impl BootOpts {
+    pub(crate) fn bootstrap(&self) -> Option<BootstrapOptions> {
+        (!self.no_boot && self.bootstrap_file.is_none()).then(|| BootstrapOptions {
+            entry: self.entry.clone().unwrap_or_else(|| ENTRY.to_owned()),
+            call_frame: !self.boot_jump,
+            ..Default::default()
+        })
+    }
+
+    /// Fills in what the flags leave out from the bootstrap section of `manifest`.
+    pub(crate) fn configure(&mut self, manifest: &Manifest) {
+        self.no_boot |= !manifest.bootstrap.enabled;
+        self.boot_jump |= !manifest.bootstrap.call_frame;
+        if self.entry.is_none() {
+            self.entry.clone_from(&manifest.bootstrap.entry);
+        }
+        if self.bootstrap_file.is_none() {
+            self.bootstrap_file.clone_from(&manifest.bootstrap.file);
+        }
+    }
+}
+
+#[derive(Args)]
+pub(crate) struct BuildOpts {
+    #[clap(flatten)]
+    boot: BootOpts,
+    /// Comment the output with the VM instruction and source line behind each block
+    #[clap(long, action, default_value_t = false)]
+    annotate: bool,
+    /// Lower calls to jumps into one shared call routine, shrinking the output
+    #[clap(long, action, default_value_t = false)]
+    compact_calls: bool,
+    /// End functions with a jump into one shared return routine, shrinking the output
+    #[clap(long, action, default_value_t = false)]
+    shared_return: bool,
+    /// Keep functions unreachable from Sys.init in the output
+    #[clap(long, action, default_value_t = false)]
+    keep_unused: bool,
+    /// Inline calls of leaf functions with at most this many instructions at optimization level 1
+    #[clap(long, default_value_t = 8)]
+    inline: usize,
+    /// Optimization level, 1 shares the comparison routines and removes redundant instructions, 2
+    /// also folds conditional jumps on constants, 0 by default
+    #[clap(
+        short = 'O',
+        long,
+        env = "JACK_VM_OPT_LEVEL",
+        value_parser = clap::value_parser!(u8).range(0..=2)
+    )]
+    opt_level: Option<u8>,
+    /// Run an optimization pass the level leaves out, such as `fold-branches`
+    #[clap(long)]
+    enable_pass: Vec<Pass>,
+    /// Skip an optimization pass of the level, such as `peephole`
+    #[clap(long)]
+    disable_pass: Vec<Pass>,
+    /// Print the output of every optimization pass over every function
+    #[clap(long, action, default_value_t = false)]
+    dump_passes: bool,
+    /// Also write the call graph of the program to this file, in the DOT language of Graphviz
+    #[clap(long)]
+    call_graph: Option<PathBuf>,
+    /// Also link this hand-written assembly file after the generated code, besides the `.asm` files
+    /// given as input
+    #[clap(long)]
+    asm: Vec<PathBuf>,
+    /// Link the classes listed in this file first, one name per line, in that order, instead of all
+    /// in name order
+    #[clap(long)]
+    link_order: Option<PathBuf>,
+    /// Also write the assembly of each class on its own into this directory, as `<class>.asm`
+    #[clap(long, env = "JACK_VM_INTERMEDIATE_DIR")]
+    intermediate_dir: Option<PathBuf>,
+    /// Write the assembly of each class on its own into the output directory, `out` by default, as
+    /// `<class>.asm`, instead of linking them into one program
+    #[clap(
+        long,
+        action,
+        default_value_t = false,
+        conflicts_with_all = ["emit", "stats", "timings"]
+    )]
+    no_link: bool,
+    /// Generate every class again instead of reusing the output kept in `.jack-cache/` next to the
+    /// input, leaving the cache as it is
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        env = "JACK_VM_NO_CACHE",
+        value_parser = parse_switch
+    )]
+    no_cache: bool,
+    /// What to write, assembly or the machine code when the output ends with `.hack` by default
+    #[clap(long, value_enum)]
+    emit: Option<Emit>,
+    /// Go through the whole build, generating and assembling the program, without writing any file
+    #[clap(long, action, default_value_t = false)]
+    check: bool,
+    /// Print the size of each class, the static slots it takes, how full the ROM is and how long
+    /// linking took, or write them as JSON to the file given with `--stats=<FILE>`
+    #[clap(long, num_args = 0..=1, require_equals = true)]
+    stats: Option<Option<PathBuf>>,
+    /// Print how long lexing, parsing, validating, optimizing and generating each file took, along
+    /// with checking and linking the program, or write it as JSON to the file given with
+    /// `--timings=<FILE>`
+    #[clap(long, num_args = 0..=1, require_equals = true, conflicts_with = "check")]
+    timings: Option<Option<PathBuf>>,
+}
+
+/// Output of `build`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Emit {
+    /// Linked Hack assembly, to `out.asm` by default
+    Asm,
+    /// Machine code of the linked program, one instruction per line, to `out.hack` by default
+    Hack,
+    /// Tokens of every VM file, one per line, to standard output by default
+    Tokens,
+    /// Classes as parsed, in the JSON IR of the library, to standard output by default
+    AstJson,
+    /// Code size of the linked program as JSON, to standard output by default
+    Stats,
+}
+
+impl BuildOpts {
+    /// Options generating the program as the flags ask.
+    pub(crate) fn options(&self) -> GenerateOptions {
+        let opt_level = match self.opt_level.unwrap_or_default() {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            _ => OptLevel::O2,
+        };
+        let mut pipeline = OptPipeline::new(opt_level);
+        pipeline = self
+            .enable_pass
+            .iter()
+            .copied()
+            .fold(pipeline, OptPipeline::enable);
+        pipeline = self
+            .disable_pass
+            .iter()
+            .copied()
+            .fold(pipeline, OptPipeline::disable);
+        // Dumps are also recorded to log what each pass did, which generates every class again as
+        // the cache leaves them out.
+        pipeline.dump = self.dump_passes || tracing::enabled!(Level::TRACE);
+        GenerateOptions {
+            comments: self.annotate,
+            compact_calls: self.compact_calls,
+            shared_return: self.shared_return,
+            bootstrap: self.boot.bootstrap(),
+            keep_unused: self.keep_unused,
+            inline_threshold: self.inline,
+            opt_level,
+            pipeline: Some(pipeline),
+            ..Default::default()
+        }
+    }
+
+    /// Fills in what the flags leave out from `manifest`.
+    /// Whether the classes are written on their own instead of linked.
+    pub(crate) fn no_link(&self) -> bool {
+        self.no_link
+    }
+
+    pub(crate) fn configure(&mut self, manifest: &Manifest) {
+        self.boot.configure(manifest);
+        self.opt_level = self.opt_level.or(manifest.opt_level);
+        if self.intermediate_dir.is_none() {
+            self.intermediate_dir.clone_from(&manifest.intermediate_dir);
+        }
+    }
+}
+
+/// Translates the classes of the input into one program, assembled into machine code when the
+/// output ends with `.hack`, or writes the stage of the translation `--emit` asks for.
+pub(crate) fn build(session: &Session, global: &GlobalOpts, opt: &BuildOpts) -> Result<(), Error> {
+    let (emit, output) = build_output(global, opt);
+    if emit == Emit::Tokens && !opt.check {
+        return emit_tokens(global, output);
+    }
+    let build_started = Instant::now();
+    if opt.timings.is_some() {
+        session.start_timings();
+    }
+    let options = opt.options();
+    let (program, linker) = prepare(session, global, opt, &output)?;
+    let mut ctx = Context::new(options);
+    if opt.timings.is_some() {
+        ctx = ctx.with_clock(SinceBuild(build_started));
+    }
+    if opt.check {
+        let asm = linker.lower(&mut ctx).context(LinkingSnafu)?;
+        let statics = ctx.options.layout.statics.clone();
+        assemble(&asm, statics).context(AssemblingSnafu)?;
+        return Ok(());
+    }
+    if let Some(path) = &opt.call_graph {
+        fs::write(path, program.call_graph().to_dot()).context(IOSnafu)?;
+    }
+    if let Some(dir) = &opt.intermediate_dir {
+        write_intermediates(program.classes(), &ctx.options, dir)?;
+    }
+    if opt.no_link {
+        write_intermediates(program.classes(), &ctx.options, output.path())?;
+        let classes = program.classes().len();
+        info!("wrote {classes} classes into {}", output.path().display());
+        return Ok(());
+    }
+    match emit {
+        Emit::AstJson => {
+            let classes = program
+                .classes()
+                .iter()
+                .map(ir::Class::try_from)
+                .collect::<Result<_, _>>()
+                .context(ConvertingSnafu)?;
+            return write_output(output, &ir::Module::new(classes).to_json());
+        }
+        Emit::Stats => {
+            let stats = program.stats_with(&ctx.options).context(GeneratingSnafu)?;
+            let json = serde_json::to_string_pretty(&stats).expect("stats are always serializable");
+            return write_output(output, &json);
+        }
+        Emit::Asm | Emit::Hack | Emit::Tokens => {}
+    }
+    let hack = emit == Emit::Hack;
+    let started = Instant::now();
+    let message = format!("Linking {} classes", program.classes().len());
+    let spinner = progress::spinner(session, message);
+    match cache_dir(&global.sources[0]).filter(|_| !opt.no_cache) {
+        Some(cache_dir) => build_cached(
+            session, &linker, &mut ctx, output, hack, &cache_dir, &spinner,
+        )?,
+        None => {
+            let mut writer = create(output)?;
+            if hack {
+                let asm = linker.lower(&mut ctx).context(LinkingSnafu)?;
+                let statics = ctx.options.layout.statics.clone();
+                let binary = assemble(&asm, statics).context(AssemblingSnafu)?;
+                writer
+                    .write_all(render_hack(&binary).as_bytes())
+                    .context(IOSnafu)?;
+            } else {
+                linker
+                    .write_to(&mut ctx, &mut writer)
+                    .context(LinkingSnafu)?;
+            }
+            writer.flush().context(IOSnafu)?;
+            spinner.finish_and_clear();
+        }
+    }
+    let link_time = started.elapsed();
+    info!(elapsed = ?link_time, "linked {} classes", program.classes().len());
+    for dump in ctx.dumps() {
+        let lines = dump.output.lines().count();
+        trace!("{} left {} with {lines} lines", dump.pass, dump.function);
+        if opt.dump_passes {
+            eprint!("// {} after {}\n{}", dump.function, dump.pass, dump.output);
+        }
+    }
+    if let Some(path) = &opt.stats {
+        let report = BuildReport::new(&program, &linker, &ctx.options, link_time)?;
+        match path {
+            Some(path) => {
+                let json =
+                    serde_json::to_string_pretty(&report).expect("reports are always serializable");
+                fs::write(path, json).context(IOSnafu)?;
+            }
+            None => eprint!("{report}"),
+        }
+    }
+    if let Some(path) = &opt.timings {
+        let report = session.take_timings().unwrap_or_default();
+        let report = report.finish(&program, &ctx, link_time, build_started.elapsed());
+        match path {
+            Some(path) => {
+                let json =
+                    serde_json::to_string_pretty(&report).expect("reports are always serializable");
+                fs::write(path, json).context(IOSnafu)?;
+            }
+            None => eprint!("{report}"),
+        }
+    }
+    Ok(())
+}
+
+/// Clock of the generator for `build --timings`, counting from the start of the build.
+#[derive(Debug)]
+struct SinceBuild(Instant);
+
+impl Clock for SinceBuild {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// What `build` emits, along with the file it writes it into, or the directory with `--no-link`.
+pub(crate) fn build_output(global: &GlobalOpts, opt: &BuildOpts) -> (Emit, ClioPath) {
+    let hack_output = global
+        .output
+        .as_ref()
+        .is_some_and(|output| output.extension().is_some_and(|ext| ext == "hack"));
+    let emit = opt
+        .emit
+        .unwrap_or(if hack_output { Emit::Hack } else { Emit::Asm });
+    let output = match &global.output {
+        None if opt.no_link => ClioPath::local("./out".into()),
+        _ => output_path(global, emit),
+    };
+    (emit, output)
+}
+
+/// Files and directories `build` writes, each with whether it is a directory, none with `--check`.
+pub(crate) fn build_outputs(global: &GlobalOpts, opt: &BuildOpts) -> Vec<(ClioPath, bool)> {
+    if opt.check {
+        return vec![];
+    }
+    let reports = opt.stats.iter().chain(&opt.timings).flatten();
+    let files = opt.call_graph.iter().chain(reports);
+    let files = files.map(|path| (ClioPath::local(path.clone()), false));
+    let dirs = opt.intermediate_dir.iter();
+    let dirs = dirs.map(|path| (ClioPath::local(path.clone()), true));
+    let output = (build_output(global, opt).1, opt.no_link);
+    [output].into_iter().chain(files).chain(dirs).collect()
+}
+
+/// File `build` writes what `emit` selects into, standard output for what is not a program.
+pub(crate) fn output_path(global: &GlobalOpts, emit: Emit) -> ClioPath {
+    global.output.clone().unwrap_or_else(|| match emit {
+        Emit::Asm => ClioPath::local("./out.asm".into()),
+        Emit::Hack => ClioPath::local("./out.hack".into()),
+        Emit::Tokens | Emit::AstJson | Emit::Stats => ClioPath::std(),
+    })
+}
+
+/// Reads the sources of `global` into a program, reporting what the checks find in it, and a
+/// linker holding its classes along with the hand-written assembly of the input but `output`.
+pub(crate) fn prepare(
+    session: &Session,
+    global: &GlobalOpts,
+    opt: &BuildOpts,
+    output: &ClioPath,
+) -> Result<(Program, Linker), Error> {
+    let started = Instant::now();
+    let classes = read_sources(session, global)?;
+    info!(elapsed = ?started.elapsed(), "parsed {} classes", classes.len());
+    let mut program = Program::new(classes.clone());
+    let mut linker = Linker::new();
+    if let Some(path) = &opt.link_order {
+        let order = read_link_order(path, &classes)?;
+        program.reorder(&order);
+        linker.set_link_order(order);
+    }
+    let mut asm_paths = find_asm_sources(global, Some(output));
+    asm_paths.extend(opt.asm.iter().cloned());
+    let modules = read_asm_files(&asm_paths)?;
+    let started = Instant::now();
+    report_findings(session, &program, opt.boot.bootstrap().as_ref(), &modules);
+    info!(elapsed = ?started.elapsed(), "checked the program");
+    session.record_timings(|timings| timings.set_check_time(started.elapsed()));
+    session.deny_warnings()?;
+    for class in classes {
+        linker.add_class(class);
+    }
+    for asm in &modules {
+        linker.add_raw_asm(asm);
+    }
+    if let Some(path) = &opt.boot.bootstrap_file {
+        linker.set_bootstrap_asm(&fs::read_to_string(path).context(IOSnafu)?);
+    }
+    Ok((program, linker))
+}
+
+/// Class names listed in the link order file at `path`, one per line, skipping blank lines and `//`
+/// comments. Every name has to be one of `classes`.
+fn read_link_order(path: &Path, classes: &[Class]) -> Result<Vec<String>, Error> {
+    let text = fs::read_to_string(path).context(IOSnafu)?;
+    let names = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("//"));
+    let mut order = vec![];
+    for name in names {
+        if !classes.iter().any(|class| class.name() == name) {
+            return Err(Whatever {
+                message: format!(
+                    "link order {} lists `{name}`, which no source defines",
+                    path.display()
+                ),
+            });
+        }
+        order.push(name.to_owned());
+    }
+    Ok(order)
+}
+
+/// Writes the assembly of each of `classes` into `dir`, generated with `options` but without the
+/// bootstrap code or the shared routines the classes call.
+fn write_intermediates(
+    classes: &[Class],
+    options: &GenerateOptions,
+    dir: &Path,
+) -> Result<(), Error> {
+    fs::create_dir_all(dir).context(IOSnafu)?;
+    let options = GenerateOptions {
+        bootstrap: None,
+        ..options.clone()
+    };
+    for class in classes {
+        let mut ctx = Context::new(options.clone());
+        let asm = class.generate_with(&mut ctx).context(GeneratingSnafu)?;
+        fs::write(dir.join(format!("{}.asm", class.name())), asm).context(IOSnafu)?;
+    }
+    Ok(())
+}
+
+/// Writes the tokens of every VM file of `global` into `output`, one per line after the file and
+/// line the token starts at.
+fn emit_tokens(global: &GlobalOpts, output: ClioPath) -> Result<(), Error> {
+    let mut writer = create(output)?;
+    for file_path in find_sources(global)? {
+        let source_name = if file_path.is_std() {
+            format!("{}.vm", global.stdin_name)
+        } else {
+            let file_name = file_path.file_name().expect("expect file name");
+            file_name.to_string_lossy().into_owned()
+        };
+        let input = read_to_string(file_path.read_all()?).context(IOSnafu)?;
+        let source = SourceFile::new(&source_name, &input);
+        for (kind, span) in tokenize(&input) {
+            let location = source.location(span.start);
+            writeln!(writer, "{location} {kind:?} {:?}", &input[span]).context(IOSnafu)?;
+        }
+    }
+    writer.flush().context(IOSnafu)
+}
+
+/// Directory of the build cache of `input`, next to the VM files, or none for standard input.
+pub(crate) fn cache_dir(input: &ClioPath) -> Option<PathBuf> {
+    if input.is_std() {
+        None
+    } else if input.is_dir() {
+        Some(input.path().join(CACHE_DIR))
+    } else {
+        Some(
+            input
+                .path()
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(CACHE_DIR),
+        )
+    }
+}
+
+/// Links through the outputs saved in `cache_dir`, generating only the classes changed since the
+/// last build, and writes `output` only if what it holds changed. The cache is saved back without
+/// the outputs of classes changed or removed since. `spinner` is finished once the program is
+/// linked.
+fn build_cached(
+    session: &Session,
+    linker: &Linker,
+    ctx: &mut Context,
+    output: ClioPath,
+    hack: bool,
+    cache_dir: &Path,
+    spinner: &ProgressBar,
+) -> Result<(), Error> {
+    let cache_file = cache_dir.join("classes.json");
+    // A cache missing or saved by another version is started over.
+    let mut cache = fs::read_to_string(&cache_file)
+        .ok()
+        .and_then(|json| CompileCache::from_json(&json).ok())
+        .unwrap_or_default();
+    let mut asm = vec![];
+    linker
+        .lower_each_cached(ctx, &mut cache, &mut |lowered| {
+            asm.extend(lowered);
+            Ok(())
+        })
+        .context(LinkingSnafu)?;
+    let linked = if hack {
+        let statics = ctx.options.layout.statics.clone();
+        render_hack(&assemble(&asm, statics).context(AssemblingSnafu)?)
+    } else {
+        render(&asm)
+    };
+    spinner.finish_and_clear();
+    let up_to_date = cache.misses() == 0
+        && !output.is_std()
+        && fs::read_to_string(output.path()).is_ok_and(|written| written == linked);
+    if up_to_date {
+        session.status(format_args!("{} is up to date", output.path().display()));
+    } else {
+        let mut writer = create(output)?;
+        writer.write_all(linked.as_bytes()).context(IOSnafu)?;
+        writer.flush().context(IOSnafu)?;
+    }
+    info!(
+        hits = cache.hits(),
+        misses = cache.misses(),
+        "generated the classes missing from {}",
+        cache_dir.display()
+    );
+    cache.prune();
+    fs::create_dir_all(cache_dir).context(IOSnafu)?;
+    fs::write(cache_file, cache.to_json()).context(IOSnafu)
+}
+
+/// Builds whenever a VM file under the input changes, reporting the errors of each build instead of
+/// stopping at them.
+pub(crate) fn watch(session: &Session, global: &GlobalOpts, opt: &BuildOpts) -> Result<(), Error> {
+    if global.sources.iter().any(ClioPath::is_std) {
+        return Err(EmptySource {
+            message: "standard input cannot be watched".to_owned(),
+        });
+    }
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).context(WatchingSnafu)?;
+    for source in &global.sources {
+        let mode = if global.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(source.path(), mode).context(WatchingSnafu)?;
+    }
+    let report = |built: Result<(), Error>| match built {
+        Ok(()) => session.status("built, watching for changes"),
+        Err(error) => {
+            session.report_error(error);
+            session.status("watching for changes");
+        }
+    };
+    report(build(session, global, opt));
+    for event in &receiver {
+        if !changes_vm_file(event) {
+            continue;
+        }
+        // Saving a file takes several events, which are drained so they build once.
+        while receiver.recv_timeout(Duration::from_millis(50)).is_ok() {}
+        report(build(session, global, opt));
+    }
+    Ok(())
+}
+
+/// Whether `event` changed a VM file, as opposed to only reading one or touching other files.
+fn changes_vm_file(event: notify::Result<Event>) -> bool {
+    event.is_ok_and(|event| {
+        let vm_file = |path: &PathBuf| path.extension().is_some_and(|ext| ext == "vm");
+        !event.kind.is_access() && event.paths.iter().any(vm_file)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Command;
+    use crate::build::{changes_vm_file, read_link_order};
+    use crate::session::Session;
+    use crate::tests::{configured, temp_dir};
+    use notify::event::{AccessKind, ModifyKind};
+    use notify::{Event, EventKind};
+    use std::fs;
+    use vm::generate::Class;
+
+    #[test]
+    fn build_outputs() {
+        let dir = temp_dir("build");
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.main 0\npush constant 7\nreturn\n",
+        )
+        .expect("expect ok");
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n",
+        )
+        .expect("expect ok");
+        let input = dir.to_str().expect("expect utf-8");
+        for output in ["out.asm", "out.hack"] {
+            let output = dir.join(output);
+            let args = [
+                "vm-cli",
+                "-i",
+                input,
+                "-o",
+                output.to_str().expect("expect utf-8"),
+                "build",
+            ];
+            let opts = configured(args);
+            let Command::Build {
+                build,
+                watch: false,
+            } = opts.command
+            else {
+                panic!("expect build");
+            };
+            super::build(&Session::default(), &opts.global, &build).expect("expect ok");
+        }
+        let asm = fs::read_to_string(dir.join("out.asm")).expect("expect ok");
+        assert!(asm.contains("(Main.main)\n"));
+        assert!(asm.contains("(Sys.init)\n"));
+        let hack = fs::read_to_string(dir.join("out.hack")).expect("expect ok");
+        assert!(
+            hack.lines()
+                .all(|line| line.len() == 16 && line.bytes().all(|bit| matches!(bit, b'0' | b'1')))
+        );
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn check_without_writing() {
+        let dir = temp_dir("check");
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\nlabel END\ngoto END\n",
+        )
+        .expect("expect ok");
+        let input = dir.to_str().expect("expect utf-8");
+        let output = dir.join("out.hack");
+        let output = output.to_str().expect("expect utf-8");
+        let build = |session: &Session| {
+            let opts = configured(["vm-cli", "-i", input, "-o", output, "build", "--check"]);
+            let Command::Build { build, .. } = opts.command else {
+                panic!("expect build");
+            };
+            super::build(session, &opts.global, &build)
+        };
+        build(&Session::default()).expect("expect ok");
+        let mut files = fs::read_dir(&dir)
+            .expect("expect ok")
+            .map(|entry| entry.expect("expect ok").file_name());
+        assert!(files.all(|file| file == "Sys.vm"));
+
+        // Errors past parsing, here statics overflowing their segment, fail the check all the same.
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush static 300\nreturn\n",
+        )
+        .expect("expect ok");
+        build(&Session::default()).expect_err("expect err");
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn intermediate_classes() {
+        let dir = temp_dir("intermediate");
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.main 0\npush constant 7\nreturn\n",
+        )
+        .expect("expect ok");
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n",
+        )
+        .expect("expect ok");
+        let input = dir.to_str().expect("expect utf-8");
+        let output = dir.join("out.asm");
+        let classes = dir.join("classes");
+        let args = [
+            "vm-cli",
+            "-i",
+            input,
+            "-o",
+            output.to_str().expect("expect utf-8"),
+            "build",
+            "--intermediate-dir",
+            classes.to_str().expect("expect utf-8"),
+        ];
+        let opts = configured(args);
+        let Command::Build { build, .. } = opts.command else {
+            panic!("expect build");
+        };
+        super::build(&Session::default(), &opts.global, &build).expect("expect ok");
+        let main = fs::read_to_string(classes.join("Main.asm")).expect("expect ok");
+        assert!(main.starts_with("(Main.main)\n"));
+        let sys = fs::read_to_string(classes.join("Sys.asm")).expect("expect ok");
+        assert!(sys.starts_with("(Sys.init)\n"));
+        // The linked output still starts with the bootstrap code.
+        let linked = fs::read_to_string(&output).expect("expect ok");
+        assert!(!linked.starts_with("(Main.main)\n") && linked.contains(&main));
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn read_link_orders() {
+        let dir = temp_dir("link-order");
+        let path = dir.join("order.txt");
+        let classes = vec![Class::new(vec![], "Main"), Class::new(vec![], "Sys")];
+        fs::write(&path, "// Sys first\nSys\n\n  Main  \n").expect("expect ok");
+        let order = read_link_order(&path, &classes).expect("expect ok");
+        assert_eq!(order, vec!["Sys", "Main"]);
+        fs::write(&path, "Sys\nMemory\n").expect("expect ok");
+        let error = read_link_order(&path, &classes).expect_err("expect err");
+        assert!(
+            error
+                .to_string()
+                .ends_with("lists `Memory`, which no source defines")
+        );
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn watch_vm_changes() {
+        let modified = Event::new(EventKind::Modify(ModifyKind::Any));
+        let source = modified.clone().add_path("src/Main.vm".into());
+        assert!(changes_vm_file(Ok(source)));
+        assert!(!changes_vm_file(Ok(modified.add_path("out.asm".into()))));
+        let read = Event::new(EventKind::Access(AccessKind::Any)).add_path("src/Main.vm".into());
+        assert!(!changes_vm_file(Ok(read)));
+        assert!(!changes_vm_file(Err(notify::Error::generic("lost"))));
+
+        let opts = configured(["vm-cli", "-i", "-", "build", "--watch"]);
+        let Command::Build { build, watch: true } = opts.command else {
+            panic!("expect build --watch");
+        };
+        let error =
+            super::watch(&Session::default(), &opts.global, &build).expect_err("expect err");
+        assert_eq!(
+            error.to_string(),
+            "input is empty: standard input cannot be watched"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::GlobalOpts;
+use crate::build::BootOpts;
+use crate::error::Error;
+use crate::input::{find_asm_sources, read_asm_files, read_sources};
+use crate::manifest::WarningLevel;
+use crate::session::Session;
+use vm::diagnostic::{Diagnostic, DiagnosticSink, Lint};
+use vm::generate::{BootstrapOptions, Class};
+use vm::program::{CheckOptions, Program};
+
+/// Parses and checks the classes of the input, reporting the problems found.
+pub(crate) fn check(session: &Session, global: &GlobalOpts, boot: &BootOpts) -> Result<(), Error> {
+    let program = Program::new(read_sources(session, global)?);
+    let modules = read_asm_files(&find_asm_sources(global, None))?;
+    report_findings(session, &program, boot.bootstrap().as_ref(), &modules);
+    session.deny_warnings()
+}
+
+/// Prints the problems the checks of `program` find, entered through `bootstrap` if any. Calls into
+/// the classes of the labels the hand-written assembly `modules` define are taken as defined.
+pub(crate) fn report_findings(
+    session: &Session,
+    program: &Program,
+    bootstrap: Option<&BootstrapOptions>,
+    modules: &[String],
+) {
+    let mut check = CheckOptions {
+        entry: bootstrap.map(|boot| boot.entry.clone()),
+        unused_labels: session.lint_level(Lint::UnusedLabel) != WarningLevel::Allow,
+        ..Default::default()
+    };
+    let labels = modules
+        .iter()
+        .flat_map(|asm| asm.lines())
+        .filter_map(|line| line.trim().strip_prefix('('));
+    let classes = labels.filter_map(|label| Some(label.split_once('.')?.0.to_owned()));
+    check.external.extend(classes);
+    program.check_into(&check, &mut Warner { session, program });
+}
+
+/// Prints the warnings reported to it, locating them in the sources of `program`.
+struct Warner<'a> {
+    session: &'a Session,
+    program: &'a Program,
+}
+
+impl DiagnosticSink for Warner<'_> {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        let file = diagnostic.file.as_deref();
+        let mut sources = self.program.classes().iter().filter_map(Class::source);
+        let source = sources.find(|source| file == Some(source.name()));
+        self.session.warn(&diagnostic, source);
+    }
+}
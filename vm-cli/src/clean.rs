@@ -0,0 +1,44 @@
+use crate::GlobalOpts;
+use crate::build::cache_dir;
+use crate::error::{Error, IOSnafu};
+use crate::session::Session;
+use snafu::ResultExt;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Removes the build caches next to the sources of `global`, its output and `intermediate_dir`,
+/// or only prints them if `dry_run`.
+pub(crate) fn clean(
+    session: &Session,
+    global: &GlobalOpts,
+    intermediate_dir: Option<&Path>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let mut paths = global
+        .sources
+        .iter()
+        .filter_map(cache_dir)
+        .collect::<Vec<_>>();
+    let output = global.output.iter();
+    let output = output.filter(|output| output.is_local() && output.is_file());
+    paths.extend(output.map(|output| output.to_path_buf()));
+    paths.extend(intermediate_dir.map(Path::to_path_buf));
+    let mut seen = BTreeSet::new();
+    for path in paths.into_iter().filter(|path| path.exists()) {
+        if !seen.insert(fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) {
+            continue;
+        }
+        if dry_run {
+            println!("{}", path.display());
+            continue;
+        }
+        if path.is_dir() {
+            fs::remove_dir_all(&path).context(IOSnafu)?;
+        } else {
+            fs::remove_file(&path).context(IOSnafu)?;
+        }
+        session.status(format_args!("removed {}", path.display()));
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use snafu::Snafu;
+use std::io;
+use std::path::PathBuf;
+use vm::diagnostic::Diagnostic;
+
+/// Exit code of test scripts whose output differs from the output they compare it to, and of files
+/// `fmt --check` finds unformatted.
+const EXIT_FAILED: u8 = 1;
+/// Exit code of invalid flags, inputs or manifests, the code clap exits with too.
+const EXIT_USAGE: u8 = 2;
+/// Exit code of sources, assembly or machine code that do not parse.
+const EXIT_PARSE: u8 = 3;
+/// Exit code of programs that parse but cannot be built, or whose warnings are denied.
+const EXIT_SEMANTIC: u8 = 4;
+/// Exit code of failures to read, write or watch files, or to open a window.
+const EXIT_IO: u8 = 5;
+
+#[derive(Snafu, Debug)]
+#[snafu(visibility(pub(crate)))]
+pub(crate) enum Error {
+    #[snafu(display("io error"))]
+    IO { source: io::Error },
+    #[snafu(display("input is empty: {message}"))]
+    EmptySource { message: String },
+    #[snafu(display("error {} when parsing {path}", source.code()))]
+    Parsing {
+        source: vm::parse::Error,
+        path: String,
+    },
+    #[snafu(display("error {} when generating", source.code()))]
+    Generating { source: vm::generate::Error },
+    #[snafu(display("error {} when linking", source.code()))]
+    Linking { source: vm::Error },
+    #[snafu(display("failed to convert the program into the IR"))]
+    Converting { source: vm::ir::Error },
+    #[snafu(display("error {} when assembling", source.code()))]
+    Assembling { source: vm::asm::Error },
+    #[snafu(display("error {} when assembling {path}", source.code()))]
+    AssemblingSource { source: asm::Error, path: String },
+    #[snafu(display("error {} when disassembling", source.code()))]
+    Disassembling { source: vm::asm::Error },
+    #[snafu(display("error {} when running", source.code()))]
+    Running { source: vm::emulate::Error },
+    #[snafu(display("error {} in the test script {}", source.code(), path.display()))]
+    Script {
+        source: vm::script::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("{failed} of the test scripts failed"))]
+    TestsFailed { failed: usize },
+    #[snafu(display("{files} of the files are not formatted"))]
+    Unformatted { files: usize },
+    #[snafu(display("failed to watch the input"))]
+    Watching { source: notify::Error },
+    #[cfg(feature = "window")]
+    #[snafu(display("failed to show the screen"))]
+    Window { source: minifb::Error },
+    #[snafu(display("invalid manifest {}", path.display()))]
+    Manifest {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("{} already exists, --force overwrites it", path.display()))]
+    OutputExists { path: PathBuf },
+    #[snafu(display(
+        "{} is in the input directory {}, which would read it back",
+        path.display(),
+        input.display()
+    ))]
+    OutputInInput { path: PathBuf, input: PathBuf },
+    #[snafu(display(
+        "{} is a directory, which only `build --no-link` writes into",
+        path.display()
+    ))]
+    OutputIsDirectory { path: PathBuf },
+    #[snafu(display(
+        "aborting due to {warnings} denied {}",
+        if *warnings == 1 { "warning" } else { "warnings" }
+    ))]
+    DeniedWarnings { warnings: usize },
+    #[snafu(whatever, display("{message}"))]
+    Whatever { message: String },
+}
+
+impl Error {
+    /// Code the process exits with after the error.
+    pub(crate) fn exit_code(&self) -> u8 {
+        match self {
+            Error::IO { .. } | Error::Watching { .. } => EXIT_IO,
+            #[cfg(feature = "window")]
+            Error::Window { .. } => EXIT_IO,
+            Error::Parsing { .. } | Error::Script { .. } | Error::Disassembling { .. } => {
+                EXIT_PARSE
+            }
+            Error::AssemblingSource { source, .. } => match source {
+                asm::Error::Assembling { .. } => EXIT_SEMANTIC,
+                _ => EXIT_PARSE,
+            },
+            Error::TestsFailed { .. } | Error::Unformatted { .. } => EXIT_FAILED,
+            Error::Generating { .. }
+            | Error::Linking { .. }
+            | Error::Converting { .. }
+            | Error::Assembling { .. }
+            | Error::Running { .. }
+            | Error::DeniedWarnings { .. } => EXIT_SEMANTIC,
+            Error::EmptySource { .. }
+            | Error::Manifest { .. }
+            | Error::OutputExists { .. }
+            | Error::OutputInInput { .. }
+            | Error::OutputIsDirectory { .. }
+            | Error::Whatever { .. } => EXIT_USAGE,
+        }
+    }
+
+    /// The error as a diagnostic, with the code and span of the library error behind it if any.
+    pub(crate) fn diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::Parsing { source, path } => Diagnostic::from(source).with_file(path.clone()),
+            Error::Generating { source } => source.into(),
+            Error::Linking { source } => source.into(),
+            Error::Assembling { source } | Error::Disassembling { source } => source.into(),
+            Error::AssemblingSource { source, path } => {
+                Diagnostic::from(source).with_file(path.clone())
+            }
+            Error::Running { source } => source.into(),
+            Error::Script { source, path } => {
+                Diagnostic::from(source).with_file(path.display().to_string())
+            }
+            _ => {
+                let mut message = self.to_string();
+                let mut source = std::error::Error::source(self);
+                while let Some(error) = source {
+                    message = format!("{message}: {error}");
+                    source = error.source();
+                }
+                Diagnostic::error(message)
+            }
+        }
+    }
+}
+
+impl From<clio::Error> for Error {
+    fn from(value: clio::Error) -> Self {
+        let clio::Error::Io(error) = value;
+        Error::IO { source: error }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::GlobalOpts;
+use crate::error::{Error, IOSnafu, ParsingSnafu};
+use crate::input::find_sources;
+use crate::output::write_output;
+use crate::session::Session;
+use clio::ClioPath;
+use snafu::ResultExt;
+use std::io::read_to_string;
+use vm::format::format;
+use vm::parse::ParseOptions;
+use vm::source::SourceFile;
+
+/// Formats the sources of `global`, see [`format`], into the output, standard output by default,
+/// or checks that they already are formatted if `check`, listing those that are not.
+pub(crate) fn fmt(session: &Session, global: GlobalOpts, check: bool) -> Result<(), Error> {
+    // Sources of project 7 are made of top-level code, outside any function.
+    let options = ParseOptions {
+        top_level_code: true,
+        ..Default::default()
+    };
+    let mut formatted = vec![];
+    let mut unformatted = 0;
+    for file in find_sources(&global)? {
+        let path = file.path().display().to_string();
+        let name = if file.is_std() {
+            format!("{}.vm", global.stdin_name)
+        } else {
+            let name = file.file_name().expect("expect file name");
+            name.to_string_lossy().into_owned()
+        };
+        let source = read_to_string(file.read_all()?).context(IOSnafu)?;
+        session.keep_source(&path, &SourceFile::new(&name, &source));
+        let text = format(&source, &options).context(ParsingSnafu { path: path.clone() })?;
+        if check && text != source {
+            println!("{path}");
+            unformatted += 1;
+        }
+        formatted.push(text);
+    }
+    if check {
+        if unformatted > 0 {
+            return Err(Error::Unformatted { files: unformatted });
+        }
+        return Ok(());
+    }
+    let output = global.output.unwrap_or_else(ClioPath::std);
+    write_output(output, &formatted.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Command;
+    use crate::error::Error;
+    use crate::session::Session;
+    use crate::tests::{configured, temp_dir};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn fmt_canonical() {
+        let dir = temp_dir("fmt");
+        let input = dir.join("Main.vm");
+        let output = dir.join("Main.fmt");
+        fs::write(
+            &input,
+            "// entry\nfunction Main.main 0\n   push constant 7  // seven\n\nreturn\n",
+        )
+        .expect("expect ok");
+        let args = [
+            "vm-cli",
+            "-i",
+            input.to_str().expect("expect utf-8"),
+            "-o",
+            output.to_str().expect("expect utf-8"),
+        ];
+        let opts = configured(args.into_iter().chain(["fmt"]));
+        assert!(matches!(opts.command, Command::Fmt { check: false }));
+        super::fmt(&Session::default(), opts.global, false).expect("expect ok");
+        let formatted = fs::read_to_string(&output).expect("expect ok");
+        assert_eq!(
+            formatted,
+            "// entry\nfunction Main.main 0\n    push constant 7 // seven\n\n    return\n"
+        );
+
+        let check = |path: &Path| {
+            let args = ["vm-cli", "-i", path.to_str().expect("expect utf-8")];
+            let opts = configured(args.into_iter().chain(["fmt", "--check"]));
+            super::fmt(&Session::default(), opts.global, true)
+        };
+        let error = check(&input).expect_err("expect err");
+        assert!(matches!(error, Error::Unformatted { files: 1 }));
+        fs::write(&input, &formatted).expect("expect ok");
+        check(&input).expect("expect ok");
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+}
@@ -0,0 +1,466 @@
+use crate::GlobalOpts;
+use crate::error::Error::{EmptySource, Whatever};
+use crate::error::{Error, GeneratingSnafu, IOSnafu, ParsingSnafu};
+use crate::progress;
+use crate::report::FileTimings;
+use crate::session::Session;
+use clio::{ClioPath, has_extension};
+use rayon::prelude::*;
+use snafu::ResultExt;
+use std::collections::BTreeSet;
+use std::io::read_to_string;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{fs, io};
+use tracing::{debug, info};
+use vm::diagnostic::Diagnostic;
+use vm::generate::Class;
+use vm::parse::{ParseOptions, parse_with};
+use vm::program::Program;
+use vm::source::SourceFile;
+use vm::tokenize::tokenize;
+
+/// Paths `input` names: the file or directory, or `-` for standard input, or every path matching it
+/// if it is a glob pattern.
+pub(crate) fn expand_input(input: &str) -> Result<Vec<ClioPath>, Error> {
+    if !input.contains(['*', '?', '[']) {
+        let path = ClioPath::new(input)?;
+        if !path.is_std() && !path.exists() {
+            return Err(EmptySource {
+                message: format!("{input} does not exist"),
+            });
+        }
+        return Ok(vec![path]);
+    }
+    let paths = glob::glob(input)
+        .map_err(|error| Whatever {
+            message: format!("invalid pattern {input}: {error}"),
+        })?
+        .map(|path| {
+            Ok(ClioPath::local(
+                path.map_err(io::Error::from).context(IOSnafu)?,
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if paths.is_empty() {
+        return Err(EmptySource {
+            message: format!("no file matches {input}"),
+        });
+    }
+    Ok(paths)
+}
+
+/// Parses the classes of every source of `global`, see [`read_classes`], along with the classes of
+/// its library path they call, see [`read_library_classes`].
+pub(crate) fn read_sources(session: &Session, global: &GlobalOpts) -> Result<Vec<Class>, Error> {
+    let mut classes = read_classes(
+        session,
+        find_sources(global)?,
+        &global.stdin_name,
+        &ParseOptions::default(),
+    )?;
+    read_library_classes(session, &mut classes, &global.library_path)?;
+    Ok(classes)
+}
+
+/// Adds to `classes` the classes they call but do not have, parsed from the first `<class>.vm` of
+/// the directories of `library_path`, and in turn the classes those call. Classes found in none of
+/// them are left to the checks, which report the calls into them unless they are external.
+fn read_library_classes(
+    session: &Session,
+    classes: &mut Vec<Class>,
+    library_path: &[PathBuf],
+) -> Result<(), Error> {
+    let mut searched = BTreeSet::new();
+    loop {
+        let program = Program::new(classes.clone());
+        let missing = program.missing_classes().into_iter();
+        let missing = missing.filter(|class| searched.insert(class.to_string()));
+        let find = |class: &str| {
+            let paths = library_path
+                .iter()
+                .map(|dir| dir.join(format!("{class}.vm")));
+            paths.into_iter().find(|path| path.is_file())
+        };
+        let found = missing.filter_map(find).map(ClioPath::local);
+        let found = found.collect::<Vec<_>>();
+        if found.is_empty() {
+            return Ok(());
+        }
+        for file in &found {
+            debug!("found {} on the library path", file.path().display());
+        }
+        classes.extend(read_classes(session, found, "", &ParseOptions::default())?);
+    }
+}
+
+/// Every vm file of the sources of `global`, see [`find_vm_files`].
+pub(crate) fn find_sources(global: &GlobalOpts) -> Result<Vec<ClioPath>, Error> {
+    let mut vm_files = vec![];
+    let mut seen = BTreeSet::new();
+    for source in global
+        .sources
+        .iter()
+        .filter(|source| !has_extension("asm")(source))
+    {
+        for file in find_vm_files(source.clone(), global.recursive)? {
+            // The same file reached through two sources, like a directory and a file in it, is read
+            // once.
+            let path = fs::canonicalize(file.path()).unwrap_or_else(|_| file.to_path_buf());
+            if file.is_std() || seen.insert(path) {
+                debug!("found {}", file.path().display());
+                vm_files.push(file);
+            }
+        }
+    }
+    info!("found {} vm files", vm_files.len());
+    Ok(vm_files)
+}
+
+/// Every vm file at `input_path`, in its subdirectories too if `recursive`, or standard input
+/// itself.
+fn find_vm_files(input_path: ClioPath, recursive: bool) -> Result<Vec<ClioPath>, Error> {
+    let mut vm_files = if input_path.is_std() {
+        vec![input_path]
+    } else if input_path.is_dir() {
+        let vm_files = list_files(&input_path, "vm", recursive)?;
+        if vm_files.is_empty() {
+            let message = if recursive {
+                "directory does not contain any vm file"
+            } else {
+                "directory does not contain any vm file, --recursive also reads its subdirectories"
+            };
+            return Err(EmptySource {
+                message: message.to_owned(),
+            });
+        }
+        vm_files
+    } else if input_path.is_file() {
+        vec![input_path]
+    } else {
+        return Err(EmptySource {
+            message: "invalid input".to_owned(),
+        });
+    };
+    // Directories list their files in an order that differs between platforms, so files are read,
+    // and reported on, in path order.
+    vm_files.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(vm_files)
+}
+
+/// Files with `extension` in the directory `dir`, in its subdirectories too if `recursive`.
+pub(crate) fn list_files(
+    dir: &ClioPath,
+    extension: &str,
+    recursive: bool,
+) -> Result<Vec<ClioPath>, Error> {
+    if recursive {
+        return Ok(dir.clone().files(has_extension(extension))?);
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(dir.path()).context(IOSnafu)? {
+        let path = ClioPath::local(entry.context(IOSnafu)?.path());
+        if path.is_file() && has_extension(extension)(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Hand-written assembly files given as sources of `global`, leaving out `output`. The `.asm` files
+/// of input directories are not read, as they are likely earlier builds.
+pub(crate) fn find_asm_sources(global: &GlobalOpts, output: Option<&ClioPath>) -> Vec<PathBuf> {
+    let output = output
+        .filter(|output| !output.is_std())
+        .and_then(|output| fs::canonicalize(output.path()).ok());
+    let sources = global
+        .sources
+        .iter()
+        .filter(|source| !source.is_std() && has_extension("asm")(source));
+    let asm_files = sources
+        .map(|source| source.to_path_buf())
+        .filter(|file| output.is_none() || fs::canonicalize(file).ok() != output)
+        .collect::<Vec<_>>();
+    for file in &asm_files {
+        debug!("found {}", file.display());
+    }
+    asm_files
+}
+
+/// Contents of the assembly files at `paths`, in that order.
+pub(crate) fn read_asm_files(paths: &[PathBuf]) -> Result<Vec<String>, Error> {
+    paths
+        .iter()
+        .map(|path| fs::read_to_string(path).context(IOSnafu))
+        .collect()
+}
+
+/// Parses every file of `vm_files` with `options` into a class named after the file, or the source
+/// on standard input into a class named `stdin_name`.
+pub(crate) fn read_classes(
+    session: &Session,
+    vm_files: Vec<ClioPath>,
+    stdin_name: &str,
+    options: &ParseOptions,
+) -> Result<Vec<Class>, Error> {
+    let mut sources = vec![];
+    for file_path in vm_files {
+        let (file_name, source_name) = if file_path.is_std() {
+            (stdin_name.into(), format!("{stdin_name}.vm"))
+        } else {
+            let source_name = file_path.file_name().expect("expect file name");
+            let source_name = source_name.to_string_lossy().into_owned();
+            (
+                file_path.file_stem().expect("expect file name").to_owned(),
+                source_name,
+            )
+        };
+        let path = file_path.path().display().to_string();
+        let class_name = file_name.into_string().map_err(|_| Whatever {
+            message: "invalid file name".to_owned(),
+        })?;
+
+        let cached = file_path.read_all()?;
+        let input = read_to_string(cached).context(IOSnafu)?;
+        sources.push((class_name, source_name, input, path));
+    }
+
+    // Files are parsed in parallel, then reported and kept in the order they were found.
+    let bar = progress::files(session, sources.len(), "Parsing");
+    let parsed = sources
+        .into_par_iter()
+        .map(|(class_name, source_name, input, path)| {
+            bar.set_message(source_name.clone());
+            let parsed = parse_class(session, &class_name, &source_name, &input, path, options);
+            bar.inc(1);
+            parsed
+        });
+    let parsed = parsed.collect::<Vec<_>>();
+    bar.finish_and_clear();
+    let mut classes = vec![];
+    let mut failure = None;
+    for parsed in parsed {
+        match parsed {
+            Ok((class, warnings)) => {
+                for warning in &warnings {
+                    session.warn(warning, class.source());
+                }
+                classes.push(class);
+            }
+            // Every failure but the last is printed here, the last one ends the run.
+            Err(error) => {
+                if let Some(earlier) = failure.replace(error) {
+                    session.report_error(earlier);
+                }
+            }
+        }
+    }
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(classes),
+    }
+}
+
+/// Parses `input`, the source of the file `source_name` at `path`, with `options` into a class
+/// named `class_name`, along with the warnings it raises. The source is kept in `session` to quote
+/// in errors.
+fn parse_class(
+    session: &Session,
+    class_name: &str,
+    source_name: &str,
+    input: &str,
+    path: String,
+    options: &ParseOptions,
+) -> Result<(Class, Vec<Diagnostic>), Error> {
+    let source = SourceFile::new(source_name, input);
+    session.keep_source(&path, &source);
+    // The parser lexes as it goes, so lexing is timed by lexing the source once more, and taken out
+    // of the time parsing takes.
+    let started = Instant::now();
+    if session.timed() {
+        tokenize(input);
+    }
+    let lex_time = started.elapsed();
+    let started = Instant::now();
+    let parsed = parse_with(input, options).context(ParsingSnafu { path })?;
+    let parse_time = started.elapsed();
+    let started = Instant::now();
+    let class = Class::new(parsed.functions, class_name).with_source(source);
+    let warnings = parsed.warnings.into_iter();
+    let warnings = warnings.chain(class.check_name().context(GeneratingSnafu)?);
+    let warnings = warnings.map(|warning| Diagnostic::from(warning).with_file(source_name));
+    let validate_time = started.elapsed();
+    session.record_timings(|timings| {
+        let file = FileTimings::new(source_name, class_name, lex_time, parse_time, validate_time);
+        timings.add_file(file);
+    });
+    Ok((class, warnings.collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::input::{expand_input, find_vm_files, read_classes, read_sources};
+    use crate::session::Session;
+    use crate::tests::{configured, temp_dir};
+    use clio::ClioPath;
+    use std::fs;
+    use vm::parse::ParseOptions;
+    use vm::program::Program;
+
+    #[test]
+    fn read_classes_in_parallel() {
+        let dir = temp_dir("parallel");
+        for i in 0..32 {
+            let source = format!("function Class{i}.get 0\npush constant {i}\nreturn\n");
+            fs::write(dir.join(format!("Class{i}.vm")), source).expect("expect ok");
+        }
+        let found = find_vm_files(ClioPath::local(dir.clone()), false).expect("expect ok");
+        let session = Session::default();
+        let classes = read_classes(&session, found.clone(), "Main", &ParseOptions::default())
+            .expect("expect ok");
+        // Classes are kept in the order their files were found, however they were parsed.
+        let names = classes.iter().map(|class| class.name().to_owned());
+        let stems = found
+            .iter()
+            .map(|file| file.file_stem().expect("expect stem").to_string_lossy());
+        assert!(names.eq(stems));
+
+        fs::write(dir.join("Class7.vm"), "push nowhere 1\n").expect("expect ok");
+        let error = read_classes(&session, found, "Main", &ParseOptions::default())
+            .expect_err("expect err");
+        assert!(matches!(error, Error::Parsing { path, .. } if path.contains("Class7.vm")));
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn repeated_inputs_and_globs() {
+        let dir = temp_dir("globs");
+        fs::create_dir_all(dir.join("src/os")).expect("expect ok");
+        fs::write(dir.join("src/Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+        fs::write(dir.join("src/os/Math.vm"), "function Math.one 0\nreturn\n").expect("expect ok");
+        fs::write(
+            dir.join("src/os/Memory.vm"),
+            "function Memory.get 0\nreturn\n",
+        )
+        .expect("expect ok");
+        let path = |path: &str| dir.join(path).to_str().expect("expect utf-8").to_owned();
+
+        assert_eq!(expand_input("-").expect("expect ok"), vec![ClioPath::std()]);
+        let main = expand_input(&path("src/Main.vm")).expect("expect ok");
+        assert_eq!(main, vec![ClioPath::local(dir.join("src/Main.vm"))]);
+        let mut matched = expand_input(&path("src/**/M*.vm")).expect("expect ok");
+        matched.sort_by(|a, b| a.path().cmp(b.path()));
+        let expected = ["src/Main.vm", "src/os/Math.vm", "src/os/Memory.vm"];
+        assert_eq!(
+            matched,
+            expected.map(|file| ClioPath::local(dir.join(file)))
+        );
+        let error = expand_input(&path("src/Missing.vm")).expect_err("expect err");
+        assert!(error.to_string().ends_with("src/Missing.vm does not exist"));
+        let error = expand_input(&path("src/*.jack")).expect_err("expect err");
+        assert!(error.to_string().contains("no file matches"));
+        expand_input(&path("src/[.vm")).expect_err("expect err");
+
+        // A file reached through a directory and on its own is read once.
+        let args = [
+            "vm-cli",
+            "-i",
+            &path("src/os"),
+            "-i",
+            &path("src/os/Math.vm"),
+            "-i",
+            &path("src/*.vm"),
+        ];
+        let opts = configured(args.into_iter().chain(["check"]));
+        assert_eq!(opts.global.sources.len(), 3);
+        let classes = read_sources(&Session::default(), &opts.global).expect("expect ok");
+        let mut names = classes.iter().map(|class| class.name()).collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["Main", "Math", "Memory"]);
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn recursive_directories() {
+        let dir = temp_dir("recursive");
+        fs::create_dir_all(dir.join("os")).expect("expect ok");
+        fs::create_dir_all(dir.join("empty/game")).expect("expect ok");
+        fs::write(dir.join("Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+        fs::write(dir.join("os/Math.vm"), "function Math.one 0\nreturn\n").expect("expect ok");
+        fs::write(
+            dir.join("empty/game/Game.vm"),
+            "function Game.run 0\nreturn\n",
+        )
+        .expect("expect ok");
+        let input = ClioPath::local(dir.clone());
+        let top = find_vm_files(input.clone(), false).expect("expect ok");
+        assert_eq!(top, vec![ClioPath::local(dir.join("Main.vm"))]);
+        // Files are found in path order, whatever order the directory lists them in.
+        let all = find_vm_files(input, true).expect("expect ok");
+        let expected = ["Main.vm", "empty/game/Game.vm", "os/Math.vm"];
+        assert_eq!(all, expected.map(|file| ClioPath::local(dir.join(file))));
+
+        let empty = ClioPath::local(dir.join("empty"));
+        let error = find_vm_files(empty.clone(), false).expect_err("expect err");
+        assert!(
+            error
+                .to_string()
+                .ends_with("--recursive also reads its subdirectories")
+        );
+        assert_eq!(find_vm_files(empty, true).expect("expect ok").len(), 1);
+
+        let input = dir.to_str().expect("expect utf-8");
+        let opts = configured(["vm-cli", "-i", input, "--recursive", "check"]);
+        assert!(opts.global.recursive);
+        assert_eq!(
+            read_sources(&Session::default(), &opts.global)
+                .expect("expect ok")
+                .len(),
+            3
+        );
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn library_path() {
+        let dir = temp_dir("library");
+        for sub in ["src", "os", "lib"] {
+            fs::create_dir(dir.join(sub)).expect("expect ok");
+        }
+        let files = [
+            (
+                "src/Main.vm",
+                "function Main.main 0\ncall Math.abs 1\ncall Output.print 0\nreturn\n",
+            ),
+            (
+                "os/Math.vm",
+                "function Math.abs 0\ncall Memory.alloc 1\nreturn\n",
+            ),
+            ("os/Memory.vm", "function Memory.alloc 0\nreturn\n"),
+            ("os/Screen.vm", "function Screen.clear 0\nreturn\n"),
+            ("lib/Math.vm", "function Math.abs 0\nreturn\n"),
+        ];
+        for (path, source) in files {
+            fs::write(dir.join(path), source).expect("expect ok");
+        }
+        let path = |path: &str| dir.join(path).to_str().expect("expect utf-8").to_owned();
+        let args = [
+            "vm-cli",
+            "-i",
+            &path("src"),
+            "-L",
+            &path("os"),
+            "-L",
+            &path("lib"),
+        ];
+        let opts = configured(args.into_iter().chain(["check"]));
+        let classes = read_sources(&Session::default(), &opts.global).expect("expect ok");
+        // Only the classes called are read, along the calls of the classes read in turn, from the
+        // first directory that has them: `Memory` is only called by the `Math` of `os`.
+        let names = classes.iter().map(|class| class.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["Main", "Math", "Memory"]);
+        assert_eq!(Program::new(classes).missing_classes().len(), 1);
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+}
@@ -1,136 +1,559 @@
-use crate::Error::{EmptySource, Whatever};
-use clap::Parser;
-use clio::{has_extension, ClioPath};
-use snafu::{ResultExt, Snafu};
-use std::env::temp_dir;
-use std::fs::File;
-use std::io::{copy, read_to_string, BufReader, BufWriter, Write};
-use std::path::Path;
-use std::{fs, io};
-use vm::generate::{bootstrap, Class, Generate};
-use vm::parse::parse;
-
-#[derive(Snafu, Debug)]
-enum Error {
-    #[snafu(display("io error"))]
-    IO { source: io::Error },
-    #[snafu(display("input is empty: {message}"))]
-    EmptySource { message: String },
-    #[snafu(display("error when parsing {path}"))]
-    Parsing { source: vm::parse::Error, path: String },
-    #[snafu(display("error when generating"))]
-    Generating { source: vm::generate::Error },
-    #[snafu(whatever)]
-    Whatever {
-        message: String
-    }
-}
+mod assemble;
+mod build;
+mod check;
+mod clean;
+mod error;
+mod fmt;
+mod input;
+mod manifest;
+mod output;
+mod progress;
+mod report;
+mod run;
+#[cfg(feature = "window")]
+mod screen;
+mod script;
+mod session;
 
-impl From<clio::Error> for Error {
-    fn from(value: clio::Error) -> Self {
-        let clio::Error::Io(error) = value;
-        Error::IO { source: error }
-    }
-}
+use crate::assemble::hack_output;
+use crate::build::{BootOpts, BuildOpts};
+use crate::error::Error::Whatever;
+use crate::error::{Error, IOSnafu, ManifestSnafu};
+use crate::input::expand_input;
+use crate::manifest::{MANIFEST, Manifest};
+use crate::output::{check_output, create};
+use crate::run::RunOpts;
+use crate::session::{ColorChoice, MessageFormat, Session, parse_lint, parse_switch};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use clio::ClioPath;
+use snafu::ResultExt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::{env, fs};
+use tracing::{Level, info};
+use vm::diagnostic::Lint;
+use vm::explain::explain;
 
 #[derive(Parser)]
 struct Opts {
-    #[clap(long, short, value_parser = clap::value_parser!(ClioPath).exists(), default_value=".")]
-    input: ClioPath,
-    #[clap(long, short, value_parser = clap::value_parser!(ClioPath).is_file(), default_value="./out.asm")]
-    output: ClioPath,
-    #[clap(long, action, default_value_t = false)]
-    no_boot: bool,
+    #[clap(flatten)]
+    global: GlobalOpts,
+    #[clap(subcommand)]
+    command: Command,
 }
 
-#[snafu::report]
-fn main() -> Result<(), Error> {
-    let opt = Opts::parse();
+// Flags shared by every subcommand.
+#[derive(Args)]
+struct GlobalOpts {
+    /// File, directory or glob pattern like `src/**/*.vm` to read, or `-` for a single source on
+    /// standard input, repeated to link several into one program; the sources of the manifest or
+    /// else the current directory by default
+    #[clap(long, short, global = true)]
+    input: Vec<String>,
+    /// File to write, or `-` for standard output, each subcommand picking its own default, or the
+    /// directory to write into with `build --no-link`
+    #[clap(long, short, global = true)]
+    output: Option<ClioPath>,
+    /// Overwrite the outputs if they already exist
+    #[clap(long, short, global = true, action, default_value_t = false)]
+    force: bool,
+    /// Class name of the VM source read from standard input
+    #[clap(long, global = true, default_value = "Main")]
+    stdin_name: String,
+    /// Also read the VM files in the subdirectories of input directories
+    #[clap(long, short, global = true, action, default_value_t = false)]
+    recursive: bool,
+    /// Directory to look for the classes the sources call but do not have in, as `<class>.vm`,
+    /// reading only the classes called, repeated to search several in order
+    #[clap(short = 'L', long, global = true, value_name = "DIR")]
+    library_path: Vec<PathBuf>,
+    /// Project manifest to take the build configuration from, `jack.toml` in the current directory
+    /// if there is one
+    #[clap(long, global = true, env = "JACK_VM_MANIFEST")]
+    manifest: Option<PathBuf>,
+    /// Print warnings and errors for people, or as one JSON object per line for tools
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = MessageFormat::Human,
+        env = "JACK_VM_MESSAGE_FORMAT"
+    )]
+    message_format: MessageFormat,
+    /// Color warnings and errors, by default when standard error is a terminal and `NO_COLOR` is
+    /// not set
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        env = "JACK_VM_COLOR"
+    )]
+    color: ColorChoice,
+    /// Log what the command does to standard error: its steps and how long they took with `-v`,
+    /// each file found too with `-vv`, and what each optimization pass did with `-vvv`
+    #[clap(long, short, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Print nothing but errors, leaving out warnings, progress and progress bars
+    #[clap(
+        long,
+        short,
+        global = true,
+        action,
+        default_value_t = false,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+    /// Warn about a lint, like `-Wunused-label` for the labels no jump targets, which are left out
+    /// by default
+    #[clap(
+        short = 'W',
+        long = "warn",
+        global = true,
+        value_name = "LINT",
+        value_parser = parse_lint
+    )]
+    warn: Vec<Lint>,
+    /// Leave out the warnings of a lint, like `-Aundefined-call`
+    #[clap(
+        short = 'A',
+        long = "allow",
+        global = true,
+        value_name = "LINT",
+        value_parser = parse_lint
+    )]
+    allow: Vec<Lint>,
+    /// Fail when a lint warns, like `-Dunreachable-code`, overriding `--warn` and `--allow`
+    #[clap(
+        short = 'D',
+        long = "deny",
+        global = true,
+        value_name = "LINT",
+        value_parser = parse_lint
+    )]
+    deny: Vec<Lint>,
+    /// Fail when any warning is printed, like `warnings = "deny"` in the manifest, which
+    /// `--deny-warnings=false` overrides
+    #[clap(
+        long,
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        env = "JACK_VM_DENY_WARNINGS",
+        value_parser = parse_switch
+    )]
+    deny_warnings: Option<bool>,
+    /// Files and directories to read, resolved from the input and the manifest.
+    #[clap(skip)]
+    sources: Vec<ClioPath>,
+}
 
+impl GlobalOpts {
+    /// Reads the manifest, if any.
+    fn manifest(&self) -> Result<Option<Manifest>, Error> {
+        let path = match &self.manifest {
+            Some(path) => path.clone(),
+            None if Path::new(MANIFEST).is_file() => PathBuf::from(MANIFEST),
+            None => return Ok(None),
+        };
+        let text = fs::read_to_string(&path).context(IOSnafu)?;
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let manifest = Manifest::parse(&text, dir.unwrap_or(Path::new(".")));
+        let manifest = manifest.context(ManifestSnafu { path: path.clone() })?;
+        info!("read the manifest {}", path.display());
+        if manifest.opt_level.is_some_and(|level| level > 2) {
+            return Err(Whatever {
+                message: "the opt-level of the manifest has to be 0, 1 or 2".to_owned(),
+            });
+        }
+        Ok(Some(manifest))
+    }
 
-    let temp = temp_dir().canonicalize().context(IOSnafu)?;
-    let temp = temp.join("jack-vm");
-    if temp.exists() { 
-        fs::remove_dir_all(&temp).context(IOSnafu)?;
+    /// Resolves the sources to read, the inputs if given or the sources of `manifest`, followed by
+    /// its libraries. Glob patterns are expanded, and sources found more than once are read once.
+    fn configure(&mut self, manifest: Option<&Manifest>) -> Result<(), Error> {
+        let mut sources = vec![];
+        if !self.input.is_empty() {
+            for input in &self.input {
+                sources.extend(expand_input(input)?);
+            }
+        } else if let Some(manifest) = manifest {
+            for source in &manifest.sources {
+                sources.extend(expand_input(&source.to_string_lossy())?);
+            }
+        } else {
+            sources.push(ClioPath::local(".".into()));
+        }
+        if let Some(manifest) = manifest {
+            let local = |path: &PathBuf| ClioPath::local(path.clone());
+            sources.extend(manifest.libraries.iter().map(local));
+        }
+        for source in sources {
+            if !self.sources.contains(&source) {
+                self.sources.push(source);
+            }
+        }
+        Ok(())
     }
-    fs::create_dir(&temp).context(IOSnafu)?;
-    compile(opt.input, temp.as_path())?;
-    link(temp.as_path(), opt.output.path(), !opt.no_boot)
 }
 
-fn compile(input_path: ClioPath, out_path: &Path) -> Result<(), Error> {
-    if input_path.is_dir() {
-        let vm_files = input_path
-            .files(has_extension("vm"))?;
-        if vm_files.is_empty() {
-            return Err(EmptySource {
-                message: "directory does not contain any vm file".to_owned(),
-            });
+#[derive(Subcommand)]
+enum Command {
+    /// Translate VM files into one assembly program, `out.asm` by default, assembled into machine
+    /// code when the output ends with `.hack`, or emit another stage of the translation
+    Build {
+        #[clap(flatten)]
+        build: BuildOpts,
+        /// Build again whenever a VM file of the input changes, until interrupted
+        #[clap(long, action, default_value_t = false)]
+        watch: bool,
+    },
+    /// Parse and check VM files, reporting warnings without writing anything
+    Check(BootOpts),
+    /// Print VM files in canonical form, to standard output unless an output is given
+    Fmt {
+        /// Print the files that are not in canonical form instead, failing if there are any
+        #[clap(long, action, default_value_t = false)]
+        check: bool,
+    },
+    /// Build a program and run it in the emulator of the Hack computer, until it halts
+    Run {
+        #[clap(flatten)]
+        build: BuildOpts,
+        #[clap(flatten)]
+        run: RunOpts,
+    },
+    /// Run the test scripts of the CPU emulator of nand2tetris, the `.tst` files next to the
+    /// sources, against the program built from the sources in their directory, comparing their
+    /// output with their `.cmp` file
+    Test(BuildOpts),
+    /// Assemble a Hack assembly file into machine code, next to the input by default, reporting
+    /// every line that does not assemble
+    Asm,
+    /// Turn Hack machine code, in the text-based `.hack` format or as raw big-endian words, back
+    /// into assembly, labelling jump targets, to standard output unless an output is given
+    Disasm,
+    /// Remove the build cache next to the sources, along with the output and the intermediate
+    /// directory of the manifest
+    Clean {
+        /// Print what would be removed without removing anything
+        #[clap(long, action, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Describe an error code at length, with an example failing with it and how to fix it
+    Explain {
+        /// Code of the error, like `VM0102`
+        code: String,
+    },
+    /// Print the completion script of a shell, to standard output unless an output is given
+    Completions { shell: Shell },
+}
+
+impl Command {
+    /// Files and directories the subcommand writes in the end, each with whether it is a directory.
+    fn outputs(&self, global: &GlobalOpts) -> Vec<(ClioPath, bool)> {
+        match self {
+            Command::Build { build, .. } => build::build_outputs(global, build),
+            Command::Run { run, .. } => run.outputs(),
+            Command::Fmt { check: false } | Command::Disasm | Command::Completions { .. } => global
+                .output
+                .iter()
+                .map(|output| (output.clone(), false))
+                .collect(),
+            Command::Asm => hack_output(global)
+                .map(|output| (output, false))
+                .into_iter()
+                .collect(),
+            _ => vec![],
         }
-        for file_path in vm_files {
-            let file_name = file_path.file_stem().expect("expect file name").to_owned();
-            let path = file_path.to_string();
-
-            let cached = file_path.read_all()?;
-            let input = read_to_string(cached).context(IOSnafu)?;
-            let parsed_fn = parse(&input).context(ParsingSnafu { path })?;
-            let class = Class::new(parsed_fn, file_name.to_str().ok_or(Whatever { message: "invalid file name".to_owned() })?);
-            let generated = class.generate().context(GeneratingSnafu)?;
-
-            let out_file_path = out_path.join(file_name).with_extension("asm");
-            let mut out_file = File::create(out_file_path).context(IOSnafu)?;
-            out_file.write(generated.as_bytes()).context(IOSnafu)?;
+    }
+}
+
+impl Opts {
+    /// Fills in what the flags leave out from `manifest`, see [`GlobalOpts::configure`].
+    fn configure(&mut self, manifest: Option<&Manifest>) -> Result<(), Error> {
+        self.global.configure(manifest)?;
+        // Unlike the other variables, `JACK_VM_OUTPUT` is only read by `build` and `run`, as the
+        // other subcommands write elsewhere by default.
+        if let Command::Build { .. } | Command::Run { .. } = self.command
+            && self.global.output.is_none()
+        {
+            let output = env::var_os("JACK_VM_OUTPUT").filter(|output| !output.is_empty());
+            self.global.output = output.map(ClioPath::new).transpose()?;
+        }
+        let Some(manifest) = manifest else {
+            return Ok(());
+        };
+        match &mut self.command {
+            Command::Build { build, .. } | Command::Run { build, .. } | Command::Test(build) => {
+                build.configure(manifest);
+                // The output of the manifest is a file, while unlinked classes are written into a
+                // directory.
+                if self.global.output.is_none() && !build.no_link() {
+                    self.global.output = manifest.output.clone().map(ClioPath::local);
+                }
+            }
+            Command::Check(boot) => boot.configure(manifest),
+            Command::Clean { .. } if self.global.output.is_none() => {
+                self.global.output = manifest.output.clone().map(ClioPath::local);
+            }
+            _ => {}
         }
-        return Ok(());
+        Ok(())
     }
-    if input_path.is_file() {
-        let file_name = input_path.file_name().expect("expect file name").to_owned();
-        let path = input_path.to_string();
-
-        let cached = input_path.read_all()?;
-        let input = read_to_string(cached).context(IOSnafu)?;
-        let parsed_fn = parse(&input).context(ParsingSnafu { path })?;
-        let class = Class::new(parsed_fn, file_name.to_str().ok_or(Whatever { message: "invalid file name".to_owned() })?);
-        let generated = class.generate().context(GeneratingSnafu)?;
-
-        let out_file_path = out_path.join(file_name).with_extension("asm");
-        let mut out_file = File::create(out_file_path).context(IOSnafu)?;
-        out_file.write(generated.as_bytes()).context(IOSnafu)?;
+}
+
+fn main() -> ExitCode {
+    let opt = Opts::parse();
+    let mut session = Session::new(&opt.global);
+    init_logging(&opt.global, session.color());
+    match run(&mut session, opt) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            let code = error.exit_code();
+            session.report_error(error);
+            ExitCode::from(code)
+        }
     }
-    Err(EmptySource {
-        message: "invalid input".to_owned(),
-    })
 }
 
-fn link(path: &Path, out_path: &Path, boot: bool) -> Result<(), Error> {
-    let read_dir = path.read_dir().context(IOSnafu)?;
-    let mut asm_files = vec![];
-    for entry in read_dir {
-        let entry = entry.context(IOSnafu)?.path();
-        if entry.is_dir() {
-            continue
+/// Logs to standard error at the level the verbosity of `global` asks for, colored if `color`.
+fn init_logging(global: &GlobalOpts, color: bool) {
+    let level = match global.verbose {
+        0 if global.quiet => Level::ERROR,
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_ansi(color)
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+fn run(session: &mut Session, mut opt: Opts) -> Result<(), Error> {
+    // Completions and explanations are printed before reading the manifest, as they do not depend
+    // on it.
+    match &opt.command {
+        Command::Completions { shell } => {
+            for (output, dir) in opt.command.outputs(&opt.global) {
+                check_output(&opt.global, &output, dir)?;
+            }
+            return completions(*shell, opt.global.output);
         }
-        let Some(ext) = entry.extension() else {
-            continue
-        };
-        if ext != "asm" {
-            continue
+        Command::Explain { code } => return explain_code(code),
+        _ => {}
+    }
+    let manifest = opt.global.manifest()?;
+    opt.configure(manifest.as_ref())?;
+    if let Some(manifest) = &manifest {
+        session.configure(&opt.global, manifest);
+    }
+    // The outputs are checked once resolved, whether given by the flags or the manifest, or left to
+    // the default of the subcommand.
+    for (output, dir) in opt.command.outputs(&opt.global) {
+        check_output(&opt.global, &output, dir)?;
+    }
+    let session = &*session;
+    match opt.command {
+        Command::Build {
+            build,
+            watch: false,
+        } => build::build(session, &opt.global, &build),
+        Command::Build { build, watch: true } => build::watch(session, &opt.global, &build),
+        Command::Check(boot) => check::check(session, &opt.global, &boot),
+        Command::Fmt { check } => fmt::fmt(session, opt.global, check),
+        Command::Run { build, run } => run::run_program(session, &opt.global, &build, &run),
+        Command::Test(build) => script::test(session, &opt.global, &build),
+        Command::Asm => assemble::assemble_file(session, opt.global),
+        Command::Disasm => assemble::disassemble_file(opt.global),
+        Command::Clean { dry_run } => {
+            let manifest = manifest.as_ref();
+            let intermediate_dir =
+                manifest.and_then(|manifest| manifest.intermediate_dir.as_deref());
+            clean::clean(session, &opt.global, intermediate_dir, dry_run)
+        }
+        Command::Explain { code } => explain_code(&code),
+        Command::Completions { shell } => completions(shell, opt.global.output),
+    }
+}
+
+/// Prints the explanation of the error `code`, see [`explain`].
+fn explain_code(code: &str) -> Result<(), Error> {
+    match explain(code) {
+        Some(explanation) => {
+            print!("{explanation}");
+            Ok(())
         }
-        asm_files.push(entry)
+        None => Err(Whatever {
+            message: format!("unknown error code {code}, expected one like VM0102"),
+        }),
     }
-    if asm_files.is_empty() {
-        return Err(EmptySource { message: "directory does not contain any asm file".to_owned() })
+}
+
+/// Writes the completion script of `shell` into `output`, standard output by default.
+fn completions(shell: Shell, output: Option<ClioPath>) -> Result<(), Error> {
+    // The script is generated in memory first, as writing it fails with a panic.
+    let mut script = vec![];
+    let bin_name = env!("CARGO_BIN_NAME");
+    clap_complete::generate(shell, &mut Opts::command(), bin_name, &mut script);
+    let mut writer = create(output.unwrap_or_else(ClioPath::std))?;
+    writer.write_all(&script).context(IOSnafu)?;
+    writer.flush().context(IOSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::{Command, Opts, completions};
+    use clap::Parser;
+    use clap_complete::Shell;
+    use clio::ClioPath;
+    use std::path::{Path, PathBuf};
+    use std::{env, fs, process};
+    use vm::generate::OptLevel;
+
+    /// Empty directory for the files of the test `name`.
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("vm-cli-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("expect ok");
+        dir
     }
-    
-    let out_file = File::create(out_path).context(IOSnafu)?;
-    let mut writer = BufWriter::new(out_file);
-    if boot {
-        writer.write(bootstrap().as_bytes()).context(IOSnafu)?;
+
+    /// Options of the command line `args`, configured without a manifest.
+    pub(crate) fn configured<'a>(args: impl IntoIterator<Item = &'a str>) -> Opts {
+        let mut opts = Opts::try_parse_from(args).expect("expect ok");
+        opts.configure(None).expect("expect ok");
+        opts
     }
-    for file_path in asm_files {
-        let file = File::open(file_path).context(IOSnafu)?;
-        let mut reader = BufReader::new(file);
-        copy(&mut reader, &mut writer).context(IOSnafu)?;
+
+    #[test]
+    fn subcommands() {
+        let opts = Opts::try_parse_from(["vm-cli", "build", "-O", "2", "-o", "out.hack"])
+            .expect("expect ok");
+        assert!(matches!(opts.command, Command::Build { watch: false, .. }));
+        assert_eq!(
+            opts.global.output.expect("expect output").path(),
+            Path::new("out.hack")
+        );
+        let opts =
+            Opts::try_parse_from(["vm-cli", "check", "--entry", "Main.main"]).expect("expect ok");
+        let Command::Check(boot) = opts.command else {
+            panic!("expect check");
+        };
+        assert_eq!(
+            boot.bootstrap().expect("expect bootstrap").entry,
+            "Main.main"
+        );
+        let opts = Opts::try_parse_from(["vm-cli", "check", "--no-boot"]).expect("expect ok");
+        assert!(matches!(opts.command, Command::Check(boot) if boot.bootstrap().is_none()));
+        for command in ["fmt", "run", "asm", "disasm"] {
+            Opts::try_parse_from(["vm-cli", command]).expect("expect ok");
+        }
+        // Build flags belong to the subcommands building programs.
+        assert!(Opts::try_parse_from(["vm-cli", "fmt", "-O", "1"]).is_err());
+        assert!(Opts::try_parse_from(["vm-cli"]).is_err());
+    }
+
+    #[test]
+    fn complete_shells() {
+        let dir = temp_dir("completions");
+        let script = dir.join("vm-cli.bash");
+        completions(Shell::Bash, Some(ClioPath::local(script.clone()))).expect("expect ok");
+        let script = fs::read_to_string(&script).expect("expect ok");
+        assert!(script.contains("_vm__cli()") && script.contains("--message-format"));
+        let opts = Opts::try_parse_from(["vm-cli", "completions", "zsh"]).expect("expect ok");
+        assert!(matches!(
+            opts.command,
+            Command::Completions { shell: Shell::Zsh }
+        ));
+        assert!(Opts::try_parse_from(["vm-cli", "completions", "cmd"]).is_err());
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+
+    #[test]
+    fn manifest_layering() {
+        let dir = temp_dir("manifest");
+        fs::create_dir_all(dir.join("src")).expect("expect ok");
+        fs::create_dir_all(dir.join("lib")).expect("expect ok");
+        let manifest = dir.join("jack.toml");
+        let manifest_arg = manifest.to_str().expect("expect utf-8");
+        fs::write(
+            &manifest,
+            "sources = [\"src\"]\nlibraries = [\"lib\"]\noutput = \"game.hack\"\nopt-level = 2\n\
+             [bootstrap]\nentry = \"Main.main\"\ncall-frame = false\n",
+        )
+        .expect("expect ok");
+        let parse = |args: &[&str]| {
+            let args = ["vm-cli", "--manifest", manifest_arg]
+                .into_iter()
+                .chain(args.iter().copied());
+            let mut opts = Opts::try_parse_from(args).expect("expect ok");
+            let manifest = opts.global.manifest().expect("expect ok");
+            opts.configure(manifest.as_ref()).expect("expect ok");
+            opts
+        };
+
+        // The manifest fills in what the flags leave out.
+        let opts = parse(&["build"]);
+        assert_eq!(
+            opts.global.sources,
+            [dir.join("src"), dir.join("lib")].map(ClioPath::local)
+        );
+        assert_eq!(
+            opts.global.output,
+            Some(ClioPath::local(dir.join("game.hack")))
+        );
+        let Command::Build { build, .. } = opts.command else {
+            panic!("expect build");
+        };
+        let options = build.options();
+        assert_eq!(options.opt_level, OptLevel::O2);
+        let bootstrap = options.bootstrap.expect("expect bootstrap");
+        assert_eq!(
+            (bootstrap.entry.as_str(), bootstrap.call_frame),
+            ("Main.main", false)
+        );
+
+        // Flags take precedence over it, and the libraries still follow the input.
+        let input = dir.to_str().expect("expect utf-8");
+        let opts = parse(&[
+            "-i", input, "-o", "out.asm", "build", "-O", "0", "--entry", "Sys.init",
+        ]);
+        assert_eq!(
+            opts.global.sources,
+            [dir.clone(), dir.join("lib")].map(ClioPath::local)
+        );
+        assert_eq!(opts.global.output, Some(ClioPath::local("out.asm".into())));
+        let Command::Build { build, .. } = opts.command else {
+            panic!("expect build");
+        };
+        let options = build.options();
+        assert_eq!(options.opt_level, OptLevel::O0);
+        assert_eq!(
+            options.bootstrap.expect("expect bootstrap").entry,
+            "Sys.init"
+        );
+        let Command::Check(boot) = parse(&["check", "--no-boot"]).command else {
+            panic!("expect check");
+        };
+        assert!(boot.bootstrap().is_none());
+
+        fs::write(&manifest, "opt-level = 3\n").expect("expect ok");
+        let opts = Opts::try_parse_from(["vm-cli", "--manifest", manifest_arg, "build"])
+            .expect("expect ok");
+        opts.global.manifest().expect_err("expect err");
+        fs::write(&manifest, "opt-level = \"fast\"\n").expect("expect ok");
+        let error = opts.global.manifest().expect_err("expect err");
+        assert!(matches!(error, Error::Manifest { .. }));
+        fs::remove_dir_all(&dir).expect("expect ok");
     }
-    Ok(())
 }
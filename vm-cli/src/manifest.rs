@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest a project keeps its build configuration in.
+pub const MANIFEST: &str = "jack.toml";
+
+/// Build configuration of a project, read from its `jack.toml`. Flags given on the command line
+/// take precedence over it, and paths in it are relative to the directory of the manifest.
+///
+/// ```toml
+/// sources = ["src"]
+/// libraries = ["lib"]
+/// output = "build/game.hack"
+/// intermediate-dir = "build/classes"
+/// opt-level = 1
+/// warnings = "deny"
+///
+/// [bootstrap]
+/// entry = "Main.main"
+/// call-frame = false
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Manifest {
+    /// Files, directories or glob patterns of the VM sources of the project, the manifest directory
+    /// if none.
+    pub sources: Vec<PathBuf>,
+    /// Directories of VM files linked into the program along with the sources.
+    pub libraries: Vec<PathBuf>,
+    /// File `build` writes.
+    pub output: Option<PathBuf>,
+    /// Directory `build` also writes the assembly of each class into.
+    pub intermediate_dir: Option<PathBuf>,
+    pub opt_level: Option<u8>,
+    pub bootstrap: Bootstrap,
+    pub warnings: WarningLevel,
+}
+
+/// How the bootstrap code enters the program.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Bootstrap {
+    /// Whether the output starts with bootstrap code at all.
+    pub enabled: bool,
+    pub entry: Option<String>,
+    /// Whether the entry is called with a frame rather than jumped into.
+    pub call_frame: bool,
+    /// Assembly file the output starts with in place of the generated bootstrap code.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            entry: None,
+            call_frame: true,
+            file: None,
+        }
+    }
+}
+
+/// What becomes of the warnings of a build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningLevel {
+    /// Leave the warnings out.
+    Allow,
+    /// Print the warnings.
+    #[default]
+    Warn,
+    /// Print the warnings and fail the build if there are any.
+    Deny,
+}
+
+impl Manifest {
+    /// Parses the manifest `text`, making its paths relative to `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, toml::de::Error> {
+        let mut manifest = toml::from_str::<Self>(text)?;
+        if manifest.sources.is_empty() {
+            manifest.sources.push(PathBuf::new());
+        }
+        let paths = manifest
+            .sources
+            .iter_mut()
+            .chain(&mut manifest.libraries)
+            .chain(&mut manifest.output)
+            .chain(&mut manifest.intermediate_dir);
+        for path in paths.chain(&mut manifest.bootstrap.file) {
+            *path = dir.join(&*path);
+        }
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::manifest::{Manifest, WarningLevel};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn parse_manifest() {
+        const MANIFEST: &str = "\
+sources = [\"src\", \"extra\"]
+libraries = [\"lib\"]
+output = \"build/game.hack\"
+intermediate-dir = \"build/classes\"
+opt-level = 1
+warnings = \"deny\"
+
+[bootstrap]
+entry = \"Main.main\"
+file = \"boot.asm\"
+";
+        let manifest = Manifest::parse(MANIFEST, Path::new("game")).expect("expect ok");
+        let sources = vec![PathBuf::from("game/src"), PathBuf::from("game/extra")];
+        assert_eq!(manifest.sources, sources);
+        assert_eq!(manifest.libraries, vec![PathBuf::from("game/lib")]);
+        assert_eq!(manifest.output, Some(PathBuf::from("game/build/game.hack")));
+        let intermediate_dir = manifest.intermediate_dir.as_deref();
+        assert_eq!(intermediate_dir, Some(Path::new("game/build/classes")));
+        assert_eq!(manifest.opt_level, Some(1));
+        assert_eq!(manifest.bootstrap.entry.as_deref(), Some("Main.main"));
+        assert!(manifest.bootstrap.enabled && manifest.bootstrap.call_frame);
+        let file = manifest.bootstrap.file.as_deref();
+        assert_eq!(file, Some(Path::new("game/boot.asm")));
+        assert_eq!(manifest.warnings, WarningLevel::Deny);
+
+        // The sources are the directory of the manifest by default.
+        let manifest = Manifest::parse("", Path::new("game")).expect("expect ok");
+        assert_eq!(manifest.sources, vec![PathBuf::from("game")]);
+        assert_eq!(manifest.warnings, WarningLevel::Warn);
+        assert!(Manifest::parse("source = [\"src\"]", Path::new(".")).is_err());
+        assert!(Manifest::parse("warnings = \"error\"", Path::new(".")).is_err());
+    }
+}
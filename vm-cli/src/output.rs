@@ -0,0 +1,114 @@
+use crate::GlobalOpts;
+use crate::error::Error::Whatever;
+use crate::error::{Error, IOSnafu};
+use crate::input::list_files;
+use clio::{ClioPath, Output, has_extension};
+use snafu::ResultExt;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Opens `output` for writing, standard output when it is `-`.
+pub(crate) fn create(output: ClioPath) -> Result<BufWriter<Output>, Error> {
+    Ok(BufWriter::new(output.create()?))
+}
+
+/// Writes `text` into `output`.
+pub(crate) fn write_output(output: ClioPath, text: &str) -> Result<(), Error> {
+    let mut writer = create(output)?;
+    writer.write_all(text.as_bytes()).context(IOSnafu)?;
+    writer.flush().context(IOSnafu)
+}
+
+/// Fails if `output`, one the subcommand writes in the end, exists and `--force` is not passed, or
+/// if it is a VM or assembly file in a directory of the sources, which later runs would read as a
+/// source. Builds watching the input check it once, overwriting it on every build after the first.
+///
+/// The output is the directory the classes are written into if `dir`, which may exist as long as
+/// it holds no assembly, and may not be a directory of the sources.
+pub(crate) fn check_output(global: &GlobalOpts, output: &ClioPath, dir: bool) -> Result<(), Error> {
+    if output.is_std() && dir {
+        return Err(Whatever {
+            message: "--no-link writes into a directory, not standard output".to_owned(),
+        });
+    }
+    if !output.is_local() {
+        return Ok(());
+    }
+    let path = output.to_path_buf();
+    if dir {
+        if path.is_dir() && !global.force && !list_files(output, "asm", false)?.is_empty() {
+            return Err(Error::OutputExists { path });
+        }
+    } else if path.is_dir() {
+        return Err(Error::OutputIsDirectory { path });
+    } else if path.exists() && !global.force {
+        return Err(Error::OutputExists { path });
+    } else if !(has_extension("vm")(output) || has_extension("asm")(output)) {
+        return Ok(());
+    }
+    // The output may not exist yet, unlike its directory.
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    let parent = parent.unwrap_or(Path::new("."));
+    let Ok(dir) = fs::canonicalize(if dir { &path } else { parent }) else {
+        return Ok(());
+    };
+    for input in global.sources.iter().filter(|source| source.is_dir()) {
+        let input_dir = fs::canonicalize(input.path()).context(IOSnafu)?;
+        if dir == input_dir || (global.recursive && dir.starts_with(&input_dir)) {
+            return Err(Error::OutputInInput {
+                path,
+                input: input.to_path_buf(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::output::check_output;
+    use crate::tests::{configured, temp_dir};
+    use clio::ClioPath;
+    use std::fs;
+
+    #[test]
+    fn check_outputs() {
+        let dir = temp_dir("check-output");
+        let src = dir.join("src");
+        fs::create_dir(&src).expect("expect ok");
+        fs::write(src.join("Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+        let mut opts = configured(["vm-cli", "-i", src.to_str().expect("expect utf-8"), "build"]);
+        let global = &mut opts.global;
+
+        let output = ClioPath::local(dir.join("out.asm"));
+        check_output(global, &output, false).expect("expect ok");
+        fs::write(dir.join("out.asm"), "").expect("expect ok");
+        let error = check_output(global, &output, false).expect_err("expect err");
+        assert!(matches!(error, Error::OutputExists { .. }));
+        let in_input = ClioPath::local(src.join("out.asm"));
+        let error = check_output(global, &in_input, false).expect_err("expect err");
+        assert!(matches!(error, Error::OutputInInput { .. }));
+        check_output(global, &ClioPath::local(src.join("out.hack")), false).expect("expect ok");
+        let error = check_output(global, &ClioPath::local(src.clone()), false);
+        assert!(matches!(error, Err(Error::OutputIsDirectory { .. })));
+        check_output(global, &ClioPath::std(), false).expect("expect ok");
+
+        // Directories may exist as long as they hold no assembly yet.
+        check_output(global, &ClioPath::local(dir.join("classes")), true).expect("expect ok");
+        let error = check_output(global, &ClioPath::local(dir.clone()), true);
+        assert!(matches!(error, Err(Error::OutputExists { .. })));
+        let error = check_output(global, &ClioPath::local(src.clone()), true);
+        assert!(matches!(error, Err(Error::OutputInInput { .. })));
+        let error = check_output(global, &ClioPath::std(), true);
+        assert!(matches!(error, Err(Error::Whatever { .. })));
+
+        global.force = true;
+        check_output(global, &output, false).expect("expect ok");
+        check_output(global, &ClioPath::local(dir.clone()), true).expect("expect ok");
+        fs::remove_dir_all(&dir).expect("expect ok");
+    }
+}
@@ -0,0 +1,57 @@
+use crate::session::Session;
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+/// Whether progress is shown in `session`, which it is not in quiet runs or when standard output
+/// is not a terminal, such as in scripts and CI logs. Bars are drawn to standard error, and cleared
+/// once finished or dropped, a failing step included.
+fn shown(session: &Session) -> bool {
+    !session.quiet() && io::stdout().is_terminal()
+}
+
+/// Bar counting the `len` files of a step, each named in its message while it is worked on.
+pub(crate) fn files(session: &Session, len: usize, step: &str) -> ProgressBar {
+    if !shown(session) {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template("{prefix:>9} [{bar:30}] {pos}/{len} {wide_msg}")
+        .expect("the template is valid")
+        .progress_chars("=> ");
+    let bar = ProgressBar::new(len as u64)
+        .with_style(style)
+        .with_prefix(step.to_owned());
+    bar.with_finish(ProgressFinish::AndClear)
+}
+
+/// Spinner turning while a step of unknown length, described by `message`, runs.
+pub(crate) fn spinner(session: &Session, message: String) -> ProgressBar {
+    if !shown(session) {
+        return ProgressBar::hidden();
+    }
+    let style =
+        ProgressStyle::with_template("{spinner} {msg} {elapsed}").expect("the template is valid");
+    let spinner = ProgressBar::new_spinner()
+        .with_style(style)
+        .with_message(message);
+    let spinner = spinner.with_finish(ProgressFinish::AndClear);
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{files, spinner};
+    use crate::session::Session;
+    use crate::tests::configured;
+
+    #[test]
+    fn hide_progress() {
+        // Standard output is captured by the test harness, which is not a terminal.
+        let session = Session::default();
+        assert!(files(&session, 3, "Parsing").is_hidden());
+        let opts = configured(["vm-cli", "--quiet", "build"]);
+        let session = Session::new(&opts.global);
+        assert!(spinner(&session, "Linking".to_owned()).is_hidden());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::error::{Error, LinkingSnafu};
+use serde::Serialize;
+use snafu::ResultExt;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+use vm::Linker;
+use vm::asm::ROM_SIZE;
+use vm::generate::{Context, GenerateOptions};
+use vm::program::Program;
+use vm::stats::Stats;
+
+/// What `build --stats` reports of the linked program.
+#[derive(Serialize)]
+pub(crate) struct BuildReport {
+    classes: Vec<ClassReport>,
+    /// ROM instructions outside the classes: the bootstrap code, the shared routines and the
+    /// hand-written assembly.
+    shared: usize,
+    rom: usize,
+    /// Share of the ROM the program takes, in percent.
+    rom_used: f64,
+    link_ms: f64,
+}
+
+#[derive(Serialize)]
+struct ClassReport {
+    name: String,
+    /// VM instructions of the functions of the class.
+    vm_instructions: usize,
+    /// ROM instructions the class lowers to in the linked program.
+    asm_instructions: usize,
+    static_slots: u32,
+}
+
+impl BuildReport {
+    /// Reports on the program `linker` links with `options`, linked in `link_time`.
+    pub(crate) fn new(
+        program: &Program,
+        linker: &Linker,
+        options: &GenerateOptions,
+        link_time: Duration,
+    ) -> Result<Self, Error> {
+        let mut ctx = Context::new(GenerateOptions {
+            source_map: true,
+            ..options.clone()
+        });
+        let asm = linker.lower(&mut ctx).context(LinkingSnafu)?;
+        let stats = Stats::collect(&asm, &ctx.source_map().mappings);
+        let statics = program.static_usage();
+        let classes = program.classes().iter().map(|class| {
+            let defines = |name: &str| {
+                class
+                    .functions()
+                    .iter()
+                    .any(|function| function.name() == name)
+            };
+            let functions = stats.functions.iter();
+            let static_slots = statics.iter().find(|usage| usage.class == class.name());
+            ClassReport {
+                name: class.name().to_owned(),
+                vm_instructions: class
+                    .functions()
+                    .iter()
+                    .map(|function| function.instr().len())
+                    .sum(),
+                asm_instructions: functions
+                    .filter(|function| defines(&function.name))
+                    .map(|function| function.instructions)
+                    .sum(),
+                static_slots: static_slots.map_or(0, |usage| usage.slots),
+            }
+        });
+        Ok(Self {
+            classes: classes.collect(),
+            shared: stats.shared,
+            rom: stats.rom,
+            rom_used: stats.rom as f64 * 100.0 / ROM_SIZE as f64,
+            link_ms: link_time.as_secs_f64() * 1000.0,
+        })
+    }
+}
+
+impl Display for BuildReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let names = self.classes.iter().map(|class| class.name.len());
+        let width = names.max().unwrap_or_default().max("class".len());
+        let header = ("class", "vm", "asm", "statics");
+        writeln!(
+            f,
+            "{:width$}  {:>8}  {:>8}  {:>7}",
+            header.0, header.1, header.2, header.3
+        )?;
+        for class in &self.classes {
+            writeln!(
+                f,
+                "{:width$}  {:>8}  {:>8}  {:>7}",
+                class.name, class.vm_instructions, class.asm_instructions, class.static_slots
+            )?;
+        }
+        writeln!(f, "{:width$}  {:>8}  {:>8}", "shared", "", self.shared)?;
+        writeln!(
+            f,
+            "ROM {} of {ROM_SIZE} instructions ({:.1}%), linked in {:.1}ms",
+            self.rom, self.rom_used, self.link_ms
+        )
+    }
+}
+
+/// What `build --timings` reports of the time each phase of the build took, in milliseconds.
+#[derive(Debug, Serialize, Default)]
+pub(crate) struct TimingsReport {
+    files: Vec<FileTimings>,
+    /// Checking the program as a whole, after its files are validated.
+    check_ms: f64,
+    /// Linking the program and writing it, but generating its classes.
+    link_ms: f64,
+    /// The whole build, reading the sources included.
+    total_ms: f64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub(crate) struct FileTimings {
+    file: String,
+    class: String,
+    /// Lexing the source, which parsing does again as it goes.
+    lex_ms: f64,
+    /// Parsing the source, but lexing it.
+    parse_ms: f64,
+    /// Checking the class the file defines on its own.
+    validate_ms: f64,
+    optimize_ms: f64,
+    generate_ms: f64,
+}
+
+impl FileTimings {
+    /// Timings of reading the file `file` defining `class`, which took `lex_time` to lex, then
+    /// `parse_time` to parse, lexing included, and `validate_time` to check.
+    pub(crate) fn new(
+        file: &str,
+        class: &str,
+        lex_time: Duration,
+        parse_time: Duration,
+        validate_time: Duration,
+    ) -> Self {
+        Self {
+            file: file.to_owned(),
+            class: class.to_owned(),
+            lex_ms: lex_time.as_secs_f64() * 1000.0,
+            parse_ms: parse_time.saturating_sub(lex_time).as_secs_f64() * 1000.0,
+            validate_ms: validate_time.as_secs_f64() * 1000.0,
+            ..Default::default()
+        }
+    }
+}
+
+impl TimingsReport {
+    /// Adds the timings of reading a file.
+    pub(crate) fn add_file(&mut self, file: FileTimings) {
+        self.files.push(file);
+    }
+
+    /// Sets the time checking the program took.
+    pub(crate) fn set_check_time(&mut self, check_time: Duration) {
+        self.check_ms = check_time.as_secs_f64() * 1000.0;
+    }
+
+    /// Completes the timings of reading the files with the time `ctx` spent on the classes of
+    /// `program`, which it linked in `link_time`, after a build of `total_time`. Classes reused
+    /// from the cache show no optimizing or generating time.
+    pub(crate) fn finish(
+        mut self,
+        program: &Program,
+        ctx: &Context,
+        link_time: Duration,
+        total_time: Duration,
+    ) -> Self {
+        // Files are read in parallel, and reported in link order.
+        let classes = program.classes();
+        let order =
+            |file: &FileTimings| classes.iter().position(|class| class.name() == file.class);
+        self.files.sort_by_key(order);
+        let mut generating = Duration::ZERO;
+        for file in &mut self.files {
+            let times = ctx.timings().get(&file.class).copied().unwrap_or_default();
+            file.optimize_ms = times.optimize.as_secs_f64() * 1000.0;
+            file.generate_ms = times.generate.as_secs_f64() * 1000.0;
+            generating += times.optimize + times.generate;
+        }
+        self.link_ms = link_time.saturating_sub(generating).as_secs_f64() * 1000.0;
+        self.total_ms = total_time.as_secs_f64() * 1000.0;
+        self
+    }
+}
+
+impl Display for TimingsReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let names = self.files.iter().map(|file| file.file.len());
+        let width = names.max().unwrap_or_default().max("total".len());
+        write!(f, "{:width$}", "file")?;
+        for phase in ["lex", "parse", "validate", "optimize", "generate"] {
+            write!(f, "  {phase:>8}")?;
+        }
+        let row = |f: &mut Formatter<'_>, name: &str, times: [f64; 5]| {
+            write!(f, "\n{name:width$}")?;
+            times.iter().try_for_each(|time| write!(f, "  {time:>8.3}"))
+        };
+        let mut total = [0.0; 5];
+        for file in &self.files {
+            let times = [
+                file.lex_ms,
+                file.parse_ms,
+                file.validate_ms,
+                file.optimize_ms,
+                file.generate_ms,
+            ];
+            let totals = total.iter_mut().zip(times);
+            totals.for_each(|(total, time)| *total += time);
+            row(f, &file.file, times)?;
+        }
+        row(f, "total", total)?;
+        writeln!(
+            f,
+            "\ntimes in ms, checked the program in {:.3}ms, linked in {:.3}ms, built in {:.3}ms",
+            self.check_ms, self.link_ms, self.total_ms
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{BuildReport, FileTimings, TimingsReport};
+    use std::time::Duration;
+    use vm::Linker;
+    use vm::generate::{Class, Context, GenerateOptions};
+    use vm::parse::parse;
+    use vm::program::Program;
+
+    #[test]
+    fn report_classes() {
+        const MAIN_VM: &str = "function Main.main 0\npush constant 7\npop static 0\nreturn\n";
+        const SYS_VM: &str = "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n";
+        let classes = vec![
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+            Class::new(parse(SYS_VM).expect("expect ok"), "Sys"),
+        ];
+        let mut linker = Linker::new();
+        for class in &classes {
+            linker.add_class(class.clone());
+        }
+        let program = Program::new(classes);
+        let options = GenerateOptions::default();
+        let report = BuildReport::new(&program, &linker, &options, Duration::from_millis(2))
+            .expect("expect ok");
+        let vm = report.classes.iter().map(|class| class.vm_instructions);
+        assert!(vm.eq([2, 3]));
+        let statics = report.classes.iter().map(|class| class.static_slots);
+        assert!(statics.eq([1, 0]));
+        let asm = report.classes.iter().map(|class| class.asm_instructions);
+        assert_eq!(asm.sum::<usize>() + report.shared, report.rom);
+
+        let printed = report.to_string();
+        let mut lines = printed.lines();
+        assert_eq!(lines.next(), Some("class        vm       asm  statics"));
+        assert!(
+            lines
+                .next()
+                .is_some_and(|line| line.starts_with("Main          2"))
+        );
+        let summary = format!("ROM {} of 32768 instructions", report.rom);
+        assert!(printed.contains(&summary) && printed.ends_with("linked in 2.0ms\n"));
+    }
+
+    #[test]
+    fn report_timings() {
+        let classes = vec![
+            Class::new(
+                parse("function Sys.init 0\nreturn\n").expect("expect ok"),
+                "Sys",
+            ),
+            Class::new(
+                parse("function Main.main 0\nreturn\n").expect("expect ok"),
+                "Main",
+            ),
+        ];
+        let program = Program::new(classes);
+        let mut report = TimingsReport::default();
+        let ms = Duration::from_millis;
+        // Files are read in any order, and reported in link order.
+        report.add_file(FileTimings::new("Sys.vm", "Sys", ms(1), ms(2), ms(0)));
+        report.add_file(FileTimings::new("Main.vm", "Main", ms(1), ms(3), ms(1)));
+        report.set_check_time(ms(2));
+        let ctx = Context::default();
+        let report = report.finish(&program, &ctx, ms(5), ms(20));
+        let files = report
+            .files
+            .iter()
+            .map(|file| (file.file.as_str(), file.parse_ms));
+        assert!(files.eq([("Main.vm", 2.0), ("Sys.vm", 1.0)]));
+        let printed = report.to_string();
+        let mut lines = printed.lines();
+        assert_eq!(
+            lines.next(),
+            Some("file          lex     parse  validate  optimize  generate")
+        );
+        assert_eq!(
+            lines.nth(2),
+            Some("total       2.000     3.000     1.000     0.000     0.000")
+        );
+        assert!(printed.ends_with("in 2.000ms, linked in 5.000ms, built in 20.000ms\n"));
+    }
+}
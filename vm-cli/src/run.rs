@@ -0,0 +1,96 @@
+use crate::GlobalOpts;
+use crate::build::{BuildOpts, Emit, output_path, prepare};
+use crate::error::{AssemblingSnafu, Error, LinkingSnafu, RunningSnafu};
+use crate::output::write_output;
+use crate::session::Session;
+use clap::Args;
+use clio::ClioPath;
+use snafu::ResultExt;
+use vm::asm::assemble;
+use vm::emulate::{Machine, Stop};
+use vm::generate::Context;
+
+/// Instructions `run` stops after when not showing the screen, unless told otherwise.
+const MAX_CYCLES: u64 = 100_000_000;
+
+#[derive(Args)]
+pub(crate) struct RunOpts {
+    /// Stop after running this many instructions, 100000000 by default, no limit with `--screen`
+    #[clap(long)]
+    max_cycles: Option<u64>,
+    /// Show the screen of the machine in a window, passing it the keys pressed, until the window
+    /// is closed
+    #[clap(long, action, default_value_t = false)]
+    screen: bool,
+    /// Write the RAM once the program stops to this file, or `-` for standard output, one line of
+    /// address and value for every word that is not 0
+    #[clap(long, value_parser = clap::value_parser!(ClioPath).is_file())]
+    dump_ram: Option<ClioPath>,
+}
+
+impl RunOpts {
+    /// Files `run` writes.
+    pub(crate) fn outputs(&self) -> Vec<(ClioPath, bool)> {
+        self.dump_ram
+            .iter()
+            .map(|path| (path.clone(), false))
+            .collect()
+    }
+}
+
+/// Builds the program the sources of `global` make up and runs it in the emulator, until it halts
+/// or runs out of cycles.
+pub(crate) fn run_program(
+    session: &Session,
+    global: &GlobalOpts,
+    opt: &BuildOpts,
+    run: &RunOpts,
+) -> Result<(), Error> {
+    let (_, linker) = prepare(session, global, opt, &output_path(global, Emit::Asm))?;
+    let mut ctx = Context::new(opt.options());
+    let asm = linker.lower(&mut ctx).context(LinkingSnafu)?;
+    let binary = assemble(&asm, ctx.options.layout.statics.clone()).context(AssemblingSnafu)?;
+    let mut machine = Machine::new(binary);
+    let stop = if run.screen {
+        show_screen(&mut machine, run.max_cycles)?
+    } else {
+        let max_cycles = run.max_cycles.unwrap_or(MAX_CYCLES);
+        Some(machine.run(max_cycles).context(RunningSnafu)?)
+    };
+    let cycles = machine.cycles();
+    match stop {
+        Some(Stop::End | Stop::Loop) => {
+            session.status(format_args!("halted after {cycles} cycles"))
+        }
+        Some(Stop::CycleLimit) => {
+            session.status(format_args!("stopped after {cycles} cycles, still running"))
+        }
+        None => session.status(format_args!("window closed after {cycles} cycles")),
+    }
+    if let Some(path) = &run.dump_ram {
+        write_output(path.clone(), &dump_ram(&machine))?;
+    }
+    Ok(())
+}
+
+/// Lines of address and value of the words of the RAM of `machine` that are not 0.
+fn dump_ram(machine: &Machine) -> String {
+    let words = machine.ram().iter().enumerate();
+    let words = words.filter(|(_, word)| **word != 0);
+    words
+        .map(|(address, word)| format!("{address} {}\n", *word as i16))
+        .collect()
+}
+
+/// Runs `machine` showing its screen in a window, see [`crate::screen::show`].
+#[cfg(feature = "window")]
+fn show_screen(machine: &mut Machine, max_cycles: Option<u64>) -> Result<Option<Stop>, Error> {
+    crate::screen::show(machine, max_cycles)
+}
+
+#[cfg(not(feature = "window"))]
+fn show_screen(_: &mut Machine, _: Option<u64>) -> Result<Option<Stop>, Error> {
+    Err(Error::Whatever {
+        message: "--screen needs vm-cli built with the `window` feature".to_owned(),
+    })
+}
@@ -0,0 +1,110 @@
+use crate::error::{Error, RunningSnafu, WindowSnafu};
+use minifb::{Key, Scale, Window, WindowOptions};
+use snafu::ResultExt;
+use vm::emulate::{Machine, Stop};
+
+/// Pixels of the screen of the Hack computer.
+const WIDTH: usize = 512;
+const HEIGHT: usize = 256;
+/// Instructions run between two frames, about the speed of the CPU emulator of nand2tetris.
+const CYCLES_PER_FRAME: u64 = 100_000;
+
+/// Runs `machine` in a window showing its screen and passing it the keys pressed, for at most
+/// `max_cycles` instructions if given. The window stays open once the program stops, and the stop
+/// is returned when it is closed, none if the program was still running.
+pub fn show(machine: &mut Machine, max_cycles: Option<u64>) -> Result<Option<Stop>, Error> {
+    let options = WindowOptions {
+        scale: Scale::X2,
+        ..WindowOptions::default()
+    };
+    let mut window =
+        Window::new(env!("CARGO_BIN_NAME"), WIDTH, HEIGHT, options).context(WindowSnafu)?;
+    window.set_target_fps(60);
+    let mut pixels = vec![0; WIDTH * HEIGHT];
+    let mut stop = None;
+    while window.is_open() {
+        if stop.is_none() {
+            machine.press(pressed_key(&window));
+            let left = max_cycles.map_or(u64::MAX, |max| max.saturating_sub(machine.cycles()));
+            match machine
+                .run(left.min(CYCLES_PER_FRAME))
+                .context(RunningSnafu)?
+            {
+                Stop::CycleLimit if left > CYCLES_PER_FRAME => {}
+                stopped => stop = Some(stopped),
+            }
+        }
+        draw(machine.screen(), &mut pixels);
+        window
+            .update_with_buffer(&pixels, WIDTH, HEIGHT)
+            .context(WindowSnafu)?;
+    }
+    Ok(stop)
+}
+
+/// Turns the words of the screen into pixels, a set bit being black.
+fn draw(screen: &[u16], pixels: &mut [u32]) {
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        let black = screen[index / 16] >> (index % 16) & 1 == 1;
+        *pixel = if black { 0x000000 } else { 0xFFFFFF };
+    }
+}
+
+/// Code of a key held down in `window` in the character set of the Hack computer, 0 for none.
+fn pressed_key(window: &Window) -> u16 {
+    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    window
+        .get_keys()
+        .into_iter()
+        .find_map(|key| hack_key(key, shift))
+        .unwrap_or(0)
+}
+
+/// Keys typing a character other than a letter or digit, with the character typed along with shift.
+const SYMBOLS: [(Key, u8, u8); 12] = [
+    (Key::Apostrophe, b'\'', b'"'),
+    (Key::Backquote, b'`', b'~'),
+    (Key::Backslash, b'\\', b'|'),
+    (Key::Comma, b',', b'<'),
+    (Key::Equal, b'=', b'+'),
+    (Key::LeftBracket, b'[', b'{'),
+    (Key::Minus, b'-', b'_'),
+    (Key::Period, b'.', b'>'),
+    (Key::RightBracket, b']', b'}'),
+    (Key::Semicolon, b';', b':'),
+    (Key::Slash, b'/', b'?'),
+    (Key::Space, b' ', b' '),
+];
+
+/// Code the Hack computer gives `key`, typed along with shift if `shift`.
+fn hack_key(key: Key, shift: bool) -> Option<u16> {
+    // Digits, letters and then function keys are numbered in order from 0 in the keys of minifb.
+    let code = match key as u16 {
+        digit @ 0..=9 if shift => b")!@#$%^&*("[digit as usize].into(),
+        digit @ 0..=9 => '0' as u16 + digit,
+        letter @ 10..=35 if shift => 'A' as u16 + letter - 10,
+        letter @ 10..=35 => 'a' as u16 + letter - 10,
+        function @ 36..=47 => 141 + function - 36,
+        _ => match key {
+            Key::Enter => 128,
+            Key::Backspace => 129,
+            Key::Left => 130,
+            Key::Up => 131,
+            Key::Right => 132,
+            Key::Down => 133,
+            Key::Home => 134,
+            Key::End => 135,
+            Key::PageUp => 136,
+            Key::PageDown => 137,
+            Key::Insert => 138,
+            Key::Delete => 139,
+            Key::Escape => 140,
+            _ => {
+                let (_, plain, shifted) =
+                    SYMBOLS.into_iter().find(|(symbol, ..)| *symbol == key)?;
+                if shift { shifted } else { plain }.into()
+            }
+        },
+    };
+    Some(code)
+}
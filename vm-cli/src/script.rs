@@ -0,0 +1,141 @@
+use crate::GlobalOpts;
+use crate::build::BuildOpts;
+use crate::error::Error::{EmptySource, Whatever};
+use crate::error::{AssemblingSnafu, Error, IOSnafu, LinkingSnafu, ScriptSnafu};
+use crate::input::{list_files, read_classes};
+use crate::session::Session;
+use clio::ClioPath;
+use snafu::ResultExt;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use vm::Linker;
+use vm::asm::{assemble, parse_asm};
+use vm::emulate::Machine;
+use vm::generate::{Class, Context};
+use vm::parse::ParseOptions;
+use vm::script::{Mismatch, parse_script};
+
+/// Runs every test script next to the sources of `global`, see [`run_script`], failing if any
+/// fails. Scripts of the VM emulator, named `*VME.tst`, are skipped.
+pub(crate) fn test(session: &Session, global: &GlobalOpts, opt: &BuildOpts) -> Result<(), Error> {
+    let scripts = find_test_scripts(global)?;
+    if scripts.is_empty() {
+        return Err(EmptySource {
+            message: "no test script next to the sources".to_owned(),
+        });
+    }
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for path in &scripts {
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        if name.is_some_and(|name| name.ends_with("VME.tst")) {
+            session.status(format_args!(
+                "skipped {}, a script of the VM emulator",
+                path.display()
+            ));
+            skipped += 1;
+            continue;
+        }
+        match run_script(session, path, opt) {
+            Ok(None) => {
+                session.status(format_args!("passed {}", path.display()));
+                passed += 1;
+            }
+            Ok(Some(mismatch)) => {
+                eprintln!(
+                    "failed {}, line {} of the output differs",
+                    path.display(),
+                    mismatch.line
+                );
+                eprintln!("  expected: {}", mismatch.expected);
+                eprintln!("    actual: {}", mismatch.actual);
+                failed += 1;
+            }
+            Err(error) => {
+                eprintln!("failed {}", path.display());
+                session.report_error(error);
+                failed += 1;
+            }
+        }
+    }
+    session.status(format_args!(
+        "{passed} passed, {failed} failed, {skipped} skipped"
+    ));
+    if failed > 0 {
+        return Err(Error::TestsFailed { failed });
+    }
+    Ok(())
+}
+
+/// Test scripts in the directories of the sources of `global`, in their subdirectories too if it
+/// is recursive.
+fn find_test_scripts(global: &GlobalOpts) -> Result<Vec<PathBuf>, Error> {
+    let mut scripts = BTreeSet::new();
+    for source in global.sources.iter().filter(|source| !source.is_std()) {
+        let files = if source.is_dir() {
+            list_files(source, "tst", global.recursive)?
+        } else {
+            let dir = source.parent().filter(|dir| !dir.as_os_str().is_empty());
+            let dir = dir.unwrap_or(Path::new("."));
+            list_files(&ClioPath::local(dir.to_path_buf()), "tst", false)?
+        };
+        scripts.extend(files.into_iter().map(|file| file.to_path_buf()));
+    }
+    Ok(scripts.into_iter().collect())
+}
+
+/// Runs the test script at `path` against the program built from the VM files in its directory,
+/// or the assembly file it loads if there are none, returning where its output first differs from
+/// its comparison file. The output is written to its output file, as far as it matches.
+///
+/// Programs get bootstrap code only if they define the function it enters, as scripts of programs
+/// without one set up the stack themselves.
+fn run_script(session: &Session, path: &Path, opt: &BuildOpts) -> Result<Option<Mismatch>, Error> {
+    let source = fs::read_to_string(path).context(IOSnafu)?;
+    let script = parse_script(&source).context(ScriptSnafu { path })?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let Some(load) = script.load() else {
+        return Err(Whatever {
+            message: format!("{} loads no program", path.display()),
+        });
+    };
+    let vm_files = list_files(&ClioPath::local(dir.to_path_buf()), "vm", false)?;
+    let mut options = opt.options();
+    let asm = if vm_files.is_empty() {
+        let source = fs::read_to_string(dir.join(load)).context(IOSnafu)?;
+        parse_asm(&source).context(AssemblingSnafu)?
+    } else {
+        // Scripts of project 7 run programs of top-level code, outside any function.
+        let parse_options = ParseOptions {
+            top_level_code: true,
+            ..Default::default()
+        };
+        let classes = read_classes(session, vm_files, "Main", &parse_options)?;
+        let functions = classes.iter().flat_map(Class::functions);
+        if let Some(boot) = &options.bootstrap
+            && !functions
+                .clone()
+                .any(|function| function.name() == boot.entry)
+        {
+            options.bootstrap = None;
+        }
+        let mut linker = Linker::new();
+        for class in classes {
+            linker.add_class(class);
+        }
+        let mut ctx = Context::new(options.clone());
+        linker.lower(&mut ctx).context(LinkingSnafu)?
+    };
+    let binary = assemble(&asm, options.layout.statics.clone()).context(AssemblingSnafu)?;
+    let expected = match script.compare_to() {
+        Some(file) => Some(fs::read_to_string(dir.join(file)).context(IOSnafu)?),
+        None => None,
+    };
+    let outcome = script
+        .run(&mut Machine::new(binary), expected.as_deref())
+        .context(ScriptSnafu { path })?;
+    if let Some(file) = script.output_file() {
+        fs::write(dir.join(file), &outcome.output).context(IOSnafu)?;
+    }
+    Ok(outcome.mismatch)
+}
@@ -0,0 +1,350 @@
+use crate::GlobalOpts;
+use crate::error::Error;
+use crate::manifest::{Manifest, WarningLevel};
+use crate::report::TimingsReport;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, io};
+use vm::diagnostic::{Diagnostic, Lint, Renderer, Severity};
+use vm::source::SourceFile;
+
+/// How warnings and errors are printed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum MessageFormat {
+    /// For people, pointing at the line of the source
+    #[default]
+    Human,
+    /// One JSON object per line, for tools
+    Json,
+}
+
+/// When to color warnings and errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ColorChoice {
+    /// When standard error is a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color what is printed to standard error.
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && io::stderr().is_terminal()
+            }
+        }
+    }
+}
+
+/// What becomes of the warnings of each lint: the level the flags give it, or else the level of the
+/// manifest, except for `unused-label` which is only looked for when asked.
+#[derive(Debug, Default)]
+pub(crate) struct Lints {
+    /// Level of the lints the flags leave out.
+    default: WarningLevel,
+    levels: BTreeMap<Lint, WarningLevel>,
+}
+
+impl Lints {
+    pub(crate) fn new(global: &GlobalOpts, manifest: Option<&Manifest>) -> Self {
+        let default = match (global.deny_warnings, manifest) {
+            (Some(true), _) => WarningLevel::Deny,
+            (None, Some(manifest)) => manifest.warnings,
+            _ => WarningLevel::Warn,
+        };
+        let allowed = global.allow.iter().map(|lint| (*lint, WarningLevel::Allow));
+        let warned = global.warn.iter().map(|lint| (*lint, WarningLevel::Warn));
+        let denied = global.deny.iter().map(|lint| (*lint, WarningLevel::Deny));
+        Self {
+            default,
+            levels: allowed.chain(warned).chain(denied).collect(),
+        }
+    }
+
+    /// Level of the warnings of `lint`, or of warnings of no lint.
+    pub(crate) fn level(&self, lint: Option<Lint>) -> WarningLevel {
+        match lint {
+            Some(lint) if self.levels.contains_key(&lint) => self.levels[&lint],
+            Some(Lint::UnusedLabel) => WarningLevel::Allow,
+            _ => self.default,
+        }
+    }
+}
+
+/// Parses the name of a lint, listing the lints if it names none.
+pub(crate) fn parse_lint(name: &str) -> Result<Lint, String> {
+    Lint::from_name(name).ok_or_else(|| {
+        let names = Lint::ALL.map(|lint| lint.name());
+        format!("unknown lint, expected one of {}", names.join(", "))
+    })
+}
+
+/// Parses the value of a switch, like `--deny-warnings=false` or `JACK_VM_NO_CACHE=1`, an empty one
+/// turning it off as of an environment variable set to nothing.
+pub(crate) fn parse_switch(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "y" | "yes" | "t" | "true" | "on" | "1" => Ok(true),
+        "" | "n" | "no" | "f" | "false" | "off" | "0" => Ok(false),
+        _ => Err("expected true, false, yes, no, on, off, 1 or 0".to_owned()),
+    }
+}
+
+/// State of one run of the CLI, shared by the subcommands: how they report what they find, and
+/// what they reported so far.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    /// What becomes of the warnings of each lint.
+    lints: Lints,
+    /// How warnings and errors are printed.
+    message_format: MessageFormat,
+    /// Whether only errors are printed, see [`Session::status`].
+    quiet: bool,
+    /// Renders warnings and errors for people, in color when standard error supports it.
+    renderer: Renderer,
+    /// Files read, to quote in errors, see [`Session::keep_source`].
+    sources: Mutex<Vec<(String, SourceFile)>>,
+    /// Denied warnings found since they were last [counted](Session::deny_warnings).
+    denied: AtomicUsize,
+    /// Time spent on each phase of the build, kept while `build --timings` measures it.
+    timings: Mutex<Option<TimingsReport>>,
+}
+
+impl Session {
+    /// Session printing warnings and errors in the message format and colors of `global`, with the
+    /// lint levels of its flags.
+    pub(crate) fn new(global: &GlobalOpts) -> Self {
+        Self {
+            lints: Lints::new(global, None),
+            message_format: global.message_format,
+            quiet: global.quiet,
+            renderer: Renderer {
+                color: global.color.enabled(),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Whether what is printed to standard error is colored.
+    pub(crate) fn color(&self) -> bool {
+        self.renderer.color
+    }
+
+    /// Whether only errors are printed.
+    pub(crate) fn quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Reports warnings as the flags of `global` say, and else as `manifest` says.
+    pub(crate) fn configure(&mut self, global: &GlobalOpts, manifest: &Manifest) {
+        self.lints = Lints::new(global, Some(manifest));
+    }
+
+    /// Level of the warnings of `lint`, see [`Lints`].
+    pub(crate) fn lint_level(&self, lint: Lint) -> WarningLevel {
+        self.lints.level(Some(lint))
+    }
+
+    /// Prints the warning `diagnostic`, unless its lint is allowed, see
+    /// [`Session::print_diagnostic`], or as an error counted if denied. Quiet runs only print
+    /// denied ones.
+    pub(crate) fn warn(&self, diagnostic: &Diagnostic, source: Option<&SourceFile>) {
+        match self.lints.level(diagnostic.lint) {
+            WarningLevel::Allow => {}
+            WarningLevel::Warn if !self.quiet => self.print_diagnostic(diagnostic, source),
+            WarningLevel::Warn => {}
+            // Denied warnings are errors, printed as such even when the run is quiet.
+            WarningLevel::Deny => {
+                let denied = Diagnostic {
+                    severity: Severity::Error,
+                    ..diagnostic.clone()
+                };
+                self.print_diagnostic(&denied, source);
+                self.denied.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Prints `message` about the progress of the command to standard error, unless the run is
+    /// quiet.
+    pub(crate) fn status(&self, message: impl Display) {
+        if !self.quiet {
+            eprintln!("{message}");
+        }
+    }
+
+    /// Prints `diagnostic` to standard error in the message format, as a line of JSON or quoting
+    /// the line of `source` it points at.
+    pub(crate) fn print_diagnostic(&self, diagnostic: &Diagnostic, source: Option<&SourceFile>) {
+        if self.message_format == MessageFormat::Json {
+            let json =
+                serde_json::to_string(diagnostic).expect("diagnostics are always serializable");
+            eprintln!("{json}");
+        } else {
+            eprintln!("{}", self.renderer.render(diagnostic, source));
+        }
+    }
+
+    /// Prints `error` as a diagnostic, quoting the source it points into if it was read.
+    pub(crate) fn report_error(&self, error: Error) {
+        let diagnostic = error.diagnostic();
+        let source = diagnostic
+            .file
+            .as_deref()
+            .and_then(|file| self.read_source(file));
+        self.print_diagnostic(&diagnostic, source.as_ref());
+    }
+
+    /// Keeps `source`, read from `path`, to quote in the errors pointing into it, in place of a
+    /// file read before from the same path.
+    pub(crate) fn keep_source(&self, path: &str, source: &SourceFile) {
+        let mut sources = self.sources.lock().expect("sources are never poisoned");
+        sources.retain(|(kept, _)| kept != path);
+        sources.push((path.to_owned(), source.clone()));
+    }
+
+    /// File read from the path or by the name `file`, see [`Session::keep_source`].
+    fn read_source(&self, file: &str) -> Option<SourceFile> {
+        let sources = self.sources.lock().expect("sources are never poisoned");
+        let mut found = sources
+            .iter()
+            .filter(|(path, source)| path == file || source.name() == file);
+        found.next().map(|(_, source)| source.clone())
+    }
+
+    /// Starts keeping the time spent on each phase of the build, see [`Session::record_timings`].
+    pub(crate) fn start_timings(&self) {
+        *self.timings.lock().expect("timings are never poisoned") = Some(TimingsReport::default());
+    }
+
+    /// Whether the time spent on each phase of the build is kept.
+    pub(crate) fn timed(&self) -> bool {
+        self.timings
+            .lock()
+            .expect("timings are never poisoned")
+            .is_some()
+    }
+
+    /// Records into the timings kept, if any, see [`Session::start_timings`].
+    pub(crate) fn record_timings(&self, record: impl FnOnce(&mut TimingsReport)) {
+        if let Some(timings) = self
+            .timings
+            .lock()
+            .expect("timings are never poisoned")
+            .as_mut()
+        {
+            record(timings);
+        }
+    }
+
+    /// Timings kept since they were started, no longer keeping them.
+    pub(crate) fn take_timings(&self) -> Option<TimingsReport> {
+        self.timings
+            .lock()
+            .expect("timings are never poisoned")
+            .take()
+    }
+
+    /// Fails if any denied warning was printed since the last call, starting the count over.
+    pub(crate) fn deny_warnings(&self) -> Result<(), Error> {
+        let warnings = self.denied.swap(0, Ordering::Relaxed);
+        if warnings > 0 {
+            return Err(Error::DeniedWarnings { warnings });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::manifest::{Manifest, WarningLevel};
+    use crate::session::{Lints, Session, parse_switch};
+    use crate::tests::configured;
+    use vm::diagnostic::{Diagnostic, Lint};
+
+    #[test]
+    fn deny_warnings() {
+        let unused = Diagnostic::warning("unused");
+        let opts = configured(["vm-cli", "check"]);
+        let session = Session::new(&opts.global);
+        session.warn(&unused, None);
+        session.deny_warnings().expect("expect ok");
+
+        let mut session = Session::new(&opts.global);
+        let manifest = Manifest {
+            warnings: WarningLevel::Deny,
+            ..Default::default()
+        };
+        session.configure(&opts.global, &manifest);
+        session.deny_warnings().expect("expect ok");
+        session.warn(&unused, None);
+        session.warn(&Diagnostic::warning("unreachable"), None);
+        let error = session.deny_warnings().expect_err("expect err");
+        assert_eq!(error.to_string(), "aborting due to 2 denied warnings");
+        // The count starts over once checked.
+        session.deny_warnings().expect("expect ok");
+
+        // Quiet runs still deny the warnings they leave out.
+        let opts = configured(["vm-cli", "--quiet", "check"]);
+        let mut session = Session::new(&opts.global);
+        session.configure(&opts.global, &manifest);
+        session.warn(&unused, None);
+        let error = session.deny_warnings().expect_err("expect err");
+        assert!(matches!(error, Error::DeniedWarnings { warnings: 1 }));
+    }
+
+    #[test]
+    fn lint_levels() {
+        let args = [
+            "vm-cli",
+            "-Wunused-label",
+            "-Aundefined-call",
+            "-Dunreachable-code",
+            "check",
+        ];
+        let opts = configured(args);
+        let lints = Lints::new(&opts.global, None);
+        assert_eq!(lints.level(Some(Lint::UnusedLabel)), WarningLevel::Warn);
+        assert_eq!(lints.level(Some(Lint::UndefinedCall)), WarningLevel::Allow);
+        assert_eq!(lints.level(Some(Lint::UnreachableCode)), WarningLevel::Deny);
+        assert_eq!(lints.level(Some(Lint::LiteralOverflow)), WarningLevel::Warn);
+        assert_eq!(lints.level(None), WarningLevel::Warn);
+
+        let manifest = Manifest {
+            warnings: WarningLevel::Deny,
+            ..Default::default()
+        };
+        let opts = configured(["vm-cli", "-Aundefined-call", "check"]);
+        let lints = Lints::new(&opts.global, Some(&manifest));
+        assert_eq!(lints.level(Some(Lint::LiteralOverflow)), WarningLevel::Deny);
+        assert_eq!(lints.level(Some(Lint::UndefinedCall)), WarningLevel::Allow);
+        // `unused-label` is only looked for when asked.
+        assert_eq!(lints.level(Some(Lint::UnusedLabel)), WarningLevel::Allow);
+        let opts = configured(["vm-cli", "--deny-warnings=false", "check"]);
+        let lints = Lints::new(&opts.global, Some(&manifest));
+        assert_eq!(lints.level(Some(Lint::LiteralOverflow)), WarningLevel::Warn);
+        assert_eq!(parse_switch(""), Ok(false));
+        assert_eq!(parse_switch("On"), Ok(true));
+        assert!(parse_switch("maybe").is_err());
+
+        let args = [
+            "vm-cli",
+            "-Wunreachable-code",
+            "-Dunreachable-code",
+            "--deny-warnings",
+            "check",
+        ];
+        let lints = Lints::new(&configured(args).global, None);
+        assert_eq!(lints.level(Some(Lint::UnreachableCode)), WarningLevel::Deny);
+        assert_eq!(lints.level(Some(Lint::LiteralOverflow)), WarningLevel::Deny);
+    }
+}
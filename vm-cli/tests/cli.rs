@@ -0,0 +1,817 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::{env, fs, process};
+
+/// Empty directory for the files of the test `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("vm-cli-it-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("expect ok");
+    dir
+}
+
+/// Runs vm-cli with `args`, feeding it `stdin`.
+fn run(args: &[&str], stdin: &str) -> Output {
+    run_in(Path::new("."), args, stdin)
+}
+
+/// Runs vm-cli with `args` and the environment variable `key` set to `value`.
+fn run_with_env(args: &[&str], key: &str, value: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vm-cli"))
+        .args(args)
+        .env(key, value)
+        .output()
+        .expect("expect ok")
+}
+
+/// Runs vm-cli with `args` in the directory `dir`, feeding it `stdin`.
+fn run_in(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vm-cli"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("expect ok");
+    // Runs failing before reading their input close it early, which is not an error here.
+    let mut input = child.stdin.take().expect("expect stdin");
+    let _ = input.write_all(stdin.as_bytes());
+    drop(input);
+    child.wait_with_output().expect("expect ok")
+}
+
+#[test]
+fn stdin_to_stdout() {
+    let output = run(
+        &["-i", "-", "-o", "-", "build", "--no-boot"],
+        "function Main.main 0\nreturn\n",
+    );
+    assert!(output.status.success());
+    let asm = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(asm.starts_with("(Main.main)\n"));
+
+    let args = [
+        "-i",
+        "-",
+        "-o",
+        "-",
+        "--stdin-name",
+        "Foo",
+        "build",
+        "--no-boot",
+    ];
+    let output = run(&args, "function Foo.bar 0\nreturn\n");
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .expect("expect utf-8")
+            .contains("(Foo.bar)\n")
+    );
+
+    let output = run(
+        &["-i", "-", "fmt"],
+        "function Main.main 0\n  push constant 1 // one\nreturn\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        b"function Main.main 0\n    push constant 1 // one\n    return\n"
+    );
+
+    let output = run(&["-i", "-", "asm"], "@5\nD=A\n");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"0000000000000101\n1110110000010000\n");
+}
+
+#[test]
+fn stream_output() {
+    let dir = temp_dir("stream");
+    // The output is kept out of the input directory, which would read it back.
+    let src = dir.join("src");
+    fs::create_dir(&src).expect("expect ok");
+    fs::write(
+        src.join("Main.vm"),
+        "function Main.main 0\ncall Math.get 0\nreturn\n",
+    )
+    .expect("expect ok");
+    fs::write(
+        src.join("Math.vm"),
+        "function Math.get 0\npush constant 3\nreturn\n",
+    )
+    .expect("expect ok");
+    fs::write(
+        src.join("Sys.vm"),
+        "function Sys.init 0\ncall Main.main 0\nreturn\n",
+    )
+    .expect("expect ok");
+    let input = src.to_str().expect("expect utf-8");
+    let out = dir.join("out.asm");
+    let output = run(
+        &[
+            "-i",
+            input,
+            "-o",
+            out.to_str().expect("expect utf-8"),
+            "build",
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    let output = run(&["-i", input, "-o", "-", "build"], "");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, fs::read(&out).expect("expect ok"));
+    // The output is written straight from memory, with no class files left next to it.
+    let mut files = fs::read_dir(&src)
+        .expect("expect ok")
+        .map(|entry| entry.expect("expect ok").file_name());
+    assert!(files.all(|file| file != "Main.asm"));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn cache_hits() {
+    let dir = temp_dir("cache");
+    let src = dir.join("src");
+    fs::create_dir(&src).expect("expect ok");
+    let main = src.join("Main.vm");
+    fs::write(&main, "function Main.main 0\npush constant 1\nreturn\n").expect("expect ok");
+    fs::write(
+        src.join("Sys.vm"),
+        "function Sys.init 0\ncall Main.main 0\nreturn\n",
+    )
+    .expect("expect ok");
+    let input = src.to_str().expect("expect utf-8");
+    let out = dir.join("out.asm");
+    let args = [
+        "-i",
+        input,
+        "-o",
+        out.to_str().expect("expect utf-8"),
+        "--force",
+        "build",
+    ];
+    let output = run(&args, "");
+    assert!(output.status.success());
+    let cache = src.join(".jack-cache/classes.json");
+    assert!(cache.is_file());
+    let first = fs::read_to_string(&out).expect("expect ok");
+
+    // Nothing changed, so nothing is generated or written again.
+    let output = run(&args, "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is up to date"));
+    assert_eq!(fs::read_to_string(&out).expect("expect ok"), first);
+
+    fs::write(&main, "function Main.main 0\npush constant 2\nreturn\n").expect("expect ok");
+    let output = run(&args, "");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("is up to date"));
+    let second = fs::read_to_string(&out).expect("expect ok");
+    assert_ne!(second, first);
+
+    // Building without the cache leaves it as it was.
+    let saved = fs::read_to_string(&cache).expect("expect ok");
+    fs::write(&main, "function Main.main 0\npush constant 3\nreturn\n").expect("expect ok");
+    let output = run(&[&args[..], &["--no-cache"]].concat(), "");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&cache).expect("expect ok"), saved);
+    assert_ne!(fs::read_to_string(&out).expect("expect ok"), second);
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn manifest_in_current_dir() {
+    let dir = temp_dir("manifest");
+    fs::create_dir_all(dir.join("src")).expect("expect ok");
+    fs::create_dir_all(dir.join("lib")).expect("expect ok");
+    let manifest = "sources = [\"src\"]\nlibraries = [\"lib\"]\noutput = \"build.asm\"\n\
+        [bootstrap]\nentry = \"Main.main\"\n";
+    fs::write(dir.join("jack.toml"), manifest).expect("expect ok");
+    fs::write(
+        dir.join("src/Main.vm"),
+        "function Main.main 0\ncall Math.one 0\nreturn\n",
+    )
+    .expect("expect ok");
+    fs::write(
+        dir.join("lib/Math.vm"),
+        "function Math.one 0\npush constant 1\nreturn\n",
+    )
+    .expect("expect ok");
+    let output = run_in(&dir, &["build"], "");
+    assert!(output.status.success());
+    let asm = fs::read_to_string(dir.join("build.asm")).expect("expect ok");
+    assert!(asm.contains("@Main.main\n") && asm.contains("(Math.one)\n"));
+
+    // Denied warnings, here a call into a function no class defines, fail the check.
+    let manifest = "sources = [\"src\"]\nlibraries = [\"lib\"]\nwarnings = \"deny\"\n";
+    fs::write(dir.join("jack.toml"), manifest).expect("expect ok");
+    fs::write(
+        dir.join("src/Main.vm"),
+        "function Main.main 0\ncall Math.two 0\nreturn\n",
+    )
+    .expect("expect ok");
+    let output = run_in(&dir, &["check"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("aborting due to 1 denied warning\n"));
+    let output = run_in(&dir, &["--manifest", "none.toml", "check"], "");
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn emit_stages() {
+    let source = "function Main.main 0\npush constant 7\nreturn\n";
+    let output = run(
+        &["-i", "-", "build", "--no-boot", "--emit", "tokens"],
+        source,
+    );
+    assert!(output.status.success());
+    let tokens = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(tokens.starts_with("Main.vm:1 Function \"function\"\nMain.vm:1 Ident \"Main.main\"\n"));
+    assert!(tokens.contains("Main.vm:2 LitInt \"7\"\n"));
+
+    let output = run(
+        &["-i", "-", "build", "--no-boot", "--emit", "ast-json"],
+        source,
+    );
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(json.contains("{\"op\":\"push\",\"segment\":\"constant\",\"index\":7}"));
+
+    let output = run(
+        &["-i", "-", "build", "--no-boot", "--emit", "stats"],
+        source,
+    );
+    assert!(output.status.success());
+    let stats = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(stats.contains("\"name\": \"Main.main\""));
+
+    // Machine code is written for `--emit hack` whatever the output is called.
+    let output = run(
+        &["-i", "-", "-o", "-", "build", "--no-boot", "--emit", "hack"],
+        source,
+    );
+    assert!(output.status.success());
+    let hack = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(hack.lines().all(|line| line.len() == 16));
+    let output = run(&["-i", "-", "build", "--emit", "ir"], source);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn json_messages() {
+    let source = "function Main.main 0\ncall Main.darw 0\nreturn\nfunction Main.draw 0\nreturn\n";
+    let output = run(&["-i", "-", "--message-format", "json", "check"], source);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(
+        stderr
+            .lines()
+            .all(|line| line.starts_with('{') && line.ends_with('}'))
+    );
+    assert!(stderr.contains(
+        "\"file\":\"Main.vm\",\"span\":{\"start\":21,\"end\":37},\
+         \"suggestion\":\"did you mean `Main.draw`?\",\"lint\":\"undefined-call\"}"
+    ));
+
+    let output = run(
+        &["-i", "-", "--message-format", "json", "check"],
+        "function Main.main 0\nif-got END\n",
+    );
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("expect utf-8"),
+        "{\"code\":\"VM0003\",\"severity\":\"error\",\"message\":\"unexpected token\",\
+         \"file\":\"-\",\"span\":{\"start\":23,\"end\":24},\
+         \"suggestion\":\"did you mean `if-goto`?\"}\n"
+    );
+
+    // People get the line of the source instead.
+    let output = run(&["-i", "-", "check"], source);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "warning[undefined-call]: `Main.darw` called in Main.main (Main) is not defined\n --> \
+         Main.vm:2:1\n  |\n\
+         2 | call Main.darw 0\n  | ^^^^^^^^^^^^^^^^\n  = help: did you mean `Main.draw`?\n"
+    ));
+}
+
+#[test]
+fn render_errors() {
+    let dir = temp_dir("render");
+    let main = dir.join("Main.vm");
+    fs::write(&main, "function Main.main 0\nif-got END\n").expect("expect ok");
+    let main = main.to_str().expect("expect utf-8");
+    let output = run(&["-i", main, "check"], "");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("expect utf-8"),
+        format!(
+            "error[VM0003]: unexpected token\n --> {main}:2:3\n  |\n2 | if-got END\n  |   ^\n\
+             \x20 = help: did you mean `if-goto`?\n"
+        )
+    );
+    let output = run(&["-i", main, "--color", "always", "check"], "");
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(stderr.starts_with("\x1b[1;31merror[VM0003]\x1b[0m"));
+    let output = run_with_env(&["-i", main, "--color", "auto", "check"], "NO_COLOR", "1");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains('\x1b'));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn bootstrap_file() {
+    let dir = temp_dir("bootstrap");
+    let boot = dir.join("boot.asm");
+    fs::write(&boot, "@256\nD=A\n@SP\nM=D\n@Sys.init\n0;JMP\n").expect("expect ok");
+    let boot = boot.to_str().expect("expect utf-8");
+    let source = "function Sys.init 0\nlabel END\ngoto END\n";
+    let args = ["-i", "-", "--stdin-name", "Sys", "-o", "-", "build"];
+    let output = run(&[&args[..], &["--bootstrap-file", boot]].concat(), source);
+    assert!(output.status.success());
+    let asm = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(asm.starts_with("@256\nD=A\n@SP\nM=D\n@Sys.init\n0;JMP\n(Sys.init)\n"));
+
+    // It replaces the generated bootstrap code, so it cannot be combined with its flags.
+    let output = run(
+        &[&args[..], &["--bootstrap-file", boot, "--no-boot"]].concat(),
+        source,
+    );
+    assert!(!output.status.success());
+    let output = run(
+        &[&args[..], &["--bootstrap-file", "missing.asm"]].concat(),
+        source,
+    );
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn link_order() {
+    let dir = temp_dir("link-order");
+    for class in ["Array", "Main", "Sys"] {
+        let source = format!("function {class}.f 0\npush constant 0\nreturn\n");
+        fs::write(dir.join(format!("{class}.vm")), source).expect("expect ok");
+    }
+    let order = dir.join("order.txt");
+    fs::write(&order, "Sys\nMain\n").expect("expect ok");
+    let input = dir.to_str().expect("expect utf-8");
+    let args = ["-i", input, "-o", "-", "build", "--no-boot", "--no-cache"];
+    let output = run(&args, "");
+    let asm = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(asm.find("(Array.f)") < asm.find("(Main.f)"));
+    assert!(asm.find("(Main.f)") < asm.find("(Sys.f)"));
+
+    let order = order.to_str().expect("expect utf-8");
+    let output = run(&[&args[..], &["--link-order", order]].concat(), "");
+    assert!(output.status.success());
+    let asm = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(asm.find("(Sys.f)") < asm.find("(Main.f)"));
+    assert!(asm.find("(Main.f)") < asm.find("(Array.f)"));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn link_asm_files() {
+    let dir = temp_dir("asm");
+    fs::write(
+        dir.join("Sys.vm"),
+        "function Sys.init 0\ncall Math.one 0\ncall Game.two 0\nreturn\n",
+    )
+    .expect("expect ok");
+    fs::write(dir.join("Math.asm"), "(Math.one)\n@SP\n").expect("expect ok");
+    fs::write(dir.join("Two.asm"), "(Game.two)\n@SP\n").expect("expect ok");
+    fs::write(dir.join("out.asm"), "(Sys.init)\n").expect("expect ok");
+    let path = |file: &str| dir.join(file).to_str().expect("expect utf-8").to_owned();
+    let (input, math, two) = (path(""), path("Math.asm"), path("Two.asm"));
+
+    // Assembly files are linked when named, not when found in an input directory.
+    let output = run(
+        &["-i", &input, "-i", &math, "-o", "-", "build", "--asm", &two],
+        "",
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).is_empty());
+    let asm = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(asm.ends_with("(Math.one)\n@SP\n(Game.two)\n@SP\n"));
+    let output = run(&["-i", &input, "-i", &math, "check"], "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`Game.two` called in Sys.init"));
+
+    // Labels of the program or predefined symbols cannot be defined again.
+    for clash in ["(Sys.init)\n", "(R5)\n"] {
+        fs::write(dir.join("Math.asm"), clash).expect("expect ok");
+        let output = run(&["-i", &input, "-i", &math, "-o", "-", "build"], "");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("VM0202"));
+    }
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn stats_file() {
+    let dir = temp_dir("stats");
+    let stats = dir.join("stats.json");
+    let stats_arg = format!("--stats={}", stats.to_str().expect("expect utf-8"));
+    let source = "function Sys.init 0\nlabel END\ngoto END\n";
+    let args = ["-i", "-", "--stdin-name", "Sys", "-o", "-", "build"];
+    let output = run(&[&args[..], &[&stats_arg]].concat(), source);
+    assert!(output.status.success());
+    let json = fs::read_to_string(&stats).expect("expect ok");
+    assert!(json.contains("\"name\": \"Sys\"") && json.contains("\"rom\": "));
+
+    let output = run(&[&args[..], &["--stats"]].concat(), source);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(stderr.starts_with("class  ") && stderr.contains("ROM "));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn verbose_logs() {
+    let source = "function Sys.init 0\nlabel END\ngoto END\n";
+    let args = [
+        "-i",
+        "-",
+        "--stdin-name",
+        "Sys",
+        "-o",
+        "-",
+        "build",
+        "-O",
+        "1",
+    ];
+    let stderr = |verbose: &[&str]| {
+        let output = run(&[verbose, &args[..]].concat(), source);
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).expect("expect utf-8")
+    };
+    assert!(stderr(&[]).is_empty());
+    let info = stderr(&["-v"]);
+    assert!(info.contains("INFO parsed 1 classes") && info.contains("INFO linked 1 classes"));
+    assert!(!info.contains("DEBUG"));
+    assert!(stderr(&["-vv"]).contains("DEBUG found -\n"));
+    let trace = stderr(&["-vvv"]);
+    assert!(trace.contains("TRACE peephole left Sys.init with"));
+    // The passes are logged, not dumped.
+    assert!(!trace.contains("// Sys.init after"));
+}
+
+#[test]
+fn exit_codes() {
+    let build = ["-i", "-", "-o", "-", "build", "--no-boot"];
+    let code = |args: &[&str], stdin: &str| run(args, stdin).status.code();
+    assert_eq!(code(&build, "function Main.main 0\nreturn\n"), Some(0));
+    assert_eq!(code(&["build", "--opt-level", "9"], ""), Some(2));
+    assert_eq!(code(&["-i", "missing", "build"], ""), Some(2));
+    assert_eq!(code(&build, "push nowhere 1\n"), Some(3));
+    assert_eq!(
+        code(&build, "function Main.main 0\npush static 300\n"),
+        Some(4)
+    );
+    let unwritable = ["-i", "-", "-o", "missing/dir/out.asm", "build", "--no-boot"];
+    assert_eq!(code(&unwritable, "function Main.main 0\nreturn\n"), Some(5));
+
+    let dir = temp_dir("exit-codes");
+    fs::write(dir.join("jack.toml"), "warnings = \"deny\"\n").expect("expect ok");
+    fs::write(
+        dir.join("Main.vm"),
+        "function Main.main 0\ncall Main.draw 0\n",
+    )
+    .expect("expect ok");
+    assert_eq!(run_in(&dir, &["check"], "").status.code(), Some(4));
+    // Quiet runs still print denied warnings, as errors.
+    let output = run_in(&dir, &["--quiet", "check"], "");
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(stderr.starts_with("error[missing-entry]: entry `Sys.init` is not defined\n"));
+    assert!(stderr.ends_with("error: aborting due to 2 denied warnings\n"));
+    assert_eq!(run(&["-q", "-v", "check"], "").status.code(), Some(2));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn run_programs() {
+    let sys = "function Sys.init 0\npush constant 2\npush constant 3\nadd\npop temp 0\npush \
+               constant 1\nneg\npop temp 1\nlabel END\ngoto END\n";
+    let args = ["-i", "-", "--stdin-name", "Sys", "run", "--dump-ram", "-"];
+    let output = run(&args, sys);
+    assert!(output.status.success());
+    let ram = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(ram.lines().any(|line| line == "5 5"));
+    assert!(ram.lines().any(|line| line == "6 -1"));
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(stderr.starts_with("halted after "));
+
+    let args = [
+        "-i",
+        "-",
+        "--stdin-name",
+        "Sys",
+        "run",
+        "--max-cycles",
+        "50",
+    ];
+    let output = run(&args, sys);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("expect utf-8"),
+        "stopped after 50 cycles, still running\n"
+    );
+}
+
+#[test]
+fn test_scripts() {
+    let dir = temp_dir("test-scripts");
+    fs::write(
+        dir.join("SimpleAdd.vm"),
+        "push constant 7\npush constant 8\nadd\n",
+    )
+    .expect("expect ok");
+    let script = "load SimpleAdd.asm,\noutput-file SimpleAdd.out,\ncompare-to SimpleAdd.cmp,\n\
+                  output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2;\nset RAM[0] 256,\n\
+                  repeat 60 {\n  ticktock;\n}\noutput;\n";
+    fs::write(dir.join("SimpleAdd.tst"), script).expect("expect ok");
+    let expected = "|  RAM[0]  | RAM[256] |\n|     257  |      15  |\n";
+    fs::write(dir.join("SimpleAdd.cmp"), expected).expect("expect ok");
+    fs::write(dir.join("SimpleAddVME.tst"), "load SimpleAdd,\n").expect("expect ok");
+
+    let output = run_in(&dir, &["test"], "");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("expect utf-8"),
+        "passed ./SimpleAdd.tst\nskipped ./SimpleAddVME.tst, a script of the VM emulator\n\
+         1 passed, 0 failed, 1 skipped\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("SimpleAdd.out")).expect("expect ok"),
+        expected
+    );
+
+    fs::write(dir.join("SimpleAdd.cmp"), expected.replace("15", "16")).expect("expect ok");
+    let output = run_in(&dir, &["test"], "");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(stderr.contains("failed ./SimpleAdd.tst, line 2 of the output differs\n"));
+    assert!(stderr.ends_with("error: 1 of the test scripts failed\n"));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn clean_outputs() {
+    let dir = temp_dir("clean");
+    let manifest = "output = \"build/out.asm\"\nintermediate-dir = \"build/classes\"\n";
+    fs::write(dir.join("jack.toml"), manifest).expect("expect ok");
+    fs::write(dir.join("Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+    assert!(run_in(&dir, &["build", "--no-boot"], "").status.success());
+    let paths = [".jack-cache", "build/out.asm", "build/classes"];
+    assert!(paths.iter().all(|path| dir.join(path).exists()));
+
+    let output = run_in(&dir, &["clean", "--dry-run"], "");
+    assert!(output.status.success());
+    let listed = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert_eq!(listed.lines().count(), 3);
+    assert!(paths.iter().all(|path| dir.join(path).exists()));
+
+    let output = run_in(&dir, &["clean"], "");
+    assert!(output.status.success());
+    assert!(paths.iter().all(|path| !dir.join(path).exists()));
+    assert!(dir.join("Main.vm").exists());
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn lint_flags() {
+    let source = "function Main.main 0\nlabel UNUSED\ncall Main.draw 0\nreturn\n";
+    let check = |flags: &[&str]| {
+        let mut args = vec!["-i", "-", "--stdin-name", "Main"];
+        args.extend(flags);
+        args.extend(["check", "--no-boot"]);
+        let output = run(&args, source);
+        let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+        (output.status.code(), stderr)
+    };
+    let (code, stderr) = check(&[]);
+    assert_eq!(code, Some(0));
+    assert!(stderr.starts_with("warning[undefined-call]: "));
+    assert!(!stderr.contains("unused-label"));
+
+    let (code, stderr) = check(&["-Wunused-label", "-Aundefined-call"]);
+    assert_eq!(code, Some(0));
+    assert!(stderr.starts_with("warning[unused-label]: "));
+    assert!(!stderr.contains("undefined-call"));
+
+    let (code, stderr) = check(&["-Dundefined-call"]);
+    assert_eq!(code, Some(4));
+    assert!(stderr.starts_with("error[undefined-call]: "));
+    assert!(stderr.ends_with("error: aborting due to 1 denied warning\n"));
+    assert_eq!(check(&["--deny-warnings"]).0, Some(4));
+    assert_eq!(check(&["-Wno-such-lint"]).0, Some(2));
+}
+
+#[test]
+fn protect_outputs() {
+    let dir = temp_dir("protect");
+    let src = dir.join("src");
+    fs::create_dir(&src).expect("expect ok");
+    fs::write(src.join("Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+    let out = dir.join("out.asm");
+    let build = |args: &[&str]| run_in(&dir, &[&["-i", "src"], args, &["build"]].concat(), "");
+
+    assert!(build(&["-o", "out.asm"]).status.success());
+    let output = build(&["-o", "out.asm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("expect utf-8"),
+        "error: out.asm already exists, --force overwrites it
+"
+    );
+    assert!(build(&["-o", "out.asm", "--force"]).status.success());
+    assert!(out.is_file());
+    let output = build(&["-o", "src/out.asm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!src.join("out.asm").exists());
+
+    // Every file a build writes is checked, not only its output.
+    fs::write(dir.join("graph.dot"), "").expect("expect ok");
+    let output = build(&["-o", "-", "--call-graph", "graph.dot"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::create_dir(dir.join("classes")).expect("expect ok");
+    fs::write(dir.join("classes/Main.asm"), "").expect("expect ok");
+    let output = build(&["-o", "-", "--intermediate-dir", "classes"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run_in(&dir, &["-o", "out.asm", "completions", "bash"], "");
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn no_link() {
+    let dir = temp_dir("no-link");
+    let src = dir.join("src");
+    fs::create_dir(&src).expect("expect ok");
+    fs::write(
+        dir.join("jack.toml"),
+        "sources = [\"src\"]\noutput = \"game.asm\"\n",
+    )
+    .expect("expect ok");
+    fs::write(src.join("Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+    fs::write(
+        src.join("Sys.vm"),
+        "function Sys.init 0\ncall Main.main 0\nreturn\n",
+    )
+    .expect("expect ok");
+
+    // The output of the manifest is a file, so the classes go into `out` instead.
+    assert!(run_in(&dir, &["build", "--no-link"], "").status.success());
+    let main = fs::read_to_string(dir.join("out/Main.asm")).expect("expect ok");
+    assert!(main.starts_with("(Main.main)\n"));
+    assert!(dir.join("out/Sys.asm").is_file());
+    assert!(!dir.join("game.asm").exists());
+
+    // A directory already holding assembly is only written into with `--force`.
+    assert_eq!(
+        run_in(&dir, &["build", "--no-link"], "").status.code(),
+        Some(2)
+    );
+    assert!(
+        run_in(&dir, &["--force", "build", "--no-link"], "")
+            .status
+            .success()
+    );
+    let args = ["-o", "classes", "build", "--no-link"];
+    assert!(run_in(&dir, &args, "").status.success());
+    assert!(dir.join("classes/Main.asm").is_file());
+    let args = ["-o", "-", "build", "--no-link"];
+    assert_eq!(run_in(&dir, &args, "").status.code(), Some(2));
+    let args = ["-o", "src", "build", "--no-link"];
+    assert_eq!(run_in(&dir, &args, "").status.code(), Some(2));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn environment_variables() {
+    let dir = temp_dir("environment");
+    let src = dir.join("src");
+    fs::create_dir(&src).expect("expect ok");
+    let main = src.join("Main.vm");
+    fs::write(&main, "function Main.main 0\ncall Main.draw 0\nreturn\n").expect("expect ok");
+    let src = src.to_str().expect("expect utf-8");
+    let main = main.to_str().expect("expect utf-8");
+    let out = dir.join("env.asm");
+    let out_var = out.to_str().expect("expect utf-8");
+
+    let output = run_with_env(&["-i", src, "build"], "JACK_VM_OUTPUT", out_var);
+    assert!(output.status.success());
+    assert!(out.is_file());
+    // `fmt` prints to standard output whatever the variable says.
+    let formatted = dir.join("Main.vm");
+    let var = formatted.to_str().expect("expect utf-8");
+    let output = run_with_env(&["-i", main, "fmt"], "JACK_VM_OUTPUT", var);
+    assert!(output.status.success());
+    assert!(output.stdout.starts_with(b"function Main.main 0\n"));
+    assert!(!formatted.exists());
+
+    let check = ["-i", main, "check", "--no-boot"];
+    let output = run_with_env(&check, "JACK_VM_DENY_WARNINGS", "1");
+    assert_eq!(output.status.code(), Some(4));
+    // Set to nothing, a switch is off.
+    let output = run_with_env(&check, "JACK_VM_DENY_WARNINGS", "");
+    assert_eq!(output.status.code(), Some(0));
+    let output = run_with_env(&check, "JACK_VM_MESSAGE_FORMAT", "json");
+    assert!(output.stderr.starts_with(b"{"));
+    let output = run_with_env(
+        &["-i", src, "-o", "-", "build"],
+        "JACK_VM_NO_CACHE",
+        "maybe",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn library_path() {
+    let dir = temp_dir("library-path");
+    fs::create_dir(dir.join("src")).expect("expect ok");
+    fs::create_dir(dir.join("lib")).expect("expect ok");
+    let main = "function Main.main 0\ncall Util.abs 1\nreturn\n";
+    fs::write(dir.join("src/Main.vm"), main).expect("expect ok");
+    let util = "function Util.abs 0\nreturn\n";
+    fs::write(dir.join("lib/Util.vm"), util).expect("expect ok");
+    fs::write(
+        dir.join("lib/Unused.vm"),
+        "function Unused.clear 0\nreturn\n",
+    )
+    .expect("expect ok");
+
+    let output = run_in(&dir, &["-i", "src", "-o", "-", "build", "--no-boot"], "");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Util.abs"));
+    let args = ["-i", "src", "-L", "lib", "-o", "-", "build", "--no-boot"];
+    let output = run_in(&dir, &args, "");
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let asm = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(asm.contains("(Util.abs)\n"));
+    assert!(!asm.contains("(Unused.clear)\n"));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
+
+#[test]
+fn disassemble() {
+    let hack = "0000000000000010\n1110110000010000\n0000000000000000\n1110001100001000\n";
+    let output = run(&["-i", "-", "disasm"], hack);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"@2\nD=A\n@0\nM=D\n");
+    let output = run(&["-i", "-", "disasm"], "1110000001000000\n");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stderr.starts_with(b"error[VM0206]: "));
+}
+
+#[test]
+fn assemble_every_error() {
+    let output = run(&["-i", "-", "asm"], "D=D*M\n@1x\n");
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert_eq!(stderr.matches("error[VM0201]: ").count(), 2);
+    assert!(stderr.contains(" --> -:2:1\n"));
+    let output = run(&["-i", "-", "asm"], "(LOOP)\n(LOOP)\n(R5)\n");
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert_eq!(stderr.matches("error[VM0202]: ").count(), 2);
+}
+
+#[test]
+fn explain() {
+    let output = run(&["explain", "vm0102"], "");
+    assert!(output.status.success());
+    let explanation = String::from_utf8(output.stdout).expect("expect utf-8");
+    assert!(explanation.starts_with("VM0102: index outside of a segment\n"));
+    let output = run(&["explain", "VM9999"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("expect utf-8"),
+        "error: unknown error code VM9999, expected one like VM0102\n"
+    );
+}
+
+#[test]
+fn timings() {
+    let dir = temp_dir("timings");
+    fs::create_dir(dir.join("src")).expect("expect ok");
+    fs::write(dir.join("src/Main.vm"), "function Main.main 0\nreturn\n").expect("expect ok");
+    let args = ["-i", "src", "-o", "-", "build", "--no-boot", "--timings"];
+    let output = run_in(&dir, &args, "");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).expect("expect utf-8");
+    assert!(stderr.starts_with("file"));
+    assert!(stderr.contains("\nMain.vm "));
+
+    let args = ["-i", "src", "-o", "-", "build", "--timings=timings.json"];
+    assert!(run_in(&dir, &args, "").status.success());
+    let json = fs::read_to_string(dir.join("timings.json")).expect("expect ok");
+    assert!(json.contains("\"class\": \"Main\""));
+    // The report is an output like any other, only overwritten with `--force`.
+    assert_eq!(run_in(&dir, &args, "").status.code(), Some(2));
+    let args = ["-i", "src", "build", "--check", "--timings"];
+    assert_eq!(run_in(&dir, &args, "").status.code(), Some(2));
+    fs::remove_dir_all(&dir).expect("expect ok");
+}
@@ -0,0 +1,913 @@
+use crate::layout::{ARG, KBD, LCL, SCREEN, SP, THAT, THIS};
+use alloc::borrow::ToOwned;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+use core::ops::Range;
+use core::str::FromStr;
+use derive_more::Display;
+use snafu::{OptionExt, Snafu};
+#[cfg(feature = "std")]
+use std::io;
+
+#[derive(Snafu, Debug, PartialEq, Clone)]
+pub enum Error {
+    #[snafu(display("invalid assembly instruction `{line}`"))]
+    InvalidInstr { line: String },
+    #[snafu(display("label `{label}` is defined more than once"))]
+    DuplicateLabel { label: String },
+    #[snafu(display("label `{label}` redefines a predefined symbol"))]
+    PredefinedLabel { label: String },
+    #[snafu(display("no address left for variable `{symbol}`"))]
+    VariableOverflow { symbol: String },
+    #[snafu(display(
+        "program takes {instructions} instructions, more than the {ROM_SIZE} the ROM holds"
+    ))]
+    RomOverflow { instructions: usize },
+    #[snafu(display("invalid line `{line}` of machine code, expected 16 binary digits"))]
+    InvalidHack { line: String },
+    #[snafu(display("word {word:016b} at {address} is not a Hack instruction"))]
+    InvalidWord { word: u16, address: usize },
+}
+
+impl Error {
+    /// Stable code of the error, see [`crate::Error::code`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidInstr { .. } => "VM0201",
+            Error::DuplicateLabel { .. } | Error::PredefinedLabel { .. } => "VM0202",
+            Error::VariableOverflow { .. } => "VM0203",
+            Error::RomOverflow { .. } => "VM0204",
+            Error::InvalidHack { .. } => "VM0205",
+            Error::InvalidWord { .. } => "VM0206",
+        }
+    }
+}
+
+/// Number of instructions the Hack ROM holds.
+pub const ROM_SIZE: usize = 32768;
+
+/// Operand of an A-instruction.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Display)]
+pub enum Addr {
+    Constant(u16),
+    Symbol(String),
+}
+
+impl From<u16> for Addr {
+    fn from(value: u16) -> Self {
+        Addr::Constant(value)
+    }
+}
+
+impl From<&str> for Addr {
+    fn from(symbol: &str) -> Self {
+        Addr::Symbol(symbol.to_owned())
+    }
+}
+
+impl From<String> for Addr {
+    fn from(symbol: String) -> Self {
+        Addr::Symbol(symbol)
+    }
+}
+
+/// Registers a C-instruction stores its result in.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct Dest {
+    pub a: bool,
+    pub d: bool,
+    pub m: bool,
+}
+
+impl Dest {
+    pub const NONE: Dest = Dest::new(false, false, false);
+    pub const A: Dest = Dest::new(true, false, false);
+    pub const D: Dest = Dest::new(false, true, false);
+    pub const M: Dest = Dest::new(false, false, true);
+    pub const AM: Dest = Dest::new(true, false, true);
+    pub const AD: Dest = Dest::new(true, true, false);
+    pub const MD: Dest = Dest::new(false, true, true);
+    pub const AMD: Dest = Dest::new(true, true, true);
+
+    const fn new(a: bool, d: bool, m: bool) -> Self {
+        Self { a, d, m }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Dest::NONE
+    }
+
+    /// The d1 to d3 bits of a C-instruction.
+    pub fn bits(&self) -> u16 {
+        (self.a as u16) << 2 | (self.d as u16) << 1 | self.m as u16
+    }
+}
+
+impl fmt::Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (set, register) in [(self.a, 'A'), (self.m, 'M'), (self.d, 'D')] {
+            if set {
+                write!(f, "{register}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Dest {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut dest = Dest::NONE;
+        for register in s.chars() {
+            let set = match register {
+                'A' => &mut dest.a,
+                'D' => &mut dest.d,
+                'M' => &mut dest.m,
+                _ => return Err(()),
+            };
+            if *set {
+                return Err(());
+            }
+            *set = true;
+        }
+        Ok(dest)
+    }
+}
+
+/// Computations of the Hack ALU, displayed in their canonical spelling.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Display)]
+pub enum Comp {
+    #[display("0")]
+    Zero,
+    #[display("1")]
+    One,
+    #[display("-1")]
+    MinusOne,
+    #[display("D")]
+    D,
+    #[display("A")]
+    A,
+    #[display("M")]
+    M,
+    #[display("!D")]
+    NotD,
+    #[display("!A")]
+    NotA,
+    #[display("!M")]
+    NotM,
+    #[display("-D")]
+    NegD,
+    #[display("-A")]
+    NegA,
+    #[display("-M")]
+    NegM,
+    #[display("D+1")]
+    DPlusOne,
+    #[display("A+1")]
+    APlusOne,
+    #[display("M+1")]
+    MPlusOne,
+    #[display("D-1")]
+    DMinusOne,
+    #[display("A-1")]
+    AMinusOne,
+    #[display("M-1")]
+    MMinusOne,
+    #[display("D+A")]
+    DPlusA,
+    #[display("D+M")]
+    DPlusM,
+    #[display("D-A")]
+    DMinusA,
+    #[display("D-M")]
+    DMinusM,
+    #[display("A-D")]
+    AMinusD,
+    #[display("M-D")]
+    MMinusD,
+    #[display("D&A")]
+    DAndA,
+    #[display("D&M")]
+    DAndM,
+    #[display("D|A")]
+    DOrA,
+    #[display("D|M")]
+    DOrM,
+}
+
+impl Comp {
+    pub const ALL: [Comp; 28] = [
+        Comp::Zero,
+        Comp::One,
+        Comp::MinusOne,
+        Comp::D,
+        Comp::A,
+        Comp::M,
+        Comp::NotD,
+        Comp::NotA,
+        Comp::NotM,
+        Comp::NegD,
+        Comp::NegA,
+        Comp::NegM,
+        Comp::DPlusOne,
+        Comp::APlusOne,
+        Comp::MPlusOne,
+        Comp::DMinusOne,
+        Comp::AMinusOne,
+        Comp::MMinusOne,
+        Comp::DPlusA,
+        Comp::DPlusM,
+        Comp::DMinusA,
+        Comp::DMinusM,
+        Comp::AMinusD,
+        Comp::MMinusD,
+        Comp::DAndA,
+        Comp::DAndM,
+        Comp::DOrA,
+        Comp::DOrM,
+    ];
+
+    /// Whether the computation reads the memory at A.
+    pub fn reads_m(&self) -> bool {
+        matches!(
+            self,
+            Comp::M
+                | Comp::NotM
+                | Comp::NegM
+                | Comp::MPlusOne
+                | Comp::MMinusOne
+                | Comp::DPlusM
+                | Comp::DMinusM
+                | Comp::MMinusD
+                | Comp::DAndM
+                | Comp::DOrM
+        )
+    }
+}
+
+impl Comp {
+    /// The a and c1 to c6 bits of a C-instruction.
+    pub fn bits(&self) -> u16 {
+        let (a, c) = match self {
+            Comp::Zero => (0, 0b101010),
+            Comp::One => (0, 0b111111),
+            Comp::MinusOne => (0, 0b111010),
+            Comp::D => (0, 0b001100),
+            Comp::A => (0, 0b110000),
+            Comp::M => (1, 0b110000),
+            Comp::NotD => (0, 0b001101),
+            Comp::NotA => (0, 0b110001),
+            Comp::NotM => (1, 0b110001),
+            Comp::NegD => (0, 0b001111),
+            Comp::NegA => (0, 0b110011),
+            Comp::NegM => (1, 0b110011),
+            Comp::DPlusOne => (0, 0b011111),
+            Comp::APlusOne => (0, 0b110111),
+            Comp::MPlusOne => (1, 0b110111),
+            Comp::DMinusOne => (0, 0b001110),
+            Comp::AMinusOne => (0, 0b110010),
+            Comp::MMinusOne => (1, 0b110010),
+            Comp::DPlusA => (0, 0b000010),
+            Comp::DPlusM => (1, 0b000010),
+            Comp::DMinusA => (0, 0b010011),
+            Comp::DMinusM => (1, 0b010011),
+            Comp::AMinusD => (0, 0b000111),
+            Comp::MMinusD => (1, 0b000111),
+            Comp::DAndA => (0, 0b000000),
+            Comp::DAndM => (1, 0b000000),
+            Comp::DOrA => (0, 0b010101),
+            Comp::DOrM => (1, 0b010101),
+        };
+        a << 6 | c
+    }
+}
+
+impl FromStr for Comp {
+    type Err = ();
+
+    /// Accepts the canonical spelling, and the commutative operations on two registers with swapped
+    /// operands.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let swapped = match s.split_once(['+', '&', '|']) {
+            Some((x, y))
+                if [x, y]
+                    .iter()
+                    .all(|operand| ["A", "D", "M"].contains(operand)) =>
+            {
+                format!("{y}{}{x}", &s[x.len()..x.len() + 1])
+            }
+            _ => String::new(),
+        };
+        Comp::ALL
+            .into_iter()
+            .find(|comp| {
+                let canonical = comp.to_string();
+                canonical == s || canonical == swapped
+            })
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Display)]
+pub enum Jump {
+    #[display("JGT")]
+    Greater,
+    #[display("JEQ")]
+    Equal,
+    #[display("JGE")]
+    GreaterEqual,
+    #[display("JLT")]
+    Less,
+    #[display("JNE")]
+    NotEqual,
+    #[display("JLE")]
+    LessEqual,
+    #[display("JMP")]
+    Always,
+}
+
+impl Jump {
+    pub const ALL: [Jump; 7] = [
+        Jump::Greater,
+        Jump::Equal,
+        Jump::GreaterEqual,
+        Jump::Less,
+        Jump::NotEqual,
+        Jump::LessEqual,
+        Jump::Always,
+    ];
+}
+
+impl Jump {
+    /// The j1 to j3 bits of a C-instruction.
+    pub fn bits(&self) -> u16 {
+        match self {
+            Jump::Greater => 0b001,
+            Jump::Equal => 0b010,
+            Jump::GreaterEqual => 0b011,
+            Jump::Less => 0b100,
+            Jump::NotEqual => 0b101,
+            Jump::LessEqual => 0b110,
+            Jump::Always => 0b111,
+        }
+    }
+}
+
+impl FromStr for Jump {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Jump::ALL
+            .into_iter()
+            .find(|jump| jump.to_string() == s)
+            .ok_or(())
+    }
+}
+
+/// One line of Hack assembly.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum AsmInstr {
+    /// `@addr`
+    A(Addr),
+    /// `dest=comp;jump`
+    C {
+        dest: Dest,
+        comp: Comp,
+        jump: Option<Jump>,
+    },
+    /// `(label)`, which occupies no ROM.
+    Label(String),
+    /// `// comment`, which occupies no ROM.
+    Comment(String),
+}
+
+impl AsmInstr {
+    /// Whether the instruction ends up in ROM.
+    pub fn is_code(&self) -> bool {
+        matches!(self, AsmInstr::A(_) | AsmInstr::C { .. })
+    }
+
+    /// Whether executing the instruction changes A.
+    pub fn writes_a(&self) -> bool {
+        matches!(self, AsmInstr::C { dest, .. } if dest.a)
+    }
+}
+
+impl fmt::Display for AsmInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmInstr::A(addr) => write!(f, "@{addr}"),
+            AsmInstr::C { dest, comp, jump } => {
+                if !dest.is_empty() {
+                    write!(f, "{dest}=")?;
+                }
+                write!(f, "{comp}")?;
+                match jump {
+                    Some(jump) => write!(f, ";{jump}"),
+                    None => Ok(()),
+                }
+            }
+            AsmInstr::Label(label) => write!(f, "({label})"),
+            AsmInstr::Comment(comment) => write!(f, "// {comment}"),
+        }
+    }
+}
+
+impl FromStr for AsmInstr {
+    type Err = Error;
+
+    /// Parses a single line, which must not be blank.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidInstrSnafu { line }.build();
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix("//") {
+            return Ok(AsmInstr::Comment(comment.trim_start().to_owned()));
+        }
+        if let Some(addr) = line.strip_prefix('@') {
+            return match addr.parse() {
+                Ok(value) if value <= i16::MAX as u16 => Ok(at(Addr::Constant(value))),
+                Ok(_) => Err(invalid()),
+                Err(_) if addr.is_empty() || addr.starts_with(|c: char| c.is_ascii_digit()) => {
+                    Err(invalid())
+                }
+                Err(_) => Ok(at(addr)),
+            };
+        }
+        if let Some(label) = line
+            .strip_prefix('(')
+            .and_then(|label| label.strip_suffix(')'))
+        {
+            return Ok(AsmInstr::Label(label.to_owned()));
+        }
+        let (dest, rest) = line.split_once('=').unwrap_or(("", line));
+        let (comp, jump) = match rest.split_once(';') {
+            Some((comp, jump)) => (comp, Some(jump.parse().map_err(|_| invalid())?)),
+            None => (rest, None),
+        };
+        Ok(AsmInstr::C {
+            dest: dest.parse().map_err(|_| invalid())?,
+            comp: comp.parse().map_err(|_| invalid())?,
+            jump,
+        })
+    }
+}
+
+/// `@addr`
+pub fn at(addr: impl Into<Addr>) -> AsmInstr {
+    AsmInstr::A(addr.into())
+}
+
+/// `dest=comp`
+pub fn set(dest: Dest, comp: Comp) -> AsmInstr {
+    AsmInstr::C {
+        dest,
+        comp,
+        jump: None,
+    }
+}
+
+/// `comp;jump`
+pub fn jump(comp: Comp, jump: Jump) -> AsmInstr {
+    AsmInstr::C {
+        dest: Dest::NONE,
+        comp,
+        jump: Some(jump),
+    }
+}
+
+/// `(name)`
+pub fn label(name: impl Into<String>) -> AsmInstr {
+    AsmInstr::Label(name.into())
+}
+
+/// Whether `symbol` can name a label or variable: letters, digits, `_`, `.`, `$` and `:`, not
+/// starting with a digit.
+pub fn is_symbol(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && !symbol.starts_with(|c: char| c.is_ascii_digit())
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':'))
+}
+
+/// Length in bytes of a rendered line, newline included, to reserve per instruction. Most lines
+/// are shorter, labels and symbols are longer.
+pub const AVERAGE_LINE_LEN: usize = 6;
+
+/// Renders `asm` one instruction per line.
+pub fn render(asm: &[AsmInstr]) -> String {
+    let mut rendered = String::with_capacity(asm.len() * AVERAGE_LINE_LEN);
+    render_to(asm, &mut rendered).expect("writing to a string never fails");
+    rendered
+}
+
+/// Renders `asm` like [`render`] into `w`.
+pub fn render_to<W: fmt::Write + ?Sized>(asm: &[AsmInstr], w: &mut W) -> fmt::Result {
+    asm.iter().try_for_each(|instr| writeln!(w, "{instr}"))
+}
+
+/// Renders `asm` like [`render`] into `w`.
+#[cfg(feature = "std")]
+pub fn write_to<W: io::Write + ?Sized>(asm: &[AsmInstr], w: &mut W) -> io::Result<()> {
+    asm.iter().try_for_each(|instr| writeln!(w, "{instr}"))
+}
+
+/// Addresses of the symbols every Hack program can use.
+pub const PREDEFINED: [(&str, u16); 23] = [
+    ("SP", SP),
+    ("LCL", LCL),
+    ("ARG", ARG),
+    ("THIS", THIS),
+    ("THAT", THAT),
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", SCREEN),
+    ("KBD", KBD),
+];
+
+/// Assembles `asm` into Hack machine code. Symbols that are neither predefined nor labels are
+/// variables, allocated from `variables` in order of appearance. Constants past 32767 fail, as the
+/// first bit of a word tells C-instructions apart. Fails with the first error, see
+/// [`assemble_all`] for every error.
+pub fn assemble(asm: &[AsmInstr], variables: Range<u16>) -> Result<Vec<u16>, Error> {
+    assemble_all(asm, variables).map_err(|errors| {
+        let (_, first) = errors.into_iter().next().expect("failures hold an error");
+        first
+    })
+}
+
+/// Assembles `asm` like [`assemble`] in two passes, the first giving each label the address of the
+/// instruction after it, the second resolving the other symbols and encoding every instruction.
+/// Fails with every error found, each along with the index in `asm` of the instruction it is
+/// about, none for the overflow of the ROM.
+pub fn assemble_all(
+    asm: &[AsmInstr],
+    variables: Range<u16>,
+) -> Result<Vec<u16>, Vec<(Option<usize>, Error)>> {
+    let mut errors = vec![];
+    let mut symbols = PREDEFINED.into_iter().collect::<BTreeMap<_, _>>();
+    let instructions = define_labels(asm, &mut symbols, &mut errors);
+    if instructions > ROM_SIZE {
+        errors.push((None, Error::RomOverflow { instructions }));
+    }
+    let binary = encode(asm, &mut symbols, variables, &mut errors);
+    if errors.is_empty() {
+        Ok(binary)
+    } else {
+        Err(errors)
+    }
+}
+
+/// First pass of [`assemble_all`]: gives each label of `asm` the address of the instruction after
+/// it in `symbols`, returning how many instructions there are.
+fn define_labels<'a>(
+    asm: &'a [AsmInstr],
+    symbols: &mut BTreeMap<&'a str, u16>,
+    errors: &mut Vec<(Option<usize>, Error)>,
+) -> usize {
+    let mut address = 0;
+    for (index, instr) in asm.iter().enumerate() {
+        match instr {
+            AsmInstr::Label(label) if PREDEFINED.iter().any(|(symbol, _)| symbol == label) => {
+                errors.push((
+                    Some(index),
+                    Error::PredefinedLabel {
+                        label: label.clone(),
+                    },
+                ));
+            }
+            AsmInstr::Label(label) if symbols.contains_key(label.as_str()) => {
+                errors.push((
+                    Some(index),
+                    Error::DuplicateLabel {
+                        label: label.clone(),
+                    },
+                ));
+            }
+            AsmInstr::Label(label) => {
+                // Addresses past the ROM are reported as an overflow of the ROM.
+                symbols.insert(label, address.min(ROM_SIZE) as u16);
+            }
+            instr if instr.is_code() => address += 1,
+            _ => {}
+        }
+    }
+    address
+}
+
+/// Second pass of [`assemble_all`]: encodes `asm`, allocating a variable from `variables` to each
+/// symbol first used that is not in `symbols`.
+fn encode<'a>(
+    asm: &'a [AsmInstr],
+    symbols: &mut BTreeMap<&'a str, u16>,
+    mut variables: Range<u16>,
+    errors: &mut Vec<(Option<usize>, Error)>,
+) -> Vec<u16> {
+    let mut binary = Vec::with_capacity(asm.len());
+    for (index, instr) in asm.iter().enumerate() {
+        match instr {
+            AsmInstr::A(Addr::Constant(value)) if *value > i16::MAX as u16 => {
+                errors.push((
+                    Some(index),
+                    Error::InvalidInstr {
+                        line: instr.to_string(),
+                    },
+                ));
+            }
+            AsmInstr::A(Addr::Constant(value)) => binary.push(*value),
+            AsmInstr::A(Addr::Symbol(symbol)) => match symbols.get(symbol.as_str()) {
+                Some(address) => binary.push(*address),
+                None => match variables.next() {
+                    Some(address) => {
+                        symbols.insert(symbol, address);
+                        binary.push(address);
+                    }
+                    None => errors.push((
+                        Some(index),
+                        Error::VariableOverflow {
+                            symbol: symbol.clone(),
+                        },
+                    )),
+                },
+            },
+            AsmInstr::C { dest, comp, jump } => {
+                let jump = jump.map_or(0, |jump| jump.bits());
+                binary.push(0b111 << 13 | comp.bits() << 6 | dest.bits() << 3 | jump);
+            }
+            AsmInstr::Label(_) | AsmInstr::Comment(_) => {}
+        }
+    }
+    binary
+}
+
+/// Renders machine code in the text-based `.hack` format, one instruction per line as 16 binary
+/// digits.
+pub fn render_hack(binary: &[u16]) -> String {
+    binary.iter().map(|word| format!("{word:016b}\n")).collect()
+}
+
+/// Parses machine code in the text-based `.hack` format, skipping blank lines.
+pub fn parse_hack(hack: &str) -> Result<Vec<u16>, Error> {
+    hack.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match u16::from_str_radix(line, 2) {
+            Ok(word) if line.len() == 16 && !line.starts_with('+') => Ok(word),
+            _ => InvalidHackSnafu { line }.fail(),
+        })
+        .collect()
+}
+
+/// The instruction `word` encodes, if any. Like the CPU, the two bits of C-instructions after the
+/// first are ignored.
+pub fn decode(word: u16) -> Option<AsmInstr> {
+    if word & 0x8000 == 0 {
+        return Some(at(word));
+    }
+    let comp = Comp::ALL
+        .into_iter()
+        .find(|comp| comp.bits() == word >> 6 & 0x7f)?;
+    let dest = Dest::new(
+        word & 0b100000 != 0,
+        word & 0b10000 != 0,
+        word & 0b1000 != 0,
+    );
+    let jump = Jump::ALL
+        .into_iter()
+        .find(|jump| jump.bits() == word & 0b111);
+    Some(AsmInstr::C { dest, comp, jump })
+}
+
+/// Turns machine code back into assembly. A-instructions right before a jump load its target, so
+/// they refer to a label `L<address>` placed at the target instead, when it is in the program.
+pub fn disassemble(binary: &[u16]) -> Result<Vec<AsmInstr>, Error> {
+    let mut code = binary
+        .iter()
+        .enumerate()
+        .map(|(address, word)| {
+            decode(*word).context(InvalidWordSnafu {
+                word: *word,
+                address,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut targets = BTreeSet::new();
+    for index in 1..code.len() {
+        if let [
+            AsmInstr::A(Addr::Constant(target)),
+            AsmInstr::C { jump: Some(_), .. },
+        ] = &code[index - 1..=index]
+            && usize::from(*target) <= code.len()
+        {
+            targets.insert(*target);
+            code[index - 1] = at(format!("L{target}"));
+        }
+    }
+    let mut asm = Vec::with_capacity(code.len() + targets.len());
+    for (address, instr) in code.into_iter().enumerate() {
+        if targets.contains(&(address as u16)) {
+            asm.push(label(format!("L{address}")));
+        }
+        asm.push(instr);
+    }
+    if targets.contains(&(binary.len() as u16)) {
+        asm.push(label(format!("L{}", binary.len())));
+    }
+    Ok(asm)
+}
+
+/// Parses `asm`, skipping blank lines.
+pub fn parse_asm(asm: &str) -> Result<Vec<AsmInstr>, Error> {
+    asm.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{
+        AsmInstr, Comp, Dest, Error, Jump, ROM_SIZE, assemble, assemble_all, at, decode,
+        disassemble, is_symbol, jump, label, parse_asm, parse_hack, render, render_hack, set,
+    };
+
+    #[test]
+    fn render_asm() {
+        let asm = vec![
+            at("SP"),
+            set(Dest::AM, Comp::MMinusOne),
+            at(7),
+            jump(Comp::D, Jump::NotEqual),
+            label("Foo.bar"),
+            AsmInstr::Comment("push constant 7".to_owned()),
+            set(Dest::AMD, Comp::DAndM),
+        ];
+        assert_eq!(
+            render(&asm),
+            "@SP\nAM=M-1\n@7\nD;JNE\n(Foo.bar)\n// push constant 7\nAMD=D&M\n"
+        );
+    }
+
+    #[test]
+    fn parse_canonical() {
+        const TESTING_ASM: &str =
+            "@SP\nAM=M-1\n\n@7\nD;JNE\n(Foo.bar)\n// push constant 7\nAMD=D&M\n0;JMP\n";
+        let asm = parse_asm(TESTING_ASM).expect("expect ok");
+        assert_eq!(render(&asm), TESTING_ASM.replace("\n\n", "\n"));
+        assert_eq!(asm[2], at(7));
+    }
+
+    #[test]
+    fn parse_swapped_operands() {
+        let asm = parse_asm("M=M&D\nM=M|D\nD=A+D\nDM=M+1").expect("expect ok");
+        assert_eq!(render(&asm), "M=D&M\nM=D|M\nD=D+A\nMD=M+1\n");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for line in ["M=M*D", "@", "@32768", "@1x", "D;JMQ", "MM=D", "D=1+M"] {
+            line.parse::<AsmInstr>().expect_err("expect err");
+        }
+    }
+
+    #[test]
+    fn assemble_add() {
+        let asm = parse_asm("@2\nD=A\n@3\nD=D+A\n@0\nM=D\n").expect("expect ok");
+        let binary = assemble(&asm, 16..256).expect("expect ok");
+        assert_eq!(
+            render_hack(&binary),
+            "0000000000000010\n1110110000010000\n0000000000000011\n\
+            1110000010010000\n0000000000000000\n1110001100001000\n"
+        );
+    }
+
+    #[test]
+    fn assemble_symbols() {
+        let asm = parse_asm(
+            "// loop\n(LOOP)\n@i\nM=1\n@j\nAM=M-1;JNE\n@i\n@END\n0;JMP\n(END)\n@KBD\n@R13",
+        )
+        .expect("expect ok");
+        let binary = assemble(&asm, 16..256).expect("expect ok");
+        assert_eq!(
+            binary,
+            vec![16, 0xefc8, 17, 0xfcad, 16, 7, 0xea87, 24576, 13]
+        );
+        assemble(&asm, 16..17).expect_err("expect err");
+        assemble(&parse_asm("(A)\n(A)").expect("expect ok"), 16..256).expect_err("expect err");
+        let error = assemble(&[at(40000)], 16..256).expect_err("expect err");
+        assert_eq!(error.to_string(), "invalid assembly instruction `@40000`");
+    }
+
+    #[test]
+    fn assemble_every_error() {
+        let asm = parse_asm("(LOOP)\n@i\n(LOOP)\n(R5)\n@j\n@k\n@R5").expect("expect ok");
+        let errors = assemble_all(&asm, 16..17).expect_err("expect err");
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    Some(2),
+                    Error::DuplicateLabel {
+                        label: "LOOP".to_owned()
+                    }
+                ),
+                (
+                    Some(3),
+                    Error::PredefinedLabel {
+                        label: "R5".to_owned()
+                    }
+                ),
+                (
+                    Some(4),
+                    Error::VariableOverflow {
+                        symbol: "j".to_owned()
+                    }
+                ),
+                (
+                    Some(5),
+                    Error::VariableOverflow {
+                        symbol: "k".to_owned()
+                    }
+                ),
+            ]
+        );
+        assert!(errors.iter().all(|(_, error)| error.code() != "VM0201"));
+        // Predefined symbols keep their address.
+        let error = assemble(&asm[3..], 16..256).expect_err("expect err");
+        assert_eq!(error.code(), "VM0202");
+    }
+
+    #[test]
+    fn disassemble_with_labels() {
+        let asm = parse_asm("@2\nD=A\n(LOOP)\n@LOOP\nD=D-1;JGT\n@END\n0;JMP\n@16\nAMD=!M\n(END)")
+            .expect("expect ok");
+        let binary = assemble(&asm, 16..256).expect("expect ok");
+        let hack = render_hack(&binary);
+        assert_eq!(parse_hack(&format!("{hack}\n")).expect("expect ok"), binary);
+        let disassembled = disassemble(&binary).expect("expect ok");
+        assert_eq!(
+            render(&disassembled),
+            "@2\nD=A\n(L2)\n@L2\nD=D-1;JGT\n@L8\n0;JMP\n@16\nAMD=!M\n(L8)\n"
+        );
+        assert_eq!(assemble(&disassembled, 16..256).expect("expect ok"), binary);
+
+        assert_eq!(
+            decode(0xe000),
+            Some(AsmInstr::C {
+                dest: Dest::NONE,
+                comp: Comp::DAndA,
+                jump: None
+            })
+        );
+        assert_eq!(decode(0xe040), None);
+        disassemble(&[0, 0xe040]).expect_err("expect err");
+        for line in ["101", "00000000000000002", "+000000000000001"] {
+            parse_hack(line).expect_err("expect err");
+        }
+    }
+
+    #[test]
+    fn symbols() {
+        assert!(is_symbol("Foo.bar$ret:1"));
+        assert!(is_symbol("_tmp"));
+        assert!(!is_symbol("1st"));
+        assert!(!is_symbol("my-class"));
+        assert!(!is_symbol(""));
+    }
+
+    #[test]
+    fn assemble_rom_overflow() {
+        let mut asm = vec![set(Dest::D, Comp::Zero); ROM_SIZE];
+        asm.push(label("END"));
+        assert_eq!(assemble(&asm, 16..256).expect("expect ok").len(), ROM_SIZE);
+        asm.push(set(Dest::D, Comp::Zero));
+        let errors = assemble_all(&asm, 16..256).expect_err("expect err");
+        assert_eq!(
+            errors,
+            vec![(
+                None,
+                Error::RomOverflow {
+                    instructions: ROM_SIZE + 1
+                }
+            )]
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::asm::{AsmInstr, parse_asm, render};
+use crate::generate::{Class, Context, Error, Generate, GenerateOptions, Helper, LabelGen};
+use crate::source::Mapping;
+use crate::stats::FunctionStats;
+use alloc::borrow::ToOwned;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::hash::Hasher;
+use serde::{Deserialize, Serialize};
+
+/// Version of the generator, part of every key and of the saved outputs, as another version may
+/// generate the same class differently.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// 64-bit FNV-1a, which hashes the same on every run and platform.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+impl Write for Fnv {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Hasher::write(self, s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Hash of everything `value` prints in its debug representation.
+fn debug_hash(value: &impl fmt::Debug) -> u64 {
+    let mut hasher = Fnv::new();
+    write!(hasher, "{value:?}").expect("hashing never fails");
+    hasher.finish()
+}
+
+/// Output of one class, along with what generating it left in its context.
+#[derive(Debug, Clone)]
+struct Entry {
+    asm: Vec<AsmInstr>,
+    ctx: Context,
+}
+
+/// The outputs of a [`CompileCache`] as [`CompileCache::to_json`] saves them.
+#[derive(Serialize, Deserialize)]
+struct SavedCache {
+    /// [Version](VERSION) of the generator that saved the outputs.
+    version: String,
+    entries: Vec<Saved>,
+}
+
+/// An [`Entry`] as [`CompileCache::to_json`] saves it.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    key: (u64, u64),
+    asm: String,
+    usage: Vec<FunctionStats>,
+    helpers: Vec<Helper>,
+    labels: usize,
+    mappings: Vec<Mapping>,
+}
+
+impl Saved {
+    fn new(key: (u64, u64), entry: &Entry) -> Self {
+        Self {
+            key,
+            asm: render(&entry.asm),
+            usage: entry.ctx.usage.clone(),
+            helpers: entry.ctx.helpers.iter().copied().collect(),
+            labels: entry.ctx.labels.next,
+            mappings: entry.ctx.source_map.mappings.clone(),
+        }
+    }
+
+    fn restore(self) -> Option<((u64, u64), Entry)> {
+        let asm = parse_asm(&self.asm).ok()?;
+        let mut ctx = Context {
+            labels: LabelGen { next: self.labels },
+            line: asm.len(),
+            usage: self.usage,
+            helpers: self.helpers.into_iter().collect(),
+            ..Default::default()
+        };
+        ctx.source_map.mappings = self.mappings;
+        Some((self.key, Entry { asm, ctx }))
+    }
+}
+
+/// Generated output of classes, keyed by the hash of the class and the hash of the options it was
+/// generated with, so building a project again only generates the classes that changed.
+///
+/// A class covers its functions and their spans in the source, so moving a function within its
+/// file generates it again, as its annotations and source map point elsewhere.
+///
+/// Each class is generated on its own, its synthesized labels numbered from the
+/// [seed](GenerateOptions::label_seed) whatever was generated before. Labels stay unique as they
+/// are scoped to the function, and a class generates the same output whether it was cached or
+/// not. Contexts with hooks bypass the cache, as their output depends on more than the options, and
+/// so does instrumented code, whose counters depend on the classes generated before.
+///
+/// The outputs can be [saved](CompileCache::to_json) between builds, say to disk, so a build only
+/// generates the classes changed since the last one.
+#[derive(Debug, Clone, Default)]
+pub struct CompileCache {
+    entries: BTreeMap<(u64, u64), Entry>,
+    /// Keys of the outputs lowered since the cache was created or restored.
+    used: BTreeSet<(u64, u64)>,
+    hits: usize,
+    misses: usize,
+}
+
+impl CompileCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lowers `class` like [`Generate::lower`], reusing the output of a class with the same hash
+    /// generated with the same options by the same version.
+    pub fn lower(&mut self, class: &Class, ctx: &mut Context) -> Result<Vec<AsmInstr>, Error> {
+        if !ctx.hooks.is_empty() || ctx.options.instrument {
+            return class.lower(ctx);
+        }
+        let key = (debug_hash(class), debug_hash(&(VERSION, &ctx.options)));
+        self.used.insert(key);
+        let entry = match self.entries.get(&key) {
+            Some(entry) => {
+                self.hits += 1;
+                entry
+            }
+            None => {
+                self.misses += 1;
+                let mut fresh = Context::new(ctx.options.clone());
+                let asm = class.lower(&mut fresh)?;
+                self.entries.entry(key).or_insert(Entry { asm, ctx: fresh })
+            }
+        };
+        ctx.usage.extend(entry.ctx.usage.iter().cloned());
+        ctx.join(entry.ctx.clone());
+        Ok(entry.asm.clone())
+    }
+
+    /// Generates `class` like [`Generate::generate_with`], see [`CompileCache::lower`].
+    pub fn generate(&mut self, class: &Class, ctx: &mut Context) -> Result<String, Error> {
+        Ok(render(&self.lower(class, ctx)?))
+    }
+
+    /// Generates `classes` into one output with `options`, shared routines included, generating
+    /// only the classes missing from the cache.
+    pub fn generate_all(
+        &mut self,
+        classes: &[Class],
+        options: &GenerateOptions,
+    ) -> Result<String, Error> {
+        let mut ctx = Context::new(options.clone());
+        let mut asm = ctx.lower_bootstrap();
+        for class in classes {
+            asm.extend(self.lower(class, &mut ctx)?);
+        }
+        asm.extend(ctx.lower_helpers());
+        ctx.check_rom()?;
+        Ok(render(&asm))
+    }
+
+    /// Number of classes generated again from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of classes generated because the cache held no output for them.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Number of outputs held.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops every output, keeping the counts.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used.clear();
+    }
+
+    /// Drops the outputs not lowered since the cache was created or restored, such as those of
+    /// classes changed or removed since.
+    pub fn prune(&mut self) {
+        self.entries.retain(|key, _| self.used.contains(key));
+    }
+
+    /// Saves the outputs held into JSON, along with the version of the generator, which
+    /// [`CompileCache::from_json`] restores. Outputs recording
+    /// [pass dumps](crate::optimize::OptPipeline::dump) are left out, as the dumps are not saved.
+    pub fn to_json(&self) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.ctx.dumps.is_empty())
+            .map(|(key, entry)| Saved::new(*key, entry))
+            .collect::<Vec<_>>();
+        let saved = SavedCache {
+            version: VERSION.to_owned(),
+            entries,
+        };
+        serde_json::to_string(&saved).expect("cache is always serializable")
+    }
+
+    /// Restores the outputs saved by [`CompileCache::to_json`], counting from 0. Outputs whose
+    /// assembly no longer parses are dropped, and every output if another version saved them.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let saved = serde_json::from_str::<SavedCache>(json)?;
+        if saved.version != VERSION {
+            return Ok(Self::default());
+        }
+        Ok(Self {
+            entries: saved
+                .entries
+                .into_iter()
+                .filter_map(Saved::restore)
+                .collect(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::asm::render;
+    use crate::cache::{CompileCache, VERSION};
+    use crate::generate::{BootstrapOptions, Class, Context, Generate, GenerateOptions, OptLevel};
+    use crate::parse::parse;
+    use crate::program::Program;
+    use alloc::vec;
+
+    #[test]
+    fn reuse_unchanged_classes() {
+        const MAIN_VM: &str = "function Main.main 0\npush constant 1\npush constant 2\nlt\nreturn";
+        const SYS_VM: &str = "function Sys.init 0\ncall Main.main 0\nreturn";
+        let class = |source: &str, name: &str| Class::new(parse(source).expect("expect ok"), name);
+        let classes = [class(SYS_VM, "Sys"), class(MAIN_VM, "Main")];
+        let options = GenerateOptions::default();
+        let mut cache = CompileCache::new();
+        let first = cache.generate_all(&classes, &options).expect("expect ok");
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (0, 2, 2));
+        assert_eq!(
+            cache.generate_all(&classes, &options).expect("expect ok"),
+            first
+        );
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+        let changed = [
+            classes[0].clone(),
+            class(&MAIN_VM.replace("lt", "gt"), "Main"),
+        ];
+        let output = cache.generate_all(&changed, &options).expect("expect ok");
+        assert!(output.contains("D;JGT"));
+        assert_eq!((cache.hits(), cache.misses()), (3, 3));
+        let annotated = GenerateOptions {
+            comments: true,
+            ..Default::default()
+        };
+        cache.generate_all(&changed, &annotated).expect("expect ok");
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (3, 5, 5));
+    }
+
+    #[test]
+    fn restore_saved_outputs() {
+        const MAIN_VM: &str = "function Main.main 0\npush constant 1\npush constant 2\nlt\nreturn";
+        const SYS_VM: &str = "function Sys.init 0\ncall Main.main 0\nreturn";
+        let class = |source: &str, name: &str| Class::new(parse(source).expect("expect ok"), name);
+        let program = Program::new(vec![class(SYS_VM, "Sys"), class(MAIN_VM, "Main")]);
+        let options = GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            opt_level: OptLevel::O1,
+            source_map: true,
+            ..Default::default()
+        };
+        let generate = |cache: &mut CompileCache| {
+            let mut ctx = Context::new(options.clone());
+            let mut asm = vec![];
+            program
+                .lower_each_cached(&mut ctx, cache, &mut |lowered| {
+                    asm.extend(lowered);
+                    Ok(())
+                })
+                .expect("expect ok");
+            (render(&asm), ctx.source_map().clone())
+        };
+        let mut cache = CompileCache::new();
+        let first = generate(&mut cache);
+        assert_eq!(
+            first.0,
+            program
+                .generate_with(&mut Context::new(options.clone()))
+                .expect("expect ok")
+        );
+        cache
+            .generate_all(&program.classes()[..1], &GenerateOptions::default())
+            .expect("expect ok");
+        assert_eq!(cache.len(), 3);
+
+        let mut restored = CompileCache::from_json(&cache.to_json()).expect("expect ok");
+        assert_eq!(generate(&mut restored), first);
+        assert_eq!(
+            (restored.hits(), restored.misses(), restored.len()),
+            (2, 0, 3)
+        );
+        restored.prune();
+        assert_eq!(restored.len(), 2);
+        CompileCache::from_json("[{}]").expect_err("expect err");
+        let older = cache.to_json().replace(VERSION, "0.0.0");
+        assert_eq!(CompileCache::from_json(&older).expect("expect ok").len(), 0);
+    }
+}
@@ -0,0 +1,454 @@
+#[cfg(feature = "codegen")]
+use crate::generate;
+use crate::parse::{self, Span, Warning};
+use crate::source::SourceFile;
+use crate::{Error, asm, emulate, script};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Something wrong with the input, which stops the compilation.
+    Error,
+    /// Something likely wrong with the input, which still compiles.
+    Warning,
+    /// Something the compiler did the user may want to know about.
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Kind of a warning, named in kebab case like `unused-label` so each can be allowed, warned about
+/// or denied on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// An instruction with an unknown mnemonic, skipped by a tolerant parse.
+    UnknownInstruction,
+    /// A literal too large for an A-instruction.
+    LiteralOverflow,
+    /// Instructions after a `goto` or `return` that no jump reaches.
+    UnreachableCode,
+    /// A label no jump of its function targets, only looked for when asked, see
+    /// [`CheckOptions::unused_labels`](crate::program::CheckOptions::unused_labels).
+    UnusedLabel,
+    /// A function declared with the prefix of another class, sharing the statics of its own.
+    ForeignFunction,
+    /// The bootstrap code entering a function no class defines.
+    MissingEntry,
+    /// A call to a function neither the program nor an external class defines.
+    UndefinedCall,
+    /// A function defined by more than one class.
+    DuplicateFunction,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 8] = [
+        Lint::UnknownInstruction,
+        Lint::LiteralOverflow,
+        Lint::UnreachableCode,
+        Lint::UnusedLabel,
+        Lint::ForeignFunction,
+        Lint::MissingEntry,
+        Lint::UndefinedCall,
+        Lint::DuplicateFunction,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnknownInstruction => "unknown-instruction",
+            Lint::LiteralOverflow => "literal-overflow",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnusedLabel => "unused-label",
+            Lint::ForeignFunction => "foreign-function",
+            Lint::MissingEntry => "missing-entry",
+            Lint::UndefinedCall => "undefined-call",
+            Lint::DuplicateFunction => "duplicate-function",
+        }
+    }
+
+    /// The lint named `name`, if any.
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A warning or note found while compiling, handed to a [`DiagnosticSink`] as soon as it is found,
+/// or an error the compilation stopped at.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// Stable code of an error, see [`Error::code`].
+    pub code: Option<&'static str>,
+    pub severity: Severity,
+    pub message: String,
+    /// File the diagnostic points into, when known.
+    pub file: Option<String>,
+    /// Byte range in the source the diagnostic points at, when known.
+    pub span: Option<Span>,
+    /// What the input may have meant instead, like `did you mean `push`?`.
+    pub suggestion: Option<String>,
+    /// Kind of a warning, or of a denied one printed as an error, left out of the JSON of other
+    /// diagnostics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lint: Option<Lint>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            ..Self::warning(message)
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            severity: Severity::Warning,
+            message: message.into(),
+            file: None,
+            span: None,
+            suggestion: None,
+            lint: None,
+        }
+    }
+
+    pub fn note(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Note,
+            ..Self::warning(message)
+        }
+    }
+
+    pub fn with_file(self, file: impl Into<String>) -> Self {
+        Self {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Self {
+            span: Some(span),
+            ..self
+        }
+    }
+
+    pub fn with_code(self, code: &'static str) -> Self {
+        Self {
+            code: Some(code),
+            ..self
+        }
+    }
+
+    pub fn with_suggestion(self, suggestion: impl Into<String>) -> Self {
+        Self {
+            suggestion: Some(suggestion.into()),
+            ..self
+        }
+    }
+
+    pub fn with_lint(self, lint: Lint) -> Self {
+        Self {
+            lint: Some(lint),
+            ..self
+        }
+    }
+
+    /// Code of an error or else lint of a warning, shown in brackets after the severity.
+    fn label(&self) -> Option<&'static str> {
+        self.code.or(self.lint.map(|lint| lint.name()))
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if let Some(label) = self.label() {
+            write!(f, "[{label}]")?;
+        }
+        write!(f, ": {}", self.message)?;
+        match (&self.file, &self.span) {
+            (Some(file), Some(span)) => write!(f, " ({file}, {span:?})")?,
+            (Some(file), None) => write!(f, " ({file})")?,
+            (None, Some(span)) => write!(f, " (at {span:?})")?,
+            (None, None) => {}
+        }
+        match &self.suggestion {
+            Some(suggestion) => write!(f, ", {suggestion}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<Warning> for Diagnostic {
+    fn from(warning: Warning) -> Self {
+        Diagnostic::warning(warning.message)
+            .with_span(warning.span)
+            .with_lint(warning.lint)
+    }
+}
+
+/// Renders diagnostics for a terminal, quoting the line of the source they point at with the span
+/// underlined, in the colors of their severity if `color` is set.
+///
+/// ```text
+/// error[VM0003]: unexpected token
+///  --> Main.vm:2:3
+///   |
+/// 2 | if-got END
+///   |   ^
+///   = help: did you mean `if-goto`?
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Renderer {
+    pub color: bool,
+}
+
+impl Renderer {
+    /// Renders `diagnostic`, quoting `source` if it is the file the diagnostic points into.
+    pub fn render(&self, diagnostic: &Diagnostic, source: Option<&SourceFile>) -> String {
+        let severity = match diagnostic.severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+            Severity::Note => "1;36",
+        };
+        let mut header = diagnostic.severity.to_string();
+        if let Some(label) = diagnostic.label() {
+            header = format!("{header}[{label}]");
+        }
+        let message = format!(": {}", diagnostic.message);
+        let mut rendered = format!(
+            "{}{}",
+            self.paint(&header, severity),
+            self.paint(&message, "1")
+        );
+        let quoted = source.zip(diagnostic.span.as_ref());
+        match (quoted, &diagnostic.file) {
+            (Some((source, span)), file) => {
+                let line = source.line(span.start);
+                let column = source.column(span.start);
+                let text = source.line_text(line);
+                let number = line.to_string();
+                let margin = " ".repeat(number.len());
+                // The underline ends with the line, at least one character long.
+                let start = column - 1;
+                let width = text.chars().count().saturating_sub(start);
+                let end = span.end.min(source.text().len());
+                let underlined = source
+                    .text()
+                    .get(span.start..end)
+                    .map_or(1, |spanned| spanned.chars().count());
+                let carets = "^".repeat(underlined.min(width).max(1));
+                let file = file.as_deref().unwrap_or(source.name());
+                rendered += &format!(
+                    "\n{margin}{} {file}:{line}:{column}",
+                    self.paint("-->", "1;34")
+                );
+                rendered += &format!("\n{margin} {}", self.paint("|", "1;34"));
+                rendered += &format!("\n{} {text}", self.paint(&format!("{number} |"), "1;34"));
+                let indent = " ".repeat(start);
+                let carets = self.paint(&carets, severity);
+                rendered += &format!("\n{margin} {} {indent}{carets}", self.paint("|", "1;34"));
+            }
+            (None, Some(file)) => rendered += &format!("\n {} {file}", self.paint("-->", "1;34")),
+            (None, None) => {}
+        }
+        if let Some(suggestion) = &diagnostic.suggestion {
+            let margin = match quoted {
+                Some((source, span)) => " ".repeat(source.line(span.start).to_string().len()),
+                None => String::new(),
+            };
+            rendered += &format!("\n{margin} {} {suggestion}", self.paint("= help:", "1"));
+        }
+        rendered
+    }
+
+    /// Wraps `text` in the ANSI escape codes of `style` if coloring.
+    fn paint(&self, text: &str, style: &str) -> String {
+        if self.color {
+            format!("\x1b[{style}m{text}\x1b[0m")
+        } else {
+            text.into()
+        }
+    }
+}
+
+/// Renders `error` followed by the errors it was caused by, separated by colons.
+fn chain(error: &dyn core::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{message}: {error}");
+        source = error.source();
+    }
+    message
+}
+
+impl From<&parse::Error> for Diagnostic {
+    fn from(error: &parse::Error) -> Self {
+        let diagnostic = match error {
+            parse::Error::Lexing {
+                source,
+                span,
+                suggestion,
+            } => {
+                let diagnostic = Diagnostic::error(chain(source)).with_span(span.clone());
+                match suggestion.0 {
+                    Some(keyword) => {
+                        diagnostic.with_suggestion(format!("did you mean `{keyword}`?"))
+                    }
+                    None => diagnostic,
+                }
+            }
+            _ => Diagnostic::error(chain(error)),
+        };
+        diagnostic.with_code(error.code())
+    }
+}
+
+#[cfg(feature = "codegen")]
+impl From<&generate::Error> for Diagnostic {
+    fn from(error: &generate::Error) -> Self {
+        let mut diagnostic = Diagnostic::error(chain(error)).with_code(error.code());
+        if let generate::Error::Lowering { file, span, .. } = error {
+            diagnostic.file.clone_from(file);
+            diagnostic.span.clone_from(span);
+        }
+        diagnostic
+    }
+}
+
+impl From<&asm::Error> for Diagnostic {
+    fn from(error: &asm::Error) -> Self {
+        Diagnostic::error(chain(error)).with_code(error.code())
+    }
+}
+
+impl From<&emulate::Error> for Diagnostic {
+    fn from(error: &emulate::Error) -> Self {
+        Diagnostic::error(chain(error)).with_code(error.code())
+    }
+}
+
+impl From<&script::Error> for Diagnostic {
+    fn from(error: &script::Error) -> Self {
+        Diagnostic::error(chain(error)).with_code(error.code())
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::Parse { source } => source.into(),
+            #[cfg(feature = "codegen")]
+            Error::Generate { source } => source.into(),
+            Error::Assemble { source } => source.into(),
+        }
+    }
+}
+
+/// Receives the diagnostics of parsing, checking and generating as they are found, so a host like
+/// an editor can show them without waiting for the whole compilation.
+pub trait DiagnosticSink {
+    fn report(&mut self, diagnostic: Diagnostic);
+}
+
+/// Collects the diagnostics in the order they are reported.
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.push(diagnostic);
+    }
+}
+
+#[cfg(all(test, feature = "parser", feature = "codegen"))]
+mod tests {
+    use crate::compile_str;
+    use crate::diagnostic::{Diagnostic, Lint, Renderer, Severity};
+    use crate::generate::GenerateOptions;
+    use crate::source::SourceFile;
+
+    #[test]
+    fn diagnose_errors() {
+        let options = GenerateOptions::default();
+        let error = compile_str("function Sys.init 0\nif-got END", "Sys", &options)
+            .expect_err("expect err");
+        let diagnostic = Diagnostic::from(&error);
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.code, Some("VM0003"));
+        assert_eq!(diagnostic.span, Some(22..23));
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[VM0003]: unexpected token (at 22..23), did you mean `if-goto`?"
+        );
+        assert_eq!(
+            serde_json::to_string(&diagnostic).expect("expect ok"),
+            r#"{"code":"VM0003","severity":"error","message":"unexpected token","file":null,"#
+                .to_owned()
+                + r#""span":{"start":22,"end":23},"suggestion":"did you mean `if-goto`?"}"#
+        );
+
+        let error = compile_str("function Sys.init 0\npush temp 9", "Sys", &options)
+            .expect_err("expect err");
+        let diagnostic = Diagnostic::from(&error);
+        assert_eq!(
+            (diagnostic.file.as_deref(), diagnostic.span),
+            (Some("Sys.vm"), Some(20..31))
+        );
+        assert_eq!(
+            diagnostic.message,
+            "failed to lower `push temp 9` in `Sys.init` (Sys.vm:2): trying to access outside of a \
+                segment"
+        );
+    }
+
+    #[test]
+    fn render_diagnostics() {
+        const SYS_VM: &str = "function Sys.init 0\nif-got END";
+        let source = SourceFile::new("Sys.vm", SYS_VM);
+        let error =
+            compile_str(SYS_VM, "Sys", &GenerateOptions::default()).expect_err("expect err");
+        let diagnostic = Diagnostic::from(&error).with_file("Sys.vm");
+        assert_eq!(
+            Renderer::default().render(&diagnostic, Some(&source)),
+            "error[VM0003]: unexpected token\n --> Sys.vm:2:3\n  |\n2 | if-got END\n  |   ^\n\
+            \x20 = help: did you mean `if-goto`?"
+        );
+        let diagnostic = Diagnostic::warning("unused")
+            .with_file("Sys.vm")
+            .with_span(20..100);
+        assert_eq!(
+            Renderer::default().render(&diagnostic, Some(&source)),
+            "warning: unused\n --> Sys.vm:2:1\n  |\n2 | if-got END\n  | ^^^^^^^^^^"
+        );
+        assert_eq!(
+            Renderer::default().render(&diagnostic, None),
+            "warning: unused\n --> Sys.vm"
+        );
+        let diagnostic = diagnostic.with_lint(Lint::UnusedLabel);
+        assert_eq!(
+            Renderer::default().render(&diagnostic, None),
+            "warning[unused-label]: unused\n --> Sys.vm"
+        );
+        let colored = Renderer { color: true }.render(&Diagnostic::note("left out"), None);
+        assert_eq!(colored, "\x1b[1;36mnote\x1b[0m\x1b[1m: left out\x1b[0m");
+    }
+}
@@ -0,0 +1,240 @@
+use crate::layout::{KBD, RAM_SIZE, SCREEN};
+use alloc::vec;
+use alloc::vec::Vec;
+use snafu::{Snafu, ensure};
+
+#[derive(Snafu, Debug, PartialEq, Clone)]
+pub enum Error {
+    #[snafu(display("instruction {pc} accesses address {address}, past the end of the RAM"))]
+    AddressOutOfRange { pc: u16, address: u16 },
+}
+
+impl Error {
+    /// Stable code of the error, see [`crate::Error::code`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::AddressOutOfRange { .. } => "VM0301",
+        }
+    }
+}
+
+/// Why a [`Machine`] stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The program counter went past the last instruction.
+    End,
+    /// The program jumps to the instruction loading the address of the jump, forever, the way
+    /// programs end.
+    Loop,
+    /// The cycles given to [`Machine::run`] ran out first.
+    CycleLimit,
+}
+
+/// The Hack computer, running machine code from its ROM one instruction per cycle.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    rom: Vec<u16>,
+    ram: Vec<u16>,
+    a: u16,
+    d: u16,
+    pc: u16,
+    cycles: u64,
+}
+
+impl Machine {
+    /// A machine with `rom` loaded and its RAM cleared, about to run the first instruction.
+    pub fn new(rom: Vec<u16>) -> Self {
+        Self {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    /// Words of the memory-mapped screen, 32 per row of 512 pixels, the lowest bit of a word being
+    /// its leftmost pixel.
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[SCREEN as usize..KBD as usize]
+    }
+
+    /// Holds `key` down on the keyboard, 0 for none, in the character set of the Hack computer.
+    pub fn press(&mut self, key: u16) {
+        self.ram[KBD as usize] = key;
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_a(&mut self, a: u16) {
+        self.a = a;
+    }
+
+    pub fn set_d(&mut self, d: u16) {
+        self.d = d;
+    }
+
+    /// Makes the instruction at `pc` the next one to run.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Instructions run since the machine was created.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Runs at most `cycles` more instructions, stopping early when the program ends.
+    pub fn run(&mut self, cycles: u64) -> Result<Stop, Error> {
+        for _ in 0..cycles {
+            if let Some(stop) = self.step()? {
+                return Ok(stop);
+            }
+        }
+        Ok(Stop::CycleLimit)
+    }
+
+    /// Runs the next instruction, unless the program has ended.
+    pub fn step(&mut self) -> Result<Option<Stop>, Error> {
+        let Some(&instr) = self.rom.get(self.pc as usize) else {
+            return Ok(Some(Stop::End));
+        };
+        if instr & 0x8000 == 0 {
+            self.a = instr;
+            self.pc += 1;
+            self.cycles += 1;
+            return Ok(None);
+        }
+        let reads_memory = instr & 0x1000 != 0;
+        let (dest, jump) = ((instr >> 3) & 0b111, instr & 0b111);
+        if reads_memory || dest & 0b001 != 0 {
+            ensure!(
+                (self.a as usize) < RAM_SIZE,
+                AddressOutOfRangeSnafu {
+                    pc: self.pc,
+                    address: self.a
+                }
+            );
+        }
+        let y = if reads_memory {
+            self.ram[self.a as usize]
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, (instr >> 6) & 0b111111);
+        let taken = match out as i16 {
+            0 => jump & 0b010 != 0,
+            out if out < 0 => jump & 0b100 != 0,
+            _ => jump & 0b001 != 0,
+        };
+        // A jump to the instruction loading its own address, changing nothing, runs forever.
+        let target = self.a;
+        if taken
+            && dest == 0
+            && target.wrapping_add(1) == self.pc
+            && self.rom[target as usize] == target
+        {
+            return Ok(Some(Stop::Loop));
+        }
+        if dest & 0b001 != 0 {
+            self.ram[self.a as usize] = out;
+        }
+        if dest & 0b010 != 0 {
+            self.d = out;
+        }
+        if dest & 0b100 != 0 {
+            self.a = out;
+        }
+        self.pc = if taken { target } else { self.pc + 1 };
+        self.cycles += 1;
+        Ok(None)
+    }
+}
+
+/// Output of the ALU of the Hack CPU over `x` and `y`, with the zx, nx, zy, ny, f and no bits
+/// `control`.
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & (0b100000 >> n) != 0;
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+    if bit(5) { !out } else { out }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{assemble, parse_asm};
+    use crate::emulate::{Error, Machine, Stop};
+    use crate::layout::STATIC;
+
+    fn load(asm: &str) -> Machine {
+        Machine::new(assemble(&parse_asm(asm).expect("expect ok"), STATIC).expect("expect ok"))
+    }
+
+    #[test]
+    fn run_to_end() {
+        let mut machine = load("@2\nD=A\n@3\nD=D+A\n@0\nM=D\nD=D-1\nM=M-D\nAM=M+1\n");
+        assert_eq!(machine.run(100), Ok(Stop::End));
+        assert_eq!(machine.ram()[0], 2);
+        assert_eq!((machine.a(), machine.d()), (2, 4));
+        assert_eq!(machine.cycles(), 9);
+    }
+
+    #[test]
+    fn stop_at_loops() {
+        let mut machine =
+            load("@R1\nD=M\n@POSITIVE\nD;JGT\nD=-1\n(POSITIVE)\n@R0\nM=D\n(END)\n@END\n0;JMP\n");
+        machine.ram_mut()[1] = 7;
+        assert_eq!(machine.run(100), Ok(Stop::Loop));
+        assert_eq!(machine.ram()[0], 7);
+        assert_eq!(machine.pc(), 8);
+
+        let mut machine = load("(LOOP)\n@R0\nM=M+1\n@LOOP\n0;JMP\n");
+        assert_eq!(machine.run(10), Ok(Stop::CycleLimit));
+        assert_eq!(machine.ram()[0], 3);
+        assert_eq!(machine.cycles(), 10);
+    }
+
+    #[test]
+    fn read_keyboard_and_screen() {
+        let mut machine = load("@KBD\nD=M\n@SCREEN\nM=D\n");
+        machine.press(65);
+        assert_eq!(machine.run(100), Ok(Stop::End));
+        assert_eq!(machine.screen()[0], 65);
+    }
+
+    #[test]
+    fn reject_addresses_past_ram() {
+        let mut machine = load("@32767\nD=A\nA=D+A\nM=1\n");
+        let error = machine.run(100).expect_err("expect err");
+        assert_eq!(
+            error,
+            Error::AddressOutOfRange {
+                pc: 3,
+                address: 65534
+            }
+        );
+        assert_eq!(error.code(), "VM0301");
+    }
+}
@@ -0,0 +1,262 @@
+//! Extended descriptions of the error codes, see [`crate::Error::code`].
+
+use core::fmt::{self, Display, Formatter};
+
+/// What an error code means: what goes wrong, an example of code failing with it and how to fix it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Explanation {
+    pub code: &'static str,
+    /// One line summing the error up.
+    pub title: &'static str,
+    pub description: &'static str,
+    /// Code failing with the error, VM code unless said otherwise.
+    pub example: &'static str,
+    pub fix: &'static str,
+}
+
+impl Display for Explanation {
+    /// Renders the explanation for people, the example indented by four spaces.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {}\n\n{}\n\nExample:\n",
+            self.code, self.title, self.description
+        )?;
+        for line in self.example.lines() {
+            writeln!(f, "    {line}")?;
+        }
+        writeln!(f, "\n{}", self.fix)
+    }
+}
+
+/// Every error code the crate reports, in code order.
+pub const EXPLANATIONS: [Explanation; 20] = [
+    Explanation {
+        code: "VM0001",
+        title: "syntax error",
+        description: "The tokens of a line do not make up a VM instruction, like a `push` without \
+                      a segment or an index, or an instruction outside any function.",
+        example: "function Main.main 0\npush local\nreturn",
+        fix: "Give every instruction all of its operands, and start the file with a `function` \
+              declaration.",
+    },
+    Explanation {
+        code: "VM0002",
+        title: "warnings denied",
+        description: "The source parsed, but with warnings, which the parse options turn into \
+                      errors.",
+        example: "function Main.main 0\ngoto END\npush constant 1\nlabel END\nreturn",
+        fix: "Fix what the warnings point at, here by removing the code after `goto` no jump \
+              reaches, or stop denying warnings.",
+    },
+    Explanation {
+        code: "VM0003",
+        title: "invalid token",
+        description: "Part of the source is not a token of the VM language: a character no token \
+                      has, like `#`, a number larger than the parse options allow or an index of \
+                      `local`, `argument`, `this` or `that` past 32767, an identifier with \
+                      characters Hack symbols may not have or starting with the prefix reserved \
+                      for generated labels, or a block comment never closed.",
+        example: "function Main.main 0\npush constant 7\n/* return",
+        fix: "Keep identifiers to letters, digits, `_`, `.`, `$` and `:`, and close block comments \
+              with `*/`.",
+    },
+    Explanation {
+        code: "VM0101",
+        title: "instruction cannot be lowered",
+        description: "The instruction parses but means nothing on the Hack computer, like popping \
+                      into the constant segment, which has no address, using a pointer index other \
+                      than 0 and 1, or pushing a constant past 65535, which does not fit into 16 \
+                      bits.",
+        example: "function Main.main 0\npush constant 1\npop constant 0\nreturn",
+        fix: "Pop into a segment with an address, like `pop temp 0` to drop the value, and keep \
+              pointer indexes to 0 for THIS and 1 for THAT.",
+    },
+    Explanation {
+        code: "VM0102",
+        title: "index outside of a segment",
+        description: "The index of a fixed-size segment is past its end. The temp segment has 8 \
+                      words, RAM[5] to RAM[12].",
+        example: "function Main.main 0\npush temp 9\nreturn",
+        fix: "Keep temp indexes from 0 to 7, and move what does not fit into local variables.",
+    },
+    Explanation {
+        code: "VM0103",
+        title: "invalid class name",
+        description: "Labels of the generated assembly start with the class name, which comes from \
+                      the file name and so has to be a valid Hack symbol: letters, digits, `_`, \
+                      `.`, `$` and `:`, not starting with a digit.",
+        example: "// in my-game.vm\nfunction Main.main 0\nreturn",
+        fix: "Rename the file after the class it defines, like `Main.vm`.",
+    },
+    Explanation {
+        code: "VM0104",
+        title: "static segment full",
+        description: "The static variables of all classes together take more words than the static \
+                      segment holds, RAM[16] to RAM[255] by default.",
+        example: "function Main.main 0\npush constant 1\npop static 240\nreturn",
+        fix: "Use fewer static variables, keeping large data in arrays allocated on the heap \
+              instead.",
+    },
+    Explanation {
+        code: "VM0105",
+        title: "program does not fit into the ROM",
+        description: "The generated program takes more than the 32768 instructions the ROM of the \
+                      Hack computer holds. The error lists the largest functions.",
+        example: "function Main.main 0\n// thousands of instructions\nreturn",
+        fix: "Build with `-O1`, shared call and return routines or without the unused functions, \
+              or shrink the largest functions.",
+    },
+    Explanation {
+        code: "VM0106",
+        title: "failed to format the output",
+        description: "Rendering the generated assembly into text failed, which happens when the \
+                      writer it is rendered into fails.",
+        example: "// any program written into a failing writer",
+        fix: "Check the writer the output goes to.",
+    },
+    Explanation {
+        code: "VM0107",
+        title: "failed to write the output",
+        description: "Writing the generated assembly failed, like when the disk is full or the \
+                      file cannot be written.",
+        example: "// any program written to a read-only file",
+        fix: "Check that the output can be written, and that there is space left for it.",
+    },
+    Explanation {
+        code: "VM0201",
+        title: "invalid assembly instruction",
+        description: "A line of Hack assembly is neither an A-instruction, a C-instruction, a \
+                      label nor a comment. A-instructions take constants up to 32767.",
+        example: "// Hack assembly\n@SP\nM=M*D",
+        fix: "Use the computations, destinations and jumps of the Hack specification, like \
+              `M=D&M`.",
+    },
+    Explanation {
+        code: "VM0202",
+        title: "duplicate label",
+        description: "The same label is defined twice in the assembly, so jumps to it are \
+                      ambiguous, or a label redefines a predefined symbol like `R5` or `SCREEN`. \
+                      Hand-written assembly linked with the program may also define a label of a \
+                      VM function.",
+        example: "// Hack assembly\n(LOOP)\n@LOOP\n0;JMP\n(LOOP)",
+        fix: "Rename one of the labels, prefixing the labels of hand-written assembly with its \
+              file name.",
+    },
+    Explanation {
+        code: "VM0203",
+        title: "no address left for a variable",
+        description: "The assembly uses more variables, symbols that are neither labels nor \
+                      predefined, than there are addresses to allocate them to.",
+        example: "// Hack assembly with one symbol more than the free addresses\n@counter\nM=0",
+        fix: "Use fewer variables, or check for misspelled labels, which become variables too.",
+    },
+    Explanation {
+        code: "VM0204",
+        title: "assembly does not fit into the ROM",
+        description: "The assembly takes more than the 32768 instructions the ROM of the Hack \
+                      computer holds.",
+        example: "// Hack assembly of more than 32768 instructions",
+        fix: "Shrink the program, building it with optimizations or leaving out unused functions.",
+    },
+    Explanation {
+        code: "VM0205",
+        title: "invalid machine code",
+        description: "A line of a `.hack` file is not a machine word of 16 binary digits.",
+        example: "// Hack machine code\n0000000000000010\n111011000001000",
+        fix: "Check that the file is machine code, with one instruction of exactly 16 digits per \
+              line.",
+    },
+    Explanation {
+        code: "VM0206",
+        title: "not a Hack instruction",
+        description: "A word of the machine code is a C-instruction whose computation bits match \
+                      no computation of the Hack ALU, so it cannot be disassembled.",
+        example: "// Hack machine code\n1110000001000000",
+        fix: "Check that the file is Hack machine code, and for raw files that words are \
+              big-endian.",
+    },
+    Explanation {
+        code: "VM0301",
+        title: "address past the end of the RAM",
+        description: "The emulated program reads or writes memory past the 32768 words of the RAM, \
+                      usually through a pointer gone negative, which addresses read as unsigned.",
+        example: "function Main.main 0\npush constant 1\nneg\npop pointer 1\npush constant 0\n\
+                  pop that 0\nreturn",
+        fix: "Check the pointers the program sets through `pointer`, THIS and THAT before \
+              accessing memory.",
+    },
+    Explanation {
+        code: "VM0401",
+        title: "test script syntax error",
+        description: "A command of a `.tst` script does not parse, like an output list entry \
+                      without a format or a command not ended by `,` or `;`.",
+        example: "// test script\nload Prog.asm,\noutput-list RAM[0]%Q1.6.1;",
+        fix: "Write commands the way the CPU emulator of nand2tetris takes them, formats being \
+              `%D`, `%X` or `%B` followed by the padding and width.",
+    },
+    Explanation {
+        code: "VM0402",
+        title: "unknown test script variable",
+        description: "A test script sets or outputs a variable the CPU emulator does not have.",
+        example: "// test script\nset R99 1;",
+        fix: "Use `RAM[n]`, `A`, `D` or `PC`.",
+    },
+    Explanation {
+        code: "VM0403",
+        title: "unsupported test script command",
+        description: "A test script uses a command of the emulators of nand2tetris that is not \
+                      supported, like the commands of the VM emulator or of the hardware \
+                      simulator.",
+        example: "// test script\nvmstep;",
+        fix: "Run the script in the emulator of nand2tetris it was written for.",
+    },
+];
+
+/// Explanation of `code`, ignoring case.
+pub fn explain(code: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS
+        .iter()
+        .find(|explanation| explanation.code.eq_ignore_ascii_case(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::explain::{EXPLANATIONS, explain};
+    use crate::script::parse_script;
+
+    #[test]
+    fn explain_codes() {
+        assert!(
+            EXPLANATIONS
+                .windows(2)
+                .all(|pair| pair[0].code < pair[1].code)
+        );
+        assert_eq!(
+            explain("vm0102").map(|explanation| explanation.title),
+            Some("index outside of a segment")
+        );
+        assert_eq!(explain("VM9999"), None);
+        let rendered = explain("VM0102").expect("expect explanation").to_string();
+        assert!(rendered.starts_with("VM0102: index outside of a segment\n\nThe index"));
+        assert!(rendered.contains("Example:\n\n    function Main.main 0\n    push temp 9\n"));
+        for code in ["VM0401", "VM0402", "VM0403"] {
+            let example = explain(code).expect("expect explanation").example;
+            assert_eq!(parse_script(example).expect_err("expect err").code(), code);
+        }
+    }
+
+    #[cfg(all(feature = "parser", feature = "codegen"))]
+    #[test]
+    fn examples_fail_with_their_code() {
+        use crate::compile_str;
+        use crate::generate::GenerateOptions;
+
+        for code in ["VM0001", "VM0003", "VM0101", "VM0102", "VM0104"] {
+            let example = explain(code).expect("expect explanation").example;
+            let error =
+                compile_str(example, "Main", &GenerateOptions::default()).expect_err("expect err");
+            assert_eq!(error.code(), code);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Formatting of VM sources in one canonical style.
+
+use crate::parse::{Error, ParseOptions, parse_with};
+use crate::tokenize::{TokenKind, tokenize};
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Spaces the body of a function is indented by.
+const INDENT: usize = 4;
+
+/// What the formatted source is made of, each at the byte it starts at in the source.
+enum Item<'a> {
+    Code {
+        text: String,
+        indent: usize,
+        header: bool,
+    },
+    Comment(&'a str),
+}
+
+/// A line of the formatted source, blank when it has neither code nor comment.
+#[derive(Default)]
+struct Line<'a> {
+    indent: usize,
+    code: String,
+    comment: Option<&'a str>,
+    header: bool,
+}
+
+impl Line<'_> {
+    fn is_blank(&self) -> bool {
+        self.code.is_empty() && self.comment.is_none()
+    }
+
+    fn is_comment(&self) -> bool {
+        self.code.is_empty() && self.comment.is_some()
+    }
+}
+
+/// Formats the VM `source`, parsed with `options`, rendering each instruction the way it displays.
+///
+/// Functions start unindented after one blank line, along with the comments right above them, and
+/// their bodies are indented by four spaces. Runs of blank lines become one, and blank lines at the
+/// start of a body are dropped. Comments on their own line take the indentation of the code after
+/// them, and comments after code are aligned within each function.
+pub fn format(source: &str, options: &ParseOptions) -> Result<String, Error> {
+    let functions = parse_with(source, options)?.functions;
+    let tokens = tokenize(source);
+    let returns = tokens
+        .iter()
+        .filter(|(kind, _)| *kind == TokenKind::Return)
+        .map(|(_, span)| span.start);
+    let returns = returns.collect::<Vec<_>>();
+    let mut items = Vec::new();
+    for function in &functions {
+        let span = function.span();
+        let indent = if function.name().is_empty() {
+            0
+        } else {
+            let text = format!("function {} {}", function.name, function.vars);
+            items.push((
+                span.start,
+                Item::Code {
+                    text,
+                    indent: 0,
+                    header: true,
+                },
+            ));
+            INDENT
+        };
+        for (index, instr) in function.instr().iter().enumerate() {
+            let start = function
+                .instr_span(index)
+                .map_or(span.start, |span| span.start);
+            items.push((
+                start,
+                Item::Code {
+                    text: instr.to_string(),
+                    indent,
+                    header: false,
+                },
+            ));
+        }
+        if function.returned {
+            let start = returns
+                .iter()
+                .rev()
+                .find(|start| span.contains(start))
+                .copied()
+                .unwrap_or(span.end);
+            items.push((
+                start,
+                Item::Code {
+                    text: "return".to_owned(),
+                    indent,
+                    header: false,
+                },
+            ));
+        }
+    }
+    let comments = tokens
+        .iter()
+        .filter(|(kind, _)| *kind == TokenKind::Comment);
+    items.extend(
+        comments.map(|(_, span)| (span.start, Item::Comment(source[span.clone()].trim_end()))),
+    );
+    items.sort_by_key(|(start, _)| *start);
+
+    let mut lines = Vec::<Line>::new();
+    let mut last_line = None;
+    for (index, (start, item)) in items.iter().enumerate() {
+        let line = source[..*start].matches('\n').count();
+        let same_line = last_line == Some(line);
+        if last_line.is_some_and(|last| line > last + 1)
+            && lines.last().is_some_and(|last| !last.header)
+        {
+            lines.push(Line::default());
+        }
+        last_line = Some(line);
+        match item {
+            Item::Comment(comment) => {
+                if let Some(last) = lines
+                    .last_mut()
+                    .filter(|last| same_line && !last.code.is_empty())
+                {
+                    last.comment = Some(comment);
+                    continue;
+                }
+                let next = items[index + 1..].iter().find_map(|(_, item)| match item {
+                    Item::Code { indent, .. } => Some(*indent),
+                    Item::Comment(_) => None,
+                });
+                let indent = next.or(lines.last().map(|last| last.indent)).unwrap_or(0);
+                lines.push(Line {
+                    indent,
+                    comment: Some(comment),
+                    ..Default::default()
+                });
+            }
+            Item::Code {
+                text,
+                indent,
+                header,
+            } => {
+                if *header {
+                    let mut above = lines.len();
+                    while above > 0 && lines[above - 1].is_comment() {
+                        above -= 1;
+                    }
+                    if above > 0 && !lines[above - 1].is_blank() {
+                        lines.insert(above, Line::default());
+                    }
+                }
+                lines.push(Line {
+                    indent: *indent,
+                    code: text.clone(),
+                    comment: None,
+                    header: *header,
+                });
+            }
+        }
+    }
+
+    let mut formatted = String::new();
+    for function in lines.chunk_by(|_, line| !line.header) {
+        let commented = function
+            .iter()
+            .filter(|line| !line.code.is_empty() && line.comment.is_some());
+        let column = commented
+            .map(|line| line.indent + line.code.len())
+            .max()
+            .unwrap_or(0);
+        for line in function {
+            if line.is_blank() {
+                formatted.push('\n');
+                continue;
+            }
+            let code = format!("{:indent$}{}", "", line.code, indent = line.indent);
+            match line.comment {
+                Some(comment) if line.code.is_empty() => {
+                    formatted.push_str(&format!("{code}{comment}\n"))
+                }
+                Some(comment) => formatted.push_str(&format!("{code:column$} {comment}\n")),
+                None => formatted.push_str(&format!("{code}\n")),
+            }
+        }
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::format;
+    use crate::parse::{ParseOptions, parse_with};
+
+    const SOURCE: &str = "// Adds two numbers.
+function   Math.add 0   // the sum
+push argument 0
+
+
+push argument 1 // second
+add
+  return
+// Counts down.
+function Math.count 1
+
+
+label LOOP
+   push local 0 // counter
+push constant 1
+sub
+pop local 0
+goto LOOP
+";
+
+    const FORMATTED: &str = "// Adds two numbers.
+function Math.add 0 // the sum
+    push argument 0
+
+    push argument 1 // second
+    add
+    return
+
+// Counts down.
+function Math.count 1
+    label LOOP
+    push local 0 // counter
+    push constant 1
+    sub
+    pop local 0
+    goto LOOP
+";
+
+    #[test]
+    fn format_functions() {
+        let options = ParseOptions::default();
+        let formatted = format(SOURCE, &options).expect("expect ok");
+        assert_eq!(formatted, FORMATTED);
+        assert_eq!(format(&formatted, &options).expect("expect ok"), formatted);
+        let parsed = parse_with(&formatted, &options)
+            .expect("expect ok")
+            .functions;
+        assert_eq!(
+            parsed,
+            parse_with(SOURCE, &options).expect("expect ok").functions
+        );
+    }
+
+    #[test]
+    fn align_comments_and_keep_top_level_code() {
+        let options = ParseOptions {
+            top_level_code: true,
+            ..Default::default()
+        };
+        let source = "push constant 7 // seven\n push constant 8\nadd // sum\n\n\n// end\n";
+        let formatted = format(source, &options).expect("expect ok");
+        assert_eq!(
+            formatted,
+            "push constant 7 // seven\npush constant 8\nadd             // sum\n\n// end\n"
+        );
+        assert!(format("push constant 7", &ParseOptions::default()).is_err());
+    }
+}
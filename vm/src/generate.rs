@@ -1,7 +1,36 @@
+use crate::asm::{
+    AVERAGE_LINE_LEN, Addr, AsmInstr, Comp, Dest, Jump, ROM_SIZE, at, is_symbol, jump, label,
+    render, render_to, set,
+};
+use crate::diagnostic::Lint;
 use crate::generate::Error::{SegmentOverflow, Syntax};
-use crate::parse::{BranchInstr, CallInstr, Function, Instr, StackInstr, StackSegment};
-use crate::scoped::{Scoped, ToScoped};
+use crate::hook::CodegenHook;
+use crate::layout::{KBD, SCRATCH, STACK_BASE, STATIC, TEMP};
+use crate::optimize::{OptPipeline, PassDump};
+use crate::parse::{
+    BranchInstr, CallInstr, Function, Instr, MAX_ADDRESSABLE, MAX_NEGATED, Span, StackInstr,
+    StackSegment, Warning,
+};
+use crate::profile::{self, Counters};
+use crate::scoped::{Scope, Scoped, ToScoped};
+use crate::source::{Mapping, SourceFile, SourceMap};
+use crate::stats::{FunctionStats, Stats};
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt::{self, Display};
+use core::ops::Range;
+use core::time::Duration;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
+#[cfg(feature = "std")]
+use std::io;
 
 #[derive(Snafu, Debug)]
 pub enum Error {
@@ -9,87 +38,892 @@ pub enum Error {
     Syntax { message: String },
     #[snafu(display("trying to access outside of a segment"))]
     SegmentOverflow,
+    #[snafu(display("class name `{name}` is not a valid Hack symbol"))]
+    InvalidClassName { name: String },
+    #[snafu(display("statics take {slots} words, more than the {capacity} of the static segment"))]
+    StaticOverflow { slots: u32, capacity: u32 },
+    /// The output does not fit into the ROM, `functions` lists every function by size, largest
+    /// first.
+    #[snafu(display(
+        "output takes {instructions} instructions, more than the {ROM_SIZE} the ROM holds, largest \
+            functions: {}",
+        largest(functions)
+    ))]
+    RomOverflow {
+        instructions: usize,
+        functions: Vec<FunctionStats>,
+    },
+    /// Lowering `instr` of `function` failed, at `line` of `file` when the class has a source.
+    #[snafu(display("failed to lower `{instr}` in `{function}`{}", located(file, line)))]
+    Lowering {
+        source: Box<Error>,
+        function: String,
+        instr: String,
+        file: Option<String>,
+        /// 1-based line in `file`.
+        line: Option<usize>,
+        /// Byte range of the instruction in `file`.
+        span: Option<Span>,
+    },
+    #[snafu(display("failed to assemble the output"), context(false))]
+    Assemble { source: crate::asm::Error },
+    #[snafu(display("failed to format the output"), context(false))]
+    Format { source: fmt::Error },
+    #[cfg(feature = "std")]
+    #[snafu(display("failed to write the output"), context(false))]
+    Write { source: io::Error },
 }
 
-const PUSH_D: &str = "@SP\n\
-    A=M\n\
-    M=D\n\
-    @SP\n\
-    M=M+1\n";
-const POP_TO_D: &str = "@SP\n\
-    AM=M-1\n\
-    D=M\n";
-const LOAD_TOP_TO_M: &str = "@SP\n\
-    A=M-1\n";
+impl Error {
+    /// Stable code of the error, see [`crate::Error::code`]. Lowering and assembling errors keep
+    /// the code of their source.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Syntax { .. } => "VM0101",
+            Error::SegmentOverflow => "VM0102",
+            Error::InvalidClassName { .. } => "VM0103",
+            Error::StaticOverflow { .. } => "VM0104",
+            Error::RomOverflow { .. } => "VM0105",
+            Error::Lowering { source, .. } => source.code(),
+            Error::Assemble { source } => source.code(),
+            Error::Format { .. } => "VM0106",
+            #[cfg(feature = "std")]
+            Error::Write { .. } => "VM0107",
+        }
+    }
+}
+
+fn located(file: &Option<String>, line: &Option<usize>) -> String {
+    match (file, line) {
+        (Some(file), Some(line)) => format!(" ({file}:{line})"),
+        _ => String::new(),
+    }
+}
+
+fn largest(functions: &[FunctionStats]) -> String {
+    functions
+        .iter()
+        .take(5)
+        .map(|function| format!("{} ({})", function.name, function.instructions))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub(crate) fn push_d() -> Vec<AsmInstr> {
+    vec![
+        at("SP"),
+        set(Dest::A, Comp::M),
+        set(Dest::M, Comp::D),
+        at("SP"),
+        set(Dest::M, Comp::MPlusOne),
+    ]
+}
+
+pub(crate) fn pop_to_d() -> Vec<AsmInstr> {
+    vec![
+        at("SP"),
+        set(Dest::AM, Comp::MMinusOne),
+        set(Dest::D, Comp::M),
+    ]
+}
+
+pub(crate) fn load_top_to_m() -> Vec<AsmInstr> {
+    vec![at("SP"), set(Dest::A, Comp::MMinusOne)]
+}
+
+/// Pushes the pointers saved in a call frame.
+pub(crate) fn push_frame() -> Vec<AsmInstr> {
+    ["LCL", "ARG", "THIS", "THAT"]
+        .into_iter()
+        .flat_map(|pointer| [vec![at(pointer), set(Dest::D, Comp::M)], push_d()].concat())
+        .collect()
+}
+
+pub use crate::parse::RESERVED_PREFIX;
+
+/// Numbers synthesized labels so that they stay unique across everything generated with it.
+///
+/// Numbers only depend on the seed and on what was generated before, never on threads or the
+/// platform, so generating the same input again yields byte-identical output.
+#[derive(Debug, Clone, Default)]
+pub struct LabelGen {
+    pub(crate) next: usize,
+}
+
+impl LabelGen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts numbering at `seed`, see [`GenerateOptions::label_seed`].
+    pub fn with_seed(seed: usize) -> Self {
+        Self { next: seed }
+    }
+
+    /// Returns `{scope}.{n}`, where `n` was never handed out before by this generator.
+    pub fn next(&mut self, scope: &str) -> String {
+        let id = self.next;
+        self.next += 1;
+        format!("{scope}.{id}")
+    }
+}
+/// How much the generator trades readability of its output for size and speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// Lower every instruction on its own.
+    #[default]
+    O0,
+    /// Share the comparison routines, see [`Context::helpers`], drop
+    /// [unreachable code](crate::optimize::unreachable),
+    /// [fold constants](crate::optimize::fold_constants), lower `push` and `pop` pairs to
+    /// [moves](crate::optimize::collapse_moves), write 0, 1 and -1 without loading them, turn a
+    /// `call` right before `return` into a jump reusing the frame and run the
+    /// [`peephole`](crate::optimize::peephole) optimizer over every function.
+    O1,
+    /// Everything of [`OptLevel::O1`], and also [fold](crate::optimize::fold_branches) `if-goto`
+    /// on constants, dropping the code it skips.
+    O2,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GenerateOptions {
+    /// Precede the assembly of each VM instruction with the instruction as a comment, followed by
+    /// its location when the class knows its source file.
+    pub comments: bool,
+    /// Start the output of a [`Program`](crate::program::Program) with the bootstrap code.
+    pub bootstrap: Option<BootstrapOptions>,
+    /// Prepended to every label the generator synthesizes.
+    pub label_prefix: String,
+    /// Record a [`SourceMap`] in the [`Context`] while generating.
+    pub source_map: bool,
+    /// Lower `call` to a short jump into a shared routine, see [`Context::helpers`].
+    pub compact_calls: bool,
+    /// End functions with a jump into a shared return routine instead of their own epilogue.
+    pub shared_return: bool,
+    /// Keep the functions [unreachable](crate::program::Program::reachable) from the
+    /// [entry](BootstrapOptions::entry) when generating a program with bootstrap.
+    pub keep_unused: bool,
+    /// Inline calls of leaf functions with at most this many instructions into a program at
+    /// [`OptLevel::O1`], see [`inlined`](crate::optimize::inlined). 0 disables inlining.
+    pub inline_threshold: usize,
+    /// Optimizations applied on top of the options above.
+    pub opt_level: OptLevel,
+    /// Passes run over every function instead of those of the [`OptLevel`], which still decides
+    /// the optimizations outside the passes.
+    pub pipeline: Option<OptPipeline>,
+    /// Order of the functions in the output of a [`Program`](crate::program::Program).
+    pub function_order: FunctionOrder,
+    /// Where the generated code places the stack, segments and scratch registers.
+    pub layout: TargetLayout,
+    /// Number the first synthesized label gets. Outputs generated separately with seeds far
+    /// enough apart link without clashing labels; the same seed always yields the same labels.
+    pub label_seed: usize,
+    /// Count the entries and cycles of every function into RAM from [`TargetLayout::counters`] on,
+    /// to be read back with [`Profile::decode`](crate::profile::Profile::decode).
+    pub instrument: bool,
+}
+
+/// Addresses the generated code relies on, which differ on modified Hack machines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetLayout {
+    /// Address the bootstrap code starts the stack at.
+    pub stack_base: u16,
+    /// Address of `temp 0`, followed by the 7 other words of the segment.
+    pub temp_base: u16,
+    /// Addresses the assembler allocates `static` variables from.
+    pub statics: Range<u16>,
+    /// Registers the generated code uses for intermediate values, R13 to R15 on the Hack machine.
+    pub scratch: ScratchRegisters,
+    /// Word [instrumented](GenerateOptions::instrument) code saves D in while counting, followed by
+    /// the counters. Defaults to the RAM past the keyboard, which the CPU emulator has while the
+    /// Hack computer does not.
+    pub counters: u16,
+}
+
+/// Addresses of the three words the generated code clobbers for intermediate values. Moving them
+/// away from R13 to R15 leaves those free for hand-written assembly linked with the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScratchRegisters {
+    /// Stages the address a shared routine returns to, and the frame size of a tail call.
+    pub return_address: u16,
+    /// Holds the callee of a call going through a shared routine, and the operand of a shift.
+    pub target: u16,
+    /// Holds the address of a `pop` computed before the value is taken off the stack, and the
+    /// frame pointer while returning.
+    pub temporary: u16,
+}
+
+impl Default for ScratchRegisters {
+    fn default() -> Self {
+        let [return_address, target, temporary] = SCRATCH;
+        Self {
+            return_address,
+            target,
+            temporary,
+        }
+    }
+}
+
+/// How a [`Program`](crate::program::Program) lays out its functions, see
+/// [`Program::layout`](crate::program::Program::layout).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FunctionOrder {
+    /// Classes in [program](crate::program::Program) order, the functions of each in source order.
+    #[default]
+    Source,
+    /// Functions in the order the entry calls into them, each followed by the functions it calls
+    /// before returning to the functions its caller calls next, so call chains sit together.
+    /// Functions the entry never reaches follow in source order.
+    CallGraph,
+}
+
+impl GenerateOptions {
+    /// Passes run over every function, see [`GenerateOptions::pipeline`].
+    pub fn opt_pipeline(&self) -> OptPipeline {
+        self.pipeline
+            .clone()
+            .unwrap_or_else(|| OptPipeline::new(self.opt_level))
+    }
+}
+
+impl Default for TargetLayout {
+    fn default() -> Self {
+        Self {
+            stack_base: STACK_BASE,
+            temp_base: TEMP.start,
+            statics: STATIC,
+            scratch: ScratchRegisters::default(),
+            counters: KBD + 1,
+        }
+    }
+}
+
+impl TargetLayout {
+    /// Addresses of the [return address](ScratchRegisters::return_address),
+    /// [target](ScratchRegisters::target) and [temporary](ScratchRegisters::temporary) scratch
+    /// registers, by their predefined symbol where there is one.
+    pub(crate) fn scratch(&self) -> [Addr; 3] {
+        let ScratchRegisters {
+            return_address,
+            target,
+            temporary,
+        } = self.scratch;
+        [return_address, target, temporary].map(|register| match register {
+            0..16 => Addr::Symbol(format!("R{register}")),
+            _ => Addr::Constant(register),
+        })
+    }
+}
+
+/// Routines emitted once per output and shared by every instruction needing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum Helper {
+    /// Pushes the frame of a call and jumps to the callee. Expects the return address in the
+    /// [return address](ScratchRegisters::return_address) register, the callee address in the
+    /// [target](ScratchRegisters::target) one and the argument count in D.
+    Call,
+    /// Returns from the current frame.
+    Return,
+    /// Replaces the current frame with a call. Expects the argument count plus 5 in the return
+    /// address register and the callee address in the target one.
+    TailCall,
+    /// Replace the top two values on the stack with their comparison, then jump to the address in
+    /// the return address register.
+    Equal,
+    Greater,
+    Less,
+    /// Replace the top two values on the stack with their product, quotient or remainder, then
+    /// jump to the address in the return address register.
+    #[cfg(feature = "extensions")]
+    Multiply,
+    #[cfg(feature = "extensions")]
+    Divide,
+    #[cfg(feature = "extensions")]
+    Modulo,
+    /// Replace the top value on the stack with its arithmetic right shift, the bit shifted into
+    /// the lowest one given in the target register, then jump to the address in the return
+    /// address register.
+    #[cfg(feature = "extensions")]
+    ShiftRight,
+}
+
+impl Helper {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Helper::Call => "CALL",
+            Helper::Return => "RETURN",
+            Helper::TailCall => "TAIL",
+            Helper::Equal => "EQ",
+            Helper::Greater => "GT",
+            Helper::Less => "LT",
+            #[cfg(feature = "extensions")]
+            Helper::Multiply => "MUL",
+            #[cfg(feature = "extensions")]
+            Helper::Divide => "DIV",
+            #[cfg(feature = "extensions")]
+            Helper::Modulo => "MOD",
+            #[cfg(feature = "extensions")]
+            Helper::ShiftRight => "SHR",
+        }
+    }
+
+    fn lower(&self, name: &str, layout: &TargetLayout) -> Vec<AsmInstr> {
+        let [r13, r14, r15] = layout.scratch();
+        match self {
+            Helper::Call => [
+                vec![
+                    label(name),
+                    at(r15.clone()),
+                    set(Dest::M, Comp::D),
+                    at(r13.clone()),
+                    set(Dest::D, Comp::M),
+                ],
+                push_d(),
+                push_frame(),
+                vec![
+                    at("SP"),
+                    set(Dest::D, Comp::M),
+                    at(r15.clone()),
+                    set(Dest::D, Comp::DMinusM),
+                    at(5),
+                    set(Dest::D, Comp::DMinusA),
+                    at("ARG"),
+                    set(Dest::M, Comp::D),
+                    at("SP"),
+                    set(Dest::D, Comp::M),
+                    at("LCL"),
+                    set(Dest::M, Comp::D),
+                    at(r14.clone()),
+                    set(Dest::A, Comp::M),
+                    jump(Comp::Zero, Jump::Always),
+                ],
+            ]
+            .concat(),
+            Helper::Return => [vec![label(name)], lower_return(layout)].concat(),
+            // The frame of the caller is pushed above the arguments, then both are moved down to
+            // the arguments of the current frame. LCL serves as the destination, ending up right
+            // past the frame where the callee expects it.
+            Helper::TailCall => {
+                let (repeat, end) = (format!("{name}.LOOP"), format!("{name}.END"));
+                let saved = [5, 4, 3, 2].into_iter().flat_map(|offset| {
+                    [
+                        vec![
+                            at(offset),
+                            set(Dest::D, Comp::A),
+                            at("LCL"),
+                            set(Dest::A, Comp::MMinusD),
+                            set(Dest::D, Comp::M),
+                        ],
+                        push_d(),
+                    ]
+                    .concat()
+                });
+                [
+                    vec![label(name)],
+                    saved.collect(),
+                    vec![
+                        at("LCL"),
+                        set(Dest::A, Comp::MMinusOne),
+                        set(Dest::D, Comp::M),
+                    ],
+                    push_d(),
+                    vec![
+                        at(r13.clone()),
+                        set(Dest::D, Comp::M),
+                        at("SP"),
+                        set(Dest::D, Comp::MMinusD),
+                        at(r15.clone()),
+                        set(Dest::M, Comp::D),
+                        at("ARG"),
+                        set(Dest::D, Comp::M),
+                        at("LCL"),
+                        set(Dest::M, Comp::D),
+                        label(repeat.as_str()),
+                        at(r13.clone()),
+                        set(Dest::D, Comp::M),
+                        at(end.as_str()),
+                        jump(Comp::D, Jump::Equal),
+                        at(r15.clone()),
+                        set(Dest::A, Comp::M),
+                        set(Dest::D, Comp::M),
+                        at(r15.clone()),
+                        set(Dest::M, Comp::MPlusOne),
+                        at("LCL"),
+                        set(Dest::A, Comp::M),
+                        set(Dest::M, Comp::D),
+                        at("LCL"),
+                        set(Dest::M, Comp::MPlusOne),
+                        at(r13.clone()),
+                        set(Dest::M, Comp::MMinusOne),
+                        at(repeat),
+                        jump(Comp::Zero, Jump::Always),
+                        label(end),
+                        at("LCL"),
+                        set(Dest::D, Comp::M),
+                        at("SP"),
+                        set(Dest::M, Comp::D),
+                        at(r14.clone()),
+                        set(Dest::A, Comp::M),
+                        jump(Comp::Zero, Jump::Always),
+                    ],
+                ]
+                .concat()
+            }
+            Helper::Equal => lower_comparison_helper(name, Jump::Equal, layout),
+            Helper::Greater => lower_comparison_helper(name, Jump::Greater, layout),
+            Helper::Less => lower_comparison_helper(name, Jump::Less, layout),
+            #[cfg(feature = "extensions")]
+            Helper::Multiply => lower_multiply_helper(name, layout),
+            #[cfg(feature = "extensions")]
+            Helper::Divide => lower_division_helper(name, layout, false),
+            #[cfg(feature = "extensions")]
+            Helper::Modulo => lower_division_helper(name, layout, true),
+            #[cfg(feature = "extensions")]
+            Helper::ShiftRight => lower_shift_right_helper(name, layout),
+        }
+    }
+}
+
+/// State shared by everything generated into one output.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub options: GenerateOptions,
+    pub labels: LabelGen,
+    /// Source of the class being generated.
+    pub(crate) source: Option<SourceFile>,
+    /// Source offset of the instruction being generated.
+    pub(crate) offset: Option<usize>,
+    /// Function being generated.
+    pub(crate) function: Option<String>,
+    /// Number of assembly lines generated so far.
+    pub(crate) line: usize,
+    pub(crate) source_map: SourceMap,
+    pub(crate) helpers: BTreeSet<Helper>,
+    /// ROM instructions taken by each function generated so far, see [`Context::check_rom`].
+    pub(crate) usage: Vec<FunctionStats>,
+    /// ROM instructions taken by the bootstrap code and the shared routines.
+    pub(crate) shared: usize,
+    pub(crate) hooks: Vec<Arc<dyn CodegenHook>>,
+    pub(crate) dumps: Vec<PassDump>,
+    pub(crate) counters: Counters,
+    /// Measures [`Context::timings`] when set.
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+    pub(crate) timings: BTreeMap<String, PhaseTimes>,
+}
+
+/// Source of the time [`Context::timings`] measures, as the crate has no clock of its own.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Time elapsed since any fixed instant.
+    fn now(&self) -> Duration;
+}
+
+/// Time spent on the functions of a class, see [`Context::with_clock`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PhaseTimes {
+    /// Running the optimization passes, the peephole optimizer included.
+    pub optimize: Duration,
+    /// Lowering the optimized functions into assembly.
+    pub generate: Duration,
+}
+
+impl Context {
+    pub fn new(options: GenerateOptions) -> Self {
+        Self {
+            labels: LabelGen::with_seed(options.label_seed),
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Registers `hook` to inject assembly into everything generated from now on, after the hooks
+    /// registered before.
+    pub fn with_hook(mut self, hook: impl CodegenHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Measures the time spent optimizing and generating the functions of each class with `clock`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Reading of the clock, zero without one.
+    fn now(&self) -> Duration {
+        self.clock
+            .as_ref()
+            .map_or(Duration::ZERO, |clock| clock.now())
+    }
+
+    /// Time spent on each class generated so far, by class name, measured when a
+    /// [clock](Context::with_clock) is set.
+    pub fn timings(&self) -> &BTreeMap<String, PhaseTimes> {
+        &self.timings
+    }
+
+    /// Returns the shared routines used by everything generated so far.
+    ///
+    /// [`Program`](crate::program::Program) appends them on its own; output generated class by
+    /// class has to include them exactly once.
+    pub fn helpers(&mut self) -> String {
+        render(&self.lower_helpers())
+    }
+
+    /// Lowers the shared routines used by everything generated so far, see [`Context::helpers`].
+    pub fn lower_helpers(&mut self) -> Vec<AsmInstr> {
+        let helpers = self
+            .helpers
+            .iter()
+            .flat_map(|helper| helper.lower(&self.synthesized(helper.name()), &self.options.layout))
+            .collect::<Vec<_>>();
+        self.map(&"helpers", &helpers);
+        self.shared += code_len(&helpers);
+        helpers
+    }
+
+    /// Lowers the [`bootstrap`] code set in the options, if any, counting it towards the output.
+    pub fn lower_bootstrap(&mut self) -> Vec<AsmInstr> {
+        let Some(options) = &self.options.bootstrap else {
+            return vec![];
+        };
+        let boot = lower_bootstrap(options, &self.options.layout);
+        self.map(&"bootstrap", &boot);
+        self.shared += code_len(&boot);
+        boot
+    }
+
+    /// Checks that everything generated so far fits into the ROM.
+    pub fn check_rom(&self) -> Result<(), Error> {
+        let instructions = self.shared
+            + self
+                .usage
+                .iter()
+                .map(|function| function.instructions)
+                .sum::<usize>();
+        if instructions <= ROM_SIZE {
+            return Ok(());
+        }
+        let mut functions = self.usage.clone();
+        functions.sort_by_key(|function| core::cmp::Reverse(function.instructions));
+        Err(Error::RomOverflow {
+            instructions,
+            functions,
+        })
+    }
+
+    /// Output of every optimization pass over the functions generated so far, recorded when
+    /// [`OptPipeline::dump`] is set.
+    pub fn dumps(&self) -> &[PassDump] {
+        &self.dumps
+    }
+
+    /// Mappings from generated lines back to VM instructions, filled when
+    /// [`GenerateOptions::source_map`] is set.
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// Functions counted by everything generated so far, filled when
+    /// [`GenerateOptions::instrument`] is set.
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    fn locate(&mut self, offset: Option<usize>) {
+        self.offset = offset;
+    }
+
+    fn location(&self) -> Option<(&SourceFile, usize)> {
+        self.source.as_ref().zip(self.offset)
+    }
+
+    /// Wraps `source`, the error lowering `instr` of the current function, with where it occurred.
+    fn lowering_error(&self, source: Error, instr: &Instr, span: Option<Span>) -> Error {
+        let location = self.location();
+        Error::Lowering {
+            source: Box::new(source),
+            function: self.function.clone().unwrap_or_default(),
+            instr: instr.to_string(),
+            file: location.map(|(source, _)| source.name().to_owned()),
+            line: location.map(|(source, offset)| source.line(offset)),
+            span: location.and(span),
+        }
+    }
+
+    /// Returns the comment preceding the assembly of `instr`, if comments are enabled.
+    fn annotation(&self, instr: &impl Display) -> Vec<AsmInstr> {
+        match (self.options.comments, self.location()) {
+            (false, _) => vec![],
+            (true, Some((source, offset))) => {
+                vec![AsmInstr::Comment(format!(
+                    "{instr} ({})",
+                    source.location(offset)
+                ))]
+            }
+            (true, None) => vec![AsmInstr::Comment(instr.to_string())],
+        }
+    }
+
+    /// Accounts for `generated`, the assembly of `instr`, being appended to the output.
+    pub(crate) fn map(&mut self, instr: &impl Display, generated: &[AsmInstr]) {
+        let start = self.line;
+        self.line += generated.len();
+        if self.options.source_map {
+            let (file, line) = self
+                .location()
+                .map(|(source, offset)| (source.name().to_owned(), source.line(offset)))
+                .unzip();
+            self.source_map.mappings.push(Mapping {
+                asm: start..self.line,
+                file,
+                line,
+                function: self.function.clone(),
+                instr: instr.to_string(),
+            });
+        }
+    }
+
+    fn synthesized(&self, label: &str) -> String {
+        format!("{}{RESERVED_PREFIX}{label}", self.options.label_prefix)
+    }
+
+    /// Marks `helper` as used and returns a jump into it.
+    fn jump_to_helper(&mut self, helper: Helper) -> Vec<AsmInstr> {
+        self.helpers.insert(helper);
+        vec![
+            at(self.synthesized(helper.name())),
+            jump(Comp::Zero, Jump::Always),
+        ]
+    }
+
+    /// Lowers a comparison in `scope` to a call of the shared `helper` when the optimization level
+    /// allows, falling back to inlining it with `jump`.
+    fn comparison(&mut self, scope: &str, helper: Helper, jump: Jump) -> Vec<AsmInstr> {
+        if self.options.opt_level < OptLevel::O1 {
+            return lower_comparison(jump, self.comparison_label(scope));
+        }
+        self.call_helper(scope, helper)
+    }
+
+    /// Lowers a call in `scope` of the shared `helper`, which returns to the address in the
+    /// [return address](ScratchRegisters::return_address) register.
+    fn call_helper(&mut self, scope: &str, helper: Helper) -> Vec<AsmInstr> {
+        let label = self.labels.next(scope);
+        let return_label = self.synthesized(&format!("RET.{label}"));
+        let [r13, ..] = self.options.layout.scratch();
+        [
+            vec![
+                at(return_label.as_str()),
+                set(Dest::D, Comp::A),
+                at(r13.clone()),
+                set(Dest::M, Comp::D),
+            ],
+            self.jump_to_helper(helper),
+            vec![self::label(return_label)],
+        ]
+        .concat()
+    }
+
+    /// Returns a context generating independently of this one, to be [joined](Context::join) back.
+    ///
+    /// Labels synthesized by the fork continue the numbering of this context, so they stay unique
+    /// as long as the two generate into different scopes.
+    fn fork(&self) -> Context {
+        Context {
+            options: self.options.clone(),
+            labels: self.labels.clone(),
+            source: self.source.clone(),
+            hooks: self.hooks.clone(),
+            counters: self.counters.clone(),
+            clock: self.clock.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Takes over the state of `fork`, whose output is appended to the output of this context.
+    pub(crate) fn join(&mut self, fork: Context) {
+        let line = self.line;
+        self.labels.next = self.labels.next.max(fork.labels.next);
+        self.source_map
+            .mappings
+            .extend(fork.source_map.mappings.into_iter().map(|mut mapping| {
+                mapping.asm = mapping.asm.start + line..mapping.asm.end + line;
+                mapping
+            }));
+        self.line += fork.line;
+        self.helpers.extend(fork.helpers);
+        self.dumps.extend(fork.dumps);
+        self.counters.extend(&fork.counters);
+        for (class, times) in fork.timings {
+            let joined = self.timings.entry(class).or_default();
+            joined.optimize += times.optimize;
+            joined.generate += times.generate;
+        }
+    }
+
+    /// Moves the lines generated since `first_line`, and the mappings of them from `first_mapping`
+    /// on, to where `lines` says each one ended up.
+    fn move_lines(&mut self, first_line: usize, first_mapping: usize, lines: &[usize]) {
+        let moved = |line: usize| first_line + lines[line - first_line];
+        for mapping in &mut self.source_map.mappings[first_mapping..] {
+            mapping.asm = moved(mapping.asm.start)..moved(mapping.asm.end);
+        }
+        self.line = moved(self.line);
+    }
+
+    /// Lowers what the hooks inject through `callback`.
+    fn hooked(&mut self, callback: impl Fn(&dyn CodegenHook) -> Vec<AsmInstr>) -> Vec<AsmInstr> {
+        let injected = self
+            .hooks
+            .iter()
+            .flat_map(|hook| callback(hook.as_ref()))
+            .collect::<Vec<_>>();
+        if !injected.is_empty() {
+            self.map(&"hook", &injected);
+        }
+        injected
+    }
+
+    /// Returns a fresh pair of `TRUE`/`END` labels for a comparison in `scope`.
+    fn comparison_label(&mut self, scope: &str) -> (String, String) {
+        let label = self.labels.next(scope);
+        (
+            self.synthesized(&format!("TRUE.{label}")),
+            self.synthesized(&format!("END.{label}")),
+        )
+    }
+}
 
 pub trait Generate {
     type Error;
-    fn generate(&self) -> Result<String, Self::Error>;
+    fn generate(&self) -> Result<String, Self::Error> {
+        self.generate_with(&mut Context::default())
+    }
+
+    fn generate_with(&self, ctx: &mut Context) -> Result<String, Self::Error> {
+        self.lower(ctx).map(|asm| render(&asm))
+    }
+
+    /// Renders the output into `w` piece by piece instead of collecting it first.
+    fn generate_to<W: fmt::Write>(&self, ctx: &mut Context, w: &mut W) -> Result<(), Self::Error>
+    where
+        Self::Error: From<fmt::Error>,
+    {
+        self.lower_each(ctx, &mut |asm| Ok(render_to(&asm, w)?))
+    }
+
+    /// Writes the output into `w` piece by piece instead of collecting it first.
+    #[cfg(feature = "std")]
+    fn write_to<W: io::Write>(&self, ctx: &mut Context, w: &mut W) -> Result<(), Self::Error>
+    where
+        Self::Error: From<io::Error>,
+    {
+        self.lower_each(ctx, &mut |asm| Ok(crate::asm::write_to(&asm, w)?))
+    }
+
+    /// Lowers to assembly, which [`Generate::generate_with`] renders.
+    fn lower(&self, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error>;
+    /// Lowers like [`Generate::lower`], handing the output to `sink` in pieces as soon as they are
+    /// complete.
+    fn lower_each(
+        &self,
+        ctx: &mut Context,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), Self::Error>,
+    ) -> Result<(), Self::Error> {
+        sink(self.lower(ctx)?)
+    }
 }
 
 impl<T: Generate> Generate for Vec<T> {
     type Error = <T as Generate>::Error;
 
-    fn generate(&self) -> Result<String, Self::Error> {
-        self.iter().map(|item| item.generate()).collect()
+    fn lower(&self, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let lowered = self
+            .iter()
+            .map(|item| item.lower(ctx))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(lowered.concat())
+    }
+
+    fn lower_each(
+        &self,
+        ctx: &mut Context,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), Self::Error>,
+    ) -> Result<(), Self::Error> {
+        self.iter().try_for_each(|item| item.lower_each(ctx, sink))
     }
 }
 
 pub trait ScopedGenerate {
     type Error;
-    fn scoped_generate(&self, scope: &str) -> Result<String, Self::Error>;
+    fn scoped_generate(&self, scope: &Scope, ctx: &mut Context) -> Result<String, Self::Error> {
+        self.scoped_lower(scope, ctx).map(|asm| render(&asm))
+    }
+
+    /// Renders the output into `w`, see [`Generate::generate_to`].
+    fn scoped_generate_to<W: fmt::Write>(
+        &self,
+        scope: &Scope,
+        ctx: &mut Context,
+        w: &mut W,
+    ) -> Result<(), Self::Error>
+    where
+        Self::Error: From<fmt::Error>,
+    {
+        Ok(render_to(&self.scoped_lower(scope, ctx)?, w)?)
+    }
+
+    fn scoped_lower(&self, scope: &Scope, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error>;
 }
 
 impl<T: ScopedGenerate + Clone> Generate for Scoped<T> {
     type Error = <T as ScopedGenerate>::Error;
 
-    fn generate(&self) -> Result<String, Self::Error> {
-        self.value.scoped_generate(&self.scope)
+    fn lower(&self, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        self.value.scoped_lower(&self.scope, ctx)
     }
 }
 
 impl StackSegment {
-    fn generate_addr(&self, scope: &str, literal: &u32) -> Result<String, Error> {
+    fn lower_addr(
+        &self,
+        scope: &str,
+        literal: &u32,
+        layout: &TargetLayout,
+    ) -> Result<Vec<AsmInstr>, Error> {
+        let based = |base: &str| {
+            if *literal > MAX_ADDRESSABLE {
+                return Err(Syntax {
+                    message: format!("index {literal} does not fit into an A-instruction"),
+                });
+            }
+            Ok(vec![
+                at(base),
+                set(Dest::D, Comp::M),
+                at(*literal as u16),
+                set(Dest::A, Comp::DPlusA),
+            ])
+        };
         match self {
             StackSegment::Constant => Err(Syntax {
                 message: "constant has no address".to_owned(),
             }),
-            StackSegment::Local => Ok(format!(
-                "@LCL\n\
-                D=M\n\
-                @{literal}\n\
-                A=D+A\n"
-            )),
-            StackSegment::Argument => Ok(format!(
-                "@ARG\n\
-                D=M\n\
-                @{literal}\n\
-                A=D+A\n"
-            )),
-            StackSegment::This => Ok(format!(
-                "@THIS\n\
-                D=M\n\
-                @{literal}\n\
-                A=D+A\n"
-            )),
-            StackSegment::That => Ok(format!(
-                "@THAT\n\
-                D=M\n\
-                @{literal}\n\
-                A=D+A\n"
-            )),
-            StackSegment::Static => Ok(format!("@{scope}.{literal}\n")),
+            StackSegment::Local => based("LCL"),
+            StackSegment::Argument => based("ARG"),
+            StackSegment::This => based("THIS"),
+            StackSegment::That => based("THAT"),
+            StackSegment::Static => Ok(vec![at(format!("{scope}.{literal}"))]),
             StackSegment::Temp => {
                 let index = literal;
-                if *index > 7 {
+                if *index as usize >= TEMP.len() {
                     Err(SegmentOverflow)
                 } else {
-                    Ok(format!("@{}\n", 5 + index))
+                    Ok(vec![at(layout.temp_base + *index as u16)])
                 }
             }
             StackSegment::Pointer => match literal {
-                0 => Ok(String::from("@THIS\n")),
-                1 => Ok(String::from("@THAT\n")),
+                0 => Ok(vec![at("THIS")]),
+                1 => Ok(vec![at("THAT")]),
                 _ => Err(Syntax {
                     message: "no such pointer".to_owned(),
                 }),
@@ -97,281 +931,1177 @@ impl StackSegment {
         }
     }
 
-    fn generate_load_to_d(&self, scope: &str, literal: &u32) -> Result<String, Error> {
+    /// Whether the address of the segment is known without reading a base pointer, so computing it
+    /// leaves D untouched.
+    fn is_fixed(&self) -> bool {
+        matches!(
+            self,
+            StackSegment::Static | StackSegment::Temp | StackSegment::Pointer
+        )
+    }
+
+    fn lower_load_to_d(
+        &self,
+        scope: &str,
+        literal: &u32,
+        layout: &TargetLayout,
+    ) -> Result<Vec<AsmInstr>, Error> {
         match self {
-            StackSegment::Constant => Ok(format!(
-                "@{literal}\n\
-                D=A\n"
-            )),
-            _ => Ok(format!(
-                "{}\
-                D=M\n",
-                self.generate_addr(scope, literal)?
-            )),
+            StackSegment::Constant => load_constant(*literal),
+            _ => Ok([
+                self.lower_addr(scope, literal, layout)?,
+                vec![set(Dest::D, Comp::M)],
+            ]
+            .concat()),
         }
     }
 }
 
+/// Loads the constant `literal` into D. Literals past the largest an A-instruction loads, up to
+/// 65535, are taken as 16-bit two's complement, so 65535 loads -1.
+fn load_constant(literal: u32) -> Result<Vec<AsmInstr>, Error> {
+    match literal {
+        0..=MAX_ADDRESSABLE => Ok(vec![at(literal as u16), set(Dest::D, Comp::A)]),
+        // -32768 is the one negative value without a loadable magnitude.
+        MAX_NEGATED => Ok(vec![at(MAX_ADDRESSABLE as u16), set(Dest::D, Comp::NotA)]),
+        _ if literal <= 0xffff => Ok(vec![
+            at((0x10000 - literal) as u16),
+            set(Dest::D, Comp::NegA),
+        ]),
+        _ => Err(Syntax {
+            message: format!("constant {literal} does not fit into 16 bits"),
+        }),
+    }
+}
+
+/// Lowers a comparison jumping with `jump`, given labels from [`Context::comparison_label`].
+pub(crate) fn lower_comparison(
+    jump: Jump,
+    (true_label, end_label): (String, String),
+) -> Vec<AsmInstr> {
+    [
+        pop_to_d(),
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::MMinusD),
+            at(true_label.as_str()),
+            self::jump(Comp::D, jump),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::M, Comp::Zero),
+            at(end_label.as_str()),
+            self::jump(Comp::Zero, Jump::Always),
+            label(true_label),
+        ],
+        load_top_to_m(),
+        vec![set(Dest::M, Comp::MinusOne), label(end_label)],
+    ]
+    .concat()
+}
+
+/// Lowers the body of a comparison routine jumping with `jump`, see [`Helper`].
+fn lower_comparison_helper(name: &str, jump: Jump, layout: &TargetLayout) -> Vec<AsmInstr> {
+    let true_label = format!("{name}.TRUE");
+    let [r13, ..] = layout.scratch();
+    [
+        vec![label(name)],
+        pop_to_d(),
+        vec![
+            set(Dest::A, Comp::AMinusOne),
+            set(Dest::D, Comp::MMinusD),
+            set(Dest::M, Comp::MinusOne),
+            at(true_label.as_str()),
+            self::jump(Comp::D, jump),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::M, Comp::Zero),
+            label(true_label),
+            at(r13.clone()),
+            set(Dest::A, Comp::M),
+            self::jump(Comp::Zero, Jump::Always),
+        ],
+    ]
+    .concat()
+}
+
+/// Points A at the value below the top of the stack.
+#[cfg(feature = "extensions")]
+fn load_second_to_m() -> Vec<AsmInstr> {
+    [load_top_to_m(), vec![set(Dest::A, Comp::AMinusOne)]].concat()
+}
+
+/// Replaces the top two values on the stack with their product by shift-and-add, keeping the
+/// product in the target register and the bit of the multiplier being tested in the temporary one.
+#[cfg(feature = "extensions")]
+fn lower_multiply_helper(name: &str, layout: &TargetLayout) -> Vec<AsmInstr> {
+    let (repeat, skip, end) = (
+        format!("{name}.LOOP"),
+        format!("{name}.SKIP"),
+        format!("{name}.END"),
+    );
+    let [r13, r14, r15] = layout.scratch();
+    [
+        vec![
+            label(name),
+            at(r14.clone()),
+            set(Dest::M, Comp::Zero),
+            at(r15.clone()),
+            set(Dest::M, Comp::One),
+            label(repeat.as_str()),
+            at(r15.clone()),
+            set(Dest::D, Comp::M),
+            at(end.as_str()),
+            jump(Comp::D, Jump::Equal),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::DAndM),
+            at(skip.as_str()),
+            jump(Comp::D, Jump::Equal),
+        ],
+        load_second_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(r14.clone()),
+            set(Dest::M, Comp::DPlusM),
+            label(skip),
+        ],
+        load_second_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+            at(r15.clone()),
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+            at(repeat),
+            jump(Comp::Zero, Jump::Always),
+            label(end),
+            at(r14),
+            set(Dest::D, Comp::M),
+            at("SP"),
+            set(Dest::AM, Comp::MMinusOne),
+            set(Dest::A, Comp::AMinusOne),
+            set(Dest::M, Comp::D),
+            at(r13),
+            set(Dest::A, Comp::M),
+            jump(Comp::Zero, Jump::Always),
+        ],
+    ]
+    .concat()
+}
+
+/// Replaces the top two values on the stack with their quotient, or with the remainder if
+/// `remainder` is set, by long division of their magnitudes.
+///
+/// The magnitudes replace the operands and are compared as unsigned, so -32768 divides like
+/// 32768. The quotient is built in the target register and the remainder in the temporary one,
+/// while the free words above the stack hold the bits left to divide and whether to negate the
+/// result. Division by zero ends without trapping, its result is unspecified.
+#[cfg(feature = "extensions")]
+fn lower_division_helper(name: &str, layout: &TargetLayout, remainder: bool) -> Vec<AsmInstr> {
+    let label_of = |suffix: &str| format!("{name}.{suffix}");
+    let [r13, r14, r15] = layout.scratch();
+    let negated = || vec![at("SP"), set(Dest::A, Comp::MPlusOne)];
+    let counter = || vec![at("SP"), set(Dest::A, Comp::M)];
+    let result = if remainder { r15.clone() } else { r14.clone() };
+    [
+        vec![label(name)],
+        negated(),
+        vec![set(Dest::M, Comp::Zero)],
+        load_second_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(label_of("DIVIDEND")),
+            jump(Comp::D, Jump::GreaterEqual),
+        ],
+        load_second_to_m(),
+        vec![set(Dest::M, Comp::NegM)],
+        negated(),
+        vec![set(Dest::M, Comp::NotM), label(label_of("DIVIDEND"))],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(label_of("DIVISOR")),
+            jump(Comp::D, Jump::GreaterEqual),
+        ],
+        load_top_to_m(),
+        vec![set(Dest::M, Comp::NegM)],
+        // The remainder takes the sign of the dividend alone.
+        if remainder {
+            vec![]
+        } else {
+            [negated(), vec![set(Dest::M, Comp::NotM)]].concat()
+        },
+        vec![
+            label(label_of("DIVISOR")),
+            at(r14.clone()),
+            set(Dest::M, Comp::Zero),
+            at(r15.clone()),
+            set(Dest::M, Comp::Zero),
+            at(16),
+            set(Dest::D, Comp::A),
+        ],
+        counter(),
+        vec![
+            set(Dest::M, Comp::D),
+            label(label_of("LOOP")),
+            // Shifts the highest bit left of the dividend into the remainder.
+            at(r15.clone()),
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+        ],
+        load_second_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(label_of("SHIFT")),
+            jump(Comp::D, Jump::GreaterEqual),
+        ],
+        vec![
+            at(r15.clone()),
+            set(Dest::M, Comp::MPlusOne),
+            label(label_of("SHIFT")),
+        ],
+        load_second_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+            at(r14.clone()),
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+            // Compares the remainder with the divisor as unsigned: a remainder with the high bit
+            // set exceeds any divisor, a divisor with the high bit set any other remainder.
+            at(r15.clone()),
+            set(Dest::D, Comp::M),
+            at(label_of("SUBTRACT")),
+            jump(Comp::D, Jump::Less),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(label_of("NEXT")),
+            jump(Comp::D, Jump::Less),
+        ],
+        vec![at(r15.clone()), set(Dest::D, Comp::M)],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::DMinusM),
+            at(label_of("NEXT")),
+            jump(Comp::D, Jump::Less),
+        ],
+        vec![label(label_of("SUBTRACT"))],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(r15),
+            set(Dest::M, Comp::MMinusD),
+            at(r14),
+            set(Dest::M, Comp::MPlusOne),
+            label(label_of("NEXT")),
+        ],
+        counter(),
+        vec![
+            set(Dest::MD, Comp::MMinusOne),
+            at(label_of("LOOP")),
+            jump(Comp::D, Jump::Greater),
+        ],
+        negated(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(label_of("END")),
+            jump(Comp::D, Jump::Equal),
+            at(result.clone()),
+            set(Dest::M, Comp::NegM),
+            label(label_of("END")),
+            at(result),
+            set(Dest::D, Comp::M),
+            at("SP"),
+            set(Dest::AM, Comp::MMinusOne),
+            set(Dest::A, Comp::AMinusOne),
+            set(Dest::M, Comp::D),
+            at(r13),
+            set(Dest::A, Comp::M),
+            jump(Comp::Zero, Jump::Always),
+        ],
+    ]
+    .concat()
+}
+
+/// Replaces the top value on the stack with its arithmetic right shift. The bit of the value
+/// shifted into the lowest one, in the target register, and the bit of the result it lands in,
+/// in the temporary one, move up in step, and the result is built in the free word above the
+/// stack. Once the value runs out of bits, the bits above the result's are copied from the sign.
+#[cfg(feature = "extensions")]
+fn lower_shift_right_helper(name: &str, layout: &TargetLayout) -> Vec<AsmInstr> {
+    let label_of = |suffix: &str| format!("{name}.{suffix}");
+    let [r13, r14, r15] = layout.scratch();
+    let result = || vec![at("SP"), set(Dest::A, Comp::M)];
+    [
+        vec![label(name), at(r15.clone()), set(Dest::M, Comp::One)],
+        result(),
+        vec![
+            set(Dest::M, Comp::Zero),
+            label(label_of("LOOP")),
+            at(r14.clone()),
+            set(Dest::D, Comp::M),
+            at(label_of("FILL")),
+            jump(Comp::D, Jump::Equal),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::DAndM),
+            at(label_of("SKIP")),
+            jump(Comp::D, Jump::Equal),
+            at(r15.clone()),
+            set(Dest::D, Comp::M),
+        ],
+        result(),
+        vec![
+            set(Dest::M, Comp::DOrM),
+            label(label_of("SKIP")),
+            at(r14),
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+            at(r15.clone()),
+            set(Dest::D, Comp::M),
+            set(Dest::M, Comp::DPlusM),
+            at(label_of("LOOP")),
+            jump(Comp::Zero, Jump::Always),
+            label(label_of("FILL")),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at(label_of("END")),
+            jump(Comp::D, Jump::GreaterEqual),
+            at(r15),
+            set(Dest::D, Comp::NegM),
+        ],
+        result(),
+        vec![set(Dest::M, Comp::DOrM), label(label_of("END"))],
+        result(),
+        vec![
+            set(Dest::D, Comp::M),
+            set(Dest::A, Comp::AMinusOne),
+            set(Dest::M, Comp::D),
+            at(r13),
+            set(Dest::A, Comp::M),
+            jump(Comp::Zero, Jump::Always),
+        ],
+    ]
+    .concat()
+}
+
+/// Pushes a value the ALU computes without loading it, like `0`, `1` or `-1`.
+fn lower_push_comp(comp: Comp) -> Vec<AsmInstr> {
+    vec![
+        at("SP"),
+        set(Dest::A, Comp::M),
+        set(Dest::M, comp),
+        at("SP"),
+        set(Dest::M, Comp::MPlusOne),
+    ]
+}
+
+/// The computation producing a constant 0 or 1.
+fn small_constant(literal: &u32) -> Comp {
+    if *literal == 0 { Comp::Zero } else { Comp::One }
+}
+
+/// Stores D at the address `addr` computes, going through a scratch register as computing it
+/// clobbers D.
+fn store_d_at(addr: Vec<AsmInstr>, load: Vec<AsmInstr>, layout: &TargetLayout) -> Vec<AsmInstr> {
+    let [.., r15] = layout.scratch();
+    [
+        addr,
+        vec![
+            set(Dest::D, Comp::A),
+            at(r15.clone()),
+            set(Dest::M, Comp::D),
+        ],
+        load,
+        vec![
+            at(r15.clone()),
+            set(Dest::A, Comp::M),
+            set(Dest::M, Comp::D),
+        ],
+    ]
+    .concat()
+}
+
 impl ScopedGenerate for StackInstr {
     type Error = Error;
-    fn scoped_generate(&self, scope: &str) -> Result<String, Self::Error> {
-        match &self {
+    fn scoped_lower(&self, scope: &Scope, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let annotation = ctx.annotation(self);
+        // Statics belong to the class, while labels are made unique within the function.
+        let (class, owner) = (scope.class_name().as_str(), scope.owner().as_str());
+        let specialized = ctx.options.opt_level >= OptLevel::O1;
+        let layout = &ctx.options.layout.clone();
+        let [.., r15] = layout.scratch();
+        let binary = |comp: Comp| [pop_to_d(), load_top_to_m(), vec![set(Dest::M, comp)]].concat();
+        let unary = |comp: Comp| [load_top_to_m(), vec![set(Dest::M, comp)]].concat();
+        let generated = match &self {
+            StackInstr::Push {
+                segment: StackSegment::Constant,
+                literal: literal @ (0 | 1),
+            } if specialized => lower_push_comp(small_constant(literal)),
+            StackInstr::PushTrue => lower_push_comp(Comp::MinusOne),
+            // -32768 is the one negative constant without a loadable magnitude.
+            StackInstr::PushNegated { literal: 32768.. } => [
+                vec![at(MAX_ADDRESSABLE as u16), set(Dest::D, Comp::NotA)],
+                push_d(),
+            ]
+            .concat(),
+            StackInstr::PushNegated { literal } => [
+                vec![at(*literal as u16), set(Dest::D, Comp::NegA)],
+                push_d(),
+            ]
+            .concat(),
             StackInstr::Push { segment, literal } => {
-                let load = segment.generate_load_to_d(scope, literal)?;
-                Ok(format!("{load}{PUSH_D}"))
+                [segment.lower_load_to_d(class, literal, layout)?, push_d()].concat()
             }
-            StackInstr::Pop { segment, literal } => {
-                let addr = segment.generate_addr(scope, literal)?;
-                Ok(format!(
-                    "{addr}\
-                    D=A\n\
-                    @R15\n\
-                    M=D\n\
-                    {POP_TO_D}\
-                    @R15\n\
-                    A=M\n\
-                    M=D\n"
-                ))
+            StackInstr::Pop { segment, literal } if segment.is_fixed() => [
+                pop_to_d(),
+                segment.lower_addr(class, literal, layout)?,
+                vec![set(Dest::M, Comp::D)],
+            ]
+            .concat(),
+            StackInstr::Pop { segment, literal } => store_d_at(
+                segment.lower_addr(class, literal, layout)?,
+                pop_to_d(),
+                layout,
+            ),
+            StackInstr::Move {
+                source: StackSegment::Constant,
+                source_literal: source_literal @ (0 | 1),
+                segment,
+                literal,
+            } if specialized => [
+                segment.lower_addr(class, literal, layout)?,
+                vec![set(Dest::M, small_constant(source_literal))],
+            ]
+            .concat(),
+            StackInstr::Move {
+                source,
+                source_literal,
+                segment,
+                literal,
+            } if segment.is_fixed() => [
+                source.lower_load_to_d(class, source_literal, layout)?,
+                segment.lower_addr(class, literal, layout)?,
+                vec![set(Dest::M, Comp::D)],
+            ]
+            .concat(),
+            StackInstr::Move {
+                source,
+                source_literal,
+                segment,
+                literal,
+            } => store_d_at(
+                segment.lower_addr(class, literal, layout)?,
+                source.lower_load_to_d(class, source_literal, layout)?,
+                layout,
+            ),
+            StackInstr::Pick { depth } => [
+                vec![
+                    at((depth + 1) as u16),
+                    set(Dest::D, Comp::A),
+                    at("SP"),
+                    set(Dest::A, Comp::MMinusD),
+                    set(Dest::D, Comp::M),
+                ],
+                push_d(),
+            ]
+            .concat(),
+            StackInstr::Place { depth } => [
+                vec![
+                    at((depth + 2) as u16),
+                    set(Dest::D, Comp::A),
+                    at("SP"),
+                    set(Dest::D, Comp::MMinusD),
+                    at(r15.clone()),
+                    set(Dest::M, Comp::D),
+                ],
+                pop_to_d(),
+                vec![
+                    at(r15.clone()),
+                    set(Dest::A, Comp::M),
+                    set(Dest::M, Comp::D),
+                ],
+            ]
+            .concat(),
+            StackInstr::Squash { count: 0 } => vec![],
+            StackInstr::Squash { count } => [
+                load_top_to_m(),
+                vec![
+                    set(Dest::D, Comp::M),
+                    at(r15.clone()),
+                    set(Dest::M, Comp::D),
+                    at(*count as u16),
+                    set(Dest::D, Comp::A),
+                    at("SP"),
+                    set(Dest::M, Comp::MMinusD),
+                    at(r15.clone()),
+                    set(Dest::D, Comp::M),
+                ],
+                load_top_to_m(),
+                vec![set(Dest::M, Comp::D)],
+            ]
+            .concat(),
+            StackInstr::Add => binary(Comp::DPlusM),
+            StackInstr::Subtract => binary(Comp::MMinusD),
+            StackInstr::Negate => unary(Comp::NegM),
+            StackInstr::Equal => ctx.comparison(owner, Helper::Equal, Jump::Equal),
+            StackInstr::Greater => ctx.comparison(owner, Helper::Greater, Jump::Greater),
+            StackInstr::Less => ctx.comparison(owner, Helper::Less, Jump::Less),
+            StackInstr::And => binary(Comp::DAndM),
+            StackInstr::Or => binary(Comp::DOrM),
+            StackInstr::Not => unary(Comp::NotM),
+            #[cfg(feature = "extensions")]
+            StackInstr::Multiply => ctx.call_helper(owner, Helper::Multiply),
+            #[cfg(feature = "extensions")]
+            StackInstr::Divide => ctx.call_helper(owner, Helper::Divide),
+            #[cfg(feature = "extensions")]
+            StackInstr::Modulo => ctx.call_helper(owner, Helper::Modulo),
+            #[cfg(feature = "extensions")]
+            StackInstr::ShiftLeft { count: 16.. } => {
+                [load_top_to_m(), vec![set(Dest::M, Comp::Zero)]].concat()
             }
-            StackInstr::Add => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                M=D+M\n"
-            )),
-            StackInstr::Subtract => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                M=M-D\n"
-            )),
-            StackInstr::Negate => Ok(format!(
-                "{LOAD_TOP_TO_M}\
-                M=-M\n"
-            )),
-            StackInstr::Equal => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                D=M-D\n\
-                @TRUE.{scope}\n\
-                D;JEQ\n\
-                {LOAD_TOP_TO_M}\
-                M=0\n\
-                @END.{scope}\n\
-                0;JMP\n\
-                (TRUE.{scope})\n\
-                {LOAD_TOP_TO_M}\
-                M=-1\n\
-                (END.{scope})\n"
-            )),
-            StackInstr::Greater => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                D=M-D\n\
-                @TRUE.{scope}\n\
-                D;JGT\n\
-                {LOAD_TOP_TO_M}\
-                M=0\n\
-                @END.{scope}\n\
-                0;JMP\n\
-                (TRUE.{scope})\n\
-                {LOAD_TOP_TO_M}\
-                M=-1\n\
-                (END.{scope})\n"
-            )),
-            StackInstr::Less => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                D=M-D\n\
-                @TRUE.{scope}\n\
-                D;JLT\n\
-                {LOAD_TOP_TO_M}\
-                M=0\n\
-                @END.{scope}\n\
-                0;JMP\n\
-                (TRUE.{scope})\n\
-                {LOAD_TOP_TO_M}\
-                M=-1\n\
-                (END.{scope})\n"
-            )),
-            StackInstr::And => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                M=M&D\n"
-            )),
-            StackInstr::Or => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                M=M|D\n",
-            )),
-            StackInstr::Not => Ok(format!(
-                "{POP_TO_D}{LOAD_TOP_TO_M}\
-                M=!M\n"
-            )),
-        }
+            #[cfg(feature = "extensions")]
+            StackInstr::ShiftLeft { count } => {
+                let doubled = [set(Dest::D, Comp::M), set(Dest::M, Comp::DPlusM)];
+                [
+                    load_top_to_m(),
+                    doubled
+                        .iter()
+                        .cloned()
+                        .cycle()
+                        .take(*count as usize * 2)
+                        .collect(),
+                ]
+                .concat()
+            }
+            #[cfg(feature = "extensions")]
+            StackInstr::ShiftRight { count } => {
+                let [_, r14, _] = layout.scratch();
+                let bit = match count {
+                    0..15 => vec![at(1u16 << count), set(Dest::D, Comp::A)],
+                    15 => vec![at(MAX_ADDRESSABLE as u16), set(Dest::D, Comp::NotA)],
+                    _ => vec![set(Dest::D, Comp::Zero)],
+                };
+                [
+                    bit,
+                    vec![at(r14), set(Dest::M, Comp::D)],
+                    ctx.call_helper(owner, Helper::ShiftRight),
+                ]
+                .concat()
+            }
+        };
+        let generated = [annotation, generated].concat();
+        ctx.map(self, &generated);
+        Ok(generated)
     }
 }
 
 impl ScopedGenerate for CallInstr {
     type Error = Error;
 
-    fn scoped_generate(&self, scope: &str) -> Result<String, Self::Error> {
-        let arg_offset = 5 + self.args;
-        let callee = &self.ident;
-        Ok(format!(
-            "@{scope}\n\
-            D=A\n\
-            {PUSH_D}\
-            @LCL\n\
-            D=M\n\
-            {PUSH_D}\
-            @ARG\n\
-            D=M\n\
-            {PUSH_D}\
-            @THIS\n\
-            D=M\n\
-            {PUSH_D}\
-            @THAT\n\
-            D=M\n\
-            {PUSH_D}\
-            @SP\n\
-            D=M\n\
-            @{arg_offset}\n\
-            D=D-A\n\
-            @ARG\n\
-            M=D\n\
-            @SP\n\
-            D=M\n\
-            @LCL\n\
-            M=D\n\
-            @{callee}\n\
-            0;JMP\n\
-            ({scope})\n"
-        ))
+    fn scoped_lower(&self, scope: &Scope, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let annotation = ctx.annotation(self);
+        // Outside a function the scope itself names the return address.
+        let return_label = scope
+            .return_label()
+            .map_or_else(|| scope.to_string(), |label| ctx.synthesized(&label));
+        let scope = return_label.as_str();
+        let args = self.args as u16;
+        let callee = self.ident.as_str();
+        let [r13, r14, _] = ctx.options.layout.scratch();
+        let generated = if ctx.options.compact_calls {
+            [
+                annotation,
+                vec![
+                    at(scope),
+                    set(Dest::D, Comp::A),
+                    at(r13.clone()),
+                    set(Dest::M, Comp::D),
+                    at(callee),
+                    set(Dest::D, Comp::A),
+                    at(r14.clone()),
+                    set(Dest::M, Comp::D),
+                    at(args),
+                    set(Dest::D, Comp::A),
+                ],
+                ctx.jump_to_helper(Helper::Call),
+                vec![label(scope)],
+            ]
+            .concat()
+        } else {
+            [
+                annotation,
+                vec![at(scope), set(Dest::D, Comp::A)],
+                push_d(),
+                push_frame(),
+                vec![
+                    at("SP"),
+                    set(Dest::D, Comp::M),
+                    at(5 + args),
+                    set(Dest::D, Comp::DMinusA),
+                    at("ARG"),
+                    set(Dest::M, Comp::D),
+                    at("SP"),
+                    set(Dest::D, Comp::M),
+                    at("LCL"),
+                    set(Dest::M, Comp::D),
+                    at(callee),
+                    jump(Comp::Zero, Jump::Always),
+                    label(scope),
+                ],
+            ]
+            .concat()
+        };
+        ctx.map(self, &generated);
+        Ok(generated)
+    }
+}
+
+impl CallInstr {
+    /// Lowers the call followed by `return` to a jump reusing the current frame.
+    fn lower_tail(&self, ctx: &mut Context) -> Vec<AsmInstr> {
+        let annotated = format!("{self}; return");
+        let [r13, r14, _] = ctx.options.layout.scratch();
+        let generated = [
+            ctx.annotation(&annotated),
+            vec![
+                at(self.args as u16 + 5),
+                set(Dest::D, Comp::A),
+                at(r13.clone()),
+                set(Dest::M, Comp::D),
+                at(self.ident.as_str()),
+                set(Dest::D, Comp::A),
+                at(r14.clone()),
+                set(Dest::M, Comp::D),
+            ],
+            ctx.jump_to_helper(Helper::TailCall),
+        ]
+        .concat();
+        ctx.map(&annotated, &generated);
+        generated
     }
 }
 
 impl ScopedGenerate for BranchInstr {
     type Error = Error;
 
-    fn scoped_generate(&self, scope: &str) -> Result<String, Self::Error> {
-        match self {
-            BranchInstr::Label { ident } => Ok(format!("({scope}.{ident})\n")),
-            BranchInstr::Goto { ident } => Ok(format!(
-                "@{scope}.{ident}\n\
-                0;JMP\n"
-            )),
-            BranchInstr::CondGoto { ident } => Ok(format!(
-                "{LOAD_TOP_TO_M}\
-                D=M\n\
-                @{scope}.{ident}\n\
-                D;JLT"
-            )),
-        }
+    fn scoped_lower(&self, scope: &Scope, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let annotation = ctx.annotation(self);
+        // Labels belong to the function, as `function$label`, so functions of a class may reuse
+        // them.
+        let scope = scope.owner();
+        let generated = match self {
+            BranchInstr::Label { ident } => vec![label(format!("{scope}${ident}"))],
+            BranchInstr::Goto { ident } => {
+                vec![
+                    at(format!("{scope}${ident}")),
+                    jump(Comp::Zero, Jump::Always),
+                ]
+            }
+            BranchInstr::CondGoto { ident } => [
+                pop_to_d(),
+                vec![
+                    at(format!("{scope}${ident}")),
+                    jump(Comp::D, Jump::NotEqual),
+                ],
+            ]
+            .concat(),
+        };
+        let generated = [annotation, generated].concat();
+        ctx.map(self, &generated);
+        Ok(generated)
     }
 }
 
+pub(crate) fn lower_return(layout: &TargetLayout) -> Vec<AsmInstr> {
+    let [_, r14, _] = layout.scratch();
+    let restored = ["THAT", "THIS", "ARG"].into_iter().flat_map(|pointer| {
+        [
+            at("LCL"),
+            set(Dest::AM, Comp::MMinusOne),
+            set(Dest::D, Comp::M),
+            at(pointer),
+            set(Dest::M, Comp::D),
+        ]
+    });
+    [
+        vec![
+            at(5),
+            set(Dest::D, Comp::A),
+            at("LCL"),
+            set(Dest::A, Comp::MMinusD),
+            set(Dest::D, Comp::M),
+            at(r14.clone()),
+            set(Dest::M, Comp::D),
+        ],
+        load_top_to_m(),
+        vec![
+            set(Dest::D, Comp::M),
+            at("ARG"),
+            set(Dest::A, Comp::M),
+            set(Dest::M, Comp::D),
+            set(Dest::D, Comp::APlusOne),
+            at("SP"),
+            set(Dest::M, Comp::D),
+        ],
+        restored.collect(),
+        vec![
+            at("LCL"),
+            set(Dest::A, Comp::MMinusOne),
+            set(Dest::D, Comp::M),
+            at("LCL"),
+            set(Dest::M, Comp::D),
+            at(r14.clone()),
+            set(Dest::A, Comp::M),
+            jump(Comp::Zero, Jump::Always),
+        ],
+    ]
+    .concat()
+}
+
+/// Lowers a `return`, jumping into the shared routine with [`GenerateOptions::shared_return`].
+fn lower_return_site(ctx: &mut Context) -> Vec<AsmInstr> {
+    let epilogue = if ctx.options.shared_return {
+        ctx.jump_to_helper(Helper::Return)
+    } else {
+        lower_return(&ctx.options.layout)
+    };
+    let returned = [ctx.annotation(&"return"), epilogue].concat();
+    ctx.map(&"return", &returned);
+    returned
+}
+
 impl ScopedGenerate for Function {
     type Error = Error;
 
-    fn scoped_generate(&self, scope: &str) -> Result<String, Self::Error> {
-        let fn_scope = &self.name;
-        let body = self
-            .instr
-            .iter()
-            .enumerate()
-            .map(|(index, item)| match item {
-                Instr::Stack { data } => {
-                    match data {
-                        StackInstr::Push { segment: StackSegment::Static, .. } => data.scoped_generate(scope),
-                        StackInstr::Pop { segment: StackSegment::Static, .. } => data.scoped_generate(scope),
-                        _ => data.scoped_generate(&format!("{fn_scope}.{index}"))
-                    }
-                },
-                Instr::Call { data } => data.scoped_generate(&format!("{scope}$ret.{index}")),
-                Instr::Branch { data } => data.scoped_generate(scope),
-            })
-            .collect::<Result<String, _>>()?;
-        let init_local_vars =
-            vec![StackInstr::push(StackSegment::Constant, 0).to_scoped(scope); self.vars as usize]
-                .generate()?;
-        let returned = if self.returned { 
-            format!("            @5\n\
-            D=A\n\
-            @LCL\n\
-            A=M-D\n\
-            D=M\n\
-            @R14\n\
-            M=D\n\
-            {LOAD_TOP_TO_M}\
-            D=M\n\
-            @ARG\n\
-            A=M\n\
-            M=D\n\
-            D=A+1\n\
-            @SP\n\
-            M=D\n\
-            @LCL\n\
-            AM=M-1\n\
-            D=M\n\
-            @THAT\n\
-            M=D\n\
-            @LCL\n\
-            AM=M-1\n\
-            D=M\n\
-            @THIS\n\
-            M=D\n\
-            @LCL\n\
-            AM=M-1\n\
-            D=M\n\
-            @ARG\n\
-            M=D\n\
-            @LCL\n\
-            A=M-1\n\
-            D=M\n\
-            @LCL\n\
-            M=D\n\
-            @R14\n\
-            A=M\n\
-            0;JMP\n")
-        } else { String::new() };
-        Ok(format!(
-            "({fn_scope})\n\
-            {init_local_vars}\
-            {body}\
-            {returned}"
-        ))
+    fn scoped_lower(&self, scope: &Scope, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let (first_line, first_mapping) = (ctx.line, ctx.source_map.mappings.len());
+        let started = ctx.now();
+        // Top-level code parses into a nameless function, which takes the class scope.
+        let scope = &scope.function(&self.name);
+        let fn_scope = scope.owner().as_str();
+        let pipeline = ctx.options.opt_pipeline();
+        let optimized;
+        let function = if pipeline.passes().is_empty() {
+            self
+        } else {
+            optimized = pipeline.rewrite(self, fn_scope, &mut ctx.dumps);
+            &optimized
+        };
+        let rewritten = ctx.now();
+        ctx.function = Some(fn_scope.to_owned());
+
+        // Blocks are generated in output order, so the source map can follow along.
+        let parsed = !function.span.is_empty();
+        ctx.locate(parsed.then_some(function.span.start));
+        let declaration = format!("function {} {}", function.name, function.vars);
+        let mut generated = Vec::with_capacity(function.estimated_len());
+        generated.extend(ctx.annotation(&declaration));
+        generated.push(label(fn_scope));
+        ctx.map(&declaration, &generated);
+        generated.extend(ctx.hooked(|hook| hook.before_function(function)));
+        generated.extend(
+            vec![
+                StackInstr::push(StackSegment::Constant, 0).to_scoped(scope);
+                function.vars as usize
+            ]
+            .lower(ctx)?,
+        );
+        // Calls right before a `return`, written out or ending the function, jump into the callee
+        // reusing the frame, which returns in their place.
+        let tail_calls = ctx.options.opt_level >= OptLevel::O1;
+        let is_tail = |index: usize| {
+            tail_calls
+                && matches!(function.instr[index], Instr::Call { .. })
+                && function
+                    .instr
+                    .get(index + 1)
+                    .map_or(function.returned, |next| *next == Instr::Return)
+        };
+        let tail_ended = function.instr.len().checked_sub(1).is_some_and(is_tail);
+        for (index, item) in function.instr.iter().enumerate() {
+            if *item == Instr::Return && index.checked_sub(1).is_some_and(is_tail) {
+                continue;
+            }
+            let span = function.instr_span(index);
+            ctx.locate(span.as_ref().map(|span| span.start));
+            generated.extend(ctx.hooked(|hook| hook.before_instr(function, item)));
+            let instr_scope = &scope.instr(index);
+            let lowered = match item {
+                Instr::Call { data } if is_tail(index) => Ok(data.lower_tail(ctx)),
+                Instr::Stack { data } => data.scoped_lower(instr_scope, ctx),
+                Instr::Call { data } => data.scoped_lower(instr_scope, ctx),
+                Instr::Branch { data } => data.scoped_lower(instr_scope, ctx),
+                Instr::Return => Ok(lower_return_site(ctx)),
+            }
+            .map_err(|source| ctx.lowering_error(source, item, span))?;
+            generated.extend(lowered);
+            generated.extend(ctx.hooked(|hook| hook.after_instr(function, item)));
+        }
+        if function.returned && !tail_ended {
+            ctx.locate(parsed.then(|| function.span.end - 1));
+            generated.extend(ctx.hooked(|hook| hook.before_instr(function, &Instr::Return)));
+            generated.extend(lower_return_site(ctx));
+        }
+        generated.extend(ctx.hooked(|hook| hook.after_function(function)));
+        ctx.function = None;
+        let lowered = ctx.now();
+        if let Some(optimized) = pipeline.peephole(&generated, fn_scope, &mut ctx.dumps) {
+            ctx.move_lines(first_line, first_mapping, &optimized.lines);
+            generated = optimized.asm;
+        }
+        let peephole = ctx.now();
+        if ctx.options.instrument {
+            let counters = profile::slot_address(ctx.counters.slot(fn_scope), &ctx.options.layout);
+            let save = ctx.options.layout.counters;
+            let mut fresh_label = || {
+                let label = ctx.labels.next(fn_scope);
+                ctx.synthesized(&format!("COUNT.{label}"))
+            };
+            let instrumented = profile::instrument(&generated, counters, save, &mut fresh_label);
+            ctx.move_lines(first_line, first_mapping, &instrumented.lines);
+            generated = instrumented.asm;
+        }
+        if ctx.clock.is_some() {
+            let finished = ctx.now();
+            let times = ctx
+                .timings
+                .entry(scope.class_name().to_string())
+                .or_default();
+            times.optimize += rewritten - started + (peephole - lowered);
+            times.generate += lowered - rewritten + (finished - peephole);
+        }
+        Ok(generated)
+    }
+}
+
+impl Instr {
+    /// Number of assembly lines the instruction lowers to at most without comments, used to
+    /// reserve output buffers up front.
+    pub fn estimated_len(&self) -> usize {
+        match self {
+            Instr::Stack { data } => match data {
+                StackInstr::Push {
+                    segment: StackSegment::Constant,
+                    ..
+                }
+                | StackInstr::PushNegated { .. }
+                | StackInstr::PushTrue => 7,
+                StackInstr::Push { .. } => 10,
+                StackInstr::Pop { .. } | StackInstr::Place { .. } => 13,
+                StackInstr::Move { .. } => 14,
+                StackInstr::Pick { .. } => 10,
+                StackInstr::Squash { .. } => 13,
+                StackInstr::Add | StackInstr::Subtract | StackInstr::And | StackInstr::Or => 6,
+                StackInstr::Negate | StackInstr::Not => 3,
+                StackInstr::Equal | StackInstr::Greater | StackInstr::Less => 18,
+                #[cfg(feature = "extensions")]
+                StackInstr::Multiply | StackInstr::Divide | StackInstr::Modulo => 7,
+                #[cfg(feature = "extensions")]
+                StackInstr::ShiftLeft { count } => (*count).min(16) as usize * 2 + 2,
+                #[cfg(feature = "extensions")]
+                StackInstr::ShiftRight { .. } => 11,
+            },
+            Instr::Call { .. } => 48,
+            Instr::Return => 39,
+            Instr::Branch { data } => match data {
+                BranchInstr::Label { .. } => 1,
+                BranchInstr::Goto { .. } => 2,
+                BranchInstr::CondGoto { .. } => 5,
+            },
+        }
     }
 }
 
+impl Function {
+    /// Number of assembly lines the function lowers to at most without comments, see
+    /// [`Instr::estimated_len`].
+    pub fn estimated_len(&self) -> usize {
+        let vars = self.vars as usize * 7;
+        let returned = if self.returned { 39 } else { 0 };
+        1 + vars + self.instr.iter().map(Instr::estimated_len).sum::<usize>() + returned
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Class {
-    functions: Vec<Function>,
-    name: String,
+    pub(crate) functions: Vec<Function>,
+    pub(crate) name: String,
+    pub(crate) source: Option<SourceFile>,
 }
 
 impl Class {
     pub fn new(functions: Vec<Function>, name: &str) -> Self {
         Self {
             functions,
-            name: name.to_owned()
+            name: name.to_owned(),
+            source: None,
+        }
+    }
+
+    /// Attaches the file the functions were parsed from, so output can point back into it.
+    pub fn with_source(self, source: SourceFile) -> Self {
+        Self {
+            source: Some(source),
+            ..self
+        }
+    }
+
+    /// Prefixes everything the class defines with `namespace`: its functions and the calls between
+    /// them, its statics and the labels inside its functions. The same class generated under
+    /// different namespaces links into one output without clashes.
+    pub fn with_namespace(self, namespace: &str) -> Self {
+        let own = self
+            .functions
+            .iter()
+            .map(|function| function.name.clone())
+            .collect::<BTreeSet<_>>();
+        let functions = self
+            .functions
+            .into_iter()
+            .map(|mut function| {
+                if !function.name.is_empty() {
+                    function.name = format!("{namespace}{}", function.name).into();
+                }
+                for instr in &mut function.instr {
+                    if let Instr::Call { data } = instr
+                        && own.contains(&data.ident)
+                    {
+                        data.ident = format!("{namespace}{}", data.ident).into();
+                    }
+                }
+                function
+            })
+            .collect();
+        Self {
+            functions,
+            name: format!("{namespace}{}", self.name),
+            ..self
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    /// File the functions were parsed from, if attached with [`Class::with_source`].
+    pub fn source(&self) -> Option<&SourceFile> {
+        self.source.as_ref()
+    }
+
+    /// Checks that the name of the class is a Hack symbol, as its statics and labels are named
+    /// after it, and warns about every function declared with the prefix of another class. Such a
+    /// function shares the statics of this class rather than the class it names, splitting the
+    /// static segment of that class in two.
+    pub fn check_name(&self) -> Result<Vec<Warning>, Error> {
+        if !is_symbol(&self.name) {
+            return Err(Error::InvalidClassName {
+                name: self.name.clone(),
+            });
+        }
+        let warnings = self
+            .functions
+            .iter()
+            .filter(|function| !function.name.is_empty())
+            .filter(|function| {
+                !function
+                    .name
+                    .strip_prefix(self.name.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+            })
+            .map(|function| Warning {
+                message: format!(
+                    "function `{}` is declared in class `{}`, whose statics it uses",
+                    function.name, self.name
+                ),
+                span: function.span.clone(),
+                lint: Lint::ForeignFunction,
+            })
+            .collect();
+        Ok(warnings)
+    }
+
+    /// Number of bytes the class generates to at most without comments, give or take the length of
+    /// its labels, so generation can reserve one buffer up front.
+    pub fn estimated_output_len(&self) -> usize {
+        let lines = self
+            .functions
+            .iter()
+            .map(Function::estimated_len)
+            .sum::<usize>();
+        lines * AVERAGE_LINE_LEN
+    }
+
+    /// Code size of the class generated on its own, including the shared routines it uses.
+    pub fn stats(&self) -> Result<Stats, Error> {
+        self.stats_with(&GenerateOptions::default())
+    }
+
+    pub fn stats_with(&self, options: &GenerateOptions) -> Result<Stats, Error> {
+        let mut ctx = Context::new(GenerateOptions {
+            source_map: true,
+            ..options.clone()
+        });
+        let mut asm = self.lower(&mut ctx)?;
+        asm.extend(ctx.lower_helpers());
+        Ok(Stats::collect(&asm, &ctx.source_map.mappings))
+    }
 }
 
 impl Generate for Class {
     type Error = Error;
 
-    fn generate(&self) -> Result<String, Self::Error> {
-        self.functions.iter().map(|fun| fun.scoped_generate(&self.name)).collect()
+    fn generate_with(&self, ctx: &mut Context) -> Result<String, Self::Error> {
+        let mut generated = String::with_capacity(self.estimated_output_len());
+        self.generate_to(ctx, &mut generated)?;
+        Ok(generated)
+    }
+
+    fn lower(&self, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let mut lowered =
+            Vec::with_capacity(self.functions.iter().map(Function::estimated_len).sum());
+        self.lower_each(ctx, &mut |asm| {
+            lowered.extend(asm);
+            Ok(())
+        })?;
+        Ok(lowered)
+    }
+
+    /// Hands over the output function by function. Functions are generated independently, in
+    /// parallel with the `parallel` feature.
+    fn lower_each(
+        &self,
+        ctx: &mut Context,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), Self::Error>,
+    ) -> Result<(), Self::Error> {
+        ctx.source = self.source.clone();
+        let scope = Scope::class(self.name.as_str());
+        if ctx.options.instrument {
+            // Counters are handed out up front, as forks handing them out on their own would clash.
+            for function in &self.functions {
+                ctx.counters
+                    .slot(scope.function(&function.name).owner().as_str());
+            }
+        }
+        let lower = |function: &Function| {
+            let mut fork = ctx.fork();
+            function
+                .scoped_lower(&scope, &mut fork)
+                .map(|asm| (asm, fork))
+        };
+        #[cfg(feature = "parallel")]
+        let lowered = self.functions.par_iter().map(lower).collect::<Vec<_>>();
+        #[cfg(not(feature = "parallel"))]
+        let lowered = self.functions.iter().map(lower).collect::<Vec<_>>();
+        ctx.source = None;
+        lowered
+            .into_iter()
+            .zip(&self.functions)
+            .try_for_each(|(lowered, function)| {
+                let (asm, fork) = lowered?;
+                ctx.join(fork);
+                let name = if function.name.is_empty() {
+                    &self.name
+                } else {
+                    function.name.as_str()
+                };
+                ctx.usage.push(FunctionStats {
+                    name: name.to_owned(),
+                    instructions: code_len(&asm),
+                });
+                sink(asm)
+            })
     }
 }
 
-pub fn bootstrap() -> String {
-    let boot = CallInstr::new("Sys.init", 0).scoped_generate("BOOTSTRAP").expect("expect ok");
-    format!("@256\n\
-    D=A\n\
-    @SP\n\
-    M=D\n\
-    {boot}")
+/// Number of ROM instructions in `asm`.
+fn code_len(asm: &[AsmInstr]) -> usize {
+    asm.iter().filter(|instr| instr.is_code()).count()
 }
 
-#[cfg(test)]
+/// The function the bootstrap code calls by default.
+pub const ENTRY: &str = "Sys.init";
+
+/// How the [`bootstrap`] code starts a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapOptions {
+    /// Function the program starts in.
+    pub entry: String,
+    /// Initial value of SP, [`TargetLayout::stack_base`] when not set.
+    pub stack_pointer: Option<u16>,
+    /// Call the entry with a full frame as `call {entry} 0` does, instead of jumping into it. The
+    /// program halts once the entry returns.
+    pub call_frame: bool,
+}
+
+impl Default for BootstrapOptions {
+    fn default() -> Self {
+        Self {
+            entry: ENTRY.to_owned(),
+            stack_pointer: None,
+            call_frame: true,
+        }
+    }
+}
+
+pub fn bootstrap(options: BootstrapOptions) -> String {
+    render(&lower_bootstrap(&options, &TargetLayout::default()))
+}
+
+/// Lowers the code setting up the stack and entering the program, see [`bootstrap`].
+pub fn lower_bootstrap(options: &BootstrapOptions, layout: &TargetLayout) -> Vec<AsmInstr> {
+    let entry = if options.call_frame {
+        let mut ctx = Context::new(GenerateOptions {
+            layout: layout.clone(),
+            ..Default::default()
+        });
+        let return_label = ctx.synthesized("BOOTSTRAP");
+        let call = CallInstr::new(options.entry.as_str(), 0)
+            .scoped_lower(&Scope::class(return_label.as_str()), &mut ctx)
+            .expect("expect ok");
+        // Loops on the return address rather than running into whatever follows.
+        [call, vec![at(return_label), jump(Comp::Zero, Jump::Always)]].concat()
+    } else {
+        vec![at(options.entry.as_str()), jump(Comp::Zero, Jump::Always)]
+    };
+    [
+        vec![
+            at(options.stack_pointer.unwrap_or(layout.stack_base)),
+            set(Dest::D, Comp::A),
+            at("SP"),
+            set(Dest::M, Comp::D),
+        ],
+        entry,
+    ]
+    .concat()
+}
+
+#[cfg(all(test, feature = "parser"))]
 mod tests {
-    use crate::generate::{Generate, ScopedGenerate};
-    use crate::parse::StackSegment::Constant;
-    use crate::parse::{BranchInstr, CallInstr, Function, StackInstr};
-    use crate::scoped::ToScoped;
+    use crate::asm::render;
+    use crate::asm::{Comp, Dest, assemble, at, set};
+    use crate::generate::{
+        BootstrapOptions, Class, Clock, Context, Error, Generate, GenerateOptions, OptLevel,
+        ScopedGenerate, ScratchRegisters, TargetLayout, bootstrap, lower_bootstrap,
+    };
+    use crate::optimize::{OptPipeline, Pass};
+    use crate::parse::StackSegment::{Constant, Local, Pointer, Static, Temp};
+    use crate::parse::parse;
+    use crate::parse::{BranchInstr, CallInstr, Function, Instr, StackInstr};
+    use crate::scoped::{Scope, ToScoped};
+    use crate::source::SourceFile;
 
     const TEST_STACK_INSTR: &str = "@1\n\
     D=A\n\
@@ -406,26 +2136,26 @@ mod tests {
     @SP\n\
     A=M-1\n\
     D=M-D\n\
-    @TRUE.Test.test.4\n\
+    @__vm$TRUE.Test.test.0\n\
     D;JEQ\n\
     @SP\n\
     A=M-1\n\
     M=0\n\
-    @END.Test.test.4\n\
+    @__vm$END.Test.test.0\n\
     0;JMP\n\
-    (TRUE.Test.test.4)\n\
+    (__vm$TRUE.Test.test.0)\n\
     @SP\n\
     A=M-1\n\
     M=-1\n\
-    (END.Test.test.4)\n";
+    (__vm$END.Test.test.0)\n";
     #[test]
     fn generate_stack_instr() {
         let instr = vec![
-            StackInstr::push(Constant, 1).to_scoped("Test.test.0"),
-            StackInstr::push(Constant, 2).to_scoped("Test.test.1"),
-            StackInstr::Add.to_scoped("Test.test.2"),
-            StackInstr::push(Constant, 3).to_scoped("Test.test.3"),
-            StackInstr::Equal.to_scoped("Test.test.4"),
+            StackInstr::push(Constant, 1).to_scoped("Test.test"),
+            StackInstr::push(Constant, 2).to_scoped("Test.test"),
+            StackInstr::Add.to_scoped("Test.test"),
+            StackInstr::push(Constant, 3).to_scoped("Test.test"),
+            StackInstr::Equal.to_scoped("Test.test"),
         ];
         let generated = instr.generate().expect("expect ok");
         assert_eq!(TEST_STACK_INSTR, generated)
@@ -470,6 +2200,10 @@ mod tests {
     D=M\n\
     @5\n\
     D=D-A\n\
+    @ARG\n\
+    M=D\n\
+    @SP\n\
+    D=M\n\
     @LCL\n\
     M=D\n\
     @Callee\n\
@@ -478,13 +2212,31 @@ mod tests {
     #[test]
     fn generate_call_instr() {
         let instr = CallInstr::new("Callee", 0);
-        let generated = instr.scoped_generate("Test.test$ret.0").expect("expect ok");
+        let generated = instr
+            .scoped_generate(&Scope::class("Test.test$ret.0"), &mut Context::default())
+            .expect("expect ok");
         assert_eq!(TEST_CALL_INSTR, generated)
     }
 
-    const TEST_BRANCH_INSTR: &str = "(Test.test.Test)\n\
-    @Test.test.Test\n\
+    const TEST_BRANCH_INSTR: &str = "(Test.test$Test)\n\
+    @Test.test$Test\n\
     0;JMP\n";
+    const TEST_COND_GOTO: &str = "@SP\n\
+    AM=M-1\n\
+    D=M\n\
+    @Test.test$Test\n\
+    D;JNE\n\
+    (Test.test$Test)\n";
+    #[test]
+    fn generate_cond_goto() {
+        let instr = vec![
+            BranchInstr::cond_goto("Test").to_scoped("Test.test"),
+            BranchInstr::label("Test").to_scoped("Test.test"),
+        ];
+        let generated = instr.generate().expect("expect ok");
+        assert_eq!(TEST_COND_GOTO, generated)
+    }
+
     #[test]
     fn generate_branch_instr() {
         let instr = vec![
@@ -494,7 +2246,7 @@ mod tests {
         let generated = instr.generate().expect("expect ok");
         assert_eq!(TEST_BRANCH_INSTR, generated)
     }
-    
+
     const TEST_FUNCTION: &str = "(Test.test)\n\
     @0\n\
     D=A\n\
@@ -503,6 +2255,13 @@ mod tests {
     M=D\n\
     @SP\n\
     M=M+1\n\
+    @5\n\
+    D=A\n\
+    @LCL\n\
+    A=M-D\n\
+    D=M\n\
+    @R14\n\
+    M=D\n\
     @SP\n\
     A=M-1\n\
     D=M\n\
@@ -527,27 +2286,815 @@ mod tests {
     D=M\n\
     @ARG\n\
     M=D\n\
-    @2\n\
-    D=A\n\
-    @LCL\n\
-    A=M-D\n\
-    D=M\n\
-    @R14\n\
-    M=D\n\
     @LCL\n\
     A=M-1\n\
     D=M\n\
     @LCL\n\
     M=D\n\
     @R14\n\
+    A=M\n\
     0;JMP\n";
     #[test]
     fn generate_function() {
+        let instr = vec![StackInstr::push(Constant, 0).into()];
+        let function = Function::new(instr, "Test.test", 0, true);
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut Context::default())
+            .expect("expect ok");
+        assert_eq!(TEST_FUNCTION, generated)
+    }
+
+    #[test]
+    fn unique_comparison_labels() {
         let instr = vec![
-            StackInstr::push(Constant, 0).into()
+            StackInstr::Equal.to_scoped("Test.test"),
+            StackInstr::Less.to_scoped("Test.test"),
+        ];
+        let mut ctx = Context::default();
+        let generated = instr.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("(__vm$TRUE.Test.test.0)"));
+        assert!(generated.contains("(__vm$TRUE.Test.test.1)"));
+        let generated = instr.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("(__vm$END.Test.test.2)"));
+        assert!(generated.contains("(__vm$END.Test.test.3)"));
+    }
+
+    #[test]
+    fn seeded_labels() {
+        let instr = vec![StackInstr::Equal.to_scoped("Test.test")];
+        let mut ctx = Context::new(GenerateOptions {
+            label_seed: 100,
+            ..Default::default()
+        });
+        let generated = instr.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("(__vm$TRUE.Test.test.100)"));
+        let mut ctx = Context::new(GenerateOptions {
+            label_seed: 100,
+            ..Default::default()
+        });
+        assert_eq!(instr.generate_with(&mut ctx).expect("expect ok"), generated);
+    }
+
+    #[test]
+    fn generate_with_options() {
+        let instr = vec![
+            StackInstr::push(Constant, 0).into(),
+            StackInstr::Equal.into(),
+            CallInstr::new("Callee", 0).into(),
         ];
         let function = Function::new(instr, "Test.test", 0, true);
-        let generated = function.scoped_generate("Test").expect("expect ok");
-        assert_eq!(TEST_FUNCTION, generated)
+        let mut ctx = Context::new(GenerateOptions {
+            comments: true,
+            label_prefix: "lib$".to_owned(),
+            ..Default::default()
+        });
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut ctx)
+            .expect("expect ok");
+        assert!(
+            generated.starts_with("// function Test.test 0\n(Test.test)\n// push constant 0\n")
+        );
+        assert!(generated.contains("// eq\n"));
+        assert!(generated.contains("// call Callee 0\n"));
+        assert!(generated.contains("// return\n"));
+        assert!(generated.contains("(lib$__vm$TRUE.Test.test.0)"));
+        assert!(generated.contains("(lib$__vm$Test.test$ret.2)"));
+    }
+
+    #[test]
+    fn locate_lowering_error() {
+        const TESTING_VM: &str = "function Foo.bar 0\npush constant 7\npop temp 9\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo")
+            .with_source(SourceFile::new("Foo.vm", TESTING_VM));
+        let error = class
+            .generate_with(&mut Context::default())
+            .expect_err("expect err");
+        assert_eq!(
+            error.to_string(),
+            "failed to lower `pop temp 9` in `Foo.bar` (Foo.vm:3)"
+        );
+        assert_eq!(error.code(), "VM0102");
+        let Error::Lowering {
+            source, line, span, ..
+        } = error
+        else {
+            panic!("expect lowering error");
+        };
+        assert!(matches!(*source, Error::SegmentOverflow));
+        assert_eq!((line, span), (Some(3), Some(35..45)));
+    }
+
+    #[test]
+    fn measure_phases() {
+        use core::sync::atomic::{AtomicU64, Ordering};
+        use core::time::Duration;
+
+        // Every reading of the clock advances it by a millisecond.
+        #[derive(Debug, Default)]
+        struct Ticks(AtomicU64);
+        impl Clock for Ticks {
+            fn now(&self) -> Duration {
+                Duration::from_millis(self.0.fetch_add(1, Ordering::Relaxed))
+            }
+        }
+        const TESTING_VM: &str =
+            "function Foo.bar 0\npush constant 7\nreturn\nfunction Foo.baz 0\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::default();
+        class.generate_with(&mut ctx).expect("expect ok");
+        assert!(ctx.timings().is_empty());
+        let mut ctx = Context::default().with_clock(Ticks::default());
+        class.generate_with(&mut ctx).expect("expect ok");
+        let times = ctx.timings()["Foo"];
+        assert_eq!(
+            (times.optimize, times.generate),
+            (Duration::from_millis(4), Duration::from_millis(4))
+        );
+    }
+
+    #[test]
+    fn generate_annotated() {
+        const TESTING_VM: &str = "function Foo.bar 0\npush constant 7\nlabel END\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo")
+            .with_source(SourceFile::new("Foo.vm", TESTING_VM));
+        let mut ctx = Context::new(GenerateOptions {
+            comments: true,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        let comments = generated
+            .lines()
+            .filter(|line| line.starts_with("//"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            vec![
+                "// function Foo.bar 0 (Foo.vm:1)",
+                "// push constant 7 (Foo.vm:2)",
+                "// label END (Foo.vm:3)",
+                "// return (Foo.vm:4)",
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_source_map() {
+        const TESTING_VM: &str = "function Foo.bar 1\npush constant 7\neq\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo")
+            .with_source(SourceFile::new("Foo.vm", TESTING_VM));
+        let mut ctx = Context::new(GenerateOptions {
+            source_map: true,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        let lines = generated.lines().collect::<Vec<_>>();
+        let mappings = &ctx.source_map().mappings;
+
+        let instr = mappings
+            .iter()
+            .map(|mapping| mapping.instr.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instr,
+            vec![
+                "function Foo.bar 1",
+                "push constant 0",
+                "push constant 7",
+                "eq",
+                "return"
+            ]
+        );
+        assert!(
+            mappings
+                .windows(2)
+                .all(|pair| pair[0].asm.end == pair[1].asm.start)
+        );
+        assert_eq!(
+            mappings.last().expect("expect mapping").asm.end,
+            lines.len()
+        );
+
+        let push = &mappings[2];
+        assert_eq!((push.file.as_deref(), push.line), (Some("Foo.vm"), Some(2)));
+        assert_eq!(push.function.as_deref(), Some("Foo.bar"));
+        assert_eq!(lines[push.asm.start], "@7");
+        assert!(ctx.source_map().to_json().contains(r#""instr":"eq""#));
+    }
+
+    #[test]
+    fn generate_compact_calls() {
+        let instr = vec![
+            CallInstr::new("Callee", 2).into(),
+            CallInstr::new("Callee", 0).into(),
+        ];
+        let function = Function::new(instr, "Test.test", 0, false);
+        let mut ctx = Context::new(GenerateOptions {
+            compact_calls: true,
+            ..Default::default()
+        });
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut ctx)
+            .expect("expect ok");
+        assert_eq!(generated.matches("@__vm$CALL\n0;JMP\n").count(), 2);
+        assert!(
+            generated
+                .contains("@__vm$Test.test$ret.0\nD=A\n@R13\nM=D\n@Callee\nD=A\n@R14\nM=D\n@2\n")
+        );
+        let helpers = ctx.helpers();
+        assert_eq!(helpers.matches("(__vm$CALL)").count(), 1);
+        assert!(Context::default().helpers().is_empty());
+    }
+
+    #[test]
+    fn generate_shared_return() {
+        let first = Function::new(vec![], "Test.first", 0, true);
+        let second = Function::new(vec![], "Test.second", 0, true);
+        let mut ctx = Context::new(GenerateOptions {
+            shared_return: true,
+            ..Default::default()
+        });
+        let generated = vec![first.to_scoped("Test"), second.to_scoped("Test")]
+            .generate_with(&mut ctx)
+            .expect("expect ok");
+        assert_eq!(
+            generated,
+            "(Test.first)\n@__vm$RETURN\n0;JMP\n(Test.second)\n@__vm$RETURN\n0;JMP\n"
+        );
+        let helpers = ctx.helpers();
+        assert!(helpers.starts_with("(__vm$RETURN)\n@5\n"));
+        assert!(helpers.ends_with("@R14\nA=M\n0;JMP\n"));
+    }
+
+    #[test]
+    fn generate_early_return() {
+        let function = Function::new(
+            vec![
+                BranchInstr::cond_goto("END").into(),
+                Instr::Return,
+                BranchInstr::label("END").into(),
+            ],
+            "Test.test",
+            0,
+            true,
+        );
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut Context::default())
+            .expect("expect ok");
+        assert_eq!(generated.matches("@R14\nA=M\n0;JMP\n").count(), 2);
+        assert!(generated.contains("@R14\nA=M\n0;JMP\n(Test.test$END)\n"));
+
+        let mut ctx = Context::new(GenerateOptions {
+            shared_return: true,
+            ..Default::default()
+        });
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut ctx)
+            .expect("expect ok");
+        assert_eq!(generated.matches("@__vm$RETURN\n0;JMP\n").count(), 2);
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn generate_extended_arithmetic() {
+        let function = Function::new(
+            vec![
+                StackInstr::Multiply.into(),
+                StackInstr::Divide.into(),
+                StackInstr::Multiply.into(),
+            ],
+            "Test.test",
+            0,
+            false,
+        );
+        let mut ctx = Context::default();
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut ctx)
+            .expect("expect ok");
+        assert_eq!(generated.matches("@__vm$MUL\n0;JMP\n").count(), 2);
+        assert!(generated.contains("@__vm$RET.Test.test.1\nD=A\n@R13\nM=D\n@__vm$DIV\n0;JMP\n"));
+        let helpers = ctx.helpers();
+        assert_eq!(helpers.matches("(__vm$MUL)").count(), 1);
+        assert_eq!(helpers.matches("(__vm$DIV)").count(), 1);
+        assert!(!helpers.contains("(__vm$MOD)"));
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn generate_shifts() {
+        let generated = StackInstr::ShiftLeft { count: 2 }
+            .scoped_generate(&Scope::class("Test"), &mut Context::default())
+            .expect("expect ok");
+        assert_eq!(generated, "@SP\nA=M-1\nD=M\nM=D+M\nD=M\nM=D+M\n");
+
+        let mut ctx = Context::default();
+        let generated = StackInstr::ShiftRight { count: 15 }
+            .scoped_generate(&Scope::class("Test"), &mut ctx)
+            .expect("expect ok");
+        assert!(generated.starts_with("@32767\nD=!A\n@R14\nM=D\n"));
+        assert!(ctx.helpers().starts_with("(__vm$SHR)\n"));
+    }
+
+    #[test]
+    fn generate_shared_comparisons() {
+        let function = Function::new(
+            vec![
+                Instr::Stack {
+                    data: StackInstr::Equal,
+                },
+                Instr::Stack {
+                    data: StackInstr::Less,
+                },
+                Instr::Stack {
+                    data: StackInstr::Equal,
+                },
+            ],
+            "Test.test",
+            0,
+            false,
+        );
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = function
+            .scoped_generate(&Scope::class("Test"), &mut ctx)
+            .expect("expect ok");
+        assert!(generated.starts_with(
+            "(Test.test)\n@__vm$RET.Test.test.0\nD=A\n@R13\nM=D\n\
+            @__vm$EQ\n0;JMP\n(__vm$RET.Test.test.0)\n"
+        ));
+        assert!(!generated.contains("JEQ"));
+        let helpers = ctx.helpers();
+        assert_eq!(helpers.matches("(__vm$EQ)").count(), 1);
+        assert_eq!(helpers.matches("(__vm$LT)").count(), 1);
+        assert!(!helpers.contains("(__vm$GT)"));
+    }
+
+    #[test]
+    fn generate_peephole() {
+        const TESTING_VM: &str =
+            "function Foo.bar 0\npush argument 0\npush constant 8\nadd\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let unoptimized = class.generate().expect("expect ok");
+        let mut ctx = Context::new(GenerateOptions {
+            source_map: true,
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("@8\nD=A\n@SP\nA=M-1\nM=D+M\n"));
+        assert!(generated.lines().count() < unoptimized.lines().count());
+
+        let mappings = &ctx.source_map().mappings;
+        assert!(
+            mappings
+                .windows(2)
+                .all(|pair| pair[0].asm.end == pair[1].asm.start)
+        );
+        assert_eq!(
+            mappings.last().expect("expect mapping").asm.end,
+            generated.lines().count()
+        );
+        assert_eq!(mappings[2].asm.len(), 2);
+    }
+
+    #[test]
+    fn generate_collapse_moves() {
+        const TESTING_VM: &str = "function Foo.bar 1\npush argument 1\npop local 0\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        let moved = "@LCL\nD=M\n@0\nA=D+A\nD=A\n@R15\nM=D\n\
+            @ARG\nD=M\n@1\nA=D+A\nD=M\n@R15\nA=M\nM=D\n";
+        assert!(generated.contains(moved));
+        assert!(!class.generate().expect("expect ok").contains(moved));
+    }
+
+    #[test]
+    fn generate_fold_constants() {
+        const TESTING_VM: &str =
+            "function Foo.bar 0\npush constant 7\npush constant 8\nadd\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.starts_with("(Foo.bar)\n@15\nD=A\n"));
+        assert!(!generated.contains("@7\n") && !generated.contains("@8\n"));
+    }
+
+    #[test]
+    fn generate_eliminate_dead_code() {
+        const TESTING_VM: &str = "function Foo.bar 0\ngoto END\npush constant 9\nlabel END\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("@Foo.bar$END\n0;JMP\n(Foo.bar$END)\n"));
+        assert!(!generated.contains("@9\n"));
+        assert!(class.generate().expect("expect ok").contains("@9\n"));
+    }
+
+    #[test]
+    fn generate_specialized_constants() {
+        let options = GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        };
+        let generate = |instr: StackInstr| {
+            instr
+                .scoped_generate(&Scope::class("Test"), &mut Context::new(options.clone()))
+                .expect("expect ok")
+        };
+        assert_eq!(
+            generate(StackInstr::push(Constant, 1)),
+            "@SP\nA=M\nM=1\n@SP\nM=M+1\n"
+        );
+        assert_eq!(
+            generate(StackInstr::PushTrue),
+            "@SP\nA=M\nM=-1\n@SP\nM=M+1\n"
+        );
+        let moved = StackInstr::Move {
+            source: Constant,
+            source_literal: 0,
+            segment: Local,
+            literal: 2,
+        };
+        assert_eq!(generate(moved), "@LCL\nD=M\n@2\nA=D+A\nM=0\n");
+        assert_eq!(
+            StackInstr::push(Constant, 1)
+                .scoped_generate(&Scope::class("Test"), &mut Context::default())
+                .expect("expect ok"),
+            "@1\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"
+        );
+
+        const TESTING_VM: &str = "function Foo.bar 1\npush constant 1\npop local 0\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let generated = class
+            .generate_with(&mut Context::new(options))
+            .expect("expect ok");
+        assert!(generated.contains("@LCL\nD=M\n@0\nA=D+A\nM=1\n"));
+    }
+
+    #[test]
+    fn generate_negative_constants() {
+        let generate = |literal| {
+            StackInstr::PushNegated { literal }
+                .scoped_generate(&Scope::class("Test"), &mut Context::default())
+                .expect("expect ok")
+        };
+        assert_eq!(generate(5), "@5\nD=-A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n");
+        assert_eq!(generate(32768), "@32767\nD=!A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n");
+
+        let generate = |instr: StackInstr| {
+            instr.scoped_generate(&Scope::class("Test"), &mut Context::default())
+        };
+        let push = |literal| generate(StackInstr::push(Constant, literal));
+        assert_eq!(
+            push(40000).expect("expect ok"),
+            "@25536\nD=-A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"
+        );
+        assert_eq!(
+            push(65535).expect("expect ok"),
+            "@1\nD=-A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"
+        );
+        assert_eq!(
+            push(32768).expect("expect ok"),
+            "@32767\nD=!A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"
+        );
+        push(65536).expect_err("expect err");
+        generate(StackInstr::push(Local, 40000)).expect_err("expect err");
+    }
+
+    #[test]
+    fn generate_direct_pop() {
+        let generate = |instr: StackInstr| {
+            instr
+                .scoped_generate(&Scope::class("Test"), &mut Context::default())
+                .expect("expect ok")
+        };
+        assert_eq!(
+            generate(StackInstr::pop(Temp, 2)),
+            "@SP\nAM=M-1\nD=M\n@7\nM=D\n"
+        );
+        assert_eq!(
+            generate(StackInstr::pop(Pointer, 1)),
+            "@SP\nAM=M-1\nD=M\n@THAT\nM=D\n"
+        );
+        assert_eq!(
+            generate(StackInstr::pop(Static, 3)),
+            "@SP\nAM=M-1\nD=M\n@Test.3\nM=D\n"
+        );
+        let moved = StackInstr::Move {
+            source: Local,
+            source_literal: 1,
+            segment: Static,
+            literal: 0,
+        };
+        assert_eq!(generate(moved), "@LCL\nD=M\n@1\nA=D+A\nD=M\n@Test.0\nM=D\n");
+        StackInstr::pop(Temp, 8)
+            .scoped_generate(&Scope::class("Test"), &mut Context::default())
+            .expect_err("expect err");
+
+        const TESTING_VM: &str = "function Foo.bar 2\npush local 1\npop static 0\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("@LCL\nD=M\n@1\nA=D+A\nD=M\n@Foo.0\nM=D\n"));
+    }
+
+    #[test]
+    fn generate_tail_call() {
+        const TESTING_VM: &str = "function Foo.loop 1\npush argument 0\ncall Foo.loop 1\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        assert!(
+            class
+                .generate()
+                .expect("expect ok")
+                .contains("(__vm$Foo.loop$ret.1)")
+        );
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert!(
+            generated
+                .ends_with("@6\nD=A\n@R13\nM=D\n@Foo.loop\nD=A\n@R14\nM=D\n@__vm$TAIL\n0;JMP\n")
+        );
+        assert!(!generated.contains("$ret"));
+        assert_eq!(ctx.helpers().matches("(__vm$TAIL)").count(), 1);
+    }
+
+    #[test]
+    fn generate_tail_call_before_base_case() {
+        const TESTING_VM: &str = "function Foo.count 0\npush argument 0\nif-goto BASE\n\
+        push argument 0\npush constant 1\nsub\ncall Foo.count 1\nreturn\nlabel BASE\n\
+        push constant 0\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        let tail =
+            "@6\nD=A\n@R13\nM=D\n@Foo.count\nD=A\n@R14\nM=D\n@__vm$TAIL\n0;JMP\n(Foo.count$BASE)\n";
+        assert!(generated.contains(tail));
+        assert!(!generated.contains("$ret"));
+        assert_eq!(generated.matches("0;JMP").count(), 2);
+    }
+
+    #[test]
+    fn lower_stack_instr() {
+        let scope = Scope::class("Test");
+        let lowered = StackInstr::And
+            .scoped_lower(&scope, &mut Context::default())
+            .expect("expect ok");
+        assert_eq!(lowered.len(), 6);
+        assert_eq!(lowered.last(), Some(&set(Dest::M, Comp::DAndM)));
+        let lowered = StackInstr::push(Static, 3)
+            .scoped_lower(&scope, &mut Context::default())
+            .expect("expect ok");
+        assert_eq!(lowered[..2], [at("Test.3"), set(Dest::D, Comp::M)]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn generate_to_writer() {
+        const TESTING_VM: &str =
+            "function Foo.bar 0\npush constant 7\nreturn\nfunction Foo.baz 0\neq\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let generated = class.generate().expect("expect ok");
+        let mut rendered = String::new();
+        class
+            .generate_to(&mut Context::default(), &mut rendered)
+            .expect("expect ok");
+        assert_eq!(rendered, generated);
+        let mut written = vec![];
+        class
+            .write_to(&mut Context::default(), &mut written)
+            .expect("expect ok");
+        assert_eq!(written, generated.as_bytes());
+    }
+
+    #[test]
+    fn estimate_output_len() {
+        const TESTING_VM: &str = "function Foo.bar 2\n\
+            push local 1\npop that 3\npush constant 9\npop static 0\nlt\nnot\nsub\n\
+            label LOOP\nif-goto LOOP\ngoto LOOP\ncall Foo.bar 2\nreturn";
+        let functions = parse(TESTING_VM).expect("expect ok");
+        for (index, instr) in functions[0].instr.iter().enumerate() {
+            let function = Function::new(vec![instr.clone()], "Foo.bar", 0, false);
+            let lowered = function
+                .scoped_lower(&Scope::class("Foo"), &mut Context::default())
+                .expect("expect ok");
+            assert!(
+                lowered.len() <= 1 + instr.estimated_len(),
+                "{index}: {instr}"
+            );
+        }
+        let class = Class::new(functions, "Foo");
+        let generated = class.generate().expect("expect ok");
+        assert!(generated.lines().count() <= class.functions()[0].estimated_len());
+        assert!(generated.len() <= class.estimated_output_len());
+    }
+
+    #[test]
+    fn generate_functions_independently() {
+        const TESTING_VM: &str = "function Foo.a 0\neq\nreturn\nfunction Foo.b 0\nlt\ngt\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            source_map: true,
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.contains("(__vm$TRUE.Foo.a.0)"));
+        assert!(generated.contains("(__vm$TRUE.Foo.b.0)"));
+        assert!(generated.contains("(__vm$TRUE.Foo.b.1)"));
+        let mappings = &ctx.source_map().mappings;
+        assert!(
+            mappings
+                .windows(2)
+                .all(|pair| pair[0].asm.end == pair[1].asm.start)
+        );
+        assert_eq!(
+            mappings.last().expect("expect mapping").asm.end,
+            generated.lines().count()
+        );
+        assert!(ctx.labels.next("Foo.c").ends_with(".2"));
+    }
+
+    #[test]
+    fn labels_belong_to_the_function() {
+        const TESTING_VM: &str = "function Sys.init 0\npush constant 1\nif-goto IF_TRUE0\n\
+        label IF_TRUE0\ncall Sys.halt 0\nreturn\nfunction Sys.halt 0\nlabel IF_TRUE0\n\
+        goto IF_TRUE0";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Sys");
+        let lowered = class.lower(&mut Context::default()).expect("expect ok");
+        assemble(&lowered, 16..256).expect("expect ok");
+        let generated = render(&lowered);
+        assert!(generated.contains("@Sys.init$IF_TRUE0\nD;JNE\n(Sys.init$IF_TRUE0)\n"));
+        assert!(generated.contains("(Sys.halt$IF_TRUE0)\n@Sys.halt$IF_TRUE0\n0;JMP\n"));
+    }
+
+    #[test]
+    fn generate_namespaced_classes() {
+        const TESTING_VM: &str = "function Foo.main 0\npush static 0\ncall Foo.helper 1\n\
+        call Math.abs 1\nlabel LOOP\ngoto LOOP\nfunction Foo.helper 0\npush argument 0\n\
+        push constant 0\neq\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::default();
+        let mut lowered = class
+            .clone()
+            .with_namespace("a$")
+            .lower(&mut ctx)
+            .expect("expect ok");
+        lowered.extend(
+            class
+                .with_namespace("b$")
+                .lower(&mut ctx)
+                .expect("expect ok"),
+        );
+        assemble(&lowered, 16..256).expect("expect ok");
+        let generated = render(&lowered);
+        assert!(generated.contains("(a$Foo.main)\n"));
+        assert!(generated.contains("(b$Foo.main$LOOP)\n"));
+        assert!(generated.contains("@b$Foo.0\n"));
+        assert!(generated.contains("@a$Foo.helper\n"));
+        assert!(generated.contains("@Math.abs\n"));
+        assert!(!generated.contains("@a$Math.abs\n"));
+    }
+
+    #[test]
+    fn dump_passes() {
+        const TESTING_VM: &str =
+            "function Foo.bar 0\npush constant 1\nif-goto END\npush constant 2\nlabel END\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut pipeline = OptPipeline::new(OptLevel::O2);
+        pipeline.dump = true;
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O2,
+            pipeline: Some(pipeline),
+            ..Default::default()
+        });
+        class.generate_with(&mut ctx).expect("expect ok");
+        let passes = ctx.dumps().iter().map(|dump| dump.pass).collect::<Vec<_>>();
+        assert_eq!(passes, Pass::ALL);
+        assert!(ctx.dumps().iter().all(|dump| dump.function == "Foo.bar"));
+        assert_eq!(ctx.dumps()[2].output, "goto END\nlabel END\n");
+        assert!(ctx.dumps()[4].output.starts_with("(Foo.bar)\n"));
+        let mut ctx = Context::new(GenerateOptions {
+            opt_level: OptLevel::O2,
+            ..Default::default()
+        });
+        class.generate_with(&mut ctx).expect("expect ok");
+        assert!(ctx.dumps().is_empty());
+    }
+
+    #[test]
+    fn check_class_name() {
+        const TESTING_VM: &str = "function Foo.bar 0\nreturn\nfunction Foobar.baz 0\nreturn\n\
+        function Bar.baz 0\nreturn";
+        let functions = parse(TESTING_VM).expect("expect ok");
+        let warnings = Class::new(functions.clone(), "Foo")
+            .check_name()
+            .expect("expect ok");
+        let messages = warnings
+            .iter()
+            .map(|warning| warning.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "function `Foobar.baz` is declared in class `Foo`, whose statics it uses",
+                "function `Bar.baz` is declared in class `Foo`, whose statics it uses",
+            ]
+        );
+        assert_eq!(warnings[1].span, 55..80);
+        Class::new(functions.clone(), "my-class")
+            .check_name()
+            .expect_err("expect err");
+        assert!(
+            Class::new(functions[..1].to_vec(), "Foo")
+                .check_name()
+                .expect("expect ok")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn generate_for_layout() {
+        let layout = TargetLayout {
+            stack_base: 2048,
+            temp_base: 100,
+            scratch: ScratchRegisters {
+                temporary: 300,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut ctx = Context::new(GenerateOptions {
+            layout: layout.clone(),
+            ..Default::default()
+        });
+        let scope = Scope::class("Test");
+        let mut generate =
+            |instr: StackInstr| instr.scoped_generate(&scope, &mut ctx).expect("expect ok");
+        assert_eq!(
+            generate(StackInstr::pop(Temp, 2)),
+            "@SP\nAM=M-1\nD=M\n@102\nM=D\n"
+        );
+        assert!(generate(StackInstr::pop(Local, 0)).ends_with("@300\nA=M\nM=D\n"));
+        let boot = render(&lower_bootstrap(&BootstrapOptions::default(), &layout));
+        assert!(boot.starts_with("@2048\nD=A\n@SP\nM=D\n"));
+    }
+
+    #[test]
+    fn generate_with_scratch_registers() {
+        const TESTING_VM: &str = "function Foo.bar 1\npush argument 0\npush argument 1\neq\n\
+        pop local 0\npush local 0\ncall Foo.bar 1\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let mut ctx = Context::new(GenerateOptions {
+            compact_calls: true,
+            shared_return: true,
+            layout: TargetLayout {
+                scratch: ScratchRegisters {
+                    return_address: 1000,
+                    target: 1001,
+                    temporary: 1002,
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let generated = class.generate_with(&mut ctx).expect("expect ok") + &ctx.helpers();
+        for register in ["@R13", "@R14", "@R15"] {
+            assert!(!generated.contains(register));
+        }
+        for register in ["@1000", "@1001", "@1002"] {
+            assert!(generated.contains(register));
+        }
+    }
+
+    #[test]
+    fn generate_bootstrap() {
+        let boot = bootstrap(BootstrapOptions::default());
+        assert!(boot.starts_with("@256\nD=A\n@SP\nM=D\n@__vm$BOOTSTRAP\nD=A\n"));
+        assert!(boot.ends_with("@Sys.init\n0;JMP\n(__vm$BOOTSTRAP)\n@__vm$BOOTSTRAP\n0;JMP\n"));
+        let boot = bootstrap(BootstrapOptions {
+            entry: "Main.main".to_owned(),
+            stack_pointer: Some(300),
+            call_frame: false,
+        });
+        assert_eq!(boot, "@300\nD=A\n@SP\nM=D\n@Main.main\n0;JMP\n");
     }
 }
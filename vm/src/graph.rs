@@ -0,0 +1,163 @@
+use crate::program::Program;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use serde::Serialize;
+
+/// The calls from one function to another with the same number of arguments.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Edge<'a> {
+    /// Calling function, empty for top-level code.
+    pub caller: &'a str,
+    pub callee: &'a str,
+    /// Number of call sites.
+    pub calls: usize,
+    pub args: u32,
+}
+
+/// Which function calls which, see [`Program::call_graph`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CallGraph<'a> {
+    /// Functions the program defines, in program order.
+    pub functions: Vec<&'a str>,
+    /// Edges in the order of their first call site.
+    pub edges: Vec<Edge<'a>>,
+}
+
+impl<'a> CallGraph<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let functions = program
+            .classes()
+            .iter()
+            .flat_map(|class| class.functions())
+            .map(|function| function.name.as_str())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let mut edges = Vec::<Edge>::new();
+        for site in program.calls() {
+            let same = |edge: &&mut Edge| {
+                edge.caller == site.caller && edge.callee == site.target && edge.args == site.args
+            };
+            match edges.iter_mut().find(same) {
+                Some(edge) => edge.calls += 1,
+                None => edges.push(Edge {
+                    caller: &site.caller,
+                    callee: &site.target,
+                    calls: 1,
+                    args: site.args,
+                }),
+            }
+        }
+        Self { functions, edges }
+    }
+
+    /// Functions `caller` calls, in the order of their first call site.
+    pub fn callees(&self, caller: &str) -> impl Iterator<Item = &'a str> {
+        let mut seen = BTreeSet::new();
+        self.edges
+            .iter()
+            .filter(move |edge| edge.caller == caller)
+            .map(|edge| edge.callee)
+            .filter(move |callee| seen.insert(*callee))
+    }
+
+    /// Names of the functions `entry` can end up calling, including itself. Top-level code is
+    /// always reachable, and so is everything it calls.
+    pub fn reachable(&self, entry: &'a str) -> BTreeSet<&'a str> {
+        let mut reachable = BTreeSet::new();
+        let mut pending = vec![entry, ""];
+        while let Some(name) = pending.pop() {
+            if reachable.insert(name) {
+                pending.extend(self.callees(name));
+            }
+        }
+        reachable.remove("");
+        reachable
+    }
+
+    /// Renders the graph in the DOT language of Graphviz. Functions called but not defined are
+    /// dashed, and each edge is labelled with its number of calls and arguments.
+    pub fn to_dot(&self) -> String {
+        let node = |name: &str| {
+            if name.is_empty() {
+                "(top level)".to_owned()
+            } else {
+                name.to_owned()
+            }
+        };
+        let mut dot = "digraph calls {\n".to_owned();
+        for function in &self.functions {
+            writeln!(dot, "    \"{function}\";").expect("writing to a string never fails");
+        }
+        let defined = self.functions.iter().copied().collect::<BTreeSet<_>>();
+        let undefined = self
+            .edges
+            .iter()
+            .flat_map(|edge| [edge.caller, edge.callee])
+            .filter(|name| !defined.contains(name))
+            .collect::<BTreeSet<_>>();
+        for name in undefined {
+            writeln!(dot, "    \"{}\" [style=dashed];", node(name))
+                .expect("writing to a string never fails");
+        }
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"calls: {}, args: {}\"];",
+                node(edge.caller),
+                edge.callee,
+                edge.calls,
+                edge.args
+            )
+            .expect("writing to a string never fails");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::generate::Class;
+    use crate::parse::parse;
+    use crate::program::Program;
+
+    #[test]
+    fn build_call_graph() {
+        const MAIN_VM: &str = "function Main.main 0\ncall Math.abs 1\ncall Main.draw 0\n\
+        call Math.abs 1\nreturn\nfunction Main.draw 0\ncall Math.abs 2\nreturn\n\
+        function Main.unused 0\nreturn";
+        let program = Program::new(vec![Class::new(parse(MAIN_VM).expect("expect ok"), "Main")]);
+        let graph = program.call_graph();
+        assert_eq!(graph.functions, ["Main.main", "Main.draw", "Main.unused"]);
+        let edges = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.caller, edge.callee, edge.calls, edge.args))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            [
+                ("Main.main", "Math.abs", 2, 1),
+                ("Main.main", "Main.draw", 1, 0),
+                ("Main.draw", "Math.abs", 1, 2),
+            ]
+        );
+        assert_eq!(
+            graph.callees("Main.main").collect::<Vec<_>>(),
+            ["Math.abs", "Main.draw"]
+        );
+        assert!(!graph.reachable("Main.main").contains("Main.unused"));
+        assert_eq!(
+            graph.to_dot(),
+            "digraph calls {\n    \"Main.main\";\n    \"Main.draw\";\n    \"Main.unused\";\n    \
+            \"Math.abs\" [style=dashed];\n    \
+            \"Main.main\" -> \"Math.abs\" [label=\"calls: 2, args: 1\"];\n    \
+            \"Main.main\" -> \"Main.draw\" [label=\"calls: 1, args: 0\"];\n    \
+            \"Main.draw\" -> \"Math.abs\" [label=\"calls: 1, args: 2\"];\n}\n"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use crate::asm::AsmInstr;
+use crate::parse::{Function, Instr};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+/// Injects assembly around the functions and instructions being lowered, registered with
+/// [`Context::with_hook`](crate::generate::Context::with_hook).
+///
+/// Every callback gets the function as it is lowered, after the optimizations of the
+/// [`OptLevel`](crate::generate::OptLevel), and returns the assembly to insert, none by default.
+/// Injected assembly goes through the peephole optimizer along with the function and counts
+/// towards its size, under the `hook` instruction in the [`Stats`](crate::stats::Stats). It runs
+/// between instructions, so D and the [scratch registers](crate::generate::ScratchRegisters) are
+/// free to use, but it has to leave the stack as it found it.
+///
+/// Functions are lowered in parallel with the `parallel` feature, so hooks only get shared access
+/// to themselves.
+pub trait CodegenHook: Debug + Send + Sync {
+    /// Assembly the function starts with, right after its label.
+    fn before_function(&self, _function: &Function) -> Vec<AsmInstr> {
+        vec![]
+    }
+
+    /// Assembly following the function. It is only reached by jumping to a label it defines, as
+    /// the function returns before.
+    fn after_function(&self, _function: &Function) -> Vec<AsmInstr> {
+        vec![]
+    }
+
+    /// Assembly running before `instr` of `function`, including the `return` ending it unless a
+    /// tail call takes its place at [`OptLevel::O1`](crate::generate::OptLevel::O1).
+    fn before_instr(&self, _function: &Function, _instr: &Instr) -> Vec<AsmInstr> {
+        vec![]
+    }
+
+    /// Assembly running after `instr` of `function`, unless it jumps away.
+    fn after_instr(&self, _function: &Function, _instr: &Instr) -> Vec<AsmInstr> {
+        vec![]
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::asm::{AsmInstr, Comp, Dest, at, set};
+    use crate::generate::{Context, ScopedGenerate};
+    use crate::hook::CodegenHook;
+    use crate::parse::{Function, Instr, parse};
+    use crate::scoped::Scope;
+
+    /// Counts calls of every function in a variable named after it.
+    #[derive(Debug)]
+    struct Profiler;
+
+    impl CodegenHook for Profiler {
+        fn before_function(&self, function: &Function) -> Vec<AsmInstr> {
+            vec![
+                at(format!("calls.{}", function.name).as_str()),
+                set(Dest::M, Comp::MPlusOne),
+            ]
+        }
+
+        fn before_instr(&self, _function: &Function, instr: &Instr) -> Vec<AsmInstr> {
+            match instr {
+                Instr::Return => vec![at("returns"), set(Dest::M, Comp::MPlusOne)],
+                _ => vec![],
+            }
+        }
+    }
+
+    #[test]
+    fn inject_assembly() {
+        const TESTING_VM: &str =
+            "function Foo.bar 0\npush constant 1\nif-goto END\nreturn\nlabel END\nreturn";
+        let functions = parse(TESTING_VM).expect("expect ok");
+        let mut ctx = Context::default().with_hook(Profiler);
+        let generated = functions[0]
+            .scoped_generate(&Scope::class("Foo"), &mut ctx)
+            .expect("expect ok");
+        assert!(generated.starts_with("(Foo.bar)\n@calls.Foo.bar\nM=M+1\n@1\n"));
+        assert_eq!(generated.matches("@returns\nM=M+1\n").count(), 2);
+        let plain = functions[0]
+            .scoped_generate(&Scope::class("Foo"), &mut Context::default())
+            .expect("expect ok");
+        assert_eq!(generated.lines().count(), plain.lines().count() + 6);
+    }
+}
@@ -0,0 +1,399 @@
+#[cfg(feature = "codegen")]
+use crate::generate;
+use crate::parse::{self, BranchInstr, CallInstr, StackInstr, StackSegment};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use snafu::{Snafu, ensure};
+
+/// Version of the IR format, bumped on every incompatible change.
+pub const VERSION: u32 = 1;
+
+#[derive(Snafu, Debug, PartialEq, Clone)]
+pub enum Error {
+    #[snafu(display("IR version {version} is not supported, expected {VERSION}"))]
+    UnsupportedVersion { version: u32 },
+    /// The instruction only exists after optimizations, or needs the `extensions` feature.
+    #[snafu(display("instruction `{instr}` has no counterpart"))]
+    Unrepresentable { instr: String },
+    #[snafu(display("malformed IR: {message}"))]
+    Malformed { message: String },
+}
+
+/// Classes of a program as parsed, for tools outside the crate, tagged with the [`VERSION`] they
+/// were written with.
+///
+/// The IR only changes along with [`VERSION`], unlike the internal types it converts from and to,
+/// which follow the needs of code generation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Module {
+    pub version: u32,
+    pub classes: Vec<Class>,
+}
+
+impl Module {
+    pub fn new(classes: Vec<Class>) -> Self {
+        Self {
+            version: VERSION,
+            classes,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("IR is always serializable")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let module = serde_json::from_str::<Module>(json).map_err(|error| Error::Malformed {
+            message: error.to_string(),
+        })?;
+        ensure!(
+            module.version == VERSION,
+            UnsupportedVersionSnafu {
+                version: module.version
+            }
+        );
+        Ok(module)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Class {
+    pub name: String,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    /// Empty for code outside any function.
+    pub name: String,
+    pub locals: u32,
+    /// Instructions in source order, including the `return` ending the function.
+    pub body: Vec<Instr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Segment {
+    Constant,
+    Local,
+    Argument,
+    This,
+    That,
+    Static,
+    Temp,
+    Pointer,
+}
+
+/// One VM instruction, serialized with its name under `op`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Instr {
+    Push {
+        segment: Segment,
+        index: u32,
+    },
+    /// `push constant -{magnitude}`.
+    PushNegative {
+        magnitude: u32,
+    },
+    Pop {
+        segment: Segment,
+        index: u32,
+    },
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+    Mul,
+    Div,
+    Mod,
+    Shl {
+        count: u32,
+    },
+    Shr {
+        count: u32,
+    },
+    Label {
+        name: String,
+    },
+    Goto {
+        name: String,
+    },
+    IfGoto {
+        name: String,
+    },
+    Call {
+        function: String,
+        args: u32,
+    },
+    Return,
+}
+
+impl From<&StackSegment> for Segment {
+    fn from(value: &StackSegment) -> Self {
+        match value {
+            StackSegment::Constant => Segment::Constant,
+            StackSegment::Local => Segment::Local,
+            StackSegment::Argument => Segment::Argument,
+            StackSegment::This => Segment::This,
+            StackSegment::That => Segment::That,
+            StackSegment::Static => Segment::Static,
+            StackSegment::Temp => Segment::Temp,
+            StackSegment::Pointer => Segment::Pointer,
+        }
+    }
+}
+
+impl From<Segment> for StackSegment {
+    fn from(value: Segment) -> Self {
+        match value {
+            Segment::Constant => StackSegment::Constant,
+            Segment::Local => StackSegment::Local,
+            Segment::Argument => StackSegment::Argument,
+            Segment::This => StackSegment::This,
+            Segment::That => StackSegment::That,
+            Segment::Static => StackSegment::Static,
+            Segment::Temp => StackSegment::Temp,
+            Segment::Pointer => StackSegment::Pointer,
+        }
+    }
+}
+
+impl TryFrom<&parse::Instr> for Instr {
+    type Error = Error;
+
+    fn try_from(value: &parse::Instr) -> Result<Self, Self::Error> {
+        let instr = match value {
+            parse::Instr::Stack { data } => match data {
+                StackInstr::Push { segment, literal } => Instr::Push {
+                    segment: segment.into(),
+                    index: *literal,
+                },
+                StackInstr::PushNegated { literal } => Instr::PushNegative {
+                    magnitude: *literal,
+                },
+                StackInstr::Pop { segment, literal } => Instr::Pop {
+                    segment: segment.into(),
+                    index: *literal,
+                },
+                StackInstr::Add => Instr::Add,
+                StackInstr::Subtract => Instr::Sub,
+                StackInstr::Negate => Instr::Neg,
+                StackInstr::Equal => Instr::Eq,
+                StackInstr::Greater => Instr::Gt,
+                StackInstr::Less => Instr::Lt,
+                StackInstr::And => Instr::And,
+                StackInstr::Or => Instr::Or,
+                StackInstr::Not => Instr::Not,
+                #[cfg(feature = "extensions")]
+                StackInstr::Multiply => Instr::Mul,
+                #[cfg(feature = "extensions")]
+                StackInstr::Divide => Instr::Div,
+                #[cfg(feature = "extensions")]
+                StackInstr::Modulo => Instr::Mod,
+                #[cfg(feature = "extensions")]
+                StackInstr::ShiftLeft { count } => Instr::Shl { count: *count },
+                #[cfg(feature = "extensions")]
+                StackInstr::ShiftRight { count } => Instr::Shr { count: *count },
+                StackInstr::Pick { .. }
+                | StackInstr::Place { .. }
+                | StackInstr::Squash { .. }
+                | StackInstr::PushTrue
+                | StackInstr::Move { .. } => {
+                    return UnrepresentableSnafu {
+                        instr: data.to_string(),
+                    }
+                    .fail();
+                }
+            },
+            parse::Instr::Call { data } => Instr::Call {
+                function: data.ident.to_string(),
+                args: data.args,
+            },
+            parse::Instr::Branch { data } => match data {
+                BranchInstr::Label { ident } => Instr::Label {
+                    name: ident.to_string(),
+                },
+                BranchInstr::Goto { ident } => Instr::Goto {
+                    name: ident.to_string(),
+                },
+                BranchInstr::CondGoto { ident } => Instr::IfGoto {
+                    name: ident.to_string(),
+                },
+            },
+            parse::Instr::Return => Instr::Return,
+        };
+        Ok(instr)
+    }
+}
+
+impl TryFrom<&Instr> for parse::Instr {
+    type Error = Error;
+
+    fn try_from(value: &Instr) -> Result<Self, Self::Error> {
+        let stack = |data: StackInstr| parse::Instr::Stack { data };
+        let instr = match value {
+            Instr::Push { segment, index } => stack(StackInstr::push((*segment).into(), *index)),
+            Instr::PushNegative { magnitude } => stack(StackInstr::PushNegated {
+                literal: *magnitude,
+            }),
+            Instr::Pop { segment, index } => stack(StackInstr::pop((*segment).into(), *index)),
+            Instr::Add => stack(StackInstr::Add),
+            Instr::Sub => stack(StackInstr::Subtract),
+            Instr::Neg => stack(StackInstr::Negate),
+            Instr::Eq => stack(StackInstr::Equal),
+            Instr::Gt => stack(StackInstr::Greater),
+            Instr::Lt => stack(StackInstr::Less),
+            Instr::And => stack(StackInstr::And),
+            Instr::Or => stack(StackInstr::Or),
+            Instr::Not => stack(StackInstr::Not),
+            #[cfg(feature = "extensions")]
+            Instr::Mul => stack(StackInstr::Multiply),
+            #[cfg(feature = "extensions")]
+            Instr::Div => stack(StackInstr::Divide),
+            #[cfg(feature = "extensions")]
+            Instr::Mod => stack(StackInstr::Modulo),
+            #[cfg(feature = "extensions")]
+            Instr::Shl { count } => stack(StackInstr::ShiftLeft { count: *count }),
+            #[cfg(feature = "extensions")]
+            Instr::Shr { count } => stack(StackInstr::ShiftRight { count: *count }),
+            #[cfg(not(feature = "extensions"))]
+            Instr::Mul | Instr::Div | Instr::Mod | Instr::Shl { .. } | Instr::Shr { .. } => {
+                return UnrepresentableSnafu {
+                    instr: alloc::format!("{value:?}"),
+                }
+                .fail();
+            }
+            Instr::Label { name } => BranchInstr::label(name.as_str()).into(),
+            Instr::Goto { name } => BranchInstr::goto(name.as_str()).into(),
+            Instr::IfGoto { name } => BranchInstr::cond_goto(name.as_str()).into(),
+            Instr::Call { function, args } => CallInstr::new(function.as_str(), *args).into(),
+            Instr::Return => parse::Instr::Return,
+        };
+        Ok(instr)
+    }
+}
+
+impl TryFrom<&parse::Function> for Function {
+    type Error = Error;
+
+    fn try_from(value: &parse::Function) -> Result<Self, Self::Error> {
+        let mut body = value
+            .instr
+            .iter()
+            .map(Instr::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        if value.returned {
+            body.push(Instr::Return);
+        }
+        Ok(Self {
+            name: value.name.to_string(),
+            locals: value.vars,
+            body,
+        })
+    }
+}
+
+impl TryFrom<&Function> for parse::Function {
+    type Error = Error;
+
+    fn try_from(value: &Function) -> Result<Self, Self::Error> {
+        let mut instr = value
+            .body
+            .iter()
+            .map(parse::Instr::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        // Like the parser, keeps the `return` ending the function out of the instructions.
+        let returned = !value.name.is_empty() && instr.last() == Some(&parse::Instr::Return);
+        if returned {
+            instr.pop();
+        }
+        Ok(parse::Function::new(
+            instr,
+            value.name.as_str(),
+            value.locals,
+            returned,
+        ))
+    }
+}
+
+#[cfg(feature = "codegen")]
+impl TryFrom<&generate::Class> for Class {
+    type Error = Error;
+
+    fn try_from(value: &generate::Class) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: value.name.clone(),
+            functions: value
+                .functions
+                .iter()
+                .map(Function::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(feature = "codegen")]
+impl TryFrom<&Class> for generate::Class {
+    type Error = Error;
+
+    fn try_from(value: &Class) -> Result<Self, Self::Error> {
+        let functions = value
+            .functions
+            .iter()
+            .map(parse::Function::try_from)
+            .collect::<Result<_, _>>()?;
+        Ok(generate::Class::new(functions, &value.name))
+    }
+}
+
+#[cfg(all(test, feature = "parser", feature = "codegen"))]
+mod tests {
+    use crate::generate::{Class, Generate};
+    use crate::ir::{self, Error, Instr, Module, Segment};
+    use crate::parse::parse;
+
+    #[test]
+    fn round_trip() {
+        const MAIN_VM: &str = "function Main.main 1\npush constant 7\npop local 0\n\
+        push constant -3\nlabel LOOP\ncall Main.main 0\nif-goto LOOP\nreturn";
+        let class = Class::new(parse(MAIN_VM).expect("expect ok"), "Main");
+        let module = Module::new(vec![ir::Class::try_from(&class).expect("expect ok")]);
+        let body = &module.classes[0].functions[0].body;
+        assert_eq!(
+            body[0],
+            Instr::Push {
+                segment: Segment::Constant,
+                index: 7
+            }
+        );
+        assert_eq!(body[2], Instr::PushNegative { magnitude: 3 });
+        assert_eq!(body.last(), Some(&Instr::Return));
+
+        let json = module.to_json();
+        assert!(json.starts_with(
+            r#"{"version":1,"classes":[{"name":"Main","functions":[{"name":"Main.main""#
+        ));
+        assert!(json.contains(r#"{"op":"push","segment":"constant","index":7}"#));
+        assert!(json.contains(r#"{"op":"if-goto","name":"LOOP"}"#));
+        let read = Module::from_json(&json).expect("expect ok");
+        assert_eq!(read, module);
+        let restored = Class::try_from(&read.classes[0]).expect("expect ok");
+        assert_eq!(
+            restored.generate().expect("expect ok"),
+            class.generate().expect("expect ok")
+        );
+
+        let newer = json.replacen(r#""version":1"#, r#""version":2"#, 1);
+        assert_eq!(
+            Module::from_json(&newer),
+            Err(Error::UnsupportedVersion { version: 2 })
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use core::ops::Range;
+
+/// Stack pointer, the address of the next free word on the stack.
+pub const SP: u16 = 0;
+/// Base of the `local` segment of the current function.
+pub const LCL: u16 = 1;
+/// Base of the `argument` segment of the current function.
+pub const ARG: u16 = 2;
+/// Base of the `this` segment, also `pointer 0`.
+pub const THIS: u16 = 3;
+/// Base of the `that` segment, also `pointer 1`.
+pub const THAT: u16 = 4;
+/// Words of the `temp` segment.
+pub const TEMP: Range<u16> = 5..13;
+/// Registers R13 to R15, free for the generated code to keep intermediate values in.
+pub const SCRATCH: [u16; 3] = [13, 14, 15];
+/// Words the assembler allocates `static` variables from.
+pub const STATIC: Range<u16> = 16..256;
+/// Address the stack starts at.
+pub const STACK_BASE: u16 = 256;
+/// First word of the memory-mapped screen, which ends at the keyboard.
+pub const SCREEN: u16 = 16384;
+/// Memory-mapped keyboard, holding the key currently pressed.
+pub const KBD: u16 = 24576;
+/// Words of RAM the CPU emulator provides, past the keyboard the Hack computer ends at.
+pub const RAM_SIZE: usize = 32768;
@@ -1,3 +1,242 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod asm;
+#[cfg(feature = "codegen")]
+pub mod cache;
+pub mod diagnostic;
+pub mod emulate;
+pub mod explain;
+#[cfg(feature = "parser")]
+pub mod format;
+#[cfg(feature = "codegen")]
 pub mod generate;
+#[cfg(feature = "codegen")]
+pub mod graph;
+#[cfg(feature = "codegen")]
+pub mod hook;
+pub mod ir;
+pub mod layout;
+#[cfg(feature = "codegen")]
+pub mod lift;
+#[cfg(feature = "codegen")]
+mod link;
+#[cfg(feature = "codegen")]
+pub mod optimize;
 pub mod parse;
+#[cfg(feature = "codegen")]
+pub mod profile;
+#[cfg(feature = "codegen")]
+pub mod program;
+#[cfg(feature = "codegen")]
 pub mod scoped;
+pub mod script;
+pub mod source;
+#[cfg(feature = "codegen")]
+pub mod stats;
+pub mod suggest;
+pub mod symbol;
+#[cfg(feature = "parser")]
+pub mod tokenize;
+
+#[cfg(all(feature = "parser", feature = "codegen"))]
+use crate::generate::{Class, Context, Generate, GenerateOptions};
+use crate::parse::Span;
+#[cfg(all(feature = "parser", feature = "codegen"))]
+use crate::program::Program;
+#[cfg(all(feature = "parser", feature = "codegen"))]
+use crate::source::SourceFile;
+#[cfg(all(feature = "parser", feature = "codegen"))]
+use alloc::string::String;
+#[cfg(all(feature = "parser", feature = "codegen"))]
+use alloc::vec::Vec;
+#[cfg(all(feature = "parser", feature = "codegen"))]
+use alloc::{format, vec};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use snafu::Snafu;
+
+#[cfg(feature = "codegen")]
+pub use crate::link::{LinkOptions, Linker};
+
+/// Any error of the crate, wrapping the error of the stage that failed as its
+/// [`source`](core::error::Error::source).
+///
+/// Every error has a stable [code](Error::code), grouped by stage:
+///
+/// | Codes    | Stage                                  |
+/// |----------|----------------------------------------|
+/// | `VM00xx` | parsing, [`parse::Error`]              |
+/// | `VM01xx` | code generation, [`generate::Error`]   |
+/// | `VM02xx` | assembly, [`asm::Error`]               |
+/// | `VM03xx` | emulation, [`emulate::Error`]          |
+/// | `VM04xx` | test scripts, [`script::Error`]        |
+///
+/// [`explain::explain`] describes each code at length.
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("failed to parse the source"), context(false))]
+    Parse { source: parse::Error },
+    #[cfg(feature = "codegen")]
+    #[snafu(display("failed to generate the output"), context(false))]
+    Generate { source: generate::Error },
+    #[snafu(display("failed to parse the assembly"), context(false))]
+    Assemble { source: asm::Error },
+}
+
+impl Error {
+    /// Stable code of the innermost error, like `VM0003`, which stays the same across releases
+    /// while messages may change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Parse { source } => source.code(),
+            #[cfg(feature = "codegen")]
+            Error::Generate { source } => source.code(),
+            Error::Assemble { source } => source.code(),
+        }
+    }
+
+    /// Byte range of the source the error points at, if any.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Parse {
+                source: parse::Error::Lexing { span, .. },
+            } => Some(span.clone()),
+            #[cfg(feature = "codegen")]
+            Error::Generate {
+                source: generate::Error::Lowering { span, .. },
+            } => span.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// Compiles the VM `source` of the class `class_name` into assembly, as a [`Program`] of that one
+/// class generated with `options`, shared routines included.
+#[cfg(all(feature = "parser", feature = "codegen"))]
+pub fn compile_str(
+    source: &str,
+    class_name: &str,
+    options: &GenerateOptions,
+) -> Result<String, Error> {
+    let functions = parse::parse(source)?;
+    let source = SourceFile::new(&format!("{class_name}.vm"), source);
+    let class = Class::new(functions, class_name).with_source(source);
+    class.check_name()?;
+    let program = Program::new(vec![class]);
+    Ok(program.generate_with(&mut Context::new(options.clone()))?)
+}
+
+/// Output of one file of [`compile_project`].
+#[cfg(all(feature = "parser", feature = "codegen"))]
+#[derive(Debug)]
+pub struct CompiledFile {
+    pub name: String,
+    pub result: Result<String, Error>,
+}
+
+/// Compiles each of the `sources`, given as file name and VM source, with [`compile_str`], the
+/// class named after the file without its `.vm` extension. Files are compiled independently, in
+/// parallel with the `parallel` feature, and their results are returned in the order of `sources`.
+#[cfg(all(feature = "parser", feature = "codegen"))]
+pub fn compile_project(
+    sources: Vec<(String, String)>,
+    options: &GenerateOptions,
+) -> Vec<CompiledFile> {
+    let compile = |(name, source): (String, String)| {
+        let class_name = name.strip_suffix(".vm").unwrap_or(&name);
+        let result = compile_str(&source, class_name, options);
+        CompiledFile { name, result }
+    };
+    #[cfg(feature = "parallel")]
+    let compiled = sources.into_par_iter().map(compile).collect();
+    #[cfg(not(feature = "parallel"))]
+    let compiled = sources.into_iter().map(compile).collect();
+    compiled
+}
+
+#[cfg(all(test, feature = "parser", feature = "codegen"))]
+mod tests {
+    use crate::generate::{BootstrapOptions, GenerateOptions};
+    use crate::{Error, compile_project, compile_str};
+
+    #[test]
+    fn compile_source() {
+        const TESTING_VM: &str =
+            "function Sys.init 0\npush constant 1\npush constant 2\neq\nreturn";
+        let generated =
+            compile_str(TESTING_VM, "Sys", &GenerateOptions::default()).expect("expect ok");
+        assert!(generated.starts_with("(Sys.init)\n"));
+        let options = GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        };
+        assert!(
+            compile_str(TESTING_VM, "Sys", &options)
+                .expect("expect ok")
+                .starts_with("@256\n")
+        );
+        assert!(matches!(
+            compile_str("push", "Sys", &options),
+            Err(Error::Parse { .. })
+        ));
+        assert!(matches!(
+            compile_str(TESTING_VM, "my-sys", &options),
+            Err(Error::Generate { .. })
+        ));
+    }
+
+    #[test]
+    fn error_codes() {
+        let options = GenerateOptions::default();
+        let error = compile_str("push", "Sys", &options).expect_err("expect err");
+        assert_eq!(error.code(), "VM0001");
+        assert_eq!(error.span(), None);
+        let error =
+            compile_str("function Sys.init 0\n/* return", "Sys", &options).expect_err("expect err");
+        assert_eq!(error.code(), "VM0003");
+        assert_eq!(error.span(), Some(20..21));
+        assert!(std::error::Error::source(&error).is_some());
+        let error = compile_str("function Sys.init 0\npush temp 9", "Sys", &options)
+            .expect_err("expect err");
+        assert_eq!(error.code(), "VM0102");
+        assert_eq!(error.span(), Some(20..31));
+        let error = compile_str("function Sys.init 0", "my-sys", &options).expect_err("expect err");
+        assert_eq!(error.code(), "VM0103");
+    }
+
+    #[test]
+    fn compile_files() {
+        let sources = vec![
+            (
+                "Sys.vm".to_owned(),
+                "function Sys.init 0\ncall Main.main 0\nreturn".to_owned(),
+            ),
+            (
+                "Main.vm".to_owned(),
+                "function Main.main 0\npush".to_owned(),
+            ),
+            (
+                "Math".to_owned(),
+                "function Math.abs 1\npush argument 0\nreturn".to_owned(),
+            ),
+        ];
+        let compiled = compile_project(sources, &GenerateOptions::default());
+        let names = compiled
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Sys.vm", "Main.vm", "Math"]);
+        let sys = compiled[0].result.as_ref().expect("expect ok");
+        assert!(sys.starts_with("(Sys.init)\n"));
+        assert!(matches!(compiled[1].result, Err(Error::Parse { .. })));
+        assert!(
+            compiled[2]
+                .result
+                .as_ref()
+                .expect("expect ok")
+                .starts_with("(Math.abs)\n")
+        );
+    }
+}
@@ -0,0 +1,598 @@
+use crate::asm::{Addr, AsmInstr, Comp, Dest, Jump, at, jump, label, set};
+use crate::generate::{
+    Helper, TargetLayout, load_top_to_m, lower_comparison, lower_return, pop_to_d, push_d,
+    push_frame,
+};
+use crate::layout::TEMP;
+use crate::parse::{
+    BranchInstr, CallInstr, Function, Instr, RESERVED_PREFIX, StackInstr, StackSegment,
+};
+use crate::symbol::Interner;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::mem;
+use core::ops::Range;
+
+/// Functions recovered from generated assembly by [`lift`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lifted {
+    /// Functions in output order. Code before the first function, like the bootstrap code, lifts
+    /// into a nameless one.
+    pub functions: Vec<Function>,
+    /// Ranges of the assembly matching no template, like the shared routines, or code rewritten by
+    /// optimizations.
+    pub unrecognized: Vec<Range<usize>>,
+}
+
+/// One instruction of a [`Template`].
+#[derive(Debug, Clone)]
+enum Pat {
+    Is(AsmInstr),
+    /// Any A-instruction, captured.
+    At,
+    /// Any label, captured.
+    Label,
+}
+
+/// What a [`Pat::At`] or a [`Pat::Label`] matched, in template order.
+#[derive(Debug, Clone, Copy)]
+enum Captured<'a> {
+    Addr(&'a Addr),
+    Label(&'a str),
+}
+
+impl<'a> Captured<'a> {
+    fn constant(&self) -> Option<u32> {
+        match self {
+            Captured::Addr(Addr::Constant(value)) => Some(*value as u32),
+            _ => None,
+        }
+    }
+
+    fn symbol(&self) -> Option<&'a str> {
+        match self {
+            Captured::Addr(Addr::Symbol(symbol)) => Some(symbol),
+            Captured::Label(label) => Some(label),
+            _ => None,
+        }
+    }
+}
+
+type Decode = Box<dyn Fn(&[Captured], &TargetLayout) -> Option<Vec<Instr>>>;
+
+/// A sequence the generator lowers some instructions to, and how to recover them from the
+/// captures of a match.
+struct Template {
+    pats: Vec<Pat>,
+    decode: Decode,
+}
+
+impl Template {
+    /// Template of `asm`, where `@` and labels with an empty name match anything.
+    fn new<F>(asm: Vec<AsmInstr>, decode: F) -> Self
+    where
+        F: Fn(&[Captured], &TargetLayout) -> Option<Vec<Instr>> + 'static,
+    {
+        let pats = asm
+            .into_iter()
+            .map(|instr| match instr {
+                AsmInstr::A(Addr::Symbol(symbol)) if symbol.is_empty() => Pat::At,
+                AsmInstr::Label(name) if name.is_empty() => Pat::Label,
+                instr => Pat::Is(instr),
+            })
+            .collect();
+        Self {
+            pats,
+            decode: Box::new(decode),
+        }
+    }
+
+    fn captures<'a>(&self, code: &[&'a AsmInstr]) -> Option<Vec<Captured<'a>>> {
+        if code.len() < self.pats.len() {
+            return None;
+        }
+        let mut captured = vec![];
+        for (pat, instr) in self.pats.iter().zip(code) {
+            match (pat, instr) {
+                (Pat::Is(expected), instr) if expected == *instr => {}
+                (Pat::At, AsmInstr::A(addr)) => captured.push(Captured::Addr(addr)),
+                (Pat::Label, AsmInstr::Label(name)) => captured.push(Captured::Label(name)),
+                _ => return None,
+            }
+        }
+        Some(captured)
+    }
+}
+
+/// The segment of a segment based at `base`.
+fn based_segment(base: &str) -> Option<StackSegment> {
+    match base {
+        "LCL" => Some(StackSegment::Local),
+        "ARG" => Some(StackSegment::Argument),
+        "THIS" => Some(StackSegment::This),
+        "THAT" => Some(StackSegment::That),
+        _ => None,
+    }
+}
+
+/// The segment and index of a fixed address, a `static`, `temp` or `pointer`.
+fn fixed_segment(addr: &Captured, layout: &TargetLayout) -> Option<(StackSegment, u32)> {
+    if let Some(address) = addr.constant() {
+        let temp = layout.temp_base as u32..layout.temp_base as u32 + TEMP.len() as u32;
+        return temp
+            .contains(&address)
+            .then(|| (StackSegment::Temp, address - layout.temp_base as u32));
+    }
+    match addr.symbol()? {
+        "THIS" => Some((StackSegment::Pointer, 0)),
+        "THAT" => Some((StackSegment::Pointer, 1)),
+        symbol => {
+            let (_, index) = symbol.rsplit_once('.')?;
+            Some((StackSegment::Static, index.parse().ok()?))
+        }
+    }
+}
+
+/// Name of a synthesized label without its prefix, none for other labels.
+fn synthesized(name: &str) -> Option<&str> {
+    name.split_once(RESERVED_PREFIX).map(|(_, name)| name)
+}
+
+/// Whether the captures at `return_label` and `label` name the same return address.
+fn returns_to(captured: &[Captured], return_label: usize, label: usize) -> bool {
+    captured[return_label]
+        .symbol()
+        .is_some_and(|name| Some(name) == captured[label].symbol())
+}
+
+fn stack(data: StackInstr) -> Option<Vec<Instr>> {
+    Some(vec![data.into()])
+}
+
+fn templates(layout: &TargetLayout) -> Vec<Template> {
+    let [r13, r14, r15] = layout.scratch();
+    let helper_call = |helper: Helper| {
+        vec![
+            at(""),
+            set(Dest::D, Comp::A),
+            at(r13.clone()),
+            set(Dest::M, Comp::D),
+            at(format!("{RESERVED_PREFIX}{}", helper.name())),
+            jump(Comp::Zero, Jump::Always),
+            label(""),
+        ]
+    };
+    let mut templates = vec![
+        Template::new(
+            [vec![at(""), set(Dest::D, Comp::A)], push_d()].concat(),
+            |captured, _| {
+                stack(StackInstr::push(
+                    StackSegment::Constant,
+                    captured[0].constant()?,
+                ))
+            },
+        ),
+        Template::new(
+            [vec![at(""), set(Dest::D, Comp::NegA)], push_d()].concat(),
+            |captured, _| {
+                stack(StackInstr::PushNegated {
+                    literal: captured[0].constant()?,
+                })
+            },
+        ),
+        Template::new(
+            [vec![at(""), set(Dest::D, Comp::NotA)], push_d()].concat(),
+            |captured, _| {
+                (captured[0].constant()? == i16::MAX as u32).then_some(())?;
+                stack(StackInstr::PushNegated { literal: 32768 })
+            },
+        ),
+        Template::new(
+            [
+                vec![
+                    at(""),
+                    set(Dest::D, Comp::M),
+                    at(""),
+                    set(Dest::A, Comp::DPlusA),
+                    set(Dest::D, Comp::M),
+                ],
+                push_d(),
+            ]
+            .concat(),
+            |captured, _| {
+                let segment = based_segment(captured[0].symbol()?)?;
+                stack(StackInstr::push(segment, captured[1].constant()?))
+            },
+        ),
+        Template::new(
+            [vec![at(""), set(Dest::D, Comp::M)], push_d()].concat(),
+            |captured, layout| {
+                let (segment, index) = fixed_segment(&captured[0], layout)?;
+                stack(StackInstr::push(segment, index))
+            },
+        ),
+        Template::new(
+            [pop_to_d(), vec![at(""), set(Dest::M, Comp::D)]].concat(),
+            |captured, layout| {
+                let (segment, index) = fixed_segment(&captured[0], layout)?;
+                stack(StackInstr::pop(segment, index))
+            },
+        ),
+        Template::new(
+            [
+                vec![
+                    at(""),
+                    set(Dest::D, Comp::M),
+                    at(""),
+                    set(Dest::A, Comp::DPlusA),
+                    set(Dest::D, Comp::A),
+                    at(r15.clone()),
+                    set(Dest::M, Comp::D),
+                ],
+                pop_to_d(),
+                vec![
+                    at(r15.clone()),
+                    set(Dest::A, Comp::M),
+                    set(Dest::M, Comp::D),
+                ],
+            ]
+            .concat(),
+            |captured, _| {
+                let segment = based_segment(captured[0].symbol()?)?;
+                stack(StackInstr::pop(segment, captured[1].constant()?))
+            },
+        ),
+        Template::new(
+            [pop_to_d(), vec![at(""), jump(Comp::D, Jump::NotEqual)]].concat(),
+            |captured, _| Some(vec![BranchInstr::cond_goto(captured[0].symbol()?).into()]),
+        ),
+        Template::new(
+            vec![at(""), jump(Comp::Zero, Jump::Always)],
+            |captured, _| {
+                let target = captured[0].symbol()?;
+                match synthesized(target) {
+                    Some(name) => (name == Helper::Return.name()).then(|| vec![Instr::Return]),
+                    None => Some(vec![BranchInstr::goto(target).into()]),
+                }
+            },
+        ),
+        Template::new(
+            [
+                vec![at(""), set(Dest::D, Comp::A)],
+                push_d(),
+                push_frame(),
+                vec![
+                    at("SP"),
+                    set(Dest::D, Comp::M),
+                    at(""),
+                    set(Dest::D, Comp::DMinusA),
+                    at("ARG"),
+                    set(Dest::M, Comp::D),
+                    at("SP"),
+                    set(Dest::D, Comp::M),
+                    at("LCL"),
+                    set(Dest::M, Comp::D),
+                    at(""),
+                    jump(Comp::Zero, Jump::Always),
+                    label(""),
+                ],
+            ]
+            .concat(),
+            |captured, _| {
+                returns_to(captured, 0, 3).then_some(())?;
+                let args = captured[1].constant()?.checked_sub(5)?;
+                Some(vec![CallInstr::new(captured[2].symbol()?, args).into()])
+            },
+        ),
+        Template::new(
+            vec![
+                at(""),
+                set(Dest::D, Comp::A),
+                at(r13.clone()),
+                set(Dest::M, Comp::D),
+                at(""),
+                set(Dest::D, Comp::A),
+                at(r14.clone()),
+                set(Dest::M, Comp::D),
+                at(""),
+                set(Dest::D, Comp::A),
+                at(format!("{RESERVED_PREFIX}{}", Helper::Call.name())),
+                jump(Comp::Zero, Jump::Always),
+                label(""),
+            ],
+            |captured, _| {
+                returns_to(captured, 0, 3).then_some(())?;
+                Some(vec![
+                    CallInstr::new(captured[1].symbol()?, captured[2].constant()?).into(),
+                ])
+            },
+        ),
+        Template::new(
+            vec![
+                at(""),
+                set(Dest::D, Comp::A),
+                at(r13.clone()),
+                set(Dest::M, Comp::D),
+                at(""),
+                set(Dest::D, Comp::A),
+                at(r14.clone()),
+                set(Dest::M, Comp::D),
+                at(format!("{RESERVED_PREFIX}{}", Helper::TailCall.name())),
+                jump(Comp::Zero, Jump::Always),
+            ],
+            |captured, _| {
+                let args = captured[0].constant()?.checked_sub(5)?;
+                Some(vec![
+                    CallInstr::new(captured[1].symbol()?, args).into(),
+                    Instr::Return,
+                ])
+            },
+        ),
+        Template::new(lower_return(layout), |_, _| Some(vec![Instr::Return])),
+    ];
+    let pushed = |comp: Comp| {
+        vec![
+            at("SP"),
+            set(Dest::A, Comp::M),
+            set(Dest::M, comp),
+            at("SP"),
+            set(Dest::M, Comp::MPlusOne),
+        ]
+    };
+    for (comp, instr) in [(Comp::Zero, 0), (Comp::One, 1)] {
+        let push = StackInstr::push(StackSegment::Constant, instr);
+        templates.push(Template::new(pushed(comp), move |_, _| stack(push.clone())));
+    }
+    templates.push(Template::new(pushed(Comp::MinusOne), |_, _| {
+        stack(StackInstr::PushNegated { literal: 1 })
+    }));
+    let binary = [
+        (Comp::DPlusM, StackInstr::Add),
+        (Comp::MMinusD, StackInstr::Subtract),
+        (Comp::DAndM, StackInstr::And),
+        (Comp::DOrM, StackInstr::Or),
+    ];
+    for (comp, instr) in binary {
+        let asm = [pop_to_d(), load_top_to_m(), vec![set(Dest::M, comp)]].concat();
+        templates.push(Template::new(asm, move |_, _| stack(instr.clone())));
+    }
+    for (comp, instr) in [
+        (Comp::NegM, StackInstr::Negate),
+        (Comp::NotM, StackInstr::Not),
+    ] {
+        let asm = [load_top_to_m(), vec![set(Dest::M, comp)]].concat();
+        templates.push(Template::new(asm, move |_, _| stack(instr.clone())));
+    }
+    let comparisons = [
+        (Jump::Equal, Helper::Equal, StackInstr::Equal),
+        (Jump::Greater, Helper::Greater, StackInstr::Greater),
+        (Jump::Less, Helper::Less, StackInstr::Less),
+    ];
+    for (jump, helper, instr) in comparisons {
+        let inlined = lower_comparison(jump, (String::new(), String::new()));
+        let shared = instr.clone();
+        templates.push(Template::new(inlined, move |captured, _| {
+            (returns_to(captured, 0, 2) && returns_to(captured, 1, 3)).then_some(())?;
+            stack(instr.clone())
+        }));
+        templates.push(Template::new(helper_call(helper), move |captured, _| {
+            returns_to(captured, 0, 1).then_some(())?;
+            stack(shared.clone())
+        }));
+    }
+    #[cfg(feature = "extensions")]
+    for (helper, instr) in [
+        (Helper::Multiply, StackInstr::Multiply),
+        (Helper::Divide, StackInstr::Divide),
+        (Helper::Modulo, StackInstr::Modulo),
+    ] {
+        templates.push(Template::new(helper_call(helper), move |captured, _| {
+            returns_to(captured, 0, 1).then_some(())?;
+            stack(instr.clone())
+        }));
+    }
+    // The longest templates go first, as shorter ones can match their beginning.
+    templates.sort_by_key(|template| core::cmp::Reverse(template.pats.len()));
+    templates
+}
+
+/// What a run of assembly lifted into.
+enum Item {
+    Label(String),
+    Instr(Vec<Instr>),
+    Unrecognized,
+}
+
+/// Recovers the VM functions `asm` was generated from, for code generated for `layout`.
+///
+/// The lifter recognizes the sequences the generator lowers instructions to, including the
+/// shared comparisons, calls and returns of [`OptLevel::O1`](crate::generate::OptLevel::O1), but
+/// not the sequences optimization passes fuse. Labels jumped to are branch labels, the others
+/// start functions. Locals are counted from the zeros a function pushes on entry, so a function
+/// whose code starts by pushing 0 lifts with one more local than it declared.
+pub fn lift(asm: &[AsmInstr], layout: &TargetLayout) -> Lifted {
+    let templates = templates(layout);
+    let (lines, code): (Vec<_>, Vec<_>) = asm
+        .iter()
+        .enumerate()
+        .filter(|(_, instr)| !matches!(instr, AsmInstr::Comment(_)))
+        .unzip();
+
+    let mut items = vec![];
+    let mut start = 0;
+    while start < code.len() {
+        let matched = templates.iter().find_map(|template| {
+            let captured = template.captures(&code[start..])?;
+            Some(((template.decode)(&captured, layout)?, template.pats.len()))
+        });
+        let (item, len) = match (matched, code[start]) {
+            (Some((instr, len)), _) => (Item::Instr(instr), len),
+            (None, AsmInstr::Label(name)) => (Item::Label(name.clone()), 1),
+            (None, _) => (Item::Unrecognized, 1),
+        };
+        let end = lines.get(start + len).copied().unwrap_or(asm.len());
+        items.push((item, lines[start]..end));
+        start += len;
+    }
+
+    let targets = items
+        .iter()
+        .flat_map(|(item, _)| match item {
+            Item::Instr(instr) => instr.as_slice(),
+            _ => &[],
+        })
+        .filter_map(|instr| match instr {
+            Instr::Branch {
+                data: BranchInstr::Goto { ident } | BranchInstr::CondGoto { ident },
+            } => Some(ident.clone()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+
+    let mut interner = Interner::new();
+    let mut lifted = Lifted {
+        functions: vec![],
+        unrecognized: vec![],
+    };
+    // Instructions of the function being lifted, none within a shared routine.
+    let mut current = Some((String::new(), vec![]));
+    for (item, range) in items {
+        match item {
+            Item::Label(name) if targets.contains(name.as_str()) => match &mut current {
+                Some((_, body)) => body.push(BranchInstr::label(name.as_str()).into()),
+                None => extend(&mut lifted.unrecognized, range),
+            },
+            Item::Label(name) => {
+                let next = synthesized(&name).is_none().then(|| (name, vec![]));
+                if let Some((name, body)) = mem::replace(&mut current, next) {
+                    lifted.functions.extend(finish(&mut interner, name, body));
+                }
+                if current.is_none() {
+                    extend(&mut lifted.unrecognized, range);
+                }
+            }
+            Item::Instr(instr) => match &mut current {
+                Some((_, body)) => body.extend(instr),
+                None => extend(&mut lifted.unrecognized, range),
+            },
+            Item::Unrecognized => extend(&mut lifted.unrecognized, range),
+        }
+    }
+    if let Some((name, body)) = current {
+        lifted.functions.extend(finish(&mut interner, name, body));
+    }
+    lifted
+}
+
+/// Adds `range` to `ranges`, merging it into the last one when they touch.
+fn extend(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+/// Turns the lifted body of the function `name` into a function, none for an empty nameless one.
+fn finish(interner: &mut Interner, name: String, mut body: Vec<Instr>) -> Option<Function> {
+    if name.is_empty() && body.is_empty() {
+        return None;
+    }
+    let zero = Instr::from(StackInstr::push(StackSegment::Constant, 0));
+    let vars = if name.is_empty() {
+        0
+    } else {
+        body.iter().take_while(|instr| **instr == zero).count()
+    };
+    body.drain(..vars);
+    // Branch labels are prefixed with the function, as `function$label`.
+    let prefix = format!("{name}$");
+    for instr in &mut body {
+        match instr {
+            Instr::Branch {
+                data:
+                    BranchInstr::Label { ident }
+                    | BranchInstr::Goto { ident }
+                    | BranchInstr::CondGoto { ident },
+            } => {
+                let local = ident.strip_prefix(prefix.as_str()).unwrap_or(ident);
+                *ident = interner.intern(local);
+            }
+            Instr::Call { data } => data.ident = interner.intern(&data.ident),
+            _ => {}
+        }
+    }
+    let returned = !name.is_empty() && body.last() == Some(&Instr::Return);
+    if returned {
+        body.pop();
+    }
+    Some(Function::new(
+        body,
+        interner.intern(&name),
+        vars as u32,
+        returned,
+    ))
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::generate::{
+        BootstrapOptions, Class, Context, Generate, GenerateOptions, OptLevel, TargetLayout,
+    };
+    use crate::lift::lift;
+    use crate::optimize::OptPipeline;
+    use crate::parse::{Function, parse};
+    use crate::program::Program;
+
+    const MAIN_VM: &str = "function Main.main 2\npush constant 7\npush constant -5\n\
+    push constant 1\npop local 0\npush argument 2\npop that 3\npush static 4\npop static 2\n\
+    push temp 3\npop temp 1\npush pointer 0\npop pointer 1\nadd\nsub\nneg\neq\ngt\nlt\nand\nor\n\
+    not\nlabel LOOP\nif-goto LOOP\ngoto END\nlabel END\ncall Main.draw 2\nreturn\n\
+    function Main.draw 0\ncall Math.abs 1\nreturn";
+
+    fn lifted(options: GenerateOptions) -> (Vec<Function>, usize) {
+        let program = Program::new(vec![Class::new(parse(MAIN_VM).expect("expect ok"), "Main")]);
+        let asm = program
+            .lower(&mut Context::new(options))
+            .expect("expect ok");
+        let lifted = lift(&asm, &TargetLayout::default());
+        let unrecognized = lifted
+            .unrecognized
+            .iter()
+            .flat_map(|range| &asm[range.clone()]);
+        (
+            lifted.functions,
+            unrecognized.filter(|instr| instr.is_code()).count(),
+        )
+    }
+
+    #[test]
+    fn lift_generated_code() {
+        let expected = parse(MAIN_VM).expect("expect ok");
+        let (functions, unrecognized) = lifted(GenerateOptions {
+            comments: true,
+            keep_unused: true,
+            ..Default::default()
+        });
+        assert_eq!(functions, expected);
+        assert_eq!(unrecognized, 0);
+
+        let (functions, _) = lifted(GenerateOptions {
+            compact_calls: true,
+            shared_return: true,
+            opt_level: OptLevel::O1,
+            pipeline: Some(OptPipeline::new(OptLevel::O0)),
+            inline_threshold: 0,
+            bootstrap: Some(BootstrapOptions {
+                entry: "Main.main".to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        // The bootstrap code calls the entry from top-level code.
+        assert_eq!(functions[0].name(), "");
+        assert_eq!(functions[1..], expected);
+    }
+}
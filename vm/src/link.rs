@@ -0,0 +1,259 @@
+use crate::Error;
+use crate::asm::{self, AsmInstr, PREDEFINED, parse_asm, render};
+use crate::cache::CompileCache;
+use crate::generate::{self, Class, Context, Generate, GenerateOptions, RESERVED_PREFIX};
+use crate::program::Program;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io;
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkOptions {
+    /// Generates the classes, starting the output with the bootstrap code when it is set.
+    pub generate: GenerateOptions,
+}
+
+/// Collects classes and hand-written assembly into one complete program, in memory.
+#[derive(Debug, Clone, Default)]
+pub struct Linker {
+    classes: Vec<Class>,
+    bootstrap: Option<String>,
+    raw: Vec<String>,
+    order: Vec<String>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_class(&mut self, class: Class) -> &mut Self {
+        self.classes.push(class);
+        self
+    }
+
+    /// Adds hand-written assembly, which goes after the generated code in the order it was added.
+    /// It is only parsed when linking, and may not define the labels of functions of the program,
+    /// of the generated routines or of other hand-written assembly.
+    pub fn add_raw_asm(&mut self, asm: &str) -> &mut Self {
+        self.raw.push(asm.to_owned());
+        self
+    }
+
+    /// Links the classes named in `order` first, in that order, see [`Program::reorder`].
+    pub fn set_link_order(&mut self, order: Vec<String>) -> &mut Self {
+        self.order = order;
+        self
+    }
+
+    /// Starts the output with hand-written assembly, for programs with their own startup sequence.
+    /// It goes before the generated bootstrap code, which is usually left out along with it.
+    pub fn set_bootstrap_asm(&mut self, asm: &str) -> &mut Self {
+        self.bootstrap = Some(asm.to_owned());
+        self
+    }
+
+    pub fn finish(&self, options: &LinkOptions) -> Result<String, Error> {
+        self.finish_with(&mut Context::new(options.generate.clone()))
+    }
+
+    /// Links the classes as one [`Program`] generated with `ctx`, between the bootstrap assembly
+    /// and the raw assembly.
+    pub fn finish_with(&self, ctx: &mut Context) -> Result<String, Error> {
+        Ok(render(&self.lower(ctx)?))
+    }
+
+    /// Lowers the program [`Linker::finish_with`] renders.
+    pub fn lower(&self, ctx: &mut Context) -> Result<Vec<AsmInstr>, Error> {
+        let mut linked = Vec::new();
+        self.lower_each(ctx, &mut |asm| {
+            linked.extend(asm);
+            Ok(())
+        })?;
+        Ok(linked)
+    }
+
+    /// Lowers like [`Linker::lower`], handing the output to `sink` in pieces as soon as they are
+    /// complete. The raw assembly is parsed first, so a mistake in it fails before any output.
+    pub fn lower_each(
+        &self,
+        ctx: &mut Context,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), generate::Error>,
+    ) -> Result<(), Error> {
+        self.link(ctx, None, sink)
+    }
+
+    /// Lowers like [`Linker::lower_each`], taking the output of the classes generated before
+    /// from `cache`, see [`Program::lower_each_cached`].
+    pub fn lower_each_cached(
+        &self,
+        ctx: &mut Context,
+        cache: &mut CompileCache,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), generate::Error>,
+    ) -> Result<(), Error> {
+        self.link(ctx, Some(cache), sink)
+    }
+
+    fn link(
+        &self,
+        ctx: &mut Context,
+        cache: Option<&mut CompileCache>,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), generate::Error>,
+    ) -> Result<(), Error> {
+        let bootstrap = self.bootstrap.as_deref().map(parse_asm).transpose()?;
+        let raw = self
+            .raw
+            .iter()
+            .map(|asm| parse_asm(asm))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut program = Program::new(self.classes.clone());
+        program.reorder(&self.order);
+        check_labels(&program, bootstrap.iter().chain(&raw))?;
+        if let Some(bootstrap) = bootstrap {
+            ctx.map(&"bootstrap", &bootstrap);
+            ctx.shared += bootstrap.iter().filter(|instr| instr.is_code()).count();
+            sink(bootstrap)?;
+        }
+        match cache {
+            Some(cache) => program.lower_each_cached(ctx, cache, sink)?,
+            None => program.lower_each(ctx, sink)?,
+        }
+        for raw in raw {
+            ctx.map(&"asm", &raw);
+            ctx.shared += raw.iter().filter(|instr| instr.is_code()).count();
+            sink(raw)?;
+        }
+        ctx.check_rom()?;
+        Ok(())
+    }
+
+    /// Writes the linked program into `w` piece by piece instead of collecting it first.
+    #[cfg(feature = "std")]
+    pub fn write_to<W: io::Write>(&self, ctx: &mut Context, w: &mut W) -> Result<(), Error> {
+        self.lower_each(ctx, &mut |asm| Ok(crate::asm::write_to(&asm, w)?))
+    }
+}
+
+/// Fails on the first label of the hand-written assembly `modules` defined twice among them, by a
+/// function of `program`, reserved for the generated code, or redefining a predefined symbol like
+/// `SP` or `R5`.
+fn check_labels<'a>(
+    program: &Program,
+    modules: impl Iterator<Item = &'a Vec<AsmInstr>>,
+) -> Result<(), asm::Error> {
+    let functions = program
+        .classes()
+        .iter()
+        .flat_map(|class| &class.functions)
+        .map(|function| function.name.as_str());
+    let mut defined = functions.collect::<BTreeSet<_>>();
+    for instr in modules.flatten() {
+        let AsmInstr::Label(label) = instr else {
+            continue;
+        };
+        if PREDEFINED.iter().any(|(symbol, _)| symbol == label) {
+            return Err(asm::Error::PredefinedLabel {
+                label: label.clone(),
+            });
+        }
+        if label.contains(RESERVED_PREFIX) || !defined.insert(label) {
+            return Err(asm::Error::DuplicateLabel {
+                label: label.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "parser", feature = "std"))]
+mod tests {
+    use crate::asm::{assemble, parse_asm};
+    use crate::generate::{BootstrapOptions, Class, Context, GenerateOptions};
+    use crate::link::{LinkOptions, Linker};
+    use crate::parse::parse;
+
+    #[test]
+    fn link_in_memory() {
+        const SYS_VM: &str = "function Sys.init 0\npush constant 2\ncall Main.double 1\nreturn";
+        const MAIN_VM: &str =
+            "function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn";
+        const MATH_ASM: &str = "(Math.unused)\n@Math.unused\n0;JMP\n";
+        let mut linker = Linker::new();
+        linker
+            .add_class(Class::new(parse(SYS_VM).expect("expect ok"), "Sys"))
+            .add_class(Class::new(parse(MAIN_VM).expect("expect ok"), "Main"))
+            .add_raw_asm(MATH_ASM);
+        let options = LinkOptions {
+            generate: GenerateOptions {
+                bootstrap: Some(BootstrapOptions::default()),
+                ..Default::default()
+            },
+        };
+        let linked = linker.finish(&options).expect("expect ok");
+        assert!(linked.starts_with("@256\n"));
+        let position = |label: &str| linked.find(label).expect("expect label");
+        assert!(position("(Main.double)") < position("(Sys.init)"));
+        assert!(linked.ends_with(MATH_ASM));
+        assemble(&parse_asm(&linked).expect("expect ok"), 16..256).expect("expect ok");
+        let mut written = vec![];
+        let ctx = || Context::new(options.generate.clone());
+        linker
+            .write_to(&mut ctx(), &mut written)
+            .expect("expect ok");
+        assert_eq!(written, linked.as_bytes());
+
+        linker.add_raw_asm("D=X");
+        linker.finish(&options).expect_err("expect err");
+        written.clear();
+        linker
+            .write_to(&mut ctx(), &mut written)
+            .expect_err("expect err");
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn link_bootstrap_asm() {
+        const SYS_VM: &str = "function Sys.init 0\nlabel LOOP\ngoto LOOP";
+        const BOOT_ASM: &str = "@2048\nD=A\n@SP\nM=D\n@Sys.init\n0;JMP\n";
+        let mut linker = Linker::new();
+        linker
+            .add_class(Class::new(parse(SYS_VM).expect("expect ok"), "Sys"))
+            .set_bootstrap_asm(BOOT_ASM);
+        let linked = linker.finish(&LinkOptions::default()).expect("expect ok");
+        assert!(linked.starts_with(BOOT_ASM));
+        assert!(linked.contains("(Sys.init)"));
+        assemble(&parse_asm(&linked).expect("expect ok"), 16..256).expect("expect ok");
+
+        linker.set_bootstrap_asm("D=X");
+        linker
+            .finish(&LinkOptions::default())
+            .expect_err("expect err");
+    }
+
+    #[test]
+    fn reject_clashing_labels() {
+        const SYS_VM: &str = "function Sys.init 0\ncall Math.abs 1\nreturn";
+        let link = |raw: &[&str]| {
+            let mut linker = Linker::new();
+            linker.add_class(Class::new(parse(SYS_VM).expect("expect ok"), "Sys"));
+            raw.iter().for_each(|asm| {
+                linker.add_raw_asm(asm);
+            });
+            linker.finish(&LinkOptions::default())
+        };
+        link(&["(Math.abs)\n@SP\n", "(Math.max)\n@SP\n"]).expect("expect ok");
+        let clashing = [
+            &["(Sys.init)\n"][..],
+            &["(Math.abs)\n", "(Math.abs)\n"],
+            &["(__vm$ret)\n"],
+            &["(R5)\n"],
+            &["(SCREEN)\n"],
+        ];
+        for raw in clashing {
+            let error = link(raw).expect_err("expect err");
+            assert_eq!(error.code(), "VM0202");
+        }
+    }
+}
@@ -0,0 +1,944 @@
+use crate::asm::{AsmInstr, Comp, Dest, at, render, set};
+use crate::generate::OptLevel;
+pub use crate::parse::unreachable;
+use crate::parse::{
+    BranchInstr, Function, Instr, MAX_ADDRESSABLE, MAX_NEGATED, StackInstr, StackSegment,
+};
+use alloc::borrow::ToOwned;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::ops::Range;
+use core::str::FromStr;
+use derive_more::Display;
+use snafu::Snafu;
+
+/// An instruction produced by a pass over VM instructions, with the range of input instructions
+/// it replaces.
+pub type Rewritten = (Instr, Range<usize>);
+
+/// A pass of an [`OptPipeline`], named for the command line by its display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+pub enum Pass {
+    /// [`fold_constants`]
+    #[display("fold-constants")]
+    FoldConstants,
+    /// [`fold_branches`]
+    #[display("fold-branches")]
+    FoldBranches,
+    /// [`eliminate_dead_code`]
+    #[display("dead-code")]
+    EliminateDeadCode,
+    /// [`collapse_moves`]
+    #[display("collapse-moves")]
+    CollapseMoves,
+    /// [`peephole`], which runs over the assembly of a function rather than its VM instructions.
+    #[display("peephole")]
+    Peephole,
+}
+
+impl Pass {
+    /// Every pass, in the order a pipeline runs them.
+    pub const ALL: [Pass; 5] = [
+        Pass::FoldConstants,
+        Pass::FoldBranches,
+        Pass::EliminateDeadCode,
+        Pass::CollapseMoves,
+        Pass::Peephole,
+    ];
+
+    fn rewrite(&self, instr: &[Instr]) -> Vec<Rewritten> {
+        match self {
+            Pass::FoldConstants => fold_constants(instr),
+            Pass::FoldBranches => fold_branches(instr),
+            Pass::EliminateDeadCode => eliminate_dead_code(instr),
+            Pass::CollapseMoves => collapse_moves(instr),
+            Pass::Peephole => unreachable!("peephole runs over assembly"),
+        }
+    }
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(display("unknown optimization pass `{name}`"))]
+pub struct UnknownPass {
+    pub name: String,
+}
+
+impl FromStr for Pass {
+    type Err = UnknownPass;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .into_iter()
+            .find(|pass| pass.to_string() == s)
+            .ok_or_else(|| UnknownPass { name: s.to_owned() })
+    }
+}
+
+/// Output of a pass over one function, recorded when [`OptPipeline::dump`] is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassDump {
+    pub function: String,
+    pub pass: Pass,
+    /// VM instructions, or assembly after the [`Pass::Peephole`], one per line.
+    pub output: String,
+}
+
+/// Passes optimizing every function, over its VM instructions before it is lowered and then over
+/// its assembly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptPipeline {
+    passes: Vec<Pass>,
+    /// Record the output of every pass, see [`Context::dumps`](crate::generate::Context::dumps).
+    pub dump: bool,
+}
+
+impl OptPipeline {
+    /// The passes `level` runs.
+    pub fn new(level: OptLevel) -> Self {
+        let passes = match level {
+            OptLevel::O0 => vec![],
+            OptLevel::O1 => vec![
+                Pass::FoldConstants,
+                Pass::EliminateDeadCode,
+                Pass::CollapseMoves,
+                Pass::Peephole,
+            ],
+            OptLevel::O2 => Pass::ALL.to_vec(),
+        };
+        Self {
+            passes,
+            dump: false,
+        }
+    }
+
+    /// Passes in the order they run.
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub fn runs(&self, pass: Pass) -> bool {
+        self.passes.contains(&pass)
+    }
+
+    /// Adds `pass` at its place in [`Pass::ALL`].
+    pub fn enable(mut self, pass: Pass) -> Self {
+        if !self.runs(pass) {
+            self.passes.push(pass);
+            self.passes.sort();
+        }
+        self
+    }
+
+    pub fn disable(mut self, pass: Pass) -> Self {
+        self.passes.retain(|enabled| *enabled != pass);
+        self
+    }
+
+    /// Runs the passes over the VM instructions of `function`, which is named `name` in `dumps`.
+    pub(crate) fn rewrite(
+        &self,
+        function: &Function,
+        name: &str,
+        dumps: &mut Vec<PassDump>,
+    ) -> Function {
+        let mut rewritten = function.clone();
+        for pass in self.passes.iter().filter(|pass| **pass != Pass::Peephole) {
+            rewritten = rewritten.rewrite(|instr| pass.rewrite(instr));
+            if self.dump {
+                let output = rewritten
+                    .instr
+                    .iter()
+                    .map(|instr| format!("{instr}\n"))
+                    .collect();
+                dumps.push(PassDump {
+                    function: name.to_owned(),
+                    pass: *pass,
+                    output,
+                });
+            }
+        }
+        rewritten
+    }
+
+    /// Runs the [`Pass::Peephole`] over the assembly of the function named `name`, if enabled.
+    pub(crate) fn peephole(
+        &self,
+        asm: &[AsmInstr],
+        name: &str,
+        dumps: &mut Vec<PassDump>,
+    ) -> Option<Peephole> {
+        if !self.runs(Pass::Peephole) {
+            return None;
+        }
+        let optimized = peephole(asm);
+        if self.dump {
+            dumps.push(PassDump {
+                function: name.to_owned(),
+                pass: Pass::Peephole,
+                output: render(&optimized.asm),
+            });
+        }
+        Some(optimized)
+    }
+}
+
+/// Drops the [`unreachable`] instructions.
+pub fn eliminate_dead_code(instr: &[Instr]) -> Vec<Rewritten> {
+    let dead = unreachable(instr);
+    instr
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|(index, _)| !dead.iter().any(|dead| dead.contains(index)))
+        .map(|(index, instr)| (instr, index..index + 1))
+        .collect()
+}
+
+/// Body replacing `call` of `callee` with `args` arguments, if `callee` can be inlined.
+///
+/// Only straight-line leaf functions qualify: no calls, branches, local variables or writes to
+/// `pointer`. Their arguments are still on the stack at the call, so accesses to them become
+/// [`StackInstr::Pick`] and [`StackInstr::Place`] at a depth tracked through the body, and the
+/// return value is [squashed](StackInstr::Squash) into the place of the arguments.
+pub fn inlined(callee: &Function, args: u32) -> Option<Vec<Instr>> {
+    if !callee.returned || callee.vars > 0 {
+        return None;
+    }
+    let mut body = Vec::with_capacity(callee.instr.len() + 1);
+    let mut depth = 0u32;
+    for instr in &callee.instr {
+        let Instr::Stack { data } = instr else {
+            return None;
+        };
+        let rewritten = match data {
+            StackInstr::Push {
+                segment: StackSegment::Argument,
+                literal,
+            } if *literal < args => StackInstr::Pick {
+                depth: depth + args - 1 - literal,
+            },
+            StackInstr::Pop {
+                segment: StackSegment::Argument,
+                literal,
+            } if *literal < args => StackInstr::Place {
+                depth: depth.checked_sub(1)? + args - 1 - literal,
+            },
+            StackInstr::Push {
+                segment: StackSegment::Argument | StackSegment::Local,
+                ..
+            }
+            | StackInstr::Pop {
+                segment: StackSegment::Argument | StackSegment::Local | StackSegment::Pointer,
+                ..
+            }
+            | StackInstr::Move { .. }
+            | StackInstr::Pick { .. }
+            | StackInstr::Place { .. }
+            | StackInstr::Squash { .. } => return None,
+            data => data.clone(),
+        };
+        // Operands pushed before the call are out of reach.
+        depth = match data {
+            StackInstr::Push { .. } | StackInstr::PushNegated { .. } | StackInstr::PushTrue => {
+                depth + 1
+            }
+            StackInstr::Pop { .. } => depth.checked_sub(1)?,
+            StackInstr::Negate | StackInstr::Not => depth.checked_sub(1)? + 1,
+            #[cfg(feature = "extensions")]
+            StackInstr::ShiftLeft { .. } | StackInstr::ShiftRight { .. } => {
+                depth.checked_sub(1)? + 1
+            }
+            _ => depth.checked_sub(2)? + 1,
+        };
+        body.push(rewritten.into());
+    }
+    let count = depth.checked_sub(1)? + args;
+    body.push(StackInstr::Squash { count }.into());
+    Some(body)
+}
+
+/// Output of [`fold_constants`] before known values are pushed again.
+enum Folded {
+    Constant(i16, Range<usize>),
+    Instr(Rewritten),
+}
+
+/// Evaluates arithmetic, logic and comparisons on constants, wrapping around like the Hack
+/// machine does, so `push constant 2` / `push constant 3` / `add` becomes `push constant 5`.
+///
+/// Negative results are pushed as their magnitude followed by `neg`, or `not` for -32768, except
+/// for -1 which gets its own [`StackInstr::PushTrue`].
+pub fn fold_constants(instr: &[Instr]) -> Vec<Rewritten> {
+    let mut folded = Vec::<Folded>::with_capacity(instr.len());
+    for (index, item) in instr.iter().enumerate() {
+        let Instr::Stack { data } = item else {
+            folded.push(Folded::Instr((item.clone(), index..index + 1)));
+            continue;
+        };
+        if let StackInstr::Push {
+            segment: StackSegment::Constant,
+            literal,
+        } = data
+            && *literal <= MAX_ADDRESSABLE
+        {
+            folded.push(Folded::Constant(*literal as i16, index..index + 1));
+            continue;
+        }
+        if let StackInstr::PushNegated { literal } = data
+            && *literal <= MAX_NEGATED
+        {
+            folded.push(Folded::Constant(
+                (*literal as i16).wrapping_neg(),
+                index..index + 1,
+            ));
+            continue;
+        }
+        if let [.., Folded::Constant(y, range)] = folded.as_mut_slice()
+            && let Some(value) = evaluate_unary(data, *y)
+        {
+            *y = value;
+            range.end = index + 1;
+            continue;
+        }
+        if let [.., Folded::Constant(x, first), Folded::Constant(y, _)] = folded.as_slice()
+            && let Some(value) = evaluate_binary(data, *x, *y)
+        {
+            let range = first.start..index + 1;
+            folded.truncate(folded.len() - 2);
+            folded.push(Folded::Constant(value, range));
+            continue;
+        }
+        folded.push(Folded::Instr((item.clone(), index..index + 1)));
+    }
+    folded
+        .into_iter()
+        .flat_map(|item| match item {
+            Folded::Instr(rewritten) => vec![rewritten],
+            Folded::Constant(value, range) => push_constant(value)
+                .into_iter()
+                .map(|instr| (instr, range.clone()))
+                .collect(),
+        })
+        .collect()
+}
+
+fn evaluate_unary(instr: &StackInstr, y: i16) -> Option<i16> {
+    match instr {
+        StackInstr::Negate => Some(y.wrapping_neg()),
+        StackInstr::Not => Some(!y),
+        #[cfg(feature = "extensions")]
+        StackInstr::ShiftLeft { count } => Some(y.checked_shl(*count).unwrap_or(0)),
+        #[cfg(feature = "extensions")]
+        StackInstr::ShiftRight { count } => Some(y >> (*count).min(15)),
+        _ => None,
+    }
+}
+
+/// Evaluates `instr` on `x` below `y` on the stack. Comparisons test the sign of `x - y`, so they
+/// overflow exactly like the generated code.
+fn evaluate_binary(instr: &StackInstr, x: i16, y: i16) -> Option<i16> {
+    let truth = |condition: bool| if condition { -1 } else { 0 };
+    match instr {
+        StackInstr::Add => Some(x.wrapping_add(y)),
+        StackInstr::Subtract => Some(x.wrapping_sub(y)),
+        StackInstr::And => Some(x & y),
+        StackInstr::Or => Some(x | y),
+        StackInstr::Equal => Some(truth(x == y)),
+        StackInstr::Greater => Some(truth(x.wrapping_sub(y) > 0)),
+        StackInstr::Less => Some(truth(x.wrapping_sub(y) < 0)),
+        #[cfg(feature = "extensions")]
+        StackInstr::Multiply => Some(x.wrapping_mul(y)),
+        // Division by zero is left to the generated code.
+        #[cfg(feature = "extensions")]
+        StackInstr::Divide => (y != 0).then(|| x.wrapping_div(y)),
+        #[cfg(feature = "extensions")]
+        StackInstr::Modulo => (y != 0).then(|| x.wrapping_rem(y)),
+        _ => None,
+    }
+}
+
+fn push_constant(value: i16) -> Vec<Instr> {
+    match value {
+        0.. => vec![StackInstr::push(StackSegment::Constant, value as u32).into()],
+        -1 => vec![StackInstr::PushTrue.into()],
+        i16::MIN => vec![
+            StackInstr::push(StackSegment::Constant, MAX_ADDRESSABLE).into(),
+            StackInstr::Not.into(),
+        ],
+        _ => vec![
+            StackInstr::push(StackSegment::Constant, value.unsigned_abs() as u32).into(),
+            StackInstr::Negate.into(),
+        ],
+    }
+}
+
+/// Rewrites each `push` directly followed by a `pop` into a [`StackInstr::Move`], which lowers
+/// without going through the stack.
+pub fn collapse_moves(instr: &[Instr]) -> Vec<Rewritten> {
+    let mut rewritten = Vec::with_capacity(instr.len());
+    let mut index = 0;
+    while index < instr.len() {
+        match (&instr[index], instr.get(index + 1)) {
+            (
+                Instr::Stack {
+                    data:
+                        StackInstr::Push {
+                            segment: source,
+                            literal: source_literal,
+                        },
+                },
+                Some(Instr::Stack {
+                    data: StackInstr::Pop { segment, literal },
+                }),
+            ) => {
+                let data = StackInstr::Move {
+                    source: source.clone(),
+                    source_literal: *source_literal,
+                    segment: segment.clone(),
+                    literal: *literal,
+                };
+                rewritten.push((data.into(), index..index + 2));
+                index += 2;
+            }
+            (instr, _) => {
+                rewritten.push((instr.clone(), index..index + 1));
+                index += 1;
+            }
+        }
+    }
+    rewritten
+}
+
+/// Turns each `if-goto` right after a constant is pushed into a `goto`, or drops it when the
+/// constant is 0, so [`eliminate_dead_code`] can then drop the code it skips.
+pub fn fold_branches(instr: &[Instr]) -> Vec<Rewritten> {
+    let mut rewritten = Vec::<Rewritten>::with_capacity(instr.len());
+    for (index, item) in instr.iter().enumerate() {
+        if let Instr::Branch {
+            data: BranchInstr::CondGoto { ident },
+        } = item
+            && let Some((taken, pushed)) = pushed_condition(&rewritten)
+        {
+            let start = rewritten[rewritten.len() - pushed].1.start;
+            rewritten.truncate(rewritten.len() - pushed);
+            if taken {
+                rewritten.push((
+                    BranchInstr::Goto {
+                        ident: ident.clone(),
+                    }
+                    .into(),
+                    start..index + 1,
+                ));
+            }
+            continue;
+        }
+        rewritten.push((item.clone(), index..index + 1));
+    }
+    rewritten
+}
+
+/// Whether the constant `rewritten` ends by pushing is non-zero, together with the number of
+/// instructions pushing it.
+fn pushed_condition(rewritten: &[Rewritten]) -> Option<(bool, usize)> {
+    let back = |offset: usize| {
+        rewritten
+            .len()
+            .checked_sub(offset)
+            .map(|index| &rewritten[index].0)
+    };
+    let constant = |instr: Option<&Instr>| match instr {
+        Some(Instr::Stack {
+            data:
+                StackInstr::Push {
+                    segment: StackSegment::Constant,
+                    literal,
+                },
+        }) if *literal <= MAX_ADDRESSABLE => Some(*literal),
+        _ => None,
+    };
+    match back(1)? {
+        Instr::Stack {
+            data: StackInstr::PushTrue,
+        } => Some((true, 1)),
+        Instr::Stack {
+            data: StackInstr::PushNegated { literal },
+        } => Some((*literal != 0, 1)),
+        Instr::Stack {
+            data: StackInstr::Negate,
+        } => constant(back(2)).map(|literal| (literal != 0, 2)),
+        // Only -1 turns into 0, which is never pushed as a constant.
+        Instr::Stack {
+            data: StackInstr::Not,
+        } => constant(back(2)).map(|_| (true, 2)),
+        instr => constant(Some(instr)).map(|literal| (literal != 0, 1)),
+    }
+}
+
+/// Assembly after [`peephole`] removed instructions from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peephole {
+    pub asm: Vec<AsmInstr>,
+    /// Index in `asm` each input instruction ended up at, followed by the length of `asm`.
+    pub lines: Vec<usize>,
+}
+
+/// A push of D followed by a pop into D. The third instruction is left open, as a pushed constant
+/// may be stored without going through D.
+fn push_pop_d() -> [AsmInstr; 8] {
+    [
+        at("SP"),
+        set(Dest::A, Comp::M),
+        set(Dest::M, Comp::D),
+        at("SP"),
+        set(Dest::M, Comp::MPlusOne),
+        at("SP"),
+        set(Dest::AM, Comp::MMinusOne),
+        set(Dest::D, Comp::M),
+    ]
+}
+
+/// Removes instructions from `asm` without changing what it computes: pushes immediately popped
+/// back into D, reloads of an address already in A, and increments undone right away.
+///
+/// Comments are kept, and do not separate instructions.
+pub fn peephole(asm: &[AsmInstr]) -> Peephole {
+    let mut asm = asm.to_vec();
+    let mut kept = vec![true; asm.len()];
+    loop {
+        let mut changed = remove_push_pop(&mut asm, &mut kept);
+        changed |= remove_reloads(&asm, &mut kept);
+        changed |= cancel_sp_steps(&mut asm, &mut kept);
+        if !changed {
+            break;
+        }
+    }
+
+    let mut next = 0;
+    let mut moved = Vec::with_capacity(asm.len() + 1);
+    let mut optimized = Vec::with_capacity(asm.len());
+    for (instr, kept) in asm.into_iter().zip(&kept) {
+        moved.push(next);
+        if *kept {
+            optimized.push(instr);
+            next += 1;
+        }
+    }
+    moved.push(next);
+    Peephole {
+        asm: optimized,
+        lines: moved,
+    }
+}
+
+/// Indices of the kept instructions and labels.
+fn code(asm: &[AsmInstr], kept: &[bool]) -> Vec<usize> {
+    (0..asm.len())
+        .filter(|index| kept[*index])
+        .filter(|index| !matches!(asm[*index], AsmInstr::Comment(_)))
+        .collect()
+}
+
+/// Removes a push of D followed by a pop into D, unless the instruction after it reads A. A pushed
+/// constant the ALU computes directly is instead computed into D.
+fn remove_push_pop(asm: &mut [AsmInstr], kept: &mut [bool]) -> bool {
+    let code = code(asm, kept);
+    let pattern = push_pop_d();
+    let mut changed = false;
+    let mut index = 0;
+    while index + pattern.len() < code.len() {
+        let window = &code[index..index + pattern.len()];
+        let computed = match asm[window[2]] {
+            AsmInstr::C {
+                dest: Dest::M,
+                comp: comp @ (Comp::D | Comp::Zero | Comp::One | Comp::MinusOne),
+                jump: None,
+            } => Some(comp),
+            _ => None,
+        };
+        let matches = window
+            .iter()
+            .zip(&pattern)
+            .enumerate()
+            .all(|(position, (instr, expected))| position == 2 || asm[*instr] == *expected);
+        match computed {
+            Some(comp) if matches && matches!(asm[code[index + pattern.len()]], AsmInstr::A(_)) => {
+                window.iter().for_each(|instr| kept[*instr] = false);
+                if comp != Comp::D {
+                    let last = window[pattern.len() - 1];
+                    asm[last] = set(Dest::D, comp);
+                    kept[last] = true;
+                }
+                changed = true;
+                index += pattern.len();
+            }
+            _ => index += 1,
+        }
+    }
+    changed
+}
+
+/// Removes A-instructions loading the address A already holds.
+fn remove_reloads(asm: &[AsmInstr], kept: &mut [bool]) -> bool {
+    let mut changed = false;
+    let mut known = None;
+    for index in code(asm, kept) {
+        match &asm[index] {
+            AsmInstr::A(addr) => {
+                if known == Some(addr) {
+                    kept[index] = false;
+                    changed = true;
+                }
+                known = Some(addr);
+            }
+            instr if matches!(instr, AsmInstr::Label(_)) || instr.writes_a() => known = None,
+            _ => {}
+        }
+    }
+    changed
+}
+
+/// Removes an increment of M directly followed by a decrement, or the other way around.
+fn cancel_sp_steps(asm: &mut [AsmInstr], kept: &mut [bool]) -> bool {
+    let (increment, decrement) = (set(Dest::M, Comp::MPlusOne), set(Dest::M, Comp::MMinusOne));
+    let code = code(asm, kept);
+    let mut changed = false;
+    let mut index = 0;
+    while index + 1 < code.len() {
+        let (first, second) = (code[index], code[index + 1]);
+        if (asm[first] == increment && asm[second] == decrement)
+            || (asm[first] == decrement && asm[second] == increment)
+        {
+            kept[first] = false;
+            kept[second] = false;
+            changed = true;
+            index += 2;
+        } else if asm[first] == increment && asm[second] == set(Dest::AM, Comp::MMinusOne) {
+            // Only the address loaded by the decrement remains.
+            kept[first] = false;
+            asm[second] = set(Dest::A, Comp::M);
+            changed = true;
+            index += 2;
+        } else {
+            index += 1;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{parse_asm, render};
+    use crate::generate::OptLevel;
+    use crate::optimize::{
+        OptPipeline, Pass, Peephole, collapse_moves, eliminate_dead_code, fold_branches,
+        fold_constants, unreachable,
+    };
+    use crate::parse::StackSegment::{Argument, Constant, Local};
+    use crate::parse::{BranchInstr, Instr, StackInstr};
+
+    #[test]
+    fn eliminate_after_goto() {
+        let goto = |ident: &str| {
+            Instr::from(BranchInstr::Goto {
+                ident: ident.into(),
+            })
+        };
+        let label = |ident: &str| {
+            Instr::from(BranchInstr::Label {
+                ident: ident.into(),
+            })
+        };
+        let instr = vec![
+            goto("A"),
+            StackInstr::push(Constant, 1).into(),
+            goto("B"),
+            label("A"),
+            goto("A"),
+            label("B"),
+            StackInstr::Add.into(),
+            goto("B"),
+            StackInstr::Add.into(),
+        ];
+        assert_eq!(unreachable(&instr), vec![1..3, 8..9]);
+        let kept = eliminate_dead_code(&instr)
+            .into_iter()
+            .map(|(_, range)| range.start)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![0, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn fold_constant_chains() {
+        let instr: Vec<Instr> = vec![
+            StackInstr::push(Constant, 2).into(),
+            StackInstr::push(Constant, 3).into(),
+            StackInstr::Add.into(),
+            StackInstr::push(Constant, 7).into(),
+            StackInstr::Subtract.into(),
+            StackInstr::push(Local, 0).into(),
+            StackInstr::push(Constant, 0).into(),
+            StackInstr::Not.into(),
+            StackInstr::Add.into(),
+        ];
+        assert_eq!(
+            fold_constants(&instr),
+            vec![
+                (StackInstr::push(Constant, 2).into(), 0..5),
+                (StackInstr::Negate.into(), 0..5),
+                (instr[5].clone(), 5..6),
+                (StackInstr::PushTrue.into(), 6..8),
+                (instr[8].clone(), 8..9),
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_constants_wrapping() {
+        let fold = |instr: Vec<StackInstr>| {
+            let instr = instr.into_iter().map(Instr::from).collect::<Vec<_>>();
+            fold_constants(&instr)
+                .into_iter()
+                .map(|(instr, _)| instr.to_string())
+                .collect::<Vec<_>>()
+        };
+        let max = || StackInstr::push(Constant, 32767);
+        assert_eq!(
+            fold(vec![max(), StackInstr::push(Constant, 1), StackInstr::Add]),
+            vec!["push constant 32767", "not"]
+        );
+        assert_eq!(
+            fold(vec![max(), max(), StackInstr::Add]),
+            vec!["push constant 2", "neg"]
+        );
+        // -32768 - 1 wraps to 32767, which is greater than 0 once more.
+        assert_eq!(
+            fold(vec![
+                max(),
+                StackInstr::Not,
+                StackInstr::push(Constant, 1),
+                StackInstr::Greater
+            ]),
+            vec!["push constant 1; neg"]
+        );
+        assert_eq!(
+            fold(vec![
+                StackInstr::push(Constant, 6),
+                StackInstr::push(Constant, 3),
+                StackInstr::Or
+            ]),
+            vec!["push constant 7"]
+        );
+        assert_eq!(
+            fold(vec![StackInstr::push(Constant, 40000), StackInstr::Negate]),
+            vec!["push constant 40000", "neg"]
+        );
+        assert_eq!(
+            fold(vec![
+                StackInstr::PushNegated { literal: 300 },
+                StackInstr::push(Constant, 100),
+                StackInstr::Add
+            ]),
+            vec!["push constant 200", "neg"]
+        );
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn fold_extended_instructions() {
+        let fold = |instr: Vec<StackInstr>| {
+            let instr = instr.into_iter().map(Instr::from).collect::<Vec<_>>();
+            fold_constants(&instr)
+                .into_iter()
+                .map(|(instr, _)| instr.to_string())
+                .collect::<Vec<_>>()
+        };
+        let push = |literal| StackInstr::push(Constant, literal);
+        assert_eq!(
+            fold(vec![push(200), push(200), StackInstr::Multiply]),
+            vec!["push constant 25536", "neg"]
+        );
+        assert_eq!(
+            fold(vec![
+                push(7),
+                StackInstr::Negate,
+                push(2),
+                StackInstr::Divide
+            ]),
+            vec!["push constant 3", "neg"]
+        );
+        assert_eq!(
+            fold(vec![
+                push(7),
+                StackInstr::Negate,
+                push(2),
+                StackInstr::Modulo
+            ]),
+            vec!["push constant 1; neg"]
+        );
+        assert_eq!(
+            fold(vec![push(7), push(0), StackInstr::Divide]),
+            vec!["push constant 7", "push constant 0", "div"]
+        );
+        assert_eq!(
+            fold(vec![push(1), StackInstr::ShiftLeft { count: 14 }]),
+            vec!["push constant 16384"]
+        );
+        assert_eq!(
+            fold(vec![push(3), StackInstr::ShiftLeft { count: 16 }]),
+            vec!["push constant 0"]
+        );
+        assert_eq!(
+            fold(vec![
+                push(8),
+                StackInstr::Negate,
+                StackInstr::ShiftRight { count: 2 }
+            ]),
+            vec!["push constant 2", "neg"]
+        );
+        assert_eq!(
+            fold(vec![
+                push(8),
+                StackInstr::Negate,
+                StackInstr::ShiftRight { count: 20 }
+            ]),
+            vec!["push constant 1; neg"]
+        );
+    }
+
+    #[test]
+    fn fold_constant_branches() {
+        let if_goto = |ident: &str| {
+            Instr::from(BranchInstr::CondGoto {
+                ident: ident.into(),
+            })
+        };
+        let instr: Vec<Instr> = vec![
+            StackInstr::push(Constant, 0).into(),
+            StackInstr::Not.into(),
+            if_goto("A"),
+            StackInstr::push(Constant, 0).into(),
+            if_goto("B"),
+            StackInstr::push(Local, 0).into(),
+            if_goto("C"),
+        ];
+        assert_eq!(
+            fold_branches(&instr),
+            vec![
+                (BranchInstr::Goto { ident: "A".into() }.into(), 0..3),
+                (instr[5].clone(), 5..6),
+                (instr[6].clone(), 6..7),
+            ]
+        );
+    }
+
+    #[test]
+    fn configure_pipeline() {
+        assert!(OptPipeline::new(OptLevel::O0).passes().is_empty());
+        let pipeline = OptPipeline::new(OptLevel::O1)
+            .enable(Pass::FoldBranches)
+            .disable(Pass::Peephole);
+        assert_eq!(
+            pipeline.passes(),
+            [
+                Pass::FoldConstants,
+                Pass::FoldBranches,
+                Pass::EliminateDeadCode,
+                Pass::CollapseMoves
+            ]
+        );
+        assert_eq!(
+            pipeline.enable(Pass::Peephole),
+            OptPipeline::new(OptLevel::O2)
+        );
+        assert_eq!(
+            "dead-code".parse::<Pass>().expect("expect ok"),
+            Pass::EliminateDeadCode
+        );
+        assert_eq!(Pass::CollapseMoves.to_string(), "collapse-moves");
+        "inline".parse::<Pass>().expect_err("expect err");
+    }
+
+    #[test]
+    fn collapse_push_pop() {
+        let instr: Vec<Instr> = vec![
+            StackInstr::push(Argument, 1).into(),
+            StackInstr::pop(Local, 0).into(),
+            StackInstr::push(Constant, 2).into(),
+            StackInstr::Add.into(),
+            StackInstr::pop(Local, 1).into(),
+        ];
+        let moved = StackInstr::Move {
+            source: Argument,
+            source_literal: 1,
+            segment: Local,
+            literal: 0,
+        };
+        assert_eq!(moved.to_string(), "push argument 1; pop local 0");
+        assert_eq!(
+            collapse_moves(&instr),
+            vec![
+                (moved.into(), 0..2),
+                (instr[2].clone(), 2..3),
+                (instr[3].clone(), 3..4),
+                (instr[4].clone(), 4..5),
+            ]
+        );
+    }
+
+    fn peephole(asm: &str) -> Peephole {
+        crate::optimize::peephole(&parse_asm(asm).expect("expect ok"))
+    }
+
+    #[test]
+    fn remove_push_pop() {
+        let optimized = peephole(
+            "@7\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n// add\n@SP\nAM=M-1\nD=M\n@SP\nA=M-1\nM=D+M\n",
+        );
+        assert_eq!(
+            render(&optimized.asm),
+            "@7\nD=A\n// add\n@SP\nA=M-1\nM=D+M\n"
+        );
+        assert_eq!(
+            optimized.lines,
+            vec![0, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn compute_pushed_constant() {
+        let optimized =
+            peephole("@SP\nA=M\nM=-1\n@SP\nM=M+1\n@SP\nAM=M-1\nD=M\n@SP\nA=M-1\nM=D+M\n");
+        assert_eq!(render(&optimized.asm), "D=-1\n@SP\nA=M-1\nM=D+M\n");
+    }
+
+    #[test]
+    fn keep_push_pop_before_jump() {
+        let optimized = peephole("@SP\nA=M\nM=D\n@SP\nM=M+1\n@SP\nAM=M-1\nD=M\nD;JNE\n");
+        assert_eq!(
+            render(&optimized.asm),
+            "@SP\nA=M\nM=D\n@SP\nA=M\nD=M\nD;JNE\n"
+        );
+    }
+
+    #[test]
+    fn remove_reloads() {
+        let optimized = peephole("@LCL\nD=M\n@LCL\nA=M\n@LCL\n(Foo.loop)\n@LCL\n");
+        assert_eq!(
+            render(&optimized.asm),
+            "@LCL\nD=M\nA=M\n@LCL\n(Foo.loop)\n@LCL\n"
+        );
+    }
+
+    #[test]
+    fn cancel_sp_steps() {
+        assert_eq!(
+            render(&peephole("@SP\nM=M+1\n@SP\nM=M-1\nD=M\n").asm),
+            "@SP\nD=M\n"
+        );
+        assert_eq!(
+            render(&peephole("@SP\nM=M+1\n@SP\nAM=M-1\nD=M\n").asm),
+            "@SP\nA=M\nD=M\n"
+        );
+    }
+}
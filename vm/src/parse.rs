@@ -1,19 +1,64 @@
+#[cfg(feature = "parser")]
+use crate::diagnostic::DiagnosticSink;
+use crate::diagnostic::Lint;
+use crate::suggest::Suggestion;
+#[cfg(feature = "parser")]
+use crate::suggest::suggest_keyword;
+#[cfg(feature = "parser")]
+use crate::symbol::Interner;
+use crate::symbol::Symbol;
+#[cfg(feature = "parser")]
+use alloc::borrow::ToOwned;
+#[cfg(feature = "parser")]
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "parser")]
 use chumsky::error::Rich;
-use chumsky::prelude::{choice, just};
+#[cfg(feature = "parser")]
+use chumsky::prelude::{choice, empty, just};
+#[cfg(feature = "parser")]
 use chumsky::{IterParser, extra};
+#[cfg(feature = "parser")]
 use chumsky::{Parser, select};
+use core::fmt::{Debug, Display, Formatter};
+use core::num::ParseIntError;
+use core::ops::Range;
 use derive_more::Display;
+#[cfg(feature = "parser")]
 use logos::Logos;
-use snafu::{ResultExt, Snafu};
-use std::fmt::{Debug, Display, Formatter};
-use std::num::ParseIntError;
+use snafu::Snafu;
+#[cfg(feature = "parser")]
+use snafu::{ResultExt, ensure};
+
+pub type Span = Range<usize>;
+#[cfg(feature = "parser")]
+type SpannedToken = (Token, Span);
 
 #[derive(Snafu, Debug, PartialEq, Clone)]
 pub enum Error {
     #[snafu(display("syntax error: {reasons}"))]
     Syntax { reasons: Reasons },
-    #[snafu(display("error while lexing"))]
-    Lexing { source: LexingError },
+    #[snafu(display("warnings denied: {warnings}"))]
+    DeniedWarnings { warnings: Warnings },
+    #[snafu(display("error while lexing at {span:?}{suggestion}"))]
+    Lexing {
+        source: LexingError,
+        span: Span,
+        suggestion: Suggestion,
+    },
+}
+
+impl Error {
+    /// Stable code of the error, see [`crate::Error::code`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Syntax { .. } => "VM0001",
+            Error::DeniedWarnings { .. } => "VM0002",
+            Error::Lexing { .. } => "VM0003",
+        }
+    }
 }
 
 #[derive(Snafu, Debug, PartialEq, Clone, Default)]
@@ -23,13 +68,24 @@ pub enum LexingError {
     UnexpectedToken,
     #[snafu(display("not an int"))]
     ParseInt { source: ParseIntError },
+    #[snafu(display("literal {literal} exceeds the maximum of {max}"))]
+    LiteralTooLarge { literal: u32, max: u32 },
+    #[snafu(display("identifier `{ident}` is outside the {charset} charset"))]
+    IdentCharset {
+        ident: String,
+        charset: IdentCharset,
+    },
+    #[snafu(display("identifier `{ident}` starts with the reserved prefix `{RESERVED_PREFIX}`"))]
+    ReservedIdent { ident: String },
+    #[snafu(display("unterminated block comment"))]
+    UnterminatedComment,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Reasons(Vec<String>);
 
 impl Display for Reasons {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         for (index, reason) in self.0.iter().enumerate() {
             write!(f, "{index}: {reason}")?
         }
@@ -37,15 +93,107 @@ impl Display for Reasons {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub message: String,
+    pub span: Span,
+    pub lint: Lint,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} at {:?}", self.message, self.span)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warnings(Vec<Warning>);
+
+impl Display for Warnings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (index, warning) in self.0.iter().enumerate() {
+            write!(f, "{index}: {warning}")?
+        }
+        Ok(())
+    }
+}
+
+/// Starts every label the generator synthesizes, kept out of user identifiers so the two never
+/// collide.
+pub const RESERVED_PREFIX: &str = "__vm$";
+
+/// The largest value an A-instruction can load.
+pub const MAX_ADDRESSABLE: u32 = 32767;
+
+/// The largest magnitude of a negative constant, making -32768 the smallest one.
+pub const MAX_NEGATED: u32 = 32768;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CommentStyle {
+    /// `// ...` line comments only, as in the reference implementation.
+    #[default]
+    Line,
+    /// Line comments plus `/* ... */` block comments.
+    LineAndBlock,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Display)]
+pub enum IdentCharset {
+    /// Letters, digits, `_` and `.`, starting with a letter.
+    #[default]
+    #[display("standard")]
+    Standard,
+    /// Also `$` and `:`, and any of `_`, `.`, `$` and `:` as the first character. This goes
+    /// beyond the VM specification, for names generated by other tools.
+    #[display("extended")]
+    Extended,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum WarningLevel {
+    /// Drop warnings silently.
+    Allow,
+    /// Report warnings alongside the parsed functions.
+    #[default]
+    Warn,
+    /// Turn any warning into [`Error::DeniedWarnings`].
+    Deny,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ParseOptions {
+    pub comments: CommentStyle,
+    /// Accept instructions before the first `function`, collected into a function with an
+    /// empty name.
+    pub top_level_code: bool,
+    /// Accept an optional `;` after each instruction, allowing several per line.
+    pub separators: bool,
+    /// Skip instructions with unrecognized mnemonics, reporting each as a warning instead of
+    /// failing the parse.
+    pub tolerant: bool,
+    /// Reject literals above this value.
+    pub max_literal: Option<u32>,
+    pub ident_charset: IdentCharset,
+    pub warnings: WarningLevel,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parsed {
+    pub functions: Vec<Function>,
+    pub warnings: Vec<Warning>,
+}
+
 impl From<ParseIntError> for LexingError {
     fn from(value: ParseIntError) -> Self {
         Self::ParseInt { source: value }
     }
 }
 
+#[cfg(feature = "parser")]
 #[derive(Logos, Debug, PartialEq, Eq, Hash, Clone, Display)]
 #[logos(skip r"([ \t\f\n]+)|//[^\n]*")]
 #[logos(error = LexingError)]
+#[logos(extras = Interner)]
 pub(crate) enum Token {
     #[display("push")]
     #[token("push")]
@@ -106,6 +254,26 @@ pub(crate) enum Token {
     #[display("not")]
     #[token("not")]
     Not,
+    #[cfg(feature = "extensions")]
+    #[display("mul")]
+    #[token("mul")]
+    Multiply,
+    #[cfg(feature = "extensions")]
+    #[display("div")]
+    #[token("div")]
+    Divide,
+    #[cfg(feature = "extensions")]
+    #[display("mod")]
+    #[token("mod")]
+    Modulo,
+    #[cfg(feature = "extensions")]
+    #[display("shl")]
+    #[token("shl")]
+    ShiftLeft,
+    #[cfg(feature = "extensions")]
+    #[display("shr")]
+    #[token("shr")]
+    ShiftRight,
 
     #[display("function")]
     #[token("function")]
@@ -127,25 +295,90 @@ pub(crate) enum Token {
     #[token("if-goto")]
     CondGoto,
 
+    #[display(";")]
+    #[token(";")]
+    Separator,
+
     #[regex("[0-9]+", |lex| lex.slice().parse())]
     LitInt(u32),
-    #[regex("[a-zA-Z][a-zA-Z0-9_.]*", |lex| lex.slice().to_owned())]
-    Ident(String),
+    /// A negative literal, holding its magnitude.
+    #[display("-{_0}")]
+    #[regex("-[0-9]+", |lex| lex.slice()[1..].parse())]
+    NegInt(u32),
+    #[regex("[a-zA-Z][a-zA-Z0-9_.]*", |lex| lex.extras.intern(lex.slice()), priority = 3)]
+    Ident(Symbol),
+    /// An identifier only the [`IdentCharset::Extended`] charset accepts.
+    #[regex("[a-zA-Z_.$:][a-zA-Z0-9_.$:]*", |lex| lex.extras.intern(lex.slice()))]
+    ExtendedIdent(Symbol),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Display)]
 pub enum StackInstr {
+    #[display("push {segment} {literal}")]
     Push { segment: StackSegment, literal: u32 },
+    #[display("pop {segment} {literal}")]
     Pop { segment: StackSegment, literal: u32 },
+    #[display("add")]
     Add,
+    #[display("sub")]
     Subtract,
+    #[display("neg")]
     Negate,
+    #[display("eq")]
     Equal,
+    #[display("gt")]
     Greater,
+    #[display("lt")]
     Less,
+    #[display("and")]
     And,
+    #[display("or")]
     Or,
+    #[display("not")]
     Not,
+    /// Wrapping product of the top two values.
+    #[cfg(feature = "extensions")]
+    #[display("mul")]
+    Multiply,
+    /// Quotient of the top two values, truncated toward zero.
+    #[cfg(feature = "extensions")]
+    #[display("div")]
+    Divide,
+    /// Remainder of the top two values, taking the sign of the dividend.
+    #[cfg(feature = "extensions")]
+    #[display("mod")]
+    Modulo,
+    /// Shifts the top value left by `count` bits.
+    #[cfg(feature = "extensions")]
+    #[display("shl {count}")]
+    ShiftLeft { count: u32 },
+    /// Shifts the top value right by `count` bits, copying the sign bit.
+    #[cfg(feature = "extensions")]
+    #[display("shr {count}")]
+    ShiftRight { count: u32 },
+    /// Pushes a copy of the value `depth` values below the top, never produced by the parser.
+    #[display("pick {depth}")]
+    Pick { depth: u32 },
+    /// Pops the top into the value `depth` values below the new top, never produced by the parser.
+    #[display("place {depth}")]
+    Place { depth: u32 },
+    /// Drops `count` values below the top, never produced by the parser.
+    #[display("squash {count}")]
+    Squash { count: u32 },
+    /// Pushes the negative constant of magnitude `literal`.
+    #[display("push constant -{literal}")]
+    PushNegated { literal: u32 },
+    /// Pushes -1, Jack's `true`, never produced by the parser.
+    #[display("push constant 1; neg")]
+    PushTrue,
+    /// A `push` directly followed by a `pop`, never produced by the parser.
+    #[display("push {source} {source_literal}; pop {segment} {literal}")]
+    Move {
+        source: StackSegment,
+        source_literal: u32,
+        segment: StackSegment,
+        literal: u32,
+    },
 }
 
 impl StackInstr {
@@ -158,63 +391,82 @@ impl StackInstr {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Display)]
 pub enum StackSegment {
+    #[display("constant")]
     Constant,
+    #[display("local")]
     Local,
+    #[display("argument")]
     Argument,
+    #[display("this")]
     This,
+    #[display("that")]
     That,
+    #[display("static")]
     Static,
+    #[display("temp")]
     Temp,
+    #[display("pointer")]
     Pointer,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Display)]
+#[display("call {ident} {args}")]
 pub struct CallInstr {
-    pub ident: String,
+    pub ident: Symbol,
     pub args: u32,
 }
 
 impl CallInstr {
-    pub fn new(ident: &str, args: u32) -> Self {
+    pub fn new(ident: impl Into<Symbol>, args: u32) -> Self {
         Self {
-            ident: ident.to_owned(),
+            ident: ident.into(),
             args,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Display)]
 pub enum BranchInstr {
-    Label { ident: String },
-    Goto { ident: String },
-    CondGoto { ident: String },
+    #[display("label {ident}")]
+    Label { ident: Symbol },
+    #[display("goto {ident}")]
+    Goto { ident: Symbol },
+    #[display("if-goto {ident}")]
+    CondGoto { ident: Symbol },
 }
 
 impl BranchInstr {
-    pub fn label(ident: &str) -> Self {
+    pub fn label(ident: impl Into<Symbol>) -> Self {
         Self::Label {
-            ident: ident.to_owned(),
+            ident: ident.into(),
         }
     }
-    pub fn goto(ident: &str) -> Self {
+    pub fn goto(ident: impl Into<Symbol>) -> Self {
         Self::Goto {
-            ident: ident.to_owned(),
+            ident: ident.into(),
         }
     }
-    pub fn cond_goto(ident: &str) -> Self {
+    pub fn cond_goto(ident: impl Into<Symbol>) -> Self {
         Self::CondGoto {
-            ident: ident.to_owned(),
+            ident: ident.into(),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Display)]
 pub enum Instr {
+    #[display("{data}")]
     Stack { data: StackInstr },
+    #[display("{data}")]
     Call { data: CallInstr },
+    #[display("{data}")]
     Branch { data: BranchInstr },
+    /// A `return` before the end of a function, the one ending it is kept in
+    /// [`Function::returned`].
+    #[display("return")]
+    Return,
 }
 
 impl From<StackInstr> for Instr {
@@ -235,25 +487,163 @@ impl From<BranchInstr> for Instr {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Function {
     pub(crate) instr: Vec<Instr>,
-    pub(crate) name: String,
+    pub(crate) name: Symbol,
     pub(crate) vars: u32,
     pub(crate) returned: bool,
+    pub(crate) span: Span,
+    /// Byte range of each instruction, parallel to `instr` when built by the parser.
+    pub(crate) spans: Vec<Span>,
 }
 
 impl Function {
-    pub fn new(instr: Vec<Instr>, name: &str, vars: u32, returned: bool) -> Self {
+    pub fn new(instr: Vec<Instr>, name: impl Into<Symbol>, vars: u32, returned: bool) -> Self {
         Self {
             instr,
-            name: name.to_owned(),
+            name: name.into(),
             vars,
-            returned
+            returned,
+            span: 0..0,
+            spans: vec![],
+        }
+    }
+
+    #[cfg(feature = "parser")]
+    fn spanned(
+        body: Vec<(Instr, Span)>,
+        name: impl Into<Symbol>,
+        vars: u32,
+        returned: bool,
+        span: Span,
+    ) -> Self {
+        let (instr, spans) = body.into_iter().unzip();
+        Self {
+            span,
+            spans,
+            ..Self::new(instr, name, vars, returned)
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Instructions of the body, without the `function` line and the `return` ending it.
+    pub fn instr(&self) -> &[Instr] {
+        &self.instr
+    }
+
+    /// Byte range of the function in its source, empty for functions not built by the parser.
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    /// Byte range of the instruction at `index`, if known.
+    pub fn instr_span(&self, index: usize) -> Option<Span> {
+        self.spans.get(index).cloned()
+    }
+
+    /// Replaces the body with the output of `pass`, each instruction spanning the instructions
+    /// it was rewritten from.
+    #[cfg(feature = "codegen")]
+    pub(crate) fn rewrite(
+        &self,
+        pass: impl Fn(&[Instr]) -> Vec<crate::optimize::Rewritten>,
+    ) -> Self {
+        let (instr, ranges): (Vec<_>, Vec<_>) = pass(&self.instr).into_iter().unzip();
+        let spans = if self.spans.is_empty() {
+            vec![]
+        } else {
+            ranges
+                .into_iter()
+                .map(|range| self.spans[range.start].start..self.spans[range.end - 1].end)
+                .collect()
+        };
+        Self {
+            instr,
+            spans,
+            ..self.clone()
         }
     }
+
+    #[cfg(all(feature = "parser", feature = "codegen"))]
+    pub(crate) fn shift(&mut self, offset: isize) {
+        self.span = shift_span(&self.span, offset);
+        self.spans = self
+            .spans
+            .iter()
+            .map(|span| shift_span(span, offset))
+            .collect();
+    }
 }
 
+/// Renders the function as VM source, one instruction per line, which parses back into it.
+/// Top-level code renders without a declaration.
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if !self.name.is_empty() {
+            writeln!(f, "function {} {}", self.name, self.vars)?;
+        }
+        for instr in &self.instr {
+            writeln!(f, "{instr}")?;
+        }
+        if self.returned {
+            writeln!(f, "return")?;
+        }
+        Ok(())
+    }
+}
+
+/// Ranges of instructions that can never execute, following a `goto` or `return` up to the next
+/// label.
+pub fn unreachable(instr: &[Instr]) -> Vec<Range<usize>> {
+    let mut dead = vec![];
+    let mut start = None;
+    for (index, item) in instr.iter().enumerate() {
+        match item {
+            Instr::Branch {
+                data: BranchInstr::Label { .. },
+            } => {
+                if let Some(start) = start.take().filter(|start| *start < index) {
+                    dead.push(start..index);
+                }
+            }
+            Instr::Branch {
+                data: BranchInstr::Goto { .. },
+            }
+            | Instr::Return
+                if start.is_none() =>
+            {
+                start = Some(index + 1);
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = start.filter(|start| *start < instr.len()) {
+        dead.push(start..instr.len());
+    }
+    dead
+}
+
+#[cfg(all(feature = "parser", feature = "codegen"))]
+pub(crate) fn shift_span(span: &Span, offset: isize) -> Span {
+    span.start.saturating_add_signed(offset)..span.end.saturating_add_signed(offset)
+}
+
+// Spans only locate a function in its source and take no part in equality.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.instr == other.instr
+            && self.name == other.name
+            && self.vars == other.vars
+            && self.returned == other.returned
+    }
+}
+
+#[cfg(feature = "parser")]
+#[cfg_attr(not(feature = "extensions"), allow(clippy::let_and_return))]
 fn stack_instr_parser<'tokens>()
 -> impl Parser<'tokens, &'tokens [Token], StackInstr, extra::Err<Rich<'tokens, Token>>> {
     let parse_segment = select! {
@@ -271,7 +661,7 @@ fn stack_instr_parser<'tokens>()
         Token::LitInt(lit) => lit
     };
 
-    choice((
+    let parse_stack = choice((
         just(Token::Add).to(StackInstr::Add),
         just(Token::Subtract).to(StackInstr::Subtract),
         just(Token::Negate).to(StackInstr::Negate),
@@ -281,6 +671,10 @@ fn stack_instr_parser<'tokens>()
         just(Token::And).to(StackInstr::And),
         just(Token::Or).to(StackInstr::Or),
         just(Token::Not).to(StackInstr::Not),
+        just(Token::Push)
+            .ignore_then(just(Token::Constant))
+            .ignore_then(select! { Token::NegInt(literal) => literal })
+            .map(|literal| StackInstr::PushNegated { literal }),
         just(Token::Push)
             .ignore_then(parse_segment)
             .then(parse_literal)
@@ -289,9 +683,23 @@ fn stack_instr_parser<'tokens>()
             .ignore_then(parse_segment)
             .then(parse_literal)
             .map(|(seg, lit)| StackInstr::pop(seg, lit)),
-    ))
+    ));
+    #[cfg(feature = "extensions")]
+    let parse_stack = parse_stack.or(choice((
+        just(Token::Multiply).to(StackInstr::Multiply),
+        just(Token::Divide).to(StackInstr::Divide),
+        just(Token::Modulo).to(StackInstr::Modulo),
+        just(Token::ShiftLeft)
+            .ignore_then(parse_literal)
+            .map(|count| StackInstr::ShiftLeft { count }),
+        just(Token::ShiftRight)
+            .ignore_then(parse_literal)
+            .map(|count| StackInstr::ShiftRight { count }),
+    )));
+    parse_stack
 }
 
+#[cfg(feature = "parser")]
 fn branch_instr_parser<'tokens>()
 -> impl Parser<'tokens, &'tokens [Token], BranchInstr, extra::Err<Rich<'tokens, Token>>> {
     let parse_ident = select! {
@@ -301,16 +709,17 @@ fn branch_instr_parser<'tokens>()
     choice((
         just(Token::Label)
             .ignore_then(parse_ident)
-            .map(|ident| BranchInstr::label(&ident)),
+            .map(BranchInstr::label),
         just(Token::Goto)
             .ignore_then(parse_ident)
-            .map(|ident| BranchInstr::goto(&ident)),
+            .map(BranchInstr::goto),
         just(Token::CondGoto)
             .ignore_then(parse_ident)
-            .map(|ident| BranchInstr::cond_goto(&ident)),
+            .map(BranchInstr::cond_goto),
     ))
 }
 
+#[cfg(feature = "parser")]
 fn instr_parser<'tokens>()
 -> impl Parser<'tokens, &'tokens [Token], Instr, extra::Err<Rich<'tokens, Token>>> {
     let parse_literal = select! {
@@ -326,13 +735,17 @@ fn instr_parser<'tokens>()
         just(Token::Call)
             .ignore_then(parse_ident)
             .then(parse_literal)
-            .map(|(ident, args)| CallInstr::new(&ident, args).into()),
+            .map(|(ident, args)| CallInstr::new(ident, args).into()),
         branch_instr_parser().map(|instr| instr.into()),
+        just(Token::Return).to(Instr::Return),
     ))
 }
 
-fn parser<'tokens>()
--> impl Parser<'tokens, &'tokens [Token], Vec<Function>, extra::Err<Rich<'tokens, Token>>> {
+/// Parses functions whose spans are token index ranges, left for the caller to map to bytes.
+#[cfg(feature = "parser")]
+fn parser<'tokens>(
+    options: &ParseOptions,
+) -> impl Parser<'tokens, &'tokens [Token], Vec<Function>, extra::Err<Rich<'tokens, Token>>> {
     let parse_literal = select! {
         Token::LitInt(lit) => lit
     };
@@ -341,42 +754,363 @@ fn parser<'tokens>()
         Token::Ident(ident) => ident
     };
 
+    let parse_separator = if options.separators {
+        just(Token::Separator).or_not().ignored().boxed()
+    } else {
+        empty().boxed()
+    };
+
     let parse_instr = instr_parser()
+        .map_with(|instr, e| (instr, e.span().into_range()))
+        .then_ignore(parse_separator.clone())
         .repeated()
-        .collect();
+        .collect::<Vec<_>>()
+        .boxed();
 
-    just(Token::Function)
+    let parse_top_level = if options.top_level_code {
+        parse_instr
+            .clone()
+            .map_with(|body, e| {
+                (!body.is_empty())
+                    .then(|| Function::spanned(body, "", 0, false, e.span().into_range()))
+            })
+            .boxed()
+    } else {
+        empty().to(None).boxed()
+    };
+
+    let parse_functions = just(Token::Function)
         .ignore_then(parse_ident)
         .then(parse_literal)
+        .then_ignore(parse_separator.clone())
         .then(parse_instr)
-        .then(just(Token::Return).or_not().map(|returned| returned.is_some()))
-        .map(|(((name, args), instr), returned)| Function::new(instr, &name, args, returned))
+        .map_with(|((name, args), mut body), e| {
+            let returned = matches!(body.last(), Some((Instr::Return, _)));
+            if returned {
+                body.pop();
+            }
+            Function::spanned(body, name, args, returned, e.span().into_range())
+        })
         .repeated()
-        .collect()
+        .collect::<Vec<_>>();
+
+    parse_top_level
+        .then(parse_functions)
+        .map(|(top_level, functions)| top_level.into_iter().chain(functions).collect())
+}
+
+/// Returns the whitespace-delimited word of `input` surrounding `span`.
+#[cfg(feature = "parser")]
+fn word_at<'a>(input: &'a str, span: &Range<usize>) -> &'a str {
+    let start = input[..span.start]
+        .rfind(char::is_whitespace)
+        .map_or(0, |index| index + 1);
+    let end = input[span.end..]
+        .find(char::is_whitespace)
+        .map_or(input.len(), |index| span.end + index);
+    &input[start..end]
 }
 
+/// Blanks out `/* ... */` comments byte for byte, keeping line breaks, so spans still point into
+/// `input`. A `/*` within a `//` comment starts no block comment.
+#[cfg(feature = "parser")]
+fn strip_block_comments(input: &str) -> Result<String, Error> {
+    let mut stripped = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = [rest.find("//"), rest.find("/*")]
+        .into_iter()
+        .flatten()
+        .min()
+    {
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("//") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            stripped.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        // The `*` opening the comment does not close it too, as in `/*/`.
+        let Some(end) = rest[2..].find("*/").map(|end| end + 4) else {
+            return Err(Error::Lexing {
+                source: LexingError::UnterminatedComment,
+                span: input.len() - rest.len()..input.len(),
+                suggestion: Suggestion::default(),
+            });
+        };
+        stripped.extend(
+            rest[..end]
+                .bytes()
+                .map(|byte| if byte == b'\n' { '\n' } else { ' ' }),
+        );
+        rest = &rest[end..];
+    }
+    stripped.push_str(rest);
+    Ok(stripped)
+}
+
+/// Drops instructions whose mnemonic is an identifier, i.e. an identifier that starts a line or
+/// follows a `;`, together with the operands on the rest of its line.
+#[cfg(feature = "parser")]
+fn skip_unknown_instr(
+    input: &str,
+    tokens: Vec<SpannedToken>,
+    warn: &mut dyn FnMut(Warning),
+) -> Vec<SpannedToken> {
+    let mut kept: Vec<SpannedToken> = vec![];
+    let mut skipping: Option<Span> = None;
+    let mut previous_end = 0;
+    for (token, span) in tokens {
+        let line_start = previous_end == 0 || input[previous_end..span.start].contains('\n');
+        previous_end = span.end;
+        if let Some(skipped) = &mut skipping {
+            if !line_start && token != Token::Separator {
+                skipped.end = span.end;
+                continue;
+            }
+            warn(Warning {
+                message: format!("unknown instruction `{}` skipped", &input[skipped.clone()]),
+                span: skipped.clone(),
+                lint: Lint::UnknownInstruction,
+            });
+            skipping = None;
+            if token == Token::Separator {
+                continue;
+            }
+        }
+        let mnemonic_position = line_start
+            || kept
+                .last()
+                .is_some_and(|(token, _)| *token == Token::Separator);
+        if mnemonic_position && matches!(token, Token::Ident(_)) {
+            skipping = Some(span);
+            continue;
+        }
+        kept.push((token, span));
+    }
+    if let Some(skipped) = skipping {
+        warn(Warning {
+            message: format!("unknown instruction `{}` skipped", &input[skipped.clone()]),
+            span: skipped,
+            lint: Lint::UnknownInstruction,
+        });
+    }
+    kept
+}
+
+#[cfg(feature = "parser")]
+fn lex(
+    input: &str,
+    options: &ParseOptions,
+    warn: &mut dyn FnMut(Warning),
+) -> Result<Vec<SpannedToken>, Error> {
+    let stripped;
+    let input = match options.comments {
+        CommentStyle::Line => input,
+        CommentStyle::LineAndBlock => {
+            stripped = strip_block_comments(input)?;
+            stripped.as_str()
+        }
+    };
+    let max_literal = options.max_literal.unwrap_or(u32::MAX);
+    let mut lexer = Token::lexer(input);
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next() {
+        let span = lexer.span();
+        let token = token.with_context(|_| LexingSnafu {
+            suggestion: suggest_keyword(word_at(input, &span)),
+            span: span.clone(),
+        })?;
+        let token = match token {
+            Token::ExtendedIdent(ident) if options.ident_charset == IdentCharset::Extended => {
+                Token::Ident(ident)
+            }
+            token => token,
+        };
+        let invalid = match &token {
+            Token::LitInt(literal) if *literal > max_literal => {
+                Some(LexingError::LiteralTooLarge {
+                    literal: *literal,
+                    max: max_literal,
+                })
+            }
+            Token::NegInt(literal) if *literal > MAX_NEGATED => {
+                Some(LexingError::LiteralTooLarge {
+                    literal: *literal,
+                    max: MAX_NEGATED,
+                })
+            }
+            // Indexes into a based segment are added to the base address, loaded whole.
+            Token::LitInt(literal)
+                if *literal > MAX_ADDRESSABLE
+                    && tokens.last().is_some_and(|(last, _)| {
+                        matches!(
+                            last,
+                            Token::Local | Token::Argument | Token::This | Token::That
+                        )
+                    }) =>
+            {
+                Some(LexingError::LiteralTooLarge {
+                    literal: *literal,
+                    max: MAX_ADDRESSABLE,
+                })
+            }
+            Token::LitInt(literal) if *literal > MAX_ADDRESSABLE => {
+                let constant = matches!(tokens.last(), Some((Token::Constant, _)));
+                let note = if constant {
+                    ", constants up to 65535 are taken as 16-bit two's complement"
+                } else {
+                    ""
+                };
+                warn(Warning {
+                    message: format!(
+                        "literal {literal} does not fit in an A-instruction (max \
+                            {MAX_ADDRESSABLE}){note}"
+                    ),
+                    span: span.clone(),
+                    lint: Lint::LiteralOverflow,
+                });
+                None
+            }
+            Token::ExtendedIdent(ident) => Some(LexingError::IdentCharset {
+                ident: ident.as_str().to_owned(),
+                charset: options.ident_charset,
+            }),
+            Token::Ident(ident) if ident.starts_with(RESERVED_PREFIX) => {
+                Some(LexingError::ReservedIdent {
+                    ident: ident.as_str().to_owned(),
+                })
+            }
+            _ => None,
+        };
+        if let Some(source) = invalid {
+            return Err(Error::Lexing {
+                source,
+                span,
+                suggestion: Suggestion::default(),
+            });
+        }
+        tokens.push((token, span));
+    }
+    if options.tolerant {
+        tokens = skip_unknown_instr(input, tokens, warn);
+    }
+    Ok(tokens)
+}
+
+#[cfg(feature = "parser")]
 pub fn parse(input: &str) -> Result<Vec<Function>, Error> {
-    let tokens = Token::lexer(input)
-        .collect::<Result<Vec<_>, _>>()
-        .context(LexingSnafu)?;
-    let result = parser().parse(&tokens).into_result();
-    result.map_err(|errors| {
+    parse_with(input, &ParseOptions::default()).map(|parsed| parsed.functions)
+}
+
+#[cfg(feature = "parser")]
+pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Parsed, Error> {
+    let mut warnings = vec![];
+    let functions = parse_warning(input, options, &mut |warning| warnings.push(warning))?;
+    let warnings = match options.warnings {
+        WarningLevel::Allow => vec![],
+        WarningLevel::Warn => warnings,
+        WarningLevel::Deny if warnings.is_empty() => warnings,
+        WarningLevel::Deny => {
+            return Err(Error::DeniedWarnings {
+                warnings: Warnings(warnings),
+            });
+        }
+    };
+    Ok(Parsed {
+        functions,
+        warnings,
+    })
+}
+
+/// Parses like [`parse_with`], reporting each warning to `diagnostics` as soon as it is found
+/// rather than along with the functions. Denied warnings are reported before failing the parse.
+#[cfg(feature = "parser")]
+pub fn parse_into(
+    input: &str,
+    options: &ParseOptions,
+    diagnostics: &mut dyn DiagnosticSink,
+) -> Result<Vec<Function>, Error> {
+    let mut denied = vec![];
+    let functions = parse_warning(input, options, &mut |warning| match options.warnings {
+        WarningLevel::Allow => {}
+        WarningLevel::Warn => diagnostics.report(warning.into()),
+        WarningLevel::Deny => {
+            diagnostics.report(warning.clone().into());
+            denied.push(warning);
+        }
+    })?;
+    ensure!(
+        denied.is_empty(),
+        DeniedWarningsSnafu {
+            warnings: Warnings(denied)
+        }
+    );
+    Ok(functions)
+}
+
+/// Parses `input`, handing every warning to `warn` whatever the [`WarningLevel`].
+#[cfg(feature = "parser")]
+fn parse_warning(
+    input: &str,
+    options: &ParseOptions,
+    warn: &mut dyn FnMut(Warning),
+) -> Result<Vec<Function>, Error> {
+    let tokens = lex(input, options, warn)?;
+    let (tokens, spans): (Vec<_>, Vec<_>) = tokens.into_iter().unzip();
+    let result = parser(options).parse(&tokens).into_result();
+    let functions = result.map_err(|errors| {
         let reasons = errors
             .clone()
             .into_iter()
-            .map(|reason| format!("{} at {}", reason.reason(), reason.span()))
+            .map(|reason| {
+                let suggestion = match reason.found() {
+                    Some(Token::Ident(ident)) => suggest_keyword(ident),
+                    _ => Suggestion::default(),
+                };
+                format!("{} at {}{suggestion}", reason.reason(), reason.span())
+            })
             .collect::<Vec<_>>();
         Error::Syntax {
             reasons: Reasons(reasons),
         }
-    })
+    })?;
+    let to_bytes = |tokens: &Span| spans[tokens.start].start..spans[tokens.end - 1].end;
+    let functions = functions
+        .into_iter()
+        .map(|mut function| {
+            function.span = to_bytes(&function.span);
+            function.spans = function.spans.iter().map(to_bytes).collect();
+            function
+        })
+        .collect::<Vec<_>>();
+    for function in &functions {
+        for dead in unreachable(&function.instr) {
+            // Dead code starts right after the `goto` or `return` ending the reachable code.
+            let after = match function.instr[dead.start - 1] {
+                Instr::Return => "return",
+                _ => "goto",
+            };
+            warn(Warning {
+                message: format!("unreachable instructions after `{after}`"),
+                span: function.spans[dead.start].start..function.spans[dead.end - 1].end,
+                lint: Lint::UnreachableCode,
+            });
+        }
+    }
+    Ok(functions)
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "parser"))]
 mod tests {
+    use crate::diagnostic::{Diagnostic, Severity};
     use crate::parse::LexingError::ParseInt;
     use crate::parse::StackSegment::Constant;
-    use crate::parse::{CallInstr, BranchInstr, Function, StackInstr, Token, parse};
+    use crate::parse::{
+        BranchInstr, CallInstr, CommentStyle, Error, Function, IdentCharset, Instr, LexingError,
+        ParseOptions, StackInstr, Token, WarningLevel, parse, parse_into, parse_with,
+    };
+    use crate::suggest::Suggestion;
     use logos::Logos;
 
     #[test]
@@ -417,4 +1151,370 @@ mod tests {
         ];
         assert_eq!(program, parsed)
     }
+
+    #[test]
+    fn suggest_misspelled_instr() {
+        let error = parse("function Test 0\nPush constant 1\nreturn").expect_err("expect err");
+        let Error::Syntax { reasons } = error else {
+            panic!("expect syntax error")
+        };
+        assert!(reasons.to_string().ends_with(", did you mean `push`?"));
+    }
+
+    #[test]
+    fn intern_identifiers() {
+        const TESTING_VM: &str = "function Main.main 0\ncall Main.draw 0\ncall Main.draw 0\n\
+        return\nfunction Main.draw 0\nreturn";
+        let functions = parse(TESTING_VM).expect("expect ok");
+        let [Instr::Call { data: first }, Instr::Call { data: second }] = &functions[0].instr[..]
+        else {
+            panic!("expect two calls");
+        };
+        assert!(first.ident.ptr_eq(&second.ident));
+        assert!(first.ident.ptr_eq(&functions[1].name));
+    }
+
+    #[test]
+    fn suggest_on_lexing_error() {
+        let error = parse("function Test 0\nif-got END\nreturn").expect_err("expect err");
+        let Error::Lexing { suggestion, .. } = error else {
+            panic!("expect lexing error")
+        };
+        assert_eq!(suggestion, Suggestion(Some("if-goto")));
+    }
+
+    #[test]
+    fn parse_block_comments() {
+        let options = ParseOptions {
+            comments: CommentStyle::LineAndBlock,
+            ..Default::default()
+        };
+        let parsed = parse_with(
+            "/* a\ncomment */ function Test 0 /* push */ return",
+            &options,
+        )
+        .expect("expect ok");
+        assert_eq!(
+            parsed.functions,
+            vec![Function::new(vec![], "Test", 0, true)]
+        );
+        assert!(parse("/* comment */ function Test 0 return").is_err());
+        let source = "function Test 0\npush constant 1 // see /* here\nreturn";
+        let parsed = parse_with(source, &options).expect("expect ok");
+        assert_eq!(
+            parsed.functions[0].instr,
+            vec![StackInstr::push(Constant, 1).into()]
+        );
+        let source = "function Test 0\n/*/ push */ push constant 1";
+        let parsed = parse_with(source, &options).expect("expect ok");
+        assert_eq!(
+            parsed.functions[0].instr,
+            vec![StackInstr::push(Constant, 1).into()]
+        );
+        let error = parse_with("/* é */ function Test 0 #", &options).expect_err("expect err");
+        assert!(matches!(error, Error::Lexing { span, .. } if span == (25..26)));
+        let error = parse_with("function Test 0 /*/ return", &options).expect_err("expect err");
+        assert!(matches!(error, Error::Lexing { span, .. } if span == (16..26)));
+    }
+
+    #[test]
+    fn parse_top_level_code() {
+        let options = ParseOptions {
+            top_level_code: true,
+            ..Default::default()
+        };
+        let parsed =
+            parse_with("push constant 1\nfunction Test 0\nreturn", &options).expect("expect ok");
+        let program = vec![
+            Function::new(vec![StackInstr::push(Constant, 1).into()], "", 0, false),
+            Function::new(vec![], "Test", 0, true),
+        ];
+        assert_eq!(program, parsed.functions);
+        assert!(parse("push constant 1\nfunction Test 0\nreturn").is_err());
+    }
+
+    #[test]
+    fn parse_max_literal() {
+        let options = ParseOptions {
+            max_literal: Some(100),
+            ..Default::default()
+        };
+        let error =
+            parse_with("function Test 0\npush constant 101", &options).expect_err("expect err");
+        assert!(matches!(
+            error,
+            Error::Lexing {
+                source: LexingError::LiteralTooLarge {
+                    literal: 101,
+                    max: 100
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_ident_charset() {
+        const INPUT: &str = "function Main$main 0\nreturn";
+        assert!(matches!(
+            parse(INPUT),
+            Err(Error::Lexing {
+                source: LexingError::IdentCharset { .. },
+                ..
+            })
+        ));
+        let options = ParseOptions {
+            ident_charset: IdentCharset::Extended,
+            ..Default::default()
+        };
+        let parsed = parse_with(INPUT, &options).expect("expect ok");
+        assert_eq!(
+            parsed.functions,
+            vec![Function::new(vec![], "Main$main", 0, true)]
+        );
+        assert!(parse("function .main 0\nreturn").is_err());
+        let parsed = parse_with("function .main 0\nreturn", &options).expect("expect ok");
+        assert_eq!(
+            parsed.functions,
+            vec![Function::new(vec![], ".main", 0, true)]
+        );
+    }
+
+    #[test]
+    fn parse_reserved_ident() {
+        const INPUT: &str = "function Main.main 0\nlabel __vm$TRUE.Main.main.0\nreturn";
+        let options = ParseOptions {
+            ident_charset: IdentCharset::Extended,
+            ..Default::default()
+        };
+        assert!(matches!(
+            parse_with(INPUT, &options),
+            Err(Error::Lexing {
+                source: LexingError::ReservedIdent { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_warning_levels() {
+        const INPUT: &str = "function Test 0\npush constant 40000\nreturn";
+        let parsed = parse_with(INPUT, &ParseOptions::default()).expect("expect ok");
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(parsed.warnings[0].span, 30..35);
+        assert!(parsed.warnings[0].message.contains("two's complement"));
+        let parsed =
+            parse_with("function Test 40000\nreturn", &ParseOptions::default()).expect("expect ok");
+        assert!(!parsed.warnings[0].message.contains("two's complement"));
+
+        let options = ParseOptions {
+            warnings: WarningLevel::Allow,
+            ..Default::default()
+        };
+        let parsed = parse_with(INPUT, &options).expect("expect ok");
+        assert!(parsed.warnings.is_empty());
+
+        let options = ParseOptions {
+            warnings: WarningLevel::Deny,
+            ..Default::default()
+        };
+        let error = parse_with(INPUT, &options).expect_err("expect err");
+        assert!(matches!(error, Error::DeniedWarnings { .. }));
+    }
+
+    #[test]
+    fn report_warnings_to_sink() {
+        const INPUT: &str =
+            "function Test 0\npush constant 40000\ngoto END\npush constant 1\nlabel END\nreturn";
+        let mut diagnostics = Vec::<Diagnostic>::new();
+        let functions =
+            parse_into(INPUT, &ParseOptions::default(), &mut diagnostics).expect("expect ok");
+        assert_eq!(functions.len(), 1);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].span, Some(30..35));
+        assert_eq!(
+            diagnostics[1].message,
+            "unreachable instructions after `goto`"
+        );
+
+        let options = ParseOptions {
+            warnings: WarningLevel::Deny,
+            ..Default::default()
+        };
+        let mut denied = Vec::<Diagnostic>::new();
+        let error = parse_into(INPUT, &options, &mut denied).expect_err("expect err");
+        assert!(matches!(error, Error::DeniedWarnings { .. }));
+        assert_eq!(denied, diagnostics);
+    }
+
+    #[test]
+    fn parse_negative_constant() {
+        let parsed = parse("function Test 0\npush constant -5\npush constant -32768\nreturn")
+            .expect("expect ok");
+        let instr = vec![
+            StackInstr::PushNegated { literal: 5 }.into(),
+            StackInstr::PushNegated { literal: 32768 }.into(),
+        ];
+        assert_eq!(parsed, vec![Function::new(instr, "Test", 0, true)]);
+        assert!(matches!(
+            parse("function Test 0\npush constant -32769\nreturn"),
+            Err(Error::Lexing {
+                source: LexingError::LiteralTooLarge {
+                    literal: 32769,
+                    max: 32768
+                },
+                ..
+            })
+        ));
+        assert!(parse("function Test 0\npush local -1\nreturn").is_err());
+        for source in [
+            "push local 40000",
+            "pop argument 32768",
+            "push this 100000",
+            "pop that 65535",
+        ] {
+            let error =
+                parse(&format!("function Test 0\n{source}\nreturn")).expect_err("expect err");
+            assert!(matches!(
+                error,
+                Error::Lexing {
+                    source: LexingError::LiteralTooLarge { max: 32767, .. },
+                    ..
+                }
+            ));
+        }
+        parse("function Test 0\npush local 32767\npop static 40000\nreturn").expect("expect ok");
+        assert!(parse("function Test 0\npop constant -1\nreturn").is_err());
+    }
+
+    #[test]
+    fn parse_early_return() {
+        const INPUT: &str =
+            "function Test 0\nif-goto END\nreturn\nlabel END\npush constant 1\nreturn";
+        let function = Function::new(
+            vec![
+                BranchInstr::cond_goto("END").into(),
+                Instr::Return,
+                BranchInstr::label("END").into(),
+                StackInstr::push(Constant, 1).into(),
+            ],
+            "Test",
+            0,
+            true,
+        );
+        assert_eq!(parse(INPUT).expect("expect ok"), vec![function]);
+        let parsed = parse("function Test 0\nreturn\npush constant 1").expect("expect ok");
+        assert!(!parsed[0].returned);
+    }
+
+    #[test]
+    fn parse_unreachable_warning() {
+        const INPUT: &str =
+            "function Test 0\ngoto END\npush constant 1\npop temp 0\nlabel END\nreturn";
+        let parsed = parse_with(INPUT, &ParseOptions::default()).expect("expect ok");
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(
+            &INPUT[parsed.warnings[0].span.clone()],
+            "push constant 1\npop temp 0"
+        );
+        assert_eq!(
+            parsed.warnings[0].message,
+            "unreachable instructions after `goto`"
+        );
+
+        let parsed = parse_with(
+            "function Test 0\nreturn\npush constant 2\nreturn",
+            &ParseOptions::default(),
+        )
+        .expect("expect ok");
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(
+            parsed.warnings[0].message,
+            "unreachable instructions after `return`"
+        );
+        assert_eq!(parsed.warnings[0].span, 23..38);
+    }
+
+    #[test]
+    fn parse_separators() {
+        const INPUT: &str = "function Test 0; push constant 1; push constant 2; add; return";
+        let options = ParseOptions {
+            separators: true,
+            ..Default::default()
+        };
+        let parsed = parse_with(INPUT, &options).expect("expect ok");
+        let instr = vec![
+            StackInstr::push(Constant, 1).into(),
+            StackInstr::push(Constant, 2).into(),
+            StackInstr::Add.into(),
+        ];
+        assert_eq!(
+            parsed.functions,
+            vec![Function::new(instr, "Test", 0, true)]
+        );
+        assert!(parse(INPUT).is_err());
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn parse_extended_arithmetic() {
+        let parsed = parse("function Test 0\nmul\ndiv\nmod\nreturn").expect("expect ok");
+        let instr = vec![
+            StackInstr::Multiply.into(),
+            StackInstr::Divide.into(),
+            StackInstr::Modulo.into(),
+        ];
+        assert_eq!(parsed, vec![Function::new(instr, "Test", 0, true)]);
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn parse_shifts() {
+        let parsed = parse("function Test 0\nshl 3\nshr 15\nreturn").expect("expect ok");
+        let instr = vec![
+            StackInstr::ShiftLeft { count: 3 }.into(),
+            StackInstr::ShiftRight { count: 15 }.into(),
+        ];
+        assert_eq!(parsed, vec![Function::new(instr, "Test", 0, true)]);
+        assert!(parse("function Test 0\nshl\nreturn").is_err());
+    }
+
+    #[test]
+    fn parse_tolerant() {
+        const INPUT: &str = "function Test 0\nxor 2 x\npush constant 1\nhalt\nreturn";
+        let options = ParseOptions {
+            tolerant: true,
+            ..Default::default()
+        };
+        let parsed = parse_with(INPUT, &options).expect("expect ok");
+        let instr = vec![StackInstr::push(Constant, 1).into()];
+        assert_eq!(
+            parsed.functions,
+            vec![Function::new(instr, "Test", 0, true)]
+        );
+        let skipped = parsed
+            .warnings
+            .iter()
+            .map(|warning| warning.span.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(skipped, vec![16..23, 40..44]);
+        assert!(parse(INPUT).is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        const INPUT: &str = "function Main.main 2 // entry\n  push constant 7\nlabel LOOP\n\
+            call Math.abs 1\nif-goto LOOP\npop local 1\nreturn\nfunction Main.empty 0\ngoto END\n\
+            label END\n";
+        let parsed = parse(INPUT).expect("expect ok");
+        let rendered = parsed.iter().map(Function::to_string).collect::<String>();
+        assert!(
+            rendered.starts_with(
+                "function Main.main 2\npush constant 7\nlabel LOOP\ncall Math.abs 1\n"
+            )
+        );
+        assert!(rendered.ends_with("return\nfunction Main.empty 0\ngoto END\nlabel END\n"));
+        assert_eq!(parse(&rendered).expect("expect ok"), parsed);
+    }
 }
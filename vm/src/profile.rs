@@ -0,0 +1,285 @@
+use crate::asm::{AsmInstr, Comp, Dest, Jump, at, jump, label, set};
+use crate::generate::TargetLayout;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use serde::Serialize;
+
+/// Words of RAM the counters of one function take: the low and high word of its calls, then of
+/// its cycles.
+pub const COUNTER_WORDS: u16 = 4;
+
+/// Functions whose counters instrumented code keeps, see
+/// [`GenerateOptions::instrument`](crate::generate::GenerateOptions::instrument).
+///
+/// The counters of the function at index `n` start right after [`TargetLayout::counters`], offset
+/// by `n` times [`COUNTER_WORDS`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Counters {
+    functions: Vec<String>,
+}
+
+impl Counters {
+    /// Index of the counters of `function`, handing out the next one the first time.
+    pub(crate) fn slot(&mut self, function: &str) -> usize {
+        match self.functions.iter().position(|name| name == function) {
+            Some(slot) => slot,
+            None => {
+                self.functions.push(function.to_owned());
+                self.functions.len() - 1
+            }
+        }
+    }
+
+    /// Address of the first counter of `function`, if it was instrumented.
+    pub fn address(&self, function: &str, layout: &TargetLayout) -> Option<u16> {
+        let slot = self.functions.iter().position(|name| name == function)?;
+        Some(slot_address(slot, layout))
+    }
+
+    /// Instrumented functions, in the order their counters are laid out.
+    pub fn functions(&self) -> &[String] {
+        &self.functions
+    }
+
+    /// Takes over the functions of `other` missing here, after those already present.
+    pub(crate) fn extend(&mut self, other: &Counters) {
+        for function in &other.functions {
+            self.slot(function);
+        }
+    }
+}
+
+/// Address of the first counter of the function at `slot`.
+pub(crate) fn slot_address(slot: usize, layout: &TargetLayout) -> u16 {
+    layout.counters + 1 + slot as u16 * COUNTER_WORDS
+}
+
+/// Assembly of a function with counting code inserted, see [`instrument`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Instrumented {
+    pub asm: Vec<AsmInstr>,
+    /// Index in `asm` each input instruction ended up at, followed by the length of `asm`.
+    pub lines: Vec<usize>,
+}
+
+/// Inserts code counting the entries and cycles of the function `asm` into the counters at
+/// `counters`, saving D into `save` around the counting. `fresh_label` returns a new label for
+/// each carry into a high word.
+///
+/// The function is cut into blocks running straight through: every label starts one and every
+/// jump ends one. A block adds its length to the cycles as it starts, so jumps into other
+/// functions and shared routines leave what runs there out.
+pub(crate) fn instrument(
+    asm: &[AsmInstr],
+    counters: u16,
+    save: u16,
+    fresh_label: &mut dyn FnMut() -> String,
+) -> Instrumented {
+    let mut instrumented = Vec::with_capacity(asm.len());
+    let mut lines = Vec::with_capacity(asm.len() + 1);
+    let mut entered = false;
+    for (index, instr) in asm.iter().enumerate() {
+        lines.push(instrumented.len());
+        instrumented.push(instr.clone());
+        let starts_block = match instr {
+            AsmInstr::Label(_) => true,
+            AsmInstr::C { jump, .. } => jump.is_some(),
+            _ => false,
+        };
+        if !starts_block {
+            continue;
+        }
+        let mut counting = vec![];
+        // The first label is the function itself, which only calls jump to.
+        if !entered && matches!(instr, AsmInstr::Label(_)) {
+            entered = true;
+            counting.extend(add(counters, 1, fresh_label()));
+        }
+        let cycles = block_len(&asm[index + 1..]);
+        if cycles > 0 {
+            counting.extend(add(counters + 2, cycles, fresh_label()));
+        }
+        if !counting.is_empty() {
+            instrumented.push(at(save));
+            instrumented.push(set(Dest::M, Comp::D));
+            instrumented.extend(counting);
+            instrumented.push(at(save));
+            instrumented.push(set(Dest::D, Comp::M));
+        }
+    }
+    lines.push(instrumented.len());
+    Instrumented {
+        asm: instrumented,
+        lines,
+    }
+}
+
+/// Number of ROM instructions up to the next label, or up to and including the next jump.
+fn block_len(asm: &[AsmInstr]) -> u16 {
+    let mut len = 0;
+    for instr in asm {
+        match instr {
+            AsmInstr::Label(_) => break,
+            AsmInstr::A(_) => len += 1,
+            AsmInstr::C { jump, .. } => {
+                len += 1;
+                if jump.is_some() {
+                    break;
+                }
+            }
+            AsmInstr::Comment(_) => {}
+        }
+    }
+    len
+}
+
+/// Adds `value` to the counter at `address`, carrying into the word after it once the low word
+/// leaves 15 bits. Clobbers D.
+fn add(address: u16, value: u16, carried: String) -> Vec<AsmInstr> {
+    vec![
+        at(value),
+        set(Dest::D, Comp::A),
+        at(address),
+        set(Dest::MD, Comp::DPlusM),
+        at(carried.as_str()),
+        jump(Comp::D, Jump::GreaterEqual),
+        at(0x7fff),
+        set(Dest::D, Comp::A),
+        at(address),
+        set(Dest::M, Comp::DAndM),
+        at(address + 1),
+        set(Dest::M, Comp::MPlusOne),
+        label(carried),
+    ]
+}
+
+/// What the counters of one function read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionProfile {
+    pub name: String,
+    /// Times the function was entered.
+    pub calls: u32,
+    /// ROM instructions the function ran itself, leaving out the counting and whatever runs in the
+    /// functions and shared routines it jumps into.
+    pub cycles: u32,
+}
+
+/// Counters of a run of instrumented code, read from a snapshot of its RAM.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Profile {
+    /// Functions that ran, by cycles, most first.
+    pub functions: Vec<FunctionProfile>,
+}
+
+impl Profile {
+    /// Reads the counters laid out in `layout` from `ram`, a snapshot starting at address 0.
+    /// Counters past the end of the snapshot read as 0.
+    pub fn decode(counters: &Counters, layout: &TargetLayout, ram: &[u16]) -> Self {
+        let word = |address: u16| ram.get(address as usize).copied().unwrap_or_default() as u32;
+        // The low word holds 15 bits, the high one counts its overflows.
+        let counter = |address: u16| word(address + 1) << 15 | word(address);
+        let mut functions = counters
+            .functions
+            .iter()
+            .filter_map(|name| {
+                let address = counters.address(name, layout)?;
+                Some(FunctionProfile {
+                    name: name.clone(),
+                    calls: counter(address),
+                    cycles: counter(address + 2),
+                })
+            })
+            .filter(|function| function.calls > 0 || function.cycles > 0)
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|function| core::cmp::Reverse(function.cycles));
+        Self { functions }
+    }
+
+    /// Cycles of every function together.
+    pub fn cycles(&self) -> u64 {
+        self.functions
+            .iter()
+            .map(|function| function.cycles as u64)
+            .sum()
+    }
+}
+
+/// Renders one line per function with its calls, cycles and share of all cycles.
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let total = self.cycles().max(1) as f64;
+        writeln!(
+            f,
+            "{:>10} {:>12} {:>6}  function",
+            "calls", "cycles", "share"
+        )?;
+        for function in &self.functions {
+            let share = function.cycles as f64 * 100.0 / total;
+            writeln!(
+                f,
+                "{:>10} {:>12} {share:>5.1}%  {}",
+                function.calls, function.cycles, function.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::generate::{Class, Context, Generate, GenerateOptions, TargetLayout};
+    use crate::parse::parse;
+    use crate::profile::{FunctionProfile, Profile};
+    use alloc::vec;
+
+    #[test]
+    fn count_functions() {
+        const MAIN_VM: &str = "function Main.main 0\nlabel LOOP\npush constant 1\nif-goto LOOP\n\
+            call Main.helper 0\nreturn\nfunction Main.helper 0\npush constant 0\nreturn";
+        let class = Class::new(parse(MAIN_VM).expect("expect ok"), "Main");
+        let options = GenerateOptions {
+            instrument: true,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(options);
+        let generated = class.generate_with(&mut ctx).expect("expect ok");
+        assert_eq!(ctx.counters().functions(), ["Main.main", "Main.helper"]);
+        let layout = TargetLayout::default();
+        assert_eq!(ctx.counters().address("Main.helper", &layout), Some(24582));
+        assert!(generated.starts_with("(Main.main)\n@24577\nM=D\n@1\nD=A\n@24578\nMD=D+M\n"));
+        assert!(generated.contains("(Main.main$LOOP)\n@24577\nM=D\n@"));
+        // Entering each function and the three blocks of the main function after the first one.
+        assert_eq!(generated.matches("(__vm$COUNT.").count(), 6);
+
+        let mut ram = vec![0; 24585];
+        ram[24578..24582].copy_from_slice(&[1, 0, 5, 2]);
+        ram[24582] = 3;
+        ram[24584] = 12;
+        let profile = Profile::decode(ctx.counters(), &layout, &ram);
+        let function = |name: &str, calls, cycles| FunctionProfile {
+            name: name.into(),
+            calls,
+            cycles,
+        };
+        assert_eq!(
+            profile.functions,
+            vec![
+                function("Main.main", 1, 65541),
+                function("Main.helper", 3, 12)
+            ]
+        );
+        assert_eq!(profile.cycles(), 65553);
+        assert_eq!(
+            profile.to_string().lines().last(),
+            Some("         3           12   0.0%  Main.helper")
+        );
+        assert!(
+            Profile::decode(ctx.counters(), &layout, &[])
+                .functions
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,1410 @@
+use crate::asm::{AsmInstr, assemble, render_to};
+use crate::cache::CompileCache;
+use crate::diagnostic::{Diagnostic, DiagnosticSink, Lint};
+use crate::generate::{
+    Class, Context, ENTRY, FunctionOrder, Generate, GenerateOptions, OptLevel, TargetLayout,
+};
+use crate::graph::CallGraph;
+use crate::optimize::inlined;
+use crate::parse::{BranchInstr, Function, Instr, Span, StackInstr, StackSegment};
+#[cfg(feature = "parser")]
+use crate::parse::{Error, ParseOptions, Parsed, parse_with, shift_span};
+use crate::source::SourceFile;
+use crate::stats::Stats;
+use crate::suggest::suggest_name;
+use crate::symbol::Symbol;
+use alloc::borrow::{Cow, ToOwned};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt::{Display, Formatter};
+use serde::Serialize;
+
+/// Labels of the functions of `class` that no `goto` or `if-goto` of the same function targets.
+fn unused_labels(class: &Class) -> Vec<Finding> {
+    let mut findings = vec![];
+    for function in &class.functions {
+        let targets = function
+            .instr
+            .iter()
+            .filter_map(|instr| match instr {
+                Instr::Branch {
+                    data: BranchInstr::Goto { ident } | BranchInstr::CondGoto { ident },
+                } => Some(ident),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        for (index, instr) in function.instr.iter().enumerate() {
+            if let Instr::Branch {
+                data: BranchInstr::Label { ident },
+            } = instr
+                && !targets.contains(ident)
+            {
+                findings.push(Finding::UnusedLabel {
+                    class: class.name.clone(),
+                    function: function.name.clone(),
+                    label: ident.clone(),
+                    span: function.instr_span(index),
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// A text edit replacing `range` of the previous source with `inserted` bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub range: Span,
+    pub inserted: usize,
+}
+
+impl Edit {
+    pub fn new(range: Span, inserted: usize) -> Self {
+        Self { range, inserted }
+    }
+
+    #[cfg(feature = "parser")]
+    fn delta(&self) -> isize {
+        self.inserted as isize - self.range.len() as isize
+    }
+}
+
+/// A `call` instruction together with where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSite {
+    pub class: String,
+    pub caller: Symbol,
+    pub target: Symbol,
+    pub args: u32,
+    pub span: Option<Span>,
+}
+
+/// Calls to the same function disagreeing on the number of arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArityConflict {
+    pub target: Symbol,
+    pub sites: Vec<CallSite>,
+}
+
+impl Display for ArityConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "`{}` is called with conflicting arities:", self.target)?;
+        for site in &self.sites {
+            write!(f, " {} in {} ({});", site.args, site.caller, site.class)?;
+        }
+        Ok(())
+    }
+}
+
+/// Slots of the static segment a class takes, one past the highest `static` index it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticUsage {
+    pub class: String,
+    pub slots: u32,
+}
+
+/// Classes of the Jack operating system, whose functions a program calls without defining them.
+pub const OS_CLASSES: [&str; 8] = [
+    "Array", "Keyboard", "Math", "Memory", "Output", "Screen", "String", "Sys",
+];
+
+/// What [`Program::check_with`] expects of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckOptions {
+    /// Function the bootstrap code enters, which has to be defined.
+    pub entry: Option<String>,
+    /// Classes whose functions are provided outside the program, [`OS_CLASSES`] by default.
+    pub external: BTreeSet<String>,
+    /// Also report labels no jump of their function targets, which are harmless and so left out
+    /// by default.
+    pub unused_labels: bool,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            entry: None,
+            external: OS_CLASSES.into_iter().map(str::to_owned).collect(),
+            unused_labels: false,
+        }
+    }
+}
+
+/// A problem in a program that would otherwise only show when the machine runs it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// The bootstrap code enters a function no class defines.
+    MissingEntry { entry: String },
+    /// A call to a function neither the program nor an external class defines.
+    UndefinedCall { site: CallSite },
+    /// A function defined more than once, by the classes listed, one entry per definition.
+    DuplicateFunction { name: String, classes: Vec<String> },
+    /// A label no jump of its function targets.
+    UnusedLabel {
+        class: String,
+        function: Symbol,
+        label: Symbol,
+        span: Option<Span>,
+    },
+}
+
+impl Finding {
+    /// Kind of warning the finding is reported as.
+    pub fn lint(&self) -> Lint {
+        match self {
+            Finding::MissingEntry { .. } => Lint::MissingEntry,
+            Finding::UndefinedCall { .. } => Lint::UndefinedCall,
+            Finding::DuplicateFunction { .. } => Lint::DuplicateFunction,
+            Finding::UnusedLabel { .. } => Lint::UnusedLabel,
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Finding::MissingEntry { entry } => write!(f, "entry `{entry}` is not defined"),
+            Finding::UndefinedCall { site } => {
+                write!(
+                    f,
+                    "`{}` called in {} ({}) is not defined",
+                    site.target, site.caller, site.class
+                )
+            }
+            Finding::DuplicateFunction { name, classes } => {
+                write!(
+                    f,
+                    "`{name}` is defined more than once, in {}",
+                    classes.join(", ")
+                )
+            }
+            Finding::UnusedLabel {
+                function, label, ..
+            } => {
+                write!(f, "label `{label}` is never jumped to in {function}")
+            }
+        }
+    }
+}
+
+/// Where a function lands in the output of a program, in [`Program::layout`] order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Placement {
+    pub class: String,
+    /// Name of the function, empty for top-level code.
+    pub function: Symbol,
+    /// The function it follows as the callee placed next, with [`FunctionOrder::CallGraph`].
+    pub caller: Option<Symbol>,
+}
+
+/// Every class linked into one output.
+///
+/// Classes are kept in name order unless [reordered](Program::reorder), and the functions of a
+/// class in source order, so the same classes generate byte-identical output however they were
+/// collected.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub(crate) classes: Vec<Class>,
+    pub(crate) calls: Vec<CallSite>,
+}
+
+impl Program {
+    pub fn new(mut classes: Vec<Class>) -> Self {
+        classes.sort_by(|a, b| a.name.cmp(&b.name));
+        let calls = Self::collect_calls(&classes);
+        Self { classes, calls }
+    }
+
+    /// Moves the classes named in `order` to the front, in that order, the others following in name
+    /// order. Names no class has are skipped.
+    pub fn reorder<S: AsRef<str>>(&mut self, order: &[S]) {
+        let position = |class: &Class| order.iter().position(|name| name.as_ref() == class.name);
+        self.classes
+            .sort_by_key(|class| position(class).unwrap_or(order.len()));
+        self.calls = Self::collect_calls(&self.classes);
+    }
+
+    fn collect_calls(classes: &[Class]) -> Vec<CallSite> {
+        classes
+            .iter()
+            .flat_map(|class| {
+                class.functions.iter().flat_map(|function| {
+                    function
+                        .instr
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, instr)| match instr {
+                            Instr::Call { data } => Some(CallSite {
+                                class: class.name.clone(),
+                                caller: function.name.clone(),
+                                target: data.ident.clone(),
+                                args: data.args,
+                                span: function.instr_span(index),
+                            }),
+                            _ => None,
+                        })
+                })
+            })
+            .collect()
+    }
+
+    pub fn classes(&self) -> &[Class] {
+        &self.classes
+    }
+
+    /// Every call site in the program, in class and instruction order.
+    pub fn calls(&self) -> &[CallSite] {
+        &self.calls
+    }
+
+    /// Checks the program against [`CheckOptions::default`], see [`Program::check_with`].
+    pub fn check(&self) -> Vec<Finding> {
+        self.check_with(&CheckOptions::default())
+    }
+
+    /// Finds the entry missing, calls to undefined functions in instruction order and functions
+    /// defined more than once in name order.
+    pub fn check_with(&self, options: &CheckOptions) -> Vec<Finding> {
+        let mut definitions = BTreeMap::<&str, Vec<String>>::new();
+        for class in &self.classes {
+            for function in class
+                .functions
+                .iter()
+                .filter(|function| !function.name.is_empty())
+            {
+                definitions
+                    .entry(&function.name)
+                    .or_default()
+                    .push(class.name.clone());
+            }
+        }
+        let missing = options
+            .entry
+            .iter()
+            .filter(|entry| !definitions.contains_key(entry.as_str()))
+            .map(|entry| Finding::MissingEntry {
+                entry: entry.clone(),
+            });
+        let external = |target: &str| {
+            let class = target.split_once('.').map_or(target, |(class, _)| class);
+            options.external.contains(class)
+        };
+        let undefined = self
+            .calls
+            .iter()
+            .filter(|site| {
+                !definitions.contains_key(site.target.as_str()) && !external(&site.target)
+            })
+            .map(|site| Finding::UndefinedCall { site: site.clone() });
+        let duplicates = definitions
+            .iter()
+            .filter(|(_, classes)| classes.len() > 1)
+            .map(|(name, classes)| Finding::DuplicateFunction {
+                name: name.to_string(),
+                classes: classes.clone(),
+            });
+        let mut findings = missing
+            .chain(undefined)
+            .chain(duplicates)
+            .collect::<Vec<_>>();
+        if options.unused_labels {
+            findings.extend(self.classes.iter().flat_map(unused_labels));
+        }
+        findings
+    }
+
+    /// Checks the program like [`Program::check_with`], reporting every finding to `diagnostics` as
+    /// a warning. Calls to undefined functions point at the call, suggesting the defined function
+    /// closest in name if any.
+    pub fn check_into(&self, options: &CheckOptions, diagnostics: &mut dyn DiagnosticSink) {
+        let defined = || {
+            self.classes
+                .iter()
+                .flat_map(|class| {
+                    class
+                        .functions
+                        .iter()
+                        .map(|function| function.name.as_str())
+                })
+                .filter(|name| !name.is_empty())
+        };
+        let file = |class_name: &str| {
+            let class = self.classes.iter().find(|class| class.name == class_name);
+            class
+                .and_then(|class| class.source.as_ref())
+                .map(SourceFile::name)
+        };
+        for finding in self.check_with(options) {
+            let mut diagnostic = Diagnostic::warning(finding.to_string()).with_lint(finding.lint());
+            if let Finding::UnusedLabel { class, span, .. } = &finding {
+                if let Some(file) = file(class) {
+                    diagnostic = diagnostic.with_file(file);
+                }
+                if let Some(span) = span {
+                    diagnostic = diagnostic.with_span(span.clone());
+                }
+            }
+            if let Finding::UndefinedCall { site } = &finding {
+                if let Some(file) = file(&site.class) {
+                    diagnostic = diagnostic.with_file(file);
+                }
+                if let Some(span) = &site.span {
+                    diagnostic = diagnostic.with_span(span.clone());
+                }
+                if let Some(name) = suggest_name(site.target.as_str(), defined()) {
+                    diagnostic = diagnostic.with_suggestion(format!("did you mean `{name}`?"));
+                }
+            }
+            diagnostics.report(diagnostic);
+        }
+    }
+
+    /// Call targets reached with different argument counts, ordered by target name.
+    pub fn arity_conflicts(&self) -> Vec<ArityConflict> {
+        let mut by_target = BTreeMap::<&Symbol, Vec<&CallSite>>::new();
+        for site in &self.calls {
+            by_target.entry(&site.target).or_default().push(site);
+        }
+        by_target
+            .into_iter()
+            .filter(|(_, sites)| sites.iter().any(|site| site.args != sites[0].args))
+            .map(|(target, sites)| ArityConflict {
+                target: target.clone(),
+                sites: sites.into_iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    /// Generates the program and assembles it into Hack machine code, see
+    /// [`render_hack`](crate::asm::render_hack) for writing it as a `.hack` file.
+    pub fn generate_binary(&self) -> Result<Vec<u16>, crate::generate::Error> {
+        self.generate_binary_with(&mut Context::default())
+    }
+
+    pub fn generate_binary_with(
+        &self,
+        ctx: &mut Context,
+    ) -> Result<Vec<u16>, crate::generate::Error> {
+        let asm = self.lower(ctx)?;
+        Ok(assemble(&asm, ctx.options.layout.statics.clone())?)
+    }
+
+    /// Code size of the linked program, as [`Program::generate_with`](Generate::generate_with)
+    /// would output it.
+    pub fn stats(&self) -> Result<Stats, crate::generate::Error> {
+        self.stats_with(&GenerateOptions::default())
+    }
+
+    pub fn stats_with(&self, options: &GenerateOptions) -> Result<Stats, crate::generate::Error> {
+        let mut ctx = Context::new(GenerateOptions {
+            source_map: true,
+            ..options.clone()
+        });
+        let asm = self.lower(&mut ctx)?;
+        let entry = options
+            .bootstrap
+            .as_ref()
+            .map_or(ENTRY, |options| &options.entry);
+        Ok(Stats {
+            layout: self.prepared(options).layout(options.function_order, entry),
+            ..Stats::collect(&asm, &ctx.source_map().mappings)
+        })
+    }
+
+    /// Static slots taken by each class using any, in class order.
+    pub fn static_usage(&self) -> Vec<StaticUsage> {
+        self.classes
+            .iter()
+            .filter_map(|class| {
+                let max = class.functions.iter().flat_map(static_indices).max()?;
+                Some(StaticUsage {
+                    class: class.name.clone(),
+                    slots: max + 1,
+                })
+            })
+            .collect()
+    }
+
+    /// Checks that the statics of all classes fit into the static segment of `layout` together.
+    pub fn check_statics(&self, layout: &TargetLayout) -> Result<(), crate::generate::Error> {
+        let slots = self
+            .static_usage()
+            .iter()
+            .map(|usage| usage.slots)
+            .sum::<u32>();
+        let capacity = layout.statics.len() as u32;
+        if slots > capacity {
+            return Err(crate::generate::Error::StaticOverflow { slots, capacity });
+        }
+        Ok(())
+    }
+
+    /// Classes called into that are not in the program, such as the classes of the operating
+    /// system, which may be found in a library. Calls of functions missing from a class of the
+    /// program do not count, as no other file could define them without defining the class twice.
+    pub fn missing_classes(&self) -> BTreeSet<&str> {
+        self.calls
+            .iter()
+            .map(|site| {
+                site.target
+                    .split_once('.')
+                    .map_or(site.target.as_str(), |(class, _)| class)
+            })
+            .filter(|class| !self.classes.iter().any(|defined| defined.name == *class))
+            .collect()
+    }
+
+    /// Names of the functions `entry` can end up calling, including itself. Top-level code is
+    /// always reachable, and so is everything it calls.
+    pub fn reachable<'a>(&'a self, entry: &'a str) -> BTreeSet<&'a str> {
+        self.call_graph().reachable(entry)
+    }
+
+    /// Which function calls which, built from the call sites.
+    pub fn call_graph(&self) -> CallGraph<'_> {
+        CallGraph::new(self)
+    }
+
+    /// Drops every function unreachable from `entry`, unless no function is called `entry`.
+    pub fn shake(&mut self, entry: &str) {
+        let defined = self.classes.iter().any(|class| {
+            class
+                .functions
+                .iter()
+                .any(|function| function.name == entry)
+        });
+        if !defined {
+            return;
+        }
+        let reachable = self
+            .reachable(entry)
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<BTreeSet<_>>();
+        for class in &mut self.classes {
+            class.functions.retain(|function| {
+                function.name.is_empty() || reachable.contains(function.name.as_str())
+            });
+        }
+        self.calls = Self::collect_calls(&self.classes);
+    }
+
+    /// Replaces calls of leaf functions with at most `threshold` instructions by their
+    /// [inlined](crate::optimize::inlined) body. Functions using `static` are only inlined into
+    /// their own class.
+    pub fn inline(&mut self, threshold: usize) {
+        let leaves = self
+            .classes
+            .iter()
+            .flat_map(|class| {
+                class
+                    .functions
+                    .iter()
+                    .filter(|function| function.instr.len() <= threshold)
+                    .filter(|function| {
+                        !function
+                            .instr
+                            .iter()
+                            .any(|instr| matches!(instr, Instr::Call { .. }))
+                    })
+                    .map(|function| {
+                        (
+                            function.name.clone(),
+                            (class.name.clone(), function.clone()),
+                        )
+                    })
+            })
+            .collect::<BTreeMap<_, _>>();
+        for class in &mut self.classes {
+            let name = class.name.clone();
+            for function in &mut class.functions {
+                *function = function.rewrite(|instr| {
+                    instr
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(index, item)| {
+                            let body = match item {
+                                Instr::Call { data } => leaves
+                                    .get(&data.ident)
+                                    .filter(|(class, callee)| {
+                                        *class == name || !uses_static(callee)
+                                    })
+                                    .and_then(|(_, callee)| inlined(callee, data.args)),
+                                _ => None,
+                            };
+                            body.unwrap_or_else(|| vec![item.clone()])
+                                .into_iter()
+                                .map(move |instr| (instr, index..index + 1))
+                        })
+                        .collect()
+                });
+            }
+        }
+        self.calls = Self::collect_calls(&self.classes);
+    }
+
+    /// Where the functions land in the output with `order`, starting from the function `entry` if
+    /// there is one. Top-level code always comes first.
+    pub fn layout(&self, order: FunctionOrder, entry: &str) -> Vec<Placement> {
+        self.order(order, entry)
+            .into_iter()
+            .map(|(class, function, caller)| Placement {
+                class: self.classes[class].name.clone(),
+                function: self.classes[class].functions[function].name.clone(),
+                caller,
+            })
+            .collect()
+    }
+
+    /// Indices of the class and function of every function in output order, along with the
+    /// caller it was placed after, see [`Program::layout`].
+    fn order(&self, order: FunctionOrder, entry: &str) -> Vec<(usize, usize, Option<Symbol>)> {
+        let indices = self.classes.iter().enumerate().flat_map(|(class, item)| {
+            (0..item.functions.len()).map(move |function| (class, function))
+        });
+        let source = indices
+            .clone()
+            .map(|(class, function)| (class, function, None));
+        if order == FunctionOrder::Source {
+            return source.collect();
+        }
+        let name = |(class, function): (usize, usize)| {
+            self.classes[class].functions[function].name.as_str()
+        };
+        let mut defined = BTreeMap::new();
+        for index in indices.clone() {
+            defined.entry(name(index)).or_insert(index);
+        }
+        let graph = self.call_graph();
+
+        let mut placed = BTreeSet::new();
+        let mut order = vec![];
+        let top_level = indices.clone().filter(|index| name(*index).is_empty());
+        let roots = defined.get(entry).into_iter().copied().chain(indices);
+        for root in top_level.chain(roots) {
+            let mut pending = vec![(root, None)];
+            while let Some((index, caller)) = pending.pop() {
+                if !placed.insert(index) {
+                    continue;
+                }
+                order.push((index.0, index.1, caller));
+                let callees = graph.callees(name(index)).collect::<Vec<_>>();
+                pending.extend(
+                    callees
+                        .into_iter()
+                        .rev()
+                        .filter_map(|callee| defined.get(callee))
+                        .map(|callee| {
+                            (
+                                *callee,
+                                Some(self.classes[index.0].functions[index.1].name.clone()),
+                            )
+                        }),
+                );
+            }
+        }
+        order
+    }
+
+    /// The program as generating it with `options` lowers it, after inlining and dropping
+    /// unreachable functions.
+    fn prepared(&self, options: &GenerateOptions) -> Cow<'_, Program> {
+        let inline = options.opt_level >= OptLevel::O1 && options.inline_threshold > 0;
+        let entry = options
+            .bootstrap
+            .as_ref()
+            .map(|options| options.entry.clone());
+        let shake = entry.filter(|_| !options.keep_unused);
+        if !inline && shake.is_none() {
+            return Cow::Borrowed(self);
+        }
+        let mut program = self.clone();
+        if inline {
+            program.inline(options.inline_threshold);
+        }
+        if let Some(entry) = shake {
+            program.shake(&entry);
+        }
+        Cow::Owned(program)
+    }
+
+    /// Generates the program like [`Generate::generate_with`], noting in `diagnostics` every
+    /// function left out as unreachable before generating.
+    pub fn generate_into(
+        &self,
+        ctx: &mut Context,
+        diagnostics: &mut dyn DiagnosticSink,
+    ) -> Result<String, crate::generate::Error> {
+        let prepared = self.prepared(&ctx.options);
+        let kept = prepared
+            .classes
+            .iter()
+            .flat_map(|class| {
+                class
+                    .functions
+                    .iter()
+                    .map(|function| function.name.as_str())
+            })
+            .collect::<BTreeSet<_>>();
+        for class in &self.classes {
+            for function in class
+                .functions
+                .iter()
+                .filter(|function| !kept.contains(function.name.as_str()))
+            {
+                let mut note =
+                    Diagnostic::note(format!("`{}` is unreachable and left out", function.name));
+                if let Some(source) = &class.source {
+                    note = note.with_file(source.name());
+                }
+                if !function.span.is_empty() {
+                    note = note.with_span(function.span.clone());
+                }
+                diagnostics.report(note);
+            }
+        }
+        let mut generated = String::new();
+        prepared.lower_classes(ctx, None, &mut |asm| Ok(render_to(&asm, &mut generated)?))?;
+        Ok(generated)
+    }
+
+    /// Lowers like [`Generate::lower_each`], taking the output of every class generated before
+    /// with the same options from `cache`, and handing the output to `sink` class by class.
+    pub fn lower_each_cached(
+        &self,
+        ctx: &mut Context,
+        cache: &mut CompileCache,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), crate::generate::Error>,
+    ) -> Result<(), crate::generate::Error> {
+        self.prepared(&ctx.options)
+            .lower_classes(ctx, Some(cache), sink)
+    }
+
+    /// Lowers every class as is, handing the output to `sink` function by function, or class by
+    /// class when they go through `cache`.
+    fn lower_classes(
+        &self,
+        ctx: &mut Context,
+        mut cache: Option<&mut CompileCache>,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), crate::generate::Error>,
+    ) -> Result<(), crate::generate::Error> {
+        self.check_statics(&ctx.options.layout)?;
+        if ctx.options.bootstrap.is_some() {
+            sink(ctx.lower_bootstrap())?;
+        }
+        match ctx.options.function_order {
+            FunctionOrder::Source => {
+                for class in &self.classes {
+                    lower_class(class, ctx, cache.as_deref_mut(), sink)?;
+                }
+            }
+            order => {
+                let entry = ctx
+                    .options
+                    .bootstrap
+                    .as_ref()
+                    .map_or(ENTRY, |options| &options.entry)
+                    .to_owned();
+                // Runs of functions from the same class are lowered together as one class.
+                let laid_out = self.order(order, &entry);
+                for run in laid_out.chunk_by(|(a, ..), (b, ..)| a == b) {
+                    let class = &self.classes[run[0].0];
+                    let functions = run
+                        .iter()
+                        .map(|(_, function, _)| class.functions[*function].clone());
+                    let run = Class {
+                        functions: functions.collect(),
+                        name: class.name.clone(),
+                        source: class.source.clone(),
+                    };
+                    lower_class(&run, ctx, cache.as_deref_mut(), sink)?;
+                }
+            }
+        }
+        sink(ctx.lower_helpers())?;
+        ctx.check_rom()
+    }
+
+    #[cfg(feature = "parser")]
+    pub fn reparse_region(old: &[Function], source: &str, edit: &Edit) -> Result<Parsed, Error> {
+        Self::reparse_region_with(old, source, edit, &ParseOptions::default())
+    }
+
+    /// Updates the functions parsed from a source after `edit` turned it into `source`.
+    ///
+    /// Only the functions touched by the edit are lexed and parsed again, the others are kept and
+    /// shifted past the edit. Warnings are reported for the reparsed region only.
+    #[cfg(feature = "parser")]
+    pub fn reparse_region_with(
+        old: &[Function],
+        source: &str,
+        edit: &Edit,
+        options: &ParseOptions,
+    ) -> Result<Parsed, Error> {
+        let delta = edit.delta();
+        let first = old
+            .iter()
+            .position(|function| function.span.end >= edit.range.start)
+            .unwrap_or(old.len());
+        let last = old
+            .iter()
+            .rposition(|function| function.span.start <= edit.range.end)
+            .map_or(first, |index| index + 1)
+            .max(first);
+
+        let start = first.checked_sub(1).map_or(0, |index| old[index].span.end);
+        let end = old.get(last).map_or(source.len(), |function| {
+            function.span.start.saturating_add_signed(delta)
+        });
+
+        let options = ParseOptions {
+            top_level_code: options.top_level_code && start == 0,
+            ..options.clone()
+        };
+        let mut reparsed =
+            parse_with(&source[start..end], &options).map_err(|error| match error {
+                Error::Lexing {
+                    source,
+                    span,
+                    suggestion,
+                } => Error::Lexing {
+                    source,
+                    span: shift_span(&span, start as isize),
+                    suggestion,
+                },
+                error => error,
+            })?;
+        reparsed
+            .functions
+            .iter_mut()
+            .for_each(|function| function.shift(start as isize));
+        reparsed.warnings.iter_mut().for_each(|warning| {
+            warning.span = shift_span(&warning.span, start as isize);
+        });
+
+        let suffix = old[last..].iter().cloned().map(|mut function| {
+            function.shift(delta);
+            function
+        });
+        let functions = old[..first]
+            .iter()
+            .cloned()
+            .chain(reparsed.functions)
+            .chain(suffix)
+            .collect();
+        Ok(Parsed {
+            functions,
+            warnings: reparsed.warnings,
+        })
+    }
+}
+
+fn uses_static(function: &Function) -> bool {
+    static_indices(function).next().is_some()
+}
+
+/// Indices of the `static` variables `function` reads or writes.
+fn static_indices(function: &Function) -> impl Iterator<Item = u32> + '_ {
+    function.instr.iter().flat_map(|instr| match instr {
+        Instr::Stack {
+            data:
+                StackInstr::Push {
+                    segment: StackSegment::Static,
+                    literal,
+                }
+                | StackInstr::Pop {
+                    segment: StackSegment::Static,
+                    literal,
+                },
+        } => vec![*literal],
+        Instr::Stack {
+            data:
+                StackInstr::Move {
+                    source,
+                    source_literal,
+                    segment,
+                    literal,
+                },
+        } => [(source, source_literal), (segment, literal)]
+            .into_iter()
+            .filter(|(segment, _)| **segment == StackSegment::Static)
+            .map(|(_, literal)| *literal)
+            .collect(),
+        _ => vec![],
+    })
+}
+
+/// Lowers `class` into `sink`, through `cache` when there is one.
+fn lower_class(
+    class: &Class,
+    ctx: &mut Context,
+    cache: Option<&mut CompileCache>,
+    sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), crate::generate::Error>,
+) -> Result<(), crate::generate::Error> {
+    match cache {
+        Some(cache) => sink(cache.lower(class, ctx)?),
+        None => class.lower_each(ctx, sink),
+    }
+}
+
+impl Generate for Program {
+    type Error = crate::generate::Error;
+
+    fn lower(&self, ctx: &mut Context) -> Result<Vec<AsmInstr>, Self::Error> {
+        let mut lowered = vec![];
+        self.lower_each(ctx, &mut |asm| {
+            lowered.extend(asm);
+            Ok(())
+        })?;
+        Ok(lowered)
+    }
+
+    fn lower_each(
+        &self,
+        ctx: &mut Context,
+        sink: &mut dyn FnMut(Vec<AsmInstr>) -> Result<(), Self::Error>,
+    ) -> Result<(), Self::Error> {
+        self.prepared(&ctx.options).lower_classes(ctx, None, sink)
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::diagnostic::{Diagnostic, Lint, Severity};
+    use crate::emulate::{Machine, Stop};
+    use crate::generate::{
+        BootstrapOptions, Class, Context, ENTRY, FunctionOrder, Generate, GenerateOptions,
+        OptLevel, TargetLayout, bootstrap,
+    };
+    use crate::parse::StackSegment::Constant;
+    use crate::parse::parse;
+    use crate::parse::{Function, ParseOptions, StackInstr, parse_with};
+    use crate::program::{CheckOptions, Edit, Finding, Program, StaticUsage};
+    use crate::source::SourceFile;
+
+    const TESTING_VM: &str = "function A 0\n\
+    push constant 1\n\
+    return\n\
+    function B 0\n\
+    push constant 2\n\
+    return\n\
+    function C 0\n\
+    push constant 3\n\
+    return";
+
+    #[test]
+    fn reparse_changed_function() {
+        let old = parse_with(TESTING_VM, &ParseOptions::default()).expect("expect ok");
+        let at = TESTING_VM.find("constant 2").expect("expect match") + "constant ".len();
+        let source = format!("{}42{}", &TESTING_VM[..at], &TESTING_VM[at + 1..]);
+
+        let reparsed = Program::reparse_region(&old.functions, &source, &Edit::new(at..at + 1, 2))
+            .expect("expect ok");
+        let full = parse_with(&source, &ParseOptions::default()).expect("expect ok");
+        assert_eq!(reparsed.functions, full.functions);
+        let spans =
+            |functions: &[Function]| functions.iter().map(Function::span).collect::<Vec<_>>();
+        assert_eq!(spans(&reparsed.functions), spans(&full.functions));
+        assert_eq!(
+            reparsed.functions[1],
+            Function::new(vec![StackInstr::push(Constant, 42).into()], "B", 0, true)
+        );
+    }
+
+    #[test]
+    fn reparse_inserted_function() {
+        let old = parse_with(TESTING_VM, &ParseOptions::default()).expect("expect ok");
+        let at = TESTING_VM.find("function C").expect("expect match");
+        let inserted = "function D 0\nreturn\n";
+        let source = format!("{}{inserted}{}", &TESTING_VM[..at], &TESTING_VM[at..]);
+
+        let reparsed =
+            Program::reparse_region(&old.functions, &source, &Edit::new(at..at, inserted.len()))
+                .expect("expect ok");
+        let full = parse_with(&source, &ParseOptions::default()).expect("expect ok");
+        assert_eq!(reparsed.functions, full.functions);
+        assert_eq!(reparsed.functions[3].span(), full.functions[3].span());
+    }
+
+    #[test]
+    fn collect_call_sites() {
+        const MAIN_VM: &str =
+            "function Main.main 0\ncall Math.max 2\ncall Output.println 0\nreturn";
+        const GAME_VM: &str = "function Game.run 0\ncall Math.max 1\nreturn";
+        let program = Program::new(vec![
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+            Class::new(parse(GAME_VM).expect("expect ok"), "Game"),
+        ]);
+        assert_eq!(program.calls().len(), 3);
+        assert_eq!(program.calls()[0].span, Some(20..35));
+
+        let conflicts = program.arity_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].target, "Math.max");
+        let callers = conflicts[0]
+            .sites
+            .iter()
+            .map(|site| (site.caller.as_str(), site.args))
+            .collect::<Vec<_>>();
+        assert_eq!(callers, vec![("Game.run", 1), ("Main.main", 2)]);
+    }
+
+    #[test]
+    fn reorder_classes() {
+        let class = |name: &str| {
+            Class::new(
+                parse(&format!("function {name}.f 0\nreturn")).expect("expect ok"),
+                name,
+            )
+        };
+        let mut program = Program::new(vec![
+            class("Sys"),
+            class("Main"),
+            class("Array"),
+            class("Game"),
+        ]);
+        let names = |program: &Program| {
+            program
+                .classes()
+                .iter()
+                .map(|class| class.name().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&program), vec!["Array", "Game", "Main", "Sys"]);
+        program.reorder(&["Sys", "Missing", "Main"]);
+        assert_eq!(names(&program), vec!["Sys", "Main", "Array", "Game"]);
+        assert_eq!(program.calls().len(), 0);
+        let output = program.generate().expect("expect ok");
+        assert!(output.find("(Sys.f)") < output.find("(Main.f)"));
+        assert!(output.find("(Main.f)") < output.find("(Array.f)"));
+    }
+
+    #[test]
+    fn generate_in_class_order() {
+        const MAIN_VM: &str = "function Main.main 0\npush argument 0\npush argument 1\nlt\nreturn";
+        const GAME_VM: &str = "function Game.run 0\npush argument 0\npush argument 1\neq\nreturn";
+        let main = Class::new(parse(MAIN_VM).expect("expect ok"), "Main");
+        let game = Class::new(parse(GAME_VM).expect("expect ok"), "Game");
+        let program = Program::new(vec![main.clone(), game.clone()]);
+        let names = program
+            .classes()
+            .iter()
+            .map(Class::name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Game", "Main"]);
+        assert_eq!(
+            program.generate().expect("expect ok"),
+            Program::new(vec![game, main])
+                .generate()
+                .expect("expect ok")
+        );
+    }
+
+    #[test]
+    fn check_program() {
+        const SYS_VM: &str =
+            "function Sys.main 0\ncall Main.main 0\ncall Math.abs 1\ncall Main.missing 0\nreturn";
+        const MAIN_VM: &str = "function Main.main 0\nreturn\nfunction Sys.main 0\nreturn";
+        let program = Program::new(vec![
+            Class::new(parse(SYS_VM).expect("expect ok"), "Sys"),
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+        ]);
+        let options = CheckOptions {
+            entry: Some(ENTRY.to_owned()),
+            ..Default::default()
+        };
+        let findings = program
+            .check_with(&options)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            findings,
+            [
+                "entry `Sys.init` is not defined",
+                "`Main.missing` called in Sys.main (Sys) is not defined",
+                "`Sys.main` is defined more than once, in Main, Sys",
+            ]
+        );
+        let options = CheckOptions {
+            external: ["Main".to_owned()].into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            program.check_with(&options).as_slice(),
+            [Finding::UndefinedCall { site }, Finding::DuplicateFunction { .. }]
+                if site.target == "Math.abs"
+        ));
+        assert_eq!(program.check().len(), 2);
+        assert_eq!(
+            program.missing_classes().into_iter().collect::<Vec<_>>(),
+            ["Math"]
+        );
+
+        let program = Program::new(vec![Class::new(
+            parse("function Main.main 0\nlabel LOOP\nlabel END\ngoto LOOP\nreturn")
+                .expect("expect ok"),
+            "Main",
+        )]);
+        assert!(program.check().is_empty());
+        let options = CheckOptions {
+            unused_labels: true,
+            ..Default::default()
+        };
+        let findings = program.check_with(&options);
+        assert_eq!(
+            findings.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["label `END` is never jumped to in Main.main"]
+        );
+        assert_eq!(findings[0].lint(), Lint::UnusedLabel);
+    }
+
+    #[test]
+    fn generate_with_bootstrap() {
+        let program = Program::new(vec![Class::new(
+            parse("function Sys.init 0\nreturn").expect("expect ok"),
+            "Sys",
+        )]);
+        let mut ctx = Context::new(GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        });
+        let generated = program.generate_with(&mut ctx).expect("expect ok");
+        assert!(generated.starts_with(&bootstrap(BootstrapOptions::default())));
+        assert!(generated.contains("(Sys.init)"));
+        assert!(!program.generate().expect("expect ok").contains("@256"));
+
+        const SYS_VM: &str = "function Sys.init 0\npush constant 2\npush constant 3\n\
+        call Main.add 2\npop static 0\nlabel HALT\ngoto HALT";
+        const MAIN_VM: &str = "function Main.add 0\npush argument 0\npush argument 1\nadd\nreturn";
+        let program = Program::new(vec![
+            Class::new(parse(SYS_VM).expect("expect ok"), "Sys"),
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+        ]);
+        let mut ctx = Context::new(ctx.options.clone());
+        let mut machine = Machine::new(program.generate_binary_with(&mut ctx).expect("expect ok"));
+        assert_eq!(machine.run(10_000), Ok(Stop::Loop));
+        assert_eq!(machine.ram()[16], 5);
+    }
+
+    #[test]
+    fn shake_unreachable_functions() {
+        const SYS_VM: &str = "function Sys.init 0\ncall Main.main 0\nreturn\n\
+        function Sys.halt 0\nreturn";
+        const MAIN_VM: &str = "function Main.main 0\ncall Main.main 0\ncall Math.abs 1\nreturn\n\
+        function Main.unused 0\ncall Sys.halt 0\nreturn";
+        let mut program = Program::new(vec![
+            Class::new(parse(SYS_VM).expect("expect ok"), "Sys"),
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+        ]);
+        let reachable = program.reachable(ENTRY).into_iter().collect::<Vec<_>>();
+        assert_eq!(reachable, vec!["Main.main", "Math.abs", "Sys.init"]);
+
+        let mut ctx = Context::new(GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        });
+        let generated = program.generate_with(&mut ctx).expect("expect ok");
+        assert!(!generated.contains("(Sys.halt)"));
+        assert!(!generated.contains("(Main.unused)"));
+        let mut ctx = Context::new(GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            keep_unused: true,
+            ..Default::default()
+        });
+        assert!(
+            program
+                .generate_with(&mut ctx)
+                .expect("expect ok")
+                .contains("(Main.unused)")
+        );
+
+        program.shake(ENTRY);
+        assert_eq!(program.classes()[0].functions().len(), 1);
+        assert_eq!(program.calls().len(), 3);
+        program.shake("Missing.entry");
+        assert_eq!(program.classes()[1].functions().len(), 1);
+    }
+
+    #[test]
+    fn report_diagnostics() {
+        const SYS_VM: &str = "function Sys.init 0\ncall Main.main 0\nreturn";
+        const MAIN_VM: &str =
+            "function Main.main 0\ncall Main.draw 1\nreturn\nfunction Main.unused 0\nreturn";
+        let program = Program::new(vec![
+            Class::new(parse(SYS_VM).expect("expect ok"), "Sys"),
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main")
+                .with_source(SourceFile::new("Main.vm", MAIN_VM)),
+        ]);
+        let mut diagnostics = Vec::<Diagnostic>::new();
+        program.check_into(&CheckOptions::default(), &mut diagnostics);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "warning[undefined-call]: `Main.draw` called in Main.main (Main) is not defined \
+                (Main.vm, 21..37)"
+        );
+
+        let mut diagnostics = Vec::<Diagnostic>::new();
+        let mut ctx = Context::new(GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        });
+        let generated = program
+            .generate_into(&mut ctx, &mut diagnostics)
+            .expect("expect ok");
+        let mut ctx = Context::new(GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        });
+        assert_eq!(
+            generated,
+            program.generate_with(&mut ctx).expect("expect ok")
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Note);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "note: `Main.unused` is unreachable and left out (Main.vm, 45..74)"
+        );
+    }
+
+    #[test]
+    fn call_graph_layout() {
+        const MAIN_VM: &str = "function Main.main 0\ncall Math.abs 1\ncall Main.draw 0\nreturn\n\
+        function Main.draw 0\ncall Math.abs 1\ncall Sys.halt 0\nreturn";
+        const MATH_VM: &str = "function Math.unused 0\nreturn\nfunction Math.abs 1\nreturn";
+        const SYS_VM: &str =
+            "function Sys.halt 0\nreturn\nfunction Sys.init 0\ncall Main.main 0\nreturn";
+        let program = Program::new(vec![
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+            Class::new(parse(MATH_VM).expect("expect ok"), "Math"),
+            Class::new(parse(SYS_VM).expect("expect ok"), "Sys"),
+        ]);
+        let layout = program.layout(FunctionOrder::CallGraph, ENTRY);
+        let placed = layout
+            .iter()
+            .map(|placement| (placement.function.as_str(), placement.caller.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placed,
+            vec![
+                ("Sys.init", None),
+                ("Main.main", Some("Sys.init")),
+                ("Math.abs", Some("Main.main")),
+                ("Main.draw", Some("Main.main")),
+                ("Sys.halt", Some("Main.draw")),
+                ("Math.unused", None),
+            ]
+        );
+        let source = program.layout(FunctionOrder::Source, ENTRY);
+        assert_eq!(source[0].function, "Main.main");
+        assert!(source.iter().all(|placement| placement.caller.is_none()));
+
+        let options = GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            function_order: FunctionOrder::CallGraph,
+            ..Default::default()
+        };
+        let generated = program
+            .generate_with(&mut Context::new(options.clone()))
+            .expect("expect ok");
+        let position = |label: &str| {
+            generated
+                .find(&format!("({label})\n"))
+                .expect("expect label")
+        };
+        assert!(position("Sys.init") < position("Main.main"));
+        assert!(position("Math.abs") < position("Main.draw"));
+        assert!(!generated.contains("(Math.unused)"));
+        let stats = program.stats_with(&options).expect("expect ok");
+        assert_eq!(stats.layout.len(), 5);
+        let names = stats
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Sys.init", "Main.main", "Math.abs", "Main.draw", "Sys.halt"]
+        );
+    }
+
+    #[test]
+    fn inline_leaf_functions() {
+        const MAIN_VM: &str = "function Main.main 0\npush constant 7\npush constant 2\n\
+        call Math.sub 2\ncall Main.get 0\nadd\npop static 0\ncall Math.get 0\nreturn\n\
+        function Main.get 0\npush static 1\nreturn";
+        const MATH_VM: &str = "function Math.sub 0\npush argument 0\npush argument 1\nsub\nreturn\n\
+        function Math.get 0\npush static 0\nreturn";
+        let mut program = Program::new(vec![
+            Class::new(parse(MAIN_VM).expect("expect ok"), "Main"),
+            Class::new(parse(MATH_VM).expect("expect ok"), "Math"),
+        ]);
+        program.inline(1);
+        assert_eq!(program.calls().len(), 2);
+        program.inline(3);
+        assert_eq!(program.calls().len(), 1);
+        assert_eq!(program.calls()[0].target, "Math.get");
+        let main = &program.classes()[0].functions()[0];
+        let body = main
+            .instr
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            vec![
+                "push constant 7",
+                "push constant 2",
+                "pick 1",
+                "pick 1",
+                "sub",
+                "squash 2",
+                "push static 1",
+                "squash 0",
+                "add",
+                "pop static 0",
+                "call Math.get 0",
+            ]
+        );
+        assert_eq!(main.instr_span(2), main.instr_span(5));
+
+        let mut ctx = Context::new(GenerateOptions {
+            inline_threshold: 3,
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        });
+        let program = Program::new(vec![Class::new(parse(MAIN_VM).expect("expect ok"), "Main")]);
+        let generated = program.generate_with(&mut ctx).expect("expect ok");
+        assert!(!generated.contains("@Main.get\n"));
+        assert!(generated.contains("@Math.get\n"));
+    }
+
+    #[test]
+    fn account_statics() {
+        let class = |name: &str, index: u32| {
+            let source = format!("function {name}.f 0\npush static 0\npop static {index}\nreturn");
+            Class::new(parse(&source).expect("expect ok"), name)
+        };
+        let program = Program::new(vec![
+            class("A", 3),
+            Class::new(vec![], "B"),
+            class("C", 199),
+        ]);
+        assert_eq!(
+            program.static_usage(),
+            vec![
+                StaticUsage {
+                    class: "A".to_owned(),
+                    slots: 4
+                },
+                StaticUsage {
+                    class: "C".to_owned(),
+                    slots: 200
+                },
+            ]
+        );
+        program
+            .check_statics(&TargetLayout::default())
+            .expect("expect ok");
+        let program = Program::new(vec![class("A", 40), class("C", 199)]);
+        program
+            .check_statics(&TargetLayout::default())
+            .expect_err("expect err");
+        let layout = TargetLayout {
+            statics: 16..300,
+            ..Default::default()
+        };
+        program.check_statics(&layout).expect("expect ok");
+        program.generate().expect_err("expect err");
+    }
+
+    #[test]
+    fn generate_binary() {
+        const TESTING_VM: &str =
+            "function Sys.init 0\npush constant 7\npop static 0\nlabel END\ngoto END";
+        let program = Program::new(vec![Class::new(
+            parse(TESTING_VM).expect("expect ok"),
+            "Sys",
+        )]);
+        let mut ctx = Context::new(GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        });
+        let binary = program.generate_binary_with(&mut ctx).expect("expect ok");
+        let generated = program
+            .generate_with(&mut Context::new(ctx.options.clone()))
+            .expect("expect ok");
+        let code = generated
+            .lines()
+            .filter(|line| !line.starts_with('('))
+            .count();
+        assert_eq!(binary.len(), code);
+        assert_eq!(binary[..2], [256, 0xec10]);
+        // `pop static 0` stores into the first variable.
+        assert!(binary.windows(2).any(|pair| pair == [16, 0xe308]));
+    }
+
+    #[test]
+    fn program_stats() {
+        const TESTING_VM: &str = "function Sys.init 0\ncall Sys.main 0\nreturn\n\
+        function Sys.main 0\npush constant 1\nreturn";
+        let program = Program::new(vec![Class::new(
+            parse(TESTING_VM).expect("expect ok"),
+            "Sys",
+        )]);
+        let options = GenerateOptions {
+            bootstrap: Some(BootstrapOptions::default()),
+            ..Default::default()
+        };
+        let stats = program.stats_with(&options).expect("expect ok");
+        let generated = program
+            .generate_with(&mut Context::new(options))
+            .expect("expect ok");
+        let code = generated
+            .lines()
+            .filter(|line| !line.starts_with('('))
+            .count();
+        assert_eq!(stats.rom, code);
+        assert_eq!(stats.functions.len(), 2);
+        assert_eq!(stats.instructions["call"].count, 1);
+        let total = stats
+            .functions
+            .iter()
+            .map(|function| function.instructions)
+            .sum::<usize>();
+        assert_eq!(stats.shared, stats.rom - total);
+        assert!(stats.shared > 0);
+    }
+
+    #[test]
+    fn rom_overflow() {
+        let big = Function::new(
+            vec![StackInstr::push(Constant, 1).into(); 5000],
+            "Foo.big",
+            0,
+            true,
+        );
+        let small = Function::new(
+            vec![StackInstr::push(Constant, 1).into()],
+            "Foo.small",
+            0,
+            true,
+        );
+        let program = Program::new(vec![Class::new(vec![small, big], "Foo")]);
+        let error = program.generate().expect_err("expect err");
+        let crate::generate::Error::RomOverflow {
+            instructions,
+            functions,
+        } = error
+        else {
+            panic!("expect rom overflow");
+        };
+        assert_eq!(instructions, 5000 * 7 + 7 + 2 * 39);
+        assert_eq!(functions[0].name, "Foo.big");
+        assert_eq!(functions[1].name, "Foo.small");
+    }
+}
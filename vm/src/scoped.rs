@@ -1,13 +1,123 @@
+use crate::symbol::Symbol;
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{self, Display, Formatter};
+
+/// Where code is lowered: a class, one of its functions, or one instruction of that function.
+///
+/// Every level keeps the levels above it, so the symbols and labels of the code are rendered from
+/// the scope when needed instead of being formatted by whoever creates it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    class: Symbol,
+    function: Option<Symbol>,
+    index: Option<usize>,
+}
+
+impl Scope {
+    pub fn class(name: impl Into<Symbol>) -> Self {
+        Self {
+            class: name.into(),
+            function: None,
+            index: None,
+        }
+    }
+
+    /// Scope of the function `name` of the class. Top-level code, which has no name, stays in the
+    /// class scope.
+    pub fn function(&self, name: &Symbol) -> Self {
+        Self {
+            class: self.class.clone(),
+            function: (!name.is_empty())
+                .then(|| name.clone())
+                .or_else(|| self.function.clone()),
+            index: None,
+        }
+    }
+
+    /// Scope of the instruction at `index` of the function.
+    pub fn instr(&self, index: usize) -> Self {
+        Self {
+            index: Some(index),
+            ..self.clone()
+        }
+    }
+
+    /// The scope one level up, none for a class.
+    pub fn parent(&self) -> Option<Self> {
+        match (&self.function, self.index) {
+            (_, Some(_)) => Some(Self {
+                index: None,
+                ..self.clone()
+            }),
+            (Some(_), None) => Some(Self::class(&self.class)),
+            (None, None) => None,
+        }
+    }
+
+    pub fn class_name(&self) -> &Symbol {
+        &self.class
+    }
+
+    pub fn function_name(&self) -> Option<&Symbol> {
+        self.function.as_ref()
+    }
+
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Name the labels of the code derive from: the function, or the class for top-level code.
+    pub fn owner(&self) -> &Symbol {
+        self.function.as_ref().unwrap_or(&self.class)
+    }
+
+    /// Label the call at this instruction returns to, none outside an instruction.
+    pub fn return_label(&self) -> Option<String> {
+        self.index
+            .map(|index| format!("{}$ret.{index}", self.owner()))
+    }
+}
+
+/// Renders the path of the scope, its [owner](Scope::owner) followed by the instruction index.
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.owner())?;
+        match self.index {
+            Some(index) => write!(f, ".{index}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<&str> for Scope {
+    fn from(value: &str) -> Self {
+        Self::class(value)
+    }
+}
+
+impl From<&Symbol> for Scope {
+    fn from(value: &Symbol) -> Self {
+        Self::class(value)
+    }
+}
+
+impl From<&Scope> for Scope {
+    fn from(value: &Scope) -> Self {
+        value.clone()
+    }
+}
+
 #[derive(Clone)]
 pub struct Scoped<T: Clone> {
-    pub scope: String,
+    pub scope: Scope,
     pub value: T,
 }
 
 impl<T: Clone> Scoped<T> {
-    pub fn new(value: T, scope: &str) -> Self {
+    pub fn new(value: T, scope: impl Into<Scope>) -> Self {
         Self {
-            scope: scope.to_owned(),
+            scope: scope.into(),
             value,
         }
     }
@@ -20,7 +130,7 @@ impl<T: Clone> Scoped<&T> {
 }
 
 pub trait ToScoped {
-    fn to_scoped(self, scope: &str) -> Scoped<Self>
+    fn to_scoped(self, scope: impl Into<Scope>) -> Scoped<Self>
     where
         Self: Sized + Clone,
     {
@@ -28,3 +138,28 @@ pub trait ToScoped {
     }
 }
 impl<T> ToScoped for T {}
+
+#[cfg(test)]
+mod tests {
+    use crate::scoped::Scope;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn scope_chain() {
+        let class = Scope::class("Main");
+        let function = class.function(&Symbol::from("Main.main"));
+        let instr = function.instr(3);
+        assert_eq!(instr.to_string(), "Main.main.3");
+        assert_eq!(instr.class_name(), "Main");
+        assert_eq!(instr.return_label().as_deref(), Some("Main.main$ret.3"));
+        assert_eq!(instr.parent(), Some(function.clone()));
+        assert_eq!(function.parent(), Some(class.clone()));
+        assert_eq!(class.parent(), None);
+
+        let top_level = class.function(&Symbol::default()).instr(0);
+        assert_eq!(top_level.owner(), "Main");
+        assert_eq!(top_level.function_name(), None);
+        assert_eq!(top_level.return_label().as_deref(), Some("Main$ret.0"));
+        assert_eq!(class.return_label(), None);
+    }
+}
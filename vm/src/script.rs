@@ -0,0 +1,639 @@
+//! Test scripts of the CPU emulator of nand2tetris, the `.tst` files run against a program with
+//! their output compared to a `.cmp` file.
+//!
+//! ```text
+//! load SimpleAdd.asm,
+//! output-file SimpleAdd.out,
+//! compare-to SimpleAdd.cmp,
+//! output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2;
+//!
+//! set RAM[0] 256,
+//! repeat 60 {
+//!   ticktock;
+//! }
+//! output;
+//! ```
+
+use crate::emulate::{self, Machine};
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::iter::Peekable;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Snafu, Debug, PartialEq, Clone)]
+pub enum Error {
+    #[snafu(display("syntax error on line {line}: {message}"))]
+    Syntax { line: usize, message: String },
+    #[snafu(display("unknown variable `{name}` on line {line}"))]
+    UnknownVariable { line: usize, name: String },
+    #[snafu(display("command `{command}` on line {line} is not supported"))]
+    Unsupported { line: usize, command: String },
+    #[snafu(display("failed to run the program"))]
+    Running { source: emulate::Error },
+}
+
+impl Error {
+    /// Stable code of the error, see [`crate::Error::code`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Syntax { .. } => "VM0401",
+            Error::UnknownVariable { .. } => "VM0402",
+            Error::Unsupported { .. } => "VM0403",
+            Error::Running { source } => source.code(),
+        }
+    }
+}
+
+/// A parsed test script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    line: usize,
+    command: Command,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    Repeat(u32, Vec<Step>),
+    Tick,
+    Tock,
+    TickTock,
+    Output,
+    /// Echoing messages and clearing them, which only the emulator shows.
+    Echo,
+}
+
+/// What a script reads from the machine and writes into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    Ram(u16),
+    A,
+    D,
+    Pc,
+}
+
+/// A column of the output, like `RAM[256]%D1.6.1`: the variable written in a format, right
+/// aligned in `width` characters between `left` and `right` spaces.
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    name: String,
+    variable: Variable,
+    format: Format,
+    left: usize,
+    width: usize,
+    right: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Decimal,
+    Hex,
+    Binary,
+}
+
+/// What running a script wrote, and where it first differed from the comparison file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Outcome {
+    /// Lines written, up to the first one differing from the comparison file.
+    pub output: String,
+    pub mismatch: Option<Mismatch>,
+}
+
+/// A line of the output differing from the line of the comparison file, numbered from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Parses the test script `text`.
+pub fn parse_script(text: &str) -> Result<Script, Error> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let steps = parse_steps(&mut tokens, None)?;
+    Ok(Script { steps })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// One of `,`, `;` and `!` ending a command, or `{` and `}` around the body of a loop.
+    Symbol(char),
+}
+
+/// Splits `text` into words and symbols along with their line, leaving out comments and quoting
+/// strings into one word.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    let Some(c) = chars.next() else {
+                        return SyntaxSnafu {
+                            line,
+                            message: "unterminated comment",
+                        }
+                        .fail();
+                    };
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            ',' | ';' | '!' | '{' | '}' => tokens.push((line, Token::Symbol(c))),
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => {
+                            return SyntaxSnafu {
+                                line,
+                                message: "unterminated string",
+                            }
+                            .fail();
+                        }
+                        Some(c) => word.push(c),
+                    }
+                }
+                tokens.push((line, Token::Word(word)));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !",;!{}\"".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push((line, Token::Word(word)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses commands up to the end of the script, or up to the `}` closing the loop opened on line
+/// `open`.
+fn parse_steps<I>(tokens: &mut Peekable<I>, open: Option<usize>) -> Result<Vec<Step>, Error>
+where
+    I: Iterator<Item = (usize, Token)>,
+{
+    let mut steps = Vec::new();
+    loop {
+        let mut words = Vec::new();
+        let mut line = 0;
+        let end = loop {
+            match tokens.next() {
+                Some((at, Token::Word(word))) => {
+                    if words.is_empty() {
+                        line = at;
+                    }
+                    words.push(word);
+                }
+                Some((at, Token::Symbol(symbol))) => break Some((at, symbol)),
+                None => break None,
+            }
+        };
+        match end {
+            Some((_, ',' | ';' | '!')) if words.is_empty() => {}
+            Some((_, ',' | ';' | '!')) => steps.push(parse_command(line, &words)?),
+            Some((at, '{')) => {
+                let count = match &words[..] {
+                    [repeat, count] if repeat == "repeat" => parse_count(at, count)?,
+                    _ => {
+                        return SyntaxSnafu {
+                            line: at,
+                            message: "`{` has to follow `repeat N`",
+                        }
+                        .fail();
+                    }
+                };
+                let body = parse_steps(tokens, Some(at))?;
+                steps.push(Step {
+                    line,
+                    command: Command::Repeat(count, body),
+                });
+                tokens.next_if(|(_, token)| matches!(token, Token::Symbol(',' | ';' | '!')));
+            }
+            Some((at, _)) if open.is_none() => {
+                return SyntaxSnafu {
+                    line: at,
+                    message: "unmatched `}`",
+                }
+                .fail();
+            }
+            Some((at, _)) => {
+                if !words.is_empty() {
+                    return SyntaxSnafu {
+                        line: at,
+                        message: "command before `}` is not ended",
+                    }
+                    .fail();
+                }
+                return Ok(steps);
+            }
+            None => {
+                if let Some(line) = open {
+                    return SyntaxSnafu {
+                        line,
+                        message: "`{` is never closed",
+                    }
+                    .fail();
+                }
+                if !words.is_empty() {
+                    return SyntaxSnafu {
+                        line,
+                        message: "last command is not ended",
+                    }
+                    .fail();
+                }
+                return Ok(steps);
+            }
+        }
+    }
+}
+
+fn parse_command(line: usize, words: &[String]) -> Result<Step, Error> {
+    let args = &words[1..];
+    let command = match (words[0].as_str(), args) {
+        ("load", [file]) => Command::Load(file.clone()),
+        ("output-file", [file]) => Command::OutputFile(file.clone()),
+        ("compare-to", [file]) => Command::CompareTo(file.clone()),
+        ("output-list", columns) => Command::OutputList(
+            columns
+                .iter()
+                .map(|column| parse_column(line, column))
+                .collect::<Result<_, _>>()?,
+        ),
+        ("set", [variable, value]) => {
+            Command::Set(parse_variable(line, variable)?, parse_value(line, value)?)
+        }
+        ("tick", []) => Command::Tick,
+        ("tock", []) => Command::Tock,
+        ("ticktock", []) => Command::TickTock,
+        ("output", []) => Command::Output,
+        ("echo", _) | ("clear-echo", []) => Command::Echo,
+        (
+            "load" | "output-file" | "compare-to" | "set" | "tick" | "tock" | "ticktock" | "output"
+            | "clear-echo",
+            _,
+        ) => {
+            return SyntaxSnafu {
+                line,
+                message: format!("wrong arguments to `{}`", words[0]),
+            }
+            .fail();
+        }
+        (command, _) => return UnsupportedSnafu { line, command }.fail(),
+    };
+    Ok(Step { line, command })
+}
+
+fn parse_count(line: usize, count: &str) -> Result<u32, Error> {
+    count.parse().ok().with_context(|| SyntaxSnafu {
+        line,
+        message: format!("invalid count `{count}`"),
+    })
+}
+
+fn parse_variable(line: usize, name: &str) -> Result<Variable, Error> {
+    let variable = match name {
+        "A" => Some(Variable::A),
+        "D" => Some(Variable::D),
+        "PC" => Some(Variable::Pc),
+        _ => name
+            .strip_prefix("RAM[")
+            .and_then(|rest| rest.strip_suffix(']'))
+            .and_then(|address| address.parse().ok())
+            .filter(|&address| (address as usize) < crate::layout::RAM_SIZE)
+            .map(Variable::Ram),
+    };
+    variable.with_context(|| UnknownVariableSnafu { line, name })
+}
+
+/// Parses a value written in decimal, or after `%D`, `%X` or `%B` in decimal, hex or binary.
+fn parse_value(line: usize, value: &str) -> Result<u16, Error> {
+    let parsed = match value.get(..2) {
+        Some("%D") => value[2..].parse::<i32>().ok(),
+        Some("%X") => i32::from_str_radix(&value[2..], 16).ok(),
+        Some("%B") => i32::from_str_radix(&value[2..], 2).ok(),
+        _ => value.parse::<i32>().ok(),
+    };
+    let value = parsed
+        .filter(|value| (i16::MIN as i32..=u16::MAX as i32).contains(value))
+        .with_context(|| SyntaxSnafu {
+            line,
+            message: format!("invalid value `{value}`"),
+        })?;
+    Ok(value as u16)
+}
+
+/// Parses a column like `RAM[0]%D2.6.2`, written in decimal 1.6.1 when it has no format.
+fn parse_column(line: usize, column: &str) -> Result<Column, Error> {
+    let (name, format) = column.split_once('%').unwrap_or((column, "D1.6.1"));
+    let invalid = || SyntaxSnafu {
+        line,
+        message: format!("invalid output format `{column}`"),
+    };
+    let mut chars = format.chars();
+    let format = match chars.next() {
+        Some('D') => Format::Decimal,
+        Some('X') => Format::Hex,
+        Some('B') => Format::Binary,
+        _ => return invalid().fail(),
+    };
+    let widths = chars
+        .as_str()
+        .split('.')
+        .map(str::parse)
+        .collect::<Result<Vec<usize>, _>>()
+        .ok();
+    let Some([left, width, right]) = widths.as_deref() else {
+        return invalid().fail();
+    };
+    Ok(Column {
+        name: name.to_owned(),
+        variable: parse_variable(line, name)?,
+        format,
+        left: *left,
+        width: *width,
+        right: *right,
+    })
+}
+
+impl Script {
+    /// File the script loads the program from, the first one if it loads several.
+    pub fn load(&self) -> Option<&str> {
+        self.find(|command| match command {
+            Command::Load(file) => Some(file.as_str()),
+            _ => None,
+        })
+    }
+
+    /// File the script writes its output into.
+    pub fn output_file(&self) -> Option<&str> {
+        self.find(|command| match command {
+            Command::OutputFile(file) => Some(file.as_str()),
+            _ => None,
+        })
+    }
+
+    /// File the script compares its output with.
+    pub fn compare_to(&self) -> Option<&str> {
+        self.find(|command| match command {
+            Command::CompareTo(file) => Some(file.as_str()),
+            _ => None,
+        })
+    }
+
+    fn find<'a>(&'a self, found: impl Fn(&'a Command) -> Option<&'a str>) -> Option<&'a str> {
+        self.steps.iter().find_map(|step| found(&step.command))
+    }
+
+    /// Runs the script against `machine`, loaded with the program beforehand, comparing the lines
+    /// it writes with `expected` if given and stopping at the first one that differs. A `*` in the
+    /// expected line matches any character.
+    pub fn run(&self, machine: &mut Machine, expected: Option<&str>) -> Result<Outcome, Error> {
+        let mut run = Run {
+            machine,
+            expected: expected.map(|expected| expected.lines().collect()),
+            columns: Vec::new(),
+            outcome: Outcome::default(),
+            lines: 0,
+        };
+        run.steps(&self.steps)?;
+        Ok(run.outcome)
+    }
+}
+
+struct Run<'a> {
+    machine: &'a mut Machine,
+    expected: Option<Vec<&'a str>>,
+    columns: Vec<Column>,
+    outcome: Outcome,
+    lines: usize,
+}
+
+impl Run<'_> {
+    /// Runs `steps`, returning whether to go on, which the first mismatch stops.
+    fn steps(&mut self, steps: &[Step]) -> Result<bool, Error> {
+        for step in steps {
+            let go_on = match &step.command {
+                Command::Load(_) | Command::OutputFile(_) | Command::CompareTo(_) => true,
+                Command::Echo | Command::Tick => true,
+                Command::OutputList(columns) => {
+                    self.columns.clone_from(columns);
+                    let header = columns.iter().map(Column::header).collect::<Vec<_>>();
+                    self.write(format!("|{}|", header.join("|")))
+                }
+                Command::Set(variable, value) => {
+                    self.set(*variable, *value);
+                    true
+                }
+                Command::Repeat(count, body) => {
+                    let mut go_on = true;
+                    for _ in 0..*count {
+                        go_on = self.steps(body)?;
+                        if !go_on {
+                            break;
+                        }
+                    }
+                    go_on
+                }
+                // A tick and a tock make up one cycle, which updates the registers on the tock.
+                Command::Tock | Command::TickTock => {
+                    self.machine.step().context(RunningSnafu)?;
+                    true
+                }
+                Command::Output => {
+                    let values = self
+                        .columns
+                        .iter()
+                        .map(|column| column.value(self.get(column.variable)));
+                    self.write(format!("|{}|", values.collect::<Vec<_>>().join("|")))
+                }
+            };
+            if !go_on {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn get(&self, variable: Variable) -> u16 {
+        match variable {
+            Variable::Ram(address) => self.machine.ram()[address as usize],
+            Variable::A => self.machine.a(),
+            Variable::D => self.machine.d(),
+            Variable::Pc => self.machine.pc(),
+        }
+    }
+
+    fn set(&mut self, variable: Variable, value: u16) {
+        match variable {
+            Variable::Ram(address) => self.machine.ram_mut()[address as usize] = value,
+            Variable::A => self.machine.set_a(value),
+            Variable::D => self.machine.set_d(value),
+            Variable::Pc => self.machine.set_pc(value),
+        }
+    }
+
+    /// Writes `line` to the output, returning whether it matches the comparison file.
+    fn write(&mut self, line: String) -> bool {
+        self.lines += 1;
+        if let Some(expected) = &self.expected {
+            let expected = expected
+                .get(self.lines - 1)
+                .map_or("", |expected| expected.trim_end_matches('\r'));
+            let matches = expected.len() == line.len()
+                && expected
+                    .chars()
+                    .zip(line.chars())
+                    .all(|(expected, actual)| expected == '*' || expected == actual);
+            if !matches {
+                self.outcome.mismatch = Some(Mismatch {
+                    line: self.lines,
+                    expected: expected.to_owned(),
+                    actual: line,
+                });
+                return false;
+            }
+        }
+        self.outcome.output.push_str(&line);
+        self.outcome.output.push('\n');
+        true
+    }
+}
+
+impl Column {
+    /// Name of the column centered in its width, the extra space going to the right.
+    fn header(&self) -> String {
+        let total = self.left + self.width + self.right;
+        let name = self.name.get(..total).unwrap_or(&self.name);
+        let left = (total - name.len()) / 2;
+        format!("{:left$}{name:<rest$}", "", rest = total - left)
+    }
+
+    fn value(&self, value: u16) -> String {
+        let width = self.width;
+        let text = match self.format {
+            Format::Decimal => (value as i16).to_string(),
+            Format::Hex => format!("{value:04X}"),
+            Format::Binary => format!("{value:016b}"),
+        };
+        // Hex and binary values keep their lowest digits when they do not fit.
+        let text = match self.format {
+            Format::Decimal => text,
+            Format::Hex | Format::Binary => {
+                let digits = format!("{text:0>width$}");
+                digits[digits.len() - width..].to_owned()
+            }
+        };
+        format!(
+            "{:left$}{text:>width$}{:right$}",
+            "",
+            "",
+            left = self.left,
+            right = self.right
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{assemble, parse_asm};
+    use crate::emulate::Machine;
+    use crate::layout::STATIC;
+    use crate::script::{Error, Mismatch, parse_script};
+
+    const SCRIPT: &str = "
+        load Add.asm,
+        output-file Add.out,
+        compare-to Add.cmp,
+        output-list RAM[0]%D2.6.2 RAM[256]%D1.6.1 D%X1.4.1;
+
+        /* Start with a stack. */
+        set RAM[0] 256,
+        repeat 9 {
+            ticktock;
+        }
+        output; // The sum is on the stack.
+    ";
+
+    fn load() -> Machine {
+        let asm = "@7\nD=A\n@8\nD=D+A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
+        Machine::new(assemble(&parse_asm(asm).expect("expect ok"), STATIC).expect("expect ok"))
+    }
+
+    #[test]
+    fn run_script() {
+        let script = parse_script(SCRIPT).expect("expect ok");
+        assert_eq!(script.load(), Some("Add.asm"));
+        assert_eq!(script.output_file(), Some("Add.out"));
+        assert_eq!(script.compare_to(), Some("Add.cmp"));
+
+        let expected = "|  RAM[0]  |RAM[256]|  D   |\r\n|     257  |     15 | 000F |\r\n";
+        let outcome = script.run(&mut load(), Some(expected)).expect("expect ok");
+        assert_eq!(outcome.mismatch, None);
+        assert_eq!(outcome.output, expected.replace('\r', ""));
+    }
+
+    #[test]
+    fn report_first_mismatch() {
+        let script = parse_script(SCRIPT).expect("expect ok");
+        let expected = "|  RAM[0]  |RAM[256]|  D   |\n|     256  |     ** | 000F |\n";
+        let outcome = script.run(&mut load(), Some(expected)).expect("expect ok");
+        let mismatch = Mismatch {
+            line: 2,
+            expected: "|     256  |     ** | 000F |".to_owned(),
+            actual: "|     257  |     15 | 000F |".to_owned(),
+        };
+        assert_eq!(outcome.mismatch, Some(mismatch));
+        assert_eq!(outcome.output, "|  RAM[0]  |RAM[256]|  D   |\n");
+    }
+
+    #[test]
+    fn reject_invalid_scripts() {
+        let error =
+            parse_script("set RAM[0] 256,\nrepeat 3 {\n ticktock;\n").expect_err("expect err");
+        assert_eq!(
+            error,
+            Error::Syntax {
+                line: 2,
+                message: "`{` is never closed".to_owned()
+            }
+        );
+        let error = parse_script("output-list ARegister%D1.6.1;").expect_err("expect err");
+        assert_eq!(error.code(), "VM0402");
+        let error = parse_script("vmstep;").expect_err("expect err");
+        assert_eq!(
+            error,
+            Error::Unsupported {
+                line: 1,
+                command: "vmstep".to_owned()
+            }
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+use serde::{Deserialize, Serialize};
+
+/// Name, text and line index of the file a class was parsed from, used to report source locations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    name: String,
+    text: Arc<str>,
+    line_starts: Arc<[usize]>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: &str) -> Self {
+        let line_starts = core::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self {
+            name: name.to_owned(),
+            text: text.into(),
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// 1-based line containing the byte `offset`.
+    pub fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset)
+    }
+
+    /// 1-based column of the byte `offset` in its line, counted in characters.
+    pub fn column(&self, offset: usize) -> usize {
+        let start = self.line_starts[self.line(offset) - 1];
+        self.text
+            .get(start..offset)
+            .map_or(1, |before| before.chars().count() + 1)
+    }
+
+    /// Text of the 1-based `line`, without its line break.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self
+            .line_starts
+            .get(line - 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.text.len(), |next| next - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    /// Renders `offset` as `{name}:{line}`.
+    pub fn location(&self, offset: usize) -> String {
+        format!("{}:{}", self.name, self.line(offset))
+    }
+}
+
+/// Generated assembly lines `asm` (0-based, end exclusive) and the VM instruction behind them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub asm: Range<usize>,
+    pub file: Option<String>,
+    /// 1-based line in `file`.
+    pub line: Option<usize>,
+    pub function: Option<String>,
+    pub instr: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct SourceMap {
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("source map is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::source::SourceFile;
+
+    #[test]
+    fn line_of_offset() {
+        let source = SourceFile::new("Foo.vm", "push constant 1\nadd\n\nreturn");
+        assert_eq!(source.line(0), 1);
+        assert_eq!(source.line(15), 1);
+        assert_eq!(source.line(16), 2);
+        assert_eq!(source.line(21), 4);
+        assert_eq!(source.location(17), "Foo.vm:2");
+        assert_eq!(source.column(0), 1);
+        assert_eq!(source.column(18), 3);
+        assert_eq!(source.line_text(1), "push constant 1");
+        assert_eq!(source.line_text(3), "");
+        assert_eq!(source.line_text(4), "return");
+        assert_eq!(source.text().len(), 27);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::asm::AsmInstr;
+use crate::program::Placement;
+use crate::source::Mapping;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// ROM instructions one function lowers to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionStats {
+    pub name: String,
+    pub instructions: usize,
+}
+
+/// How many ROM instructions one kind of VM instruction lowered to across the output.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Expansion {
+    /// Occurrences of the VM instruction.
+    pub count: usize,
+    pub instructions: usize,
+}
+
+impl Expansion {
+    /// Average ROM instructions per occurrence.
+    pub fn factor(&self) -> f64 {
+        self.instructions as f64 / self.count as f64
+    }
+}
+
+/// Code size of generated output, counted in ROM instructions, so labels and comments are left
+/// out.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Stats {
+    /// Functions in output order.
+    pub functions: Vec<FunctionStats>,
+    /// Expansion of each kind of VM instruction, keyed by the instruction without operands, such as
+    /// `push constant` or `call`.
+    pub instructions: BTreeMap<String, Expansion>,
+    /// Instructions outside any function, the bootstrap code and the shared routines.
+    pub shared: usize,
+    /// Instructions of the whole output.
+    pub rom: usize,
+    /// Where each function landed in the output of a program, empty for a class.
+    pub layout: Vec<Placement>,
+}
+
+impl Stats {
+    /// Collects the stats of `asm` from the `mappings` generated along with it.
+    pub fn collect(asm: &[AsmInstr], mappings: &[Mapping]) -> Self {
+        let mut stats = Stats {
+            rom: asm.iter().filter(|instr| instr.is_code()).count(),
+            ..Default::default()
+        };
+        for mapping in mappings {
+            let instructions = asm[mapping.asm.clone()]
+                .iter()
+                .filter(|instr| instr.is_code())
+                .count();
+            let Some(function) = &mapping.function else {
+                stats.shared += instructions;
+                continue;
+            };
+            match stats.functions.last_mut() {
+                Some(last) if last.name == *function => last.instructions += instructions,
+                _ => stats.functions.push(FunctionStats {
+                    name: function.clone(),
+                    instructions,
+                }),
+            }
+            let expansion = stats.instructions.entry(kind(&mapping.instr)).or_default();
+            expansion.count += 1;
+            expansion.instructions += instructions;
+        }
+        stats
+    }
+}
+
+/// Strips the operands from `instr`, keeping the segment of `push` and `pop`.
+fn kind(instr: &str) -> String {
+    instr
+        .split("; ")
+        .map(|part| {
+            let mut words = part.split_whitespace();
+            match words.next() {
+                Some(op @ ("push" | "pop")) => format!("{op} {}", words.next().unwrap_or_default()),
+                op => op.unwrap_or_default().to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod tests {
+    use crate::generate::{Class, GenerateOptions, OptLevel};
+    use crate::parse::parse;
+    use crate::stats::kind;
+
+    #[test]
+    fn instruction_kind() {
+        assert_eq!(kind("push constant 7"), "push constant");
+        assert_eq!(kind("call Foo.bar 2"), "call");
+        assert_eq!(kind("push local 0; pop static 1"), "push local; pop static");
+        assert_eq!(kind("return"), "return");
+    }
+
+    #[test]
+    fn class_stats() {
+        const TESTING_VM: &str = "function Foo.a 0\npush constant 1\npush constant 2\nadd\nreturn\n\
+        function Foo.b 1\npush argument 0\npush argument 1\neq\nreturn";
+        let class = Class::new(parse(TESTING_VM).expect("expect ok"), "Foo");
+        let stats = class.stats().expect("expect ok");
+        let names = stats
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Foo.a", "Foo.b"]);
+        assert_eq!(stats.instructions["push constant"].count, 3);
+        assert_eq!(stats.instructions["push constant"].factor(), 7.0);
+        assert_eq!(stats.instructions["function"].instructions, 0);
+        assert_eq!(stats.shared, 0);
+        let total = stats
+            .functions
+            .iter()
+            .map(|function| function.instructions)
+            .sum::<usize>();
+        assert_eq!(stats.rom, total);
+
+        let options = GenerateOptions {
+            opt_level: OptLevel::O1,
+            ..Default::default()
+        };
+        let optimized = class.stats_with(&options).expect("expect ok");
+        assert!(optimized.shared > 0);
+        let total = optimized
+            .functions
+            .iter()
+            .map(|function| function.instructions)
+            .sum::<usize>();
+        assert_eq!(optimized.rom, total + optimized.shared);
+        assert!(optimized.functions[1].instructions < stats.functions[1].instructions);
+    }
+}
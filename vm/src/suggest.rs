@@ -0,0 +1,88 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+pub(crate) const KEYWORDS: &[&str] = &[
+    "push", "pop", "constant", "local", "argument", "this", "that", "static", "temp", "pointer",
+    "add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not", "function", "call", "return",
+    "label", "goto", "if-goto",
+];
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Suggestion(pub Option<&'static str>);
+
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(keyword) => write!(f, ", did you mean `{keyword}`?"),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = if a_char == *b_char { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        core::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Finds the keyword closest to `word`, ignoring case.
+///
+/// Words of four or more characters tolerate two edits, shorter ones a single edit.
+pub fn suggest_keyword(word: &str) -> Suggestion {
+    Suggestion(suggest_name(&word.to_lowercase(), KEYWORDS.iter().copied()))
+}
+
+/// Finds the name of `names` closest to `word`, the first one of those as close, tolerating edits
+/// like [`suggest_keyword`].
+pub fn suggest_name<'a>(word: &str, names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let threshold = if word.chars().count() >= 4 { 2 } else { 1 };
+    names
+        .into_iter()
+        .map(|name| (edit_distance(word, name), name))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::suggest::{Suggestion, edit_distance, suggest_keyword, suggest_name};
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("push", "push"), 0);
+        assert_eq!(edit_distance("puhs", "push"), 2);
+        assert_eq!(edit_distance("pus", "push"), 1);
+        assert_eq!(edit_distance("", "add"), 3);
+    }
+
+    #[test]
+    fn suggest_misspelled_keywords() {
+        assert_eq!(suggest_keyword("Push"), Suggestion(Some("push")));
+        assert_eq!(suggest_keyword("PUSH"), Suggestion(Some("push")));
+        assert_eq!(suggest_keyword("puhs"), Suggestion(Some("push")));
+        assert_eq!(suggest_keyword("if-got"), Suggestion(Some("if-goto")));
+        assert_eq!(suggest_keyword("Main.main"), Suggestion(None));
+    }
+
+    #[test]
+    fn suggest_close_names() {
+        let names = ["Main.main", "Main.draw", "Main.drop"];
+        assert_eq!(suggest_name("Main.darw", names), Some("Main.draw"));
+        assert_eq!(suggest_name("Main.dro", names), Some("Main.drop"));
+        assert_eq!(suggest_name("Math.abs", names), None);
+        assert_eq!(suggest_name("Main.main", []), None);
+    }
+}
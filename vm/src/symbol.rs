@@ -0,0 +1,168 @@
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::borrow::Borrow;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use serde::{Serialize, Serializer};
+
+/// An interned identifier, like a function name or a label.
+///
+/// Cloning a symbol only bumps a reference count, and symbols from the same [`Interner`] share
+/// their text, so comparing them usually stops at the pointers.
+#[derive(Clone, Default, PartialOrd, Ord)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether both symbols were interned from the same text by the same [`Interner`].
+    pub fn ptr_eq(&self, other: &Symbol) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<Symbol> for str {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<Symbol> for &str {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == &*other.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(value: String) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<&Symbol> for Symbol {
+    fn from(value: &Symbol) -> Self {
+        value.clone()
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&*self.0, f)
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+/// Hands out one shared [`Symbol`] per distinct identifier.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    symbols: BTreeSet<Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, ident: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(ident) {
+            return symbol.clone();
+        }
+        let symbol = Symbol::from(ident);
+        self.symbols.insert(symbol.clone());
+        symbol
+    }
+
+    /// Number of distinct identifiers interned so far.
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbol::{Interner, Symbol};
+
+    #[test]
+    fn intern_identifiers() {
+        let mut interner = Interner::new();
+        let first = interner.intern("Main.main");
+        let second = interner.intern("Main.main");
+        assert!(first.ptr_eq(&second));
+        assert!(!first.ptr_eq(&interner.intern("Main.draw")));
+        assert_eq!(interner.len(), 2);
+        assert_eq!(first, Symbol::from("Main.main"));
+        assert_eq!(first, "Main.main");
+        assert_eq!(first.to_string(), "Main.main");
+    }
+}
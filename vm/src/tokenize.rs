@@ -0,0 +1,165 @@
+use crate::parse::{Span, Token};
+use alloc::vec;
+use alloc::vec::Vec;
+use logos::Logos;
+
+/// Kind of a lexed token, without its payload.
+///
+/// Unlike the parser's internal token type this enum is part of the public API; new kinds may be
+/// added but existing ones keep their meaning.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[non_exhaustive]
+pub enum TokenKind {
+    Push,
+    Pop,
+    Constant,
+    Local,
+    Argument,
+    This,
+    That,
+    Static,
+    Temp,
+    Pointer,
+    Add,
+    Subtract,
+    Negate,
+    Equal,
+    Greater,
+    Less,
+    And,
+    Or,
+    Not,
+    #[cfg(feature = "extensions")]
+    Multiply,
+    #[cfg(feature = "extensions")]
+    Divide,
+    #[cfg(feature = "extensions")]
+    Modulo,
+    #[cfg(feature = "extensions")]
+    ShiftLeft,
+    #[cfg(feature = "extensions")]
+    ShiftRight,
+    Function,
+    Call,
+    Return,
+    Label,
+    Goto,
+    CondGoto,
+    Separator,
+    LitInt,
+    Ident,
+    Comment,
+    /// Input the lexer could not recognize.
+    Error,
+}
+
+impl From<&Token> for TokenKind {
+    fn from(value: &Token) -> Self {
+        match value {
+            Token::Push => Self::Push,
+            Token::Pop => Self::Pop,
+            Token::Constant => Self::Constant,
+            Token::Local => Self::Local,
+            Token::Argument => Self::Argument,
+            Token::This => Self::This,
+            Token::That => Self::That,
+            Token::Static => Self::Static,
+            Token::Temp => Self::Temp,
+            Token::Pointer => Self::Pointer,
+            Token::Add => Self::Add,
+            Token::Subtract => Self::Subtract,
+            Token::Negate => Self::Negate,
+            Token::Equal => Self::Equal,
+            Token::Greater => Self::Greater,
+            Token::Less => Self::Less,
+            Token::And => Self::And,
+            Token::Or => Self::Or,
+            Token::Not => Self::Not,
+            #[cfg(feature = "extensions")]
+            Token::Multiply => Self::Multiply,
+            #[cfg(feature = "extensions")]
+            Token::Divide => Self::Divide,
+            #[cfg(feature = "extensions")]
+            Token::Modulo => Self::Modulo,
+            #[cfg(feature = "extensions")]
+            Token::ShiftLeft => Self::ShiftLeft,
+            #[cfg(feature = "extensions")]
+            Token::ShiftRight => Self::ShiftRight,
+            Token::Function => Self::Function,
+            Token::Call => Self::Call,
+            Token::Return => Self::Return,
+            Token::Label => Self::Label,
+            Token::Goto => Self::Goto,
+            Token::CondGoto => Self::CondGoto,
+            Token::Separator => Self::Separator,
+            Token::LitInt(_) | Token::NegInt(_) => Self::LitInt,
+            Token::Ident(_) | Token::ExtendedIdent(_) => Self::Ident,
+        }
+    }
+}
+
+impl TokenKind {
+    pub fn is_keyword(&self) -> bool {
+        !matches!(
+            self,
+            Self::Separator | Self::LitInt | Self::Ident | Self::Comment | Self::Error
+        )
+    }
+}
+
+/// Appends the `//` comments found between two tokens, one per line.
+fn push_comment(source: &str, gap: Span, tokens: &mut Vec<(TokenKind, Span)>) {
+    let mut from = gap.start;
+    while let Some(start) = source[from..gap.end].find("//") {
+        let start = from + start;
+        let end = source[start..gap.end]
+            .find('\n')
+            .map_or(gap.end, |index| start + index);
+        tokens.push((TokenKind::Comment, start..end));
+        from = end;
+    }
+}
+
+/// Lexes `source` into token kinds and byte spans, including comments.
+///
+/// Never fails: unrecognized input is reported as [`TokenKind::Error`] so highlighting can carry
+/// on past it.
+pub fn tokenize(source: &str) -> Vec<(TokenKind, Span)> {
+    let mut lexer = Token::lexer(source);
+    let mut tokens = vec![];
+    let mut previous_end = 0;
+    while let Some(token) = lexer.next() {
+        let span = lexer.span();
+        push_comment(source, previous_end..span.start, &mut tokens);
+        previous_end = span.end;
+        let kind = match &token {
+            Ok(token) => token.into(),
+            Err(_) => TokenKind::Error,
+        };
+        tokens.push((kind, span));
+    }
+    push_comment(source, previous_end..source.len(), &mut tokens);
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenize::{TokenKind, tokenize};
+
+    #[test]
+    fn tokenize_with_comments() {
+        let tokens = tokenize("push constant 1 // one\nlabel LOOP\n@\n// end\n// of file");
+        let expected = vec![
+            (TokenKind::Push, 0..4),
+            (TokenKind::Constant, 5..13),
+            (TokenKind::LitInt, 14..15),
+            (TokenKind::Comment, 16..22),
+            (TokenKind::Label, 23..28),
+            (TokenKind::Ident, 29..33),
+            (TokenKind::Error, 34..35),
+            (TokenKind::Comment, 36..42),
+            (TokenKind::Comment, 43..53),
+        ];
+        assert_eq!(tokens, expected);
+    }
+}